@@ -18,6 +18,26 @@ struct Storages {
     pub h: HashMap<String, HashMap<String, Value>>,
     #[new(default)]
     pub s: HashMap<String, Vec<Value>>,
+    #[new(default)]
+    pub l: HashMap<String, Vec<Value>>,
+}
+
+// the items of a list of `len` items between `start` and `stop` included,
+// negative indexes count from the end like in Redis
+fn list_range(len: usize, start: isize, stop: isize) -> std::ops::Range<usize> {
+    let len = len as isize;
+    let index = |i: isize| if i < 0 { (len + i).max(0) } else { i };
+    let start = index(start).min(len);
+    let stop = (index(stop) + 1).min(len);
+    start as usize..stop.max(start) as usize
+}
+
+// Redis replies with the counters as strings when their key is read
+fn as_data(v: &Value) -> Value {
+    match v {
+        Value::Int(i) => Value::Data(i.to_string().into_bytes()),
+        v => v.clone(),
+    }
 }
 
 #[derive(new)]
@@ -29,7 +49,7 @@ impl FakeConnection {
     pub fn get<RV: FromRedisValue>(&mut self, key: &str) -> RedisResult<RV> {
         let mut pool = POOL.lock().unwrap();
         let db = pool.entry(self.db).or_insert_with(Storages::new);
-        from_redis_value(&db.k.get(key).map_or_else(|| Value::Nil, Clone::clone))
+        from_redis_value(&db.k.get(key).map_or_else(|| Value::Nil, as_data))
     }
 
     pub fn del<RV: FromRedisValue>(&mut self, key: &str) -> RedisResult<RV> {
@@ -39,8 +59,13 @@ impl FakeConnection {
             || {
                 db.h.remove(&key.to_owned()).map_or_else(
                     || {
-                        db.s.remove(&key.to_owned())
-                            .map_or_else(|| Value::Int(0), |_| Value::Int(1))
+                        db.s.remove(&key.to_owned()).map_or_else(
+                            || {
+                                db.l.remove(&key.to_owned())
+                                    .map_or_else(|| Value::Int(0), |_| Value::Int(1))
+                            },
+                            |_| Value::Int(1),
+                        )
                     },
                     |_| Value::Int(1),
                 )
@@ -61,6 +86,18 @@ impl FakeConnection {
         from_redis_value(&Value::Okay)
     }
 
+    pub fn set_nx<V: ToRedisArgs, RV: FromRedisValue>(
+        &mut self,
+        key: &str,
+        value: V,
+    ) -> RedisResult<RV> {
+        if self.exists::<bool>(key)? {
+            return from_redis_value(&Value::Int(0));
+        }
+        self.set::<V, ()>(key, value)?;
+        from_redis_value(&Value::Int(1))
+    }
+
     pub fn exists<RV: FromRedisValue>(&mut self, key: &str) -> RedisResult<RV> {
         let mut pool = POOL.lock().unwrap();
         let db = pool.entry(self.db).or_insert_with(Storages::new);
@@ -68,10 +105,16 @@ impl FakeConnection {
         from_redis_value(&Value::Int(
             (db.k.contains_key(&key.to_owned())
                 || db.h.contains_key(&key.to_owned())
-                || db.s.contains_key(&key.to_owned())) as i64,
+                || db.s.contains_key(&key.to_owned())
+                || db.l.contains_key(&key.to_owned())) as i64,
         ))
     }
 
+    // keys never expire in the fake database
+    pub fn expire<RV: FromRedisValue>(&mut self, key: &str, _seconds: usize) -> RedisResult<RV> {
+        self.exists(key)
+    }
+
     pub fn incr<V: Into<i64> + Copy, RV: FromRedisValue>(
         &mut self,
         key: &str,
@@ -120,6 +163,25 @@ impl FakeConnection {
         from_redis_value(&Value::Int(is_new as i64))
     }
 
+    pub fn hincr<V: Into<i64> + Copy, RV: FromRedisValue>(
+        &mut self,
+        key: &str,
+        field: &str,
+        delta: V,
+    ) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        let e =
+            db.h.entry(key.to_owned())
+                .or_insert_with(HashMap::new)
+                .entry(field.to_owned())
+                .or_insert_with(|| Value::Data(b"0".to_vec()));
+        let value: i64 = from_redis_value(e)?;
+        let value = value + delta.into();
+        *e = Value::Data(value.to_string().into_bytes());
+        from_redis_value(&Value::Int(value))
+    }
+
     pub fn hset_multiple<V: ToRedisArgs, RV: FromRedisValue>(
         &mut self,
         key: &str,
@@ -178,6 +240,21 @@ impl FakeConnection {
         r
     }
 
+    pub fn hkeys<RV: FromRedisValue>(&mut self, key: &str) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        from_redis_value(&db.h.get(key).map_or_else(
+            || Value::Nil,
+            |h| {
+                Value::Bulk(
+                    h.keys()
+                        .map(|k| Value::Data(k.as_bytes().to_vec()))
+                        .collect(),
+                )
+            },
+        ))
+    }
+
     pub fn hexists<RV: FromRedisValue>(&mut self, key: &str, field: &str) -> RedisResult<RV> {
         let mut pool = POOL.lock().unwrap();
         let db = pool.entry(self.db).or_insert_with(Storages::new);
@@ -187,24 +264,27 @@ impl FakeConnection {
         )
     }
 
-    // pub fn sadd<M: ToRedisArgs, RV: FromRedisValue>(
-    //     &mut self,
-    //     key: &str,
-    //     member: M,
-    // ) -> RedisResult<RV> {
-    //     dbg!(&key);
-    //     let mut storage = STORE_S.lock().unwrap();
-    //     let v = member.to_redis_args();
-    //     let mut is_new = false;
-    //     storage
-    //         .entry(key.to_owned())
-    //         .and_modify(|h| h.push(Value::Data(v[0].clone())))
-    //         .or_insert_with(|| {
-    //             is_new = true;
-    //             vec![Value::Data(v[0].clone())]
-    //         });
-    //     from_redis_value(&Value::Int(is_new as i64))
-    // }
+    pub fn flushdb<RV: FromRedisValue>(&mut self) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        pool.insert(self.db, Storages::new());
+        from_redis_value(&Value::Okay)
+    }
+
+    pub fn sadd<M: ToRedisArgs, RV: FromRedisValue>(
+        &mut self,
+        key: &str,
+        member: M,
+    ) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        let v = Value::Data(member.to_redis_args()[0].clone());
+        let s = db.s.entry(key.to_owned()).or_insert_with(Vec::new);
+        let is_new = !s.contains(&v);
+        if is_new {
+            s.push(v);
+        }
+        from_redis_value(&Value::Int(is_new as i64))
+    }
 
     pub fn srem<M: ToRedisArgs, RV: FromRedisValue>(
         &mut self,
@@ -246,6 +326,73 @@ impl FakeConnection {
         )
     }
 
+    pub fn rpush<V: ToRedisArgs, RV: FromRedisValue>(
+        &mut self,
+        key: &str,
+        value: V,
+    ) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        let l = db.l.entry(key.to_owned()).or_insert_with(Vec::new);
+        l.push(Value::Data(value.to_redis_args()[0].clone()));
+        from_redis_value(&Value::Int(l.len() as i64))
+    }
+
+    pub fn rpop<RV: FromRedisValue>(&mut self, key: &str) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        let mut need_delete_key = false;
+        let v = db.l.get_mut(key).and_then(|l| {
+            let v = l.pop();
+            need_delete_key = l.is_empty();
+            v
+        });
+        if need_delete_key {
+            db.l.remove(key);
+        }
+        from_redis_value(&v.unwrap_or(Value::Nil))
+    }
+
+    pub fn ltrim<RV: FromRedisValue>(
+        &mut self,
+        key: &str,
+        start: isize,
+        stop: isize,
+    ) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        let mut need_delete_key = false;
+        if let Some(l) = db.l.get_mut(key) {
+            let range = list_range(l.len(), start, stop);
+            *l = l[range].to_vec();
+            need_delete_key = l.is_empty();
+        }
+        if need_delete_key {
+            db.l.remove(key);
+        }
+        from_redis_value(&Value::Okay)
+    }
+
+    pub fn llen<RV: FromRedisValue>(&mut self, key: &str) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        from_redis_value(&Value::Int(db.l.get(key).map_or(0, Vec::len) as i64))
+    }
+
+    pub fn lrange<RV: FromRedisValue>(
+        &mut self,
+        key: &str,
+        start: isize,
+        stop: isize,
+    ) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        from_redis_value(&Value::Bulk(
+            db.l.get(key)
+                .map_or_else(Vec::new, |l| l[list_range(l.len(), start, stop)].to_vec()),
+        ))
+    }
+
     pub fn sismember<M: ToRedisArgs, RV: FromRedisValue>(
         &mut self,
         key: &str,
@@ -266,6 +413,62 @@ impl FakeConnection {
             },
         )
     }
+
+    // only `*` and patterns ending with `*` are supported
+    pub fn keys<RV: FromRedisValue>(&mut self, pattern: &str) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        let prefix = pattern.trim_end_matches('*');
+        from_redis_value(&Value::Bulk(
+            db.k.keys()
+                .chain(db.h.keys())
+                .chain(db.s.keys())
+                .chain(db.l.keys())
+                .filter(|k| k.starts_with(prefix))
+                .map(|k| Value::Data(k.as_bytes().to_vec()))
+                .collect(),
+        ))
+    }
+
+    // a single pass over every key, the cursor is hidden like in `redis::Iter`
+    pub fn scan<RV: FromRedisValue>(&mut self) -> RedisResult<std::vec::IntoIter<RV>> {
+        let keys: Vec<RV> = self.keys("*")?;
+        Ok(keys.into_iter())
+    }
+
+    // `TYPE` is a reserved word
+    pub fn key_type<RV: FromRedisValue>(&mut self, key: &str) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        let t = if db.k.contains_key(key) {
+            "string"
+        } else if db.h.contains_key(key) {
+            "hash"
+        } else if db.s.contains_key(key) {
+            "set"
+        } else if db.l.contains_key(key) {
+            "list"
+        } else {
+            "none"
+        };
+        from_redis_value(&Value::Status(t.to_owned()))
+    }
+
+    pub fn hgetall<RV: FromRedisValue>(&mut self, key: &str) -> RedisResult<RV> {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        from_redis_value(&Value::Bulk(db.h.get(key).map_or_else(Vec::new, |h| {
+            h.iter()
+                .flat_map(|(f, v)| vec![Value::Data(f.as_bytes().to_vec()), as_data(v)])
+                .collect()
+        })))
+    }
+
+    // keys never expire in the fake database
+    pub fn ttl<RV: FromRedisValue>(&mut self, key: &str) -> RedisResult<RV> {
+        let exists: bool = self.exists(key)?;
+        from_redis_value(&Value::Int(if exists { -1 } else { -2 }))
+    }
 }
 
 #[derive(new)]
@@ -280,16 +483,18 @@ impl FakePipeline {
         db.k.remove(&key.to_owned());
         db.h.remove(&key.to_owned());
         db.s.remove(&key.to_owned());
+        db.l.remove(&key.to_owned());
         self
     }
 
     pub fn sadd<M: ToRedisArgs>(&mut self, key: &str, member: M) -> &mut Self {
         let mut pool = POOL.lock().unwrap();
         let db = pool.entry(self.db).or_insert_with(Storages::new);
-        let v = member.to_redis_args();
-        db.s.entry(key.to_owned())
-            .and_modify(|h| h.push(Value::Data(v[0].clone())))
-            .or_insert_with(|| vec![Value::Data(v[0].clone())]);
+        let v = Value::Data(member.to_redis_args()[0].clone());
+        let s = db.s.entry(key.to_owned()).or_insert_with(Vec::new);
+        if !s.contains(&v) {
+            s.push(v);
+        }
         self
     }
 
@@ -332,6 +537,19 @@ impl FakePipeline {
         self
     }
 
+    pub fn hincr<V: Into<i64> + Copy>(&mut self, key: &str, field: &str, delta: V) -> &mut Self {
+        let mut pool = POOL.lock().unwrap();
+        let db = pool.entry(self.db).or_insert_with(Storages::new);
+        let e =
+            db.h.entry(key.to_owned())
+                .or_insert_with(HashMap::new)
+                .entry(field.to_owned())
+                .or_insert_with(|| Value::Data(b"0".to_vec()));
+        let value: i64 = from_redis_value(e).unwrap_or(0);
+        *e = Value::Data((value + delta.into()).to_string().into_bytes());
+        self
+    }
+
     pub fn hdel(&mut self, key: &str, field: &str) -> &mut Self {
         let mut pool = POOL.lock().unwrap();
         let db = pool.entry(self.db).or_insert_with(Storages::new);
@@ -346,6 +564,26 @@ impl FakePipeline {
         self
     }
 
+    pub fn set<V: ToRedisArgs>(&mut self, key: &str, value: V) -> &mut Self {
+        let _: RedisResult<()> = FakeConnection::new(self.db).set(key, value);
+        self
+    }
+
+    pub fn hset_multiple<V: ToRedisArgs>(&mut self, key: &str, items: &[(&str, V)]) -> &mut Self {
+        let _: RedisResult<()> = FakeConnection::new(self.db).hset_multiple(key, items);
+        self
+    }
+
+    pub fn rpush<V: ToRedisArgs>(&mut self, key: &str, value: V) -> &mut Self {
+        let _: RedisResult<()> = FakeConnection::new(self.db).rpush(key, value);
+        self
+    }
+
+    // keys never expire in the fake database
+    pub fn expire(&mut self, _key: &str, _seconds: usize) -> &mut Self {
+        self
+    }
+
     pub fn ignore(&mut self) -> &mut Self {
         self
     }
@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use blake2_rfc::blake2b::blake2b;
+use hex_view::HexView;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[cfg(not(test))]
+use log::*;
+
+#[cfg(not(test))]
+use crate::{
+    cli::{BackupCommand, Opt, RestoreCommand},
+    endpoints::routes,
+};
+use crate::{
+    db,
+    error::{self, Result, ServerError},
+    types::BackupData,
+};
+
+// version of the backup file layout, bumped when it changes
+const BACKUP_FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BackupFile {
+    format: u32,
+    // seconds since the Unix epoch
+    created_at: i64,
+    // hex BLAKE2b-256 of `data`, serialised compactly
+    checksum: String,
+    // kept as a JSON value for its serialisation, hence the checksum, not to depend
+    // on the formatting of the file
+    data: Value,
+}
+
+fn checksum(data: &Value) -> Result<String> {
+    let hash = blake2b(32, &[], serde_json::to_string(data)?.as_bytes());
+    Ok(format!("{:x}", HexView::from(hash.as_bytes())))
+}
+
+pub fn write(path: &Path, data: &BackupData) -> Result<()> {
+    let data = serde_json::to_value(data)?;
+    let file = BackupFile {
+        format: BACKUP_FORMAT,
+        created_at: db::timestamp(),
+        checksum: checksum(&data)?,
+        data,
+    };
+    serde_json::to_writer(BufWriter::new(File::create(path)?), &file)?;
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<BackupData> {
+    let file: BackupFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    if file.format != BACKUP_FORMAT {
+        return Err(ServerError::new(
+            error::INTERNAL_ERROR,
+            &format!("Unsupported backup format: {}", file.format),
+        ));
+    }
+    if checksum(&file.data)? != file.checksum {
+        return Err(ServerError::new(
+            error::INTERNAL_ERROR,
+            "The backup is corrupted, its checksum does not match",
+        ));
+    }
+    Ok(serde_json::from_value(file.data)?)
+}
+
+#[cfg(not(test))]
+pub fn backup(opt: &Opt, cmd: &BackupCommand) -> Result<()> {
+    let data = routes::open_storage(opt)?.backup(cmd.with_sessions)?;
+    write(Path::new(&cmd.file), &data)?;
+    info!("Database backed up to {}", cmd.file);
+    Ok(())
+}
+
+// the backup may come from an older server, the database is migrated afterwards
+#[cfg(not(test))]
+pub fn restore(opt: &Opt, cmd: &RestoreCommand) -> Result<()> {
+    let data = read(Path::new(&cmd.file))?;
+    let mut s = routes::open_storage(opt)?;
+    s.restore(&data)?;
+    s.migrate()?;
+    info!("Database restored from {}", cmd.file);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::*;
+
+    #[test]
+    fn write_read_test() {
+        let path = std::env::temp_dir().join(format!("efficio_backup_{}.json", db::timestamp()));
+        let data = BackupData::Redis {
+            keys: vec![
+                RedisKey::new(
+                    "user_id_salt".to_owned(),
+                    None,
+                    RedisValue::String("salt".to_owned()),
+                ),
+                RedisKey::new(
+                    "sessions:1".to_owned(),
+                    Some(3600),
+                    RedisValue::Set(vec!["auth".to_owned()]),
+                ),
+            ],
+        };
+        assert_eq!(Ok(()), write(&path, &data));
+        assert_eq!(Ok(data), read(&path));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, content.replace("salt", "pepper")).unwrap();
+        assert_eq!(
+            Err(error::INTERNAL_ERROR),
+            read(&path).map_err(|e| e.status)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,12 +1,90 @@
+use std::str::FromStr;
+
 use argh::FromArgs;
 
 #[derive(FromArgs)]
 /// Efficio's backend
 pub struct Opt {
+    /// database backend: redis or sqlite
+    #[argh(option, short = 'b', default = "Backend::Redis")]
+    pub db_backend: Backend,
     /// database host
     #[argh(option, short = 'h')]
     pub db_host: Option<String>,
     /// database port
     #[argh(option, short = 'p')]
     pub db_port: Option<u32>,
+    /// database file, for the sqlite backend
+    #[argh(option, short = 'f')]
+    pub db_file: Option<String>,
+    /// hours after login at which a session expires
+    #[argh(option, default = "720")]
+    pub session_ttl: u32,
+    /// hours without use after which a session expires
+    #[argh(option, default = "168")]
+    pub session_idle_ttl: u32,
+    /// days a deleted store, aisle or product stays in the trash
+    #[argh(option, default = "30")]
+    pub trash_retention: u32,
+    /// directory where emails are written when no SMTP server is set
+    #[argh(option)]
+    pub mail_spool: Option<String>,
+    /// SMTP server to send emails through, the password is read from EFFICIO_SMTP_PASSWORD
+    #[argh(option)]
+    pub smtp_host: Option<String>,
+    /// SMTP username
+    #[argh(option)]
+    pub smtp_user: Option<String>,
+    /// sender address of the emails
+    #[argh(option)]
+    pub mail_from: Option<String>,
+    /// run a maintenance command instead of the server
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Backup(BackupCommand),
+    Restore(RestoreCommand),
+}
+
+#[derive(FromArgs)]
+/// Dump the database to a file, stop the server first for a consistent backup
+#[argh(subcommand, name = "backup")]
+pub struct BackupCommand {
+    /// file to write the backup to
+    #[argh(positional)]
+    pub file: String,
+    /// include the sessions and pending password resets, so that users stay logged in after
+    /// a restore
+    #[argh(switch)]
+    pub with_sessions: bool,
+}
+
+#[derive(FromArgs)]
+/// Load a backup into an empty database of the same backend
+#[argh(subcommand, name = "restore")]
+pub struct RestoreCommand {
+    /// file to read the backup from
+    #[argh(positional)]
+    pub file: String,
+}
+
+pub enum Backend {
+    Redis,
+    Sqlite,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(Backend::Redis),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(format!("unknown database backend: {}", s)),
+        }
+    }
 }
@@ -4,12 +4,15 @@ use redis::{self, transaction, Commands, Connection, Pipeline};
 #[cfg(test)]
 use fake_redis::{transaction, FakeConnection as Connection, FakePipeline as Pipeline};
 
-use crate::{db, error::Result, types::*};
+use serde_json::{json, Value};
+
+use crate::{db, error::*, types::*};
 
 const AISLE_NAME: &str = "name";
 const AISLE_WEIGHT: &str = "sort_weight";
 const AISLE_OWNER: &str = "owner_id";
 const AISLE_STORE: &str = "store_id";
+const AISLE_VERSION: &str = "version";
 
 fn aisle_key(id: &AisleId) -> String {
     format!("aisle:{}", **id)
@@ -19,8 +22,48 @@ fn aisles_in_store_key(id: &StoreId) -> String {
     format!("aisles_in_store:{}", **id)
 }
 
-pub fn get_aisle_owner(c: &mut Connection, aisle_id: &AisleId) -> Result<UserId> {
-    Ok(UserId(c.hget(&aisle_key(&aisle_id), AISLE_OWNER)?))
+pub fn get_aisle_store(c: &mut Connection, aisle_id: &AisleId) -> Result<StoreId> {
+    let aisle_key = aisle_key(&aisle_id);
+    if db::trash::is_trashed(c, &aisle_key)? {
+        return Err(ServerError::new(NOT_FOUND, "Aisle not found"));
+    }
+    Ok(StoreId::new(c.hget(&aisle_key, AISLE_STORE)?))
+}
+
+// `None` if the aisle doesn't exist or is in a trash, it comes without its products
+pub fn get_aisle(c: &mut Connection, aisle_id: &AisleId) -> Result<Option<(StoreId, Aisle)>> {
+    let aisle_key = aisle_key(&aisle_id);
+    if db::trash::is_trashed(c, &aisle_key)? {
+        return Ok(None);
+    }
+    let store_id: Option<String> = c.hget(&aisle_key, AISLE_STORE)?;
+    match store_id {
+        Some(store_id) => Ok(Some((
+            StoreId::new(store_id),
+            Aisle::new(
+                aisle_id.to_string(),
+                c.hget(&aisle_key, AISLE_NAME)?,
+                c.hget(&aisle_key, AISLE_WEIGHT)?,
+                vec![],
+            )
+            .with_version(c.hget(&aisle_key, AISLE_VERSION)?),
+        ))),
+        None => Ok(None),
+    }
+}
+
+pub fn get_name(c: &mut Connection, aisle_id: &AisleId) -> Result<String> {
+    Ok(c.hget(&aisle_key(&aisle_id), AISLE_NAME)?)
+}
+
+pub fn get_version(c: &mut Connection, aisle_id: &AisleId) -> Result<u64> {
+    let version: Option<u64> = c.hget(&aisle_key(&aisle_id), AISLE_VERSION)?;
+    version.ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))
+}
+
+pub fn get_aisle_ids(c: &mut Connection, store_id: &StoreId) -> Result<Vec<AisleId>> {
+    let aisles: Vec<String> = c.smembers(&aisles_in_store_key(&store_id))?;
+    Ok(aisles.into_iter().map(AisleId).collect())
 }
 
 pub fn get_aisles_in_store(c: &mut Connection, store_id: &StoreId) -> Result<Vec<Aisle>> {
@@ -35,7 +78,8 @@ pub fn get_aisles_in_store(c: &mut Connection, store_id: &StoreId) -> Result<Vec
                 c.hget(&aisle_key, AISLE_NAME)?,
                 c.hget(&aisle_key, AISLE_WEIGHT)?,
                 db::products::get_products_in_aisle(c, &aisle_id)?,
-            ))
+            )
+            .with_version(c.hget(&aisle_key, AISLE_VERSION)?))
         })
         .collect()
 }
@@ -54,9 +98,7 @@ pub fn save_aisle(
     let aisle_id = db::ids::get_next_aisle_id();
     let aisle_key = aisle_key(&aisle_id);
     let aisle_in_store_key = aisles_in_store_key(&store_id);
-    let user_id = db::sessions::get_user_id(c, &auth)?;
-    let store_owner = db::stores::get_store_owner(c, &store_id)?;
-    db::verify_permission(&user_id, &store_owner)?;
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
     let new_sort_weight = find_max_weight_in_store(c, &store_id)? + 1f32;
     transaction(c, &[&aisle_key, &aisle_in_store_key], |c, pipe| {
         pipe.hset(&aisle_key, AISLE_NAME, name)
@@ -67,10 +109,21 @@ pub fn save_aisle(
             .ignore()
             .hset(&aisle_key, AISLE_STORE, &**store_id)
             .ignore()
+            .hset(&aisle_key, AISLE_VERSION, FIRST_VERSION)
+            .ignore()
             .sadd(&aisle_in_store_key, &*aisle_id)
             .query(c)
     })?;
-
+    db::changes::record_change(c, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::AisleCreated,
+        &aisle_id,
+        Value::Null,
+        json!({ "name": name }),
+    )?;
     Ok(Aisle::new(
         aisle_id.to_string(),
         name.to_owned(),
@@ -84,29 +137,196 @@ pub fn edit_aisle(
     auth: &Auth,
     aisle_id: &AisleId,
     new_name: &str,
+    if_match: &IfMatch,
+) -> Result<()> {
+    let aisle_key = aisle_key(&aisle_id);
+    let store_id = get_aisle_store(c, &aisle_id)?;
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let (mut old_name, mut version) = (String::new(), 0);
+    transaction(c, &[&aisle_key], |c, pipe| {
+        old_name = c.hget(&aisle_key, AISLE_NAME)?;
+        version = c.hget(&aisle_key, AISLE_VERSION)?;
+        if if_match.matches(version) {
+            pipe.hset(&aisle_key, AISLE_NAME, new_name)
+                .ignore()
+                .hincr(&aisle_key, AISLE_VERSION, 1)
+                .ignore();
+        }
+        pipe.query(c)
+    })?;
+    if_match.verify(version)?;
+    db::changes::record_change(c, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::AisleRenamed,
+        &aisle_id,
+        json!({ "name": old_name }),
+        json!({ "name": new_name }),
+    )
+}
+
+// into the user's trash, with its products
+pub fn delete_aisle(
+    c: &mut Connection,
+    auth: &Auth,
+    aisle_id: &AisleId,
+    if_match: &IfMatch,
 ) -> Result<()> {
     let aisle_key = aisle_key(&aisle_id);
-    let aisle_owner = get_aisle_owner(c, &aisle_id)?;
-    db::verify_permission_auth(c, &auth, &aisle_owner)?;
-    Ok(c.hset(&aisle_key, AISLE_NAME, new_name)?)
+    let store_id = get_aisle_store(c, &aisle_id)?;
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let aisle_in_store_key = aisles_in_store_key(&store_id);
+    let mut version = 0;
+    transaction(c, &[&aisle_key, &aisle_in_store_key], |c, pipe| {
+        version = c.hget(&aisle_key, AISLE_VERSION)?;
+        if if_match.matches(version) {
+            pipe.srem(&aisle_in_store_key, &**aisle_id).ignore();
+            db::trash::transaction_trash(pipe, &aisle_key, &user_id);
+        }
+        pipe.query(c)
+    })?;
+    if_match.verify(version)?;
+    db::changes::record_change(c, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+    let name = get_name(c, &aisle_id)?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::AisleDeleted,
+        &aisle_id,
+        json!({ "name": name }),
+        Value::Null,
+    )
 }
 
-pub fn delete_aisle(c: &mut Connection, auth: &Auth, aisle_id: &AisleId) -> Result<()> {
+// back in its store, the user must still be able to edit it
+pub fn restore_aisle(
+    c: &mut Connection,
+    auth: &Auth,
+    aisle_id: &AisleId,
+) -> Result<(StoreId, Aisle)> {
     let aisle_key = aisle_key(&aisle_id);
-    let aisle_owner = get_aisle_owner(c, &aisle_id)?;
-    db::verify_permission_auth(c, &auth, &aisle_owner)?;
+    let user_id = db::trash::verify_in_trash(c, &auth, &aisle_key)?;
     let store_id = StoreId::new(c.hget(&aisle_key, AISLE_STORE)?);
+    db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
     let aisle_in_store_key = aisles_in_store_key(&store_id);
-    transaction(c, &[&aisle_key, &aisle_in_store_key], |c, mut pipe| {
+    transaction(c, &[&aisle_key, &aisle_in_store_key], |c, pipe| {
+        pipe.sadd(&aisle_in_store_key, &**aisle_id).ignore();
+        db::trash::transaction_restore(pipe, &aisle_key, &user_id);
+        pipe.query(c)
+    })?;
+    let (store_id, aisle) =
+        get_aisle(c, &aisle_id)?.ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))?;
+    let products = db::products::get_products_in_aisle(c, &aisle_id)?;
+    db::changes::record_change(c, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+    for product in &products {
+        db::changes::record_change(c, &store_id, ChangedItem::Product(product.id().0))?;
+    }
+    let name = get_name(c, &aisle_id)?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::AisleRestored,
+        &aisle_id,
+        Value::Null,
+        json!({ "name": name }),
+    )?;
+    Ok((store_id, aisle.with_products(products)))
+}
+
+// delete the aisle for good, with its products
+pub fn purge_aisle(c: &mut Connection, aisle_id: &AisleId) -> Result<()> {
+    let aisle_key = aisle_key(&aisle_id);
+    let trashed_by = db::trash::trashed_by(c, &aisle_key)?;
+    transaction(c, &[&aisle_key], |c, mut pipe| {
         db::products::transaction_purge_products_in_aisle(c, &mut pipe, &aisle_id)?;
-        pipe.srem(&aisle_in_store_key, &**aisle_id)
-            .ignore()
-            .del(&aisle_key)
-            .query(c)
+        if let Some(user_id) = &trashed_by {
+            db::trash::transaction_discard(pipe, &aisle_key, &user_id);
+        }
+        pipe.del(&aisle_key).query(c)
     })?;
     Ok(())
 }
 
+// to the end of `store_id` unless `sort_weight` is given, with its products
+pub fn move_aisle(
+    c: &mut Connection,
+    auth: &Auth,
+    aisle_id: &AisleId,
+    store_id: &StoreId,
+    sort_weight: Option<f32>,
+    if_match: &IfMatch,
+) -> Result<Aisle> {
+    let (from_store_id, aisle) =
+        get_aisle(c, &aisle_id)?.ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))?;
+    let user_id = db::verify_permission_auth(c, &auth, &from_store_id, Role::Editor)?;
+    db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let aisle_key = aisle_key(&aisle_id);
+    let from_key = aisles_in_store_key(&from_store_id);
+    let to_key = aisles_in_store_key(&store_id);
+    let new_sort_weight = match sort_weight {
+        Some(sort_weight) => sort_weight,
+        None => find_max_weight_in_store(c, &store_id)? + 1f32,
+    };
+    let mut version = 0;
+    transaction(c, &[&aisle_key, &from_key, &to_key], |c, pipe| {
+        version = c.hget(&aisle_key, AISLE_VERSION)?;
+        if if_match.matches(version) {
+            pipe.srem(&from_key, &**aisle_id)
+                .ignore()
+                .sadd(&to_key, &**aisle_id)
+                .ignore()
+                .hset(&aisle_key, AISLE_STORE, &**store_id)
+                .ignore()
+                .hset(&aisle_key, AISLE_WEIGHT, new_sort_weight)
+                .ignore()
+                .hincr(&aisle_key, AISLE_VERSION, 1)
+                .ignore();
+        }
+        pipe.query(c)
+    })?;
+    if_match.verify(version)?;
+    let products = db::products::get_products_in_aisle(c, &aisle_id)?;
+    db::changes::record_change(c, &from_store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+    if from_store_id != *store_id {
+        db::changes::record_change(c, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+        for product in &products {
+            db::changes::record_change(c, &store_id, ChangedItem::Product(product.id().0))?;
+        }
+    }
+    let before = json!({
+        "store_id": from_store_id.to_string(),
+        "sort_weight": aisle.sort_weight,
+    });
+    let after = json!({ "store_id": store_id.to_string(), "sort_weight": new_sort_weight });
+    db::history::record(
+        c,
+        &from_store_id,
+        &user_id,
+        AuditAction::AisleMoved,
+        &aisle_id,
+        before.clone(),
+        after.clone(),
+    )?;
+    if from_store_id != *store_id {
+        db::history::record(
+            c,
+            &store_id,
+            &user_id,
+            AuditAction::AisleMoved,
+            &aisle_id,
+            before,
+            after,
+        )?;
+    }
+    let mut aisle = aisle.with_version(version + 1).with_products(products);
+    aisle.sort_weight = new_sort_weight;
+    Ok(aisle)
+}
+
 pub fn transaction_purge_aisles_in_store(
     c: &mut Connection,
     mut pipe: &mut Pipeline,
@@ -128,19 +348,86 @@ pub fn transaction_purge_aisles_in_store(
     Ok(())
 }
 
+// copy the aisles of `from` and their products into `to`, owned by `owner_id`
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_copy_aisles_in_store(
+    c: &mut Connection,
+    mut pipe: &mut Pipeline,
+    from: &StoreId,
+    to: &StoreId,
+    owner_id: &UserId,
+    reset_done: bool,
+) -> Result<()> {
+    let aisles: Vec<String> = c.smembers(&aisles_in_store_key(&from))?;
+    let to_key = aisles_in_store_key(&to);
+    for aisle_id in aisles {
+        let from_id = AisleId(aisle_id);
+        let from_key = aisle_key(&from_id);
+        let name: String = c.hget(&from_key, AISLE_NAME)?;
+        let sort_weight: f32 = c.hget(&from_key, AISLE_WEIGHT)?;
+        let aisle_id = db::ids::get_next_aisle_id();
+        let aisle_key = aisle_key(&aisle_id);
+        pipe.hset(&aisle_key, AISLE_NAME, name)
+            .ignore()
+            .hset(&aisle_key, AISLE_WEIGHT, sort_weight)
+            .ignore()
+            .hset(&aisle_key, AISLE_OWNER, &**owner_id)
+            .ignore()
+            .hset(&aisle_key, AISLE_STORE, &**to)
+            .ignore()
+            .hset(&aisle_key, AISLE_VERSION, FIRST_VERSION)
+            .ignore()
+            .sadd(&to_key, &*aisle_id)
+            .ignore();
+        db::products::transaction_copy_products_in_aisle(
+            c, &mut pipe, &from_id, &aisle_id, &owner_id, reset_done,
+        )?;
+    }
+    Ok(())
+}
+
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_import_aisles(
+    pipe: &mut Pipeline,
+    store_id: &StoreId,
+    owner_id: &UserId,
+    aisles: &[ExportedAisle],
+) {
+    let store_key = aisles_in_store_key(&store_id);
+    for aisle in aisles {
+        let aisle_id = db::ids::get_next_aisle_id();
+        let aisle_key = aisle_key(&aisle_id);
+        pipe.hset(&aisle_key, AISLE_NAME, &aisle.name)
+            .ignore()
+            .hset(&aisle_key, AISLE_WEIGHT, aisle.sort_weight)
+            .ignore()
+            .hset(&aisle_key, AISLE_OWNER, &**owner_id)
+            .ignore()
+            .hset(&aisle_key, AISLE_STORE, &**store_id)
+            .ignore()
+            .hset(&aisle_key, AISLE_VERSION, FIRST_VERSION)
+            .ignore()
+            .sadd(&store_key, &*aisle_id)
+            .ignore();
+        db::products::transaction_import_products(pipe, &aisle_id, &owner_id, &aisle.products);
+    }
+}
+
+// returns the store of the aisle and its previous weight
 pub fn edit_aisle_sort_weight(
     c: &mut Connection,
     pipe: &mut Pipeline,
     auth: &Auth,
     data: &AisleItemWeight,
-) -> Result<()> {
+) -> Result<(StoreId, f32)> {
     let aisle_id = AisleId(data.id.clone());
-    let aisle_owner = get_aisle_owner(c, &aisle_id)?;
-    db::verify_permission_auth(c, &auth, &aisle_owner)?;
+    let store_id = get_aisle_store(c, &aisle_id)?;
+    db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
     let aisle_key = aisle_key(&aisle_id);
+    let previous: f32 = c.hget(&aisle_key, AISLE_WEIGHT)?;
     pipe.hset(&aisle_key, AISLE_WEIGHT, data.sort_weight)
         .ignore();
-    Ok(())
+    Ok((store_id, previous))
 }
 
 #[cfg(test)]
@@ -193,7 +480,10 @@ pub mod tests {
         let client = Client::open(get_db_addr().as_str()).unwrap();
         let mut c = client.get_connection().unwrap();
         let (_, aid) = save_aisle_for_test(&mut c);
-        assert_eq!(Ok(()), edit_aisle(&mut c, &AUTH, &aid, RENAMED));
+        assert_eq!(
+            Ok(()),
+            edit_aisle(&mut c, &AUTH, &aid, RENAMED, &IfMatch::default())
+        );
 
         let name: String = c.hget(&aisle_key(&aid), AISLE_NAME).unwrap();
         assert_eq!(RENAMED, name.as_str());
@@ -318,7 +608,14 @@ pub mod tests {
         let res = db::products::save_product(&mut c, &AUTH, "product2", &aid);
         assert_eq!(Ok(expected), res);
         let pid2 = res.unwrap().id();
-        assert_eq!(Ok(()), delete_aisle(&mut c, &AUTH, &aid));
+        assert_eq!(
+            Ok(()),
+            delete_aisle(&mut c, &AUTH, &aid, &IfMatch::default())
+        );
+        assert_eq!(Ok(None), get_aisle(&mut c, &aid));
+        assert_eq!(Ok(true), c.exists(&aisle_key(&aid)));
+
+        assert_eq!(Ok(()), db::trash::purge_trash(&mut c, db::timestamp() + 1));
         assert_eq!(Ok(false), c.exists(&aisle_key(&aid)));
         assert_eq!(
             Ok(false),
@@ -328,6 +625,48 @@ pub mod tests {
         assert_eq!(Ok(false), c.exists(&db::products::product_key(&pid2)));
     }
 
+    #[test]
+    fn move_aisle_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let (store_id, aisle_id) = save_aisle_for_test(&mut c);
+        let aisle2 = add_2nd_aisle(&mut c, &store_id);
+        let (p1, p2, _) = fill_aisles(&mut c, &aisle_id, &aisle2);
+        let store2 = db::stores::save_store(&mut c, &AUTH, "store2").unwrap();
+
+        let moved =
+            move_aisle(&mut c, &AUTH, &aisle_id, &store2, None, &IfMatch::default()).unwrap();
+        assert!((moved.sort_weight - 1f32).abs() < std::f32::EPSILON);
+        assert_eq!(FIRST_VERSION + 1, moved.version());
+        assert_eq!(
+            Ok(false),
+            c.sismember(&aisles_in_store_key(&store_id), aisle_id.to_string())
+        );
+        assert_eq!(
+            Ok(true),
+            c.sismember(&aisles_in_store_key(&store2), aisle_id.to_string())
+        );
+        assert_eq!(Ok(store2.clone()), get_aisle_store(&mut c, &aisle_id));
+        assert_eq!(
+            Ok(store2.clone()),
+            db::products::get_product_store(&mut c, &p1)
+        );
+        let mut moved_products = db::changes::store_changes(&mut c, &AUTH, &store2, 0)
+            .unwrap()
+            .products
+            .iter()
+            .map(|p| p.id().to_string())
+            .collect::<Vec<_>>();
+        moved_products.sort();
+        let mut expected = vec![p1.to_string(), p2.to_string()];
+        expected.sort();
+        assert_eq!(expected, moved_products);
+        assert_eq!(
+            Ok(vec![aisle_id.to_string()]),
+            db::changes::store_changes(&mut c, &AUTH, &store_id, 0).map(|c| c.deleted_aisles)
+        );
+    }
+
     #[test]
     fn transaction_purge_aisles_test() {
         let client = Client::open(get_db_addr().as_str()).unwrap();
@@ -365,11 +704,11 @@ pub mod tests {
         let client = Client::open(get_db_addr().as_str()).unwrap();
         let mut c = client.get_connection().unwrap();
 
-        let (_, aisle_id) = save_aisle_for_test(&mut c);
+        let (store_id, aisle_id) = save_aisle_for_test(&mut c);
         let mut pipe = Pipeline::new(c.db);
         pipe.atomic();
         assert_eq!(
-            Ok(()),
+            Ok((store_id, 1f32)),
             edit_aisle_sort_weight(
                 &mut c,
                 &mut pipe,
@@ -380,4 +719,65 @@ pub mod tests {
         assert_eq!(Ok(()), pipe.query(&mut c));
         assert_eq!(Ok(2.0f32), c.hget(&aisle_key(&aisle_id), AISLE_WEIGHT));
     }
+
+    #[test]
+    fn move_aisle_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let other_store_id = s.save_store(&auth, "other store").unwrap();
+            let other_aisle_id = s
+                .save_aisle(&auth, &other_store_id, "other aisle")
+                .unwrap()
+                .id();
+            let product_id = s
+                .save_product(&auth, "other product", &other_aisle_id)
+                .unwrap()
+                .id();
+            let moved = s
+                .move_aisle(
+                    &auth,
+                    &other_aisle_id,
+                    &f.store_id,
+                    None,
+                    &IfMatch::default(),
+                )
+                .unwrap();
+            // after the aisles already there
+            assert!((moved.sort_weight - 2.0).abs() < std::f32::EPSILON);
+            assert_eq!(Ok(f.store_id.clone()), s.aisle_store(&other_aisle_id));
+            assert_eq!(Ok(f.store_id.clone()), s.product_store(&product_id));
+            // in the history of both stores
+            for store_id in &[&f.store_id, &other_store_id] {
+                let history = s.store_history(&auth, store_id, None, 1).unwrap();
+                assert_eq!(AuditAction::AisleMoved, history.entries[0].action);
+                assert_eq!(other_aisle_id.to_string(), history.entries[0].item_id);
+                assert!(history.next.is_some());
+            }
+
+            let moved = s
+                .move_aisle(
+                    &auth,
+                    &other_aisle_id,
+                    &other_store_id,
+                    Some(5.0),
+                    &IfMatch::default(),
+                )
+                .unwrap();
+            assert!((moved.sort_weight - 5.0).abs() < std::f32::EPSILON);
+            assert_eq!(Ok(other_store_id.clone()), s.product_store(&product_id));
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.move_aisle(
+                    &auth,
+                    &AisleId("unknown".to_owned()),
+                    &f.store_id,
+                    None,
+                    &IfMatch::default()
+                )
+                .map(|_| ())
+                .map_err(|e| e.status)
+            );
+        });
+    }
 }
@@ -0,0 +1,278 @@
+#[cfg(not(test))]
+use redis::{self, Commands, Connection};
+
+#[cfg(test)]
+use fake_redis::FakeConnection as Connection;
+
+use crate::{
+    db::{atomic_pipe, migrations::SCHEMA_VERSION},
+    error::{self, Result, ServerError},
+    types::*,
+};
+
+// the keys of Efficio, the others of a shared database are left alone
+const KEYS: &[&str] = &[
+    SCHEMA_VERSION,
+    "next_user_id",
+    "user_id_salt",
+    "users",
+    "sessions",
+    "trashed_items",
+];
+const KEY_PREFIXES: &[&str] = &[
+    "user:",
+    "reset:",
+    "session:",
+    "sessions:",
+    "store:",
+    "stores:",
+    "store_members:",
+    "store_revision:",
+    "store_changes:",
+    "store_history:",
+    "aisle:",
+    "aisles_in_store:",
+    "product:",
+    "products_in_aisle:",
+    "invitation:",
+    "invitations:",
+    "trash:",
+    "undo:",
+    "op_result:",
+    "events:",
+    "event_seq:",
+];
+
+fn is_efficio_key(key: &str) -> bool {
+    KEYS.contains(&key) || KEY_PREFIXES.iter().any(|prefix| key.starts_with(prefix))
+}
+
+// `sessions`, `sessions:{user_id}`, `session:{auth}` and the password resets `reset:{token}`
+fn is_session_key(key: &str) -> bool {
+    key == "sessions"
+        || key.starts_with("sessions:")
+        || key.starts_with("session:")
+        || key.starts_with("reset:")
+}
+
+// with SCAN, which unlike KEYS doesn't block the server on a large database
+fn efficio_keys(c: &mut Connection) -> Result<Vec<String>> {
+    let keys: Vec<String> = c.scan()?.collect();
+    Ok(keys.into_iter().filter(|key| is_efficio_key(key)).collect())
+}
+
+#[cfg(not(test))]
+fn key_type(c: &mut Connection, key: &str) -> Result<String> {
+    Ok(redis::cmd("TYPE").arg(key).query(c)?)
+}
+
+#[cfg(test)]
+fn key_type(c: &mut Connection, key: &str) -> Result<String> {
+    Ok(c.key_type(key)?)
+}
+
+// every key of Efficio, sorted so that two backups of the same data are identical
+pub fn backup(c: &mut Connection, with_sessions: bool) -> Result<BackupData> {
+    let mut keys = efficio_keys(c)?;
+    keys.sort();
+    let mut entries = vec![];
+    for key in keys {
+        if !with_sessions && is_session_key(&key) {
+            continue;
+        }
+        let value = match key_type(c, &key)?.as_str() {
+            "string" => RedisValue::String(c.get(&key)?),
+            "hash" => RedisValue::Hash(c.hgetall(&key)?),
+            "set" => {
+                let mut members: Vec<String> = c.smembers(&key)?;
+                members.sort();
+                RedisValue::Set(members)
+            }
+            "list" => RedisValue::List(c.lrange(&key, 0, -1)?),
+            // expired since it was listed
+            "none" => continue,
+            t => {
+                return Err(ServerError::new(
+                    error::INTERNAL_ERROR,
+                    &format!("Key {} has an unsupported type: {}", key, t),
+                ))
+            }
+        };
+        let ttl: i64 = c.ttl(&key)?;
+        let ttl = if ttl > 0 { Some(ttl as u64) } else { None };
+        entries.push(RedisKey::new(key, ttl, value));
+    }
+    Ok(BackupData::Redis { keys: entries })
+}
+
+// The database may only have been migrated, the backup's schema version replaces its own.
+// All the keys are written at once, a failed restore leaves the database as it was.
+pub fn restore(c: &mut Connection, data: &BackupData) -> Result<()> {
+    let keys = match data {
+        BackupData::Redis { keys } => keys,
+        _ => {
+            return Err(ServerError::new(
+                error::INTERNAL_ERROR,
+                "The backup is not one of a Redis database",
+            ))
+        }
+    };
+    if efficio_keys(c)?.iter().any(|k| k != SCHEMA_VERSION) {
+        return Err(ServerError::new(
+            error::INTERNAL_ERROR,
+            "The database to restore into is not empty",
+        ));
+    }
+
+    let mut pipe = atomic_pipe(c);
+    for entry in keys {
+        let key = entry.key.as_str();
+        match entry.value {
+            RedisValue::String(ref value) => {
+                pipe.set(key, value).ignore();
+            }
+            RedisValue::Hash(ref fields) => {
+                let fields: Vec<(&str, &str)> = fields
+                    .iter()
+                    .map(|(f, v)| (f.as_str(), v.as_str()))
+                    .collect();
+                pipe.hset_multiple(key, &fields).ignore();
+            }
+            RedisValue::Set(ref members) => {
+                for member in members {
+                    pipe.sadd(key, member).ignore();
+                }
+            }
+            RedisValue::List(ref items) => {
+                for item in items {
+                    pipe.rpush(key, item).ignore();
+                }
+            }
+        }
+        if let Some(ttl) = entry.ttl {
+            pipe.expire(key, ttl as usize).ignore();
+        }
+    }
+    Ok(pipe.query(c)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        self, redis_storage::RedisStorage, sessions::tests::*, tests::*, users, Storage,
+    };
+    use fake_redis::FakeCient as Client;
+
+    #[test]
+    fn backup_restore_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let mut s = RedisStorage::new(&mut c, TTL);
+        assert_eq!(Ok(()), s.migrate());
+        let user = users::tests::gen_user();
+        let token = s.save_user(&user).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = s.save_store(&auth, "my store").unwrap();
+        let aisle_id = s.save_aisle(&auth, &store_id, "aisle").unwrap().id();
+        s.save_product(&auth, "product", &aisle_id).unwrap();
+        s.create_reset_token(&user.username, &user.email).unwrap();
+
+        let full = s.backup(true).unwrap();
+        let data = s.backup(false).unwrap();
+        assert_ne!(full, data);
+        let keys = match data {
+            BackupData::Redis { ref keys } => keys,
+            _ => panic!("not a Redis backup"),
+        };
+        assert!(keys.iter().all(|k| !is_session_key(&k.key)));
+        assert!(keys.iter().any(|k| k.key == "user_id_salt"));
+        assert!(keys.iter().any(|k| k.key == "next_user_id"));
+        assert_eq!(
+            Err(error::INTERNAL_ERROR),
+            s.restore(&data).map_err(|e| e.status)
+        );
+
+        assert_eq!(Ok(()), s.nuke());
+        assert_eq!(Ok(()), s.migrate());
+        assert_eq!(Ok(()), s.restore(&full));
+        assert_eq!(Ok(full), s.backup(true));
+        assert_eq!(Ok(()), s.validate_session(&auth));
+        assert_eq!(
+            Ok("my store".to_owned()),
+            s.list_store(&auth, &store_id).map(|s| s.name().to_owned())
+        );
+
+        let sqlite = BackupData::Sqlite {
+            schema_version: 1,
+            tables: vec![],
+        };
+        assert_eq!(Ok(()), db::flush(&mut c));
+        assert_eq!(
+            Err(error::INTERNAL_ERROR),
+            restore(&mut c, &sqlite).map_err(|e| e.status)
+        );
+    }
+
+    #[test]
+    fn restore_without_sessions_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        // another application sharing the database
+        let _: () = c.set("other:key", "value").unwrap();
+        let mut s = RedisStorage::new(&mut c, TTL);
+        assert_eq!(Ok(()), s.migrate());
+        let token = s.save_user(&users::tests::gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        s.save_store(&auth, "my store").unwrap();
+
+        let data = s.backup(false).unwrap();
+        match data {
+            BackupData::Redis { ref keys } => assert!(keys.iter().all(|k| k.key != "other:key")),
+            _ => panic!("not a Redis backup"),
+        }
+        // only the keys of Efficio count
+        assert_eq!(Ok(()), s.nuke());
+        assert_eq!(Ok(()), s.migrate());
+        let _: () = c.set("other:key", "value").unwrap();
+        let mut s = RedisStorage::new(&mut c, TTL);
+        assert_eq!(Ok(()), s.restore(&data));
+        assert_eq!(
+            Err(error::UNAUTHORISED),
+            s.validate_session(&auth).map_err(|e| e.status)
+        );
+        assert_eq!(Ok(data), s.backup(true));
+        assert_eq!(Ok(Some("value".to_owned())), c.get("other:key"));
+    }
+
+    #[test]
+    fn backup_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let full = s.backup(true).unwrap();
+            let backup = s.backup(false).unwrap();
+            // only into an empty database
+            assert_eq!(
+                Err(error::INTERNAL_ERROR),
+                s.restore(&backup).map_err(|e| e.status)
+            );
+            assert_eq!(Ok(()), s.nuke());
+            assert_eq!(Ok(()), s.restore(&backup));
+            assert_eq!(Ok(backup), s.backup(false));
+            assert_eq!(
+                Err(error::UNAUTHORISED),
+                s.validate_session(&auth).map_err(|e| e.status)
+            );
+
+            assert_eq!(Ok(()), s.nuke());
+            assert_eq!(Ok(()), s.restore(&full));
+            assert_eq!(Ok(()), s.validate_session(&auth));
+            assert_eq!(
+                Ok("store".to_owned()),
+                s.list_store(&auth, &f.store_id)
+                    .map(|s| s.name().to_owned())
+            );
+        });
+    }
+}
@@ -0,0 +1,221 @@
+#[cfg(not(test))]
+use redis::{Commands, Connection, Pipeline};
+
+#[cfg(test)]
+use fake_redis::{FakeConnection as Connection, FakePipeline as Pipeline};
+
+use crate::{db, error::*, types::*};
+
+fn store_changes_key(store_id: &StoreId) -> String {
+    format!("store_changes:{}", **store_id)
+}
+
+fn store_revision_key(store_id: &StoreId) -> String {
+    format!("store_revision:{}", **store_id)
+}
+
+// bump the revision of the store, dropping the oldest changes past `CHANGE_LOG_LEN`
+pub fn record_change(c: &mut Connection, store_id: &StoreId, item: ChangedItem) -> Result<()> {
+    let store_changes_key = store_changes_key(&store_id);
+    let revision: u64 = c.incr(&store_revision_key(&store_id), 1)?;
+    c.rpush(
+        &store_changes_key,
+        format!("{} {}", revision, item.to_string()),
+    )?;
+    c.ltrim(&store_changes_key, -(db::CHANGE_LOG_LEN as isize), -1)?;
+    Ok(())
+}
+
+pub fn get_revision(c: &mut Connection, store_id: &StoreId) -> Result<u64> {
+    let revision: Option<u64> = c.get(&store_revision_key(&store_id))?;
+    Ok(revision.unwrap_or(0))
+}
+
+pub fn store_changes(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    since: u64,
+) -> Result<StoreChanges> {
+    db::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    let revision = get_revision(c, &store_id)?;
+    let entries: Vec<String> = c.lrange(&store_changes_key(&store_id), 0, -1)?;
+    let retained = entries
+        .iter()
+        .map(|entry| {
+            let mut parts = entry.splitn(2, ' ');
+            match (parts.next().map(str::parse), parts.next()) {
+                (Some(Ok(revision)), Some(item)) => Ok((revision, item.parse()?)),
+                _ => Err(ServerError::new(INTERNAL_ERROR, "Invalid change log entry")),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut changes = StoreChanges {
+        revision,
+        ..Default::default()
+    };
+    let items = match db::changed_since(revision, retained, since) {
+        Some(items) => items,
+        None => {
+            changes.resync = true;
+            return Ok(changes);
+        }
+    };
+    for item in items {
+        match item {
+            ChangedItem::Store => changes.name = Some(db::stores::get_name(c, &store_id)?),
+            ChangedItem::Aisle(id) => match db::aisles::get_aisle(c, &AisleId(id.clone()))? {
+                Some((aisle_store_id, aisle)) if aisle_store_id == *store_id => {
+                    changes.aisles.push(aisle)
+                }
+                _ => changes.deleted_aisles.push(id),
+            },
+            ChangedItem::Product(id) => {
+                match db::products::get_product(c, &ProductId(id.clone()))? {
+                    // the aisle may be in a trash
+                    Some((aisle_id, product))
+                        if db::aisles::get_aisle(c, &aisle_id)?
+                            .map_or(false, |(aisle_store_id, _)| aisle_store_id == *store_id) =>
+                    {
+                        changes
+                            .products
+                            .push(ProductChange::new(aisle_id.to_string(), product))
+                    }
+                    _ => changes.deleted_products.push(id),
+                }
+            }
+        }
+    }
+    Ok(changes)
+}
+
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_purge_changes(pipe: &mut Pipeline, store_id: &StoreId) {
+    pipe.del(&store_changes_key(&store_id))
+        .ignore()
+        .del(&store_revision_key(&store_id))
+        .ignore();
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{aisles::tests::*, sessions::tests::*, tests::*};
+    use fake_redis::FakeCient as Client;
+
+    #[test]
+    fn store_changes_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let (store_id, aisle_id) = save_aisle_for_test(&mut c);
+        let revision = get_revision(&mut c, &store_id).unwrap();
+        assert_eq!(1, revision);
+
+        let product = db::products::save_product(&mut c, &AUTH, "product", &aisle_id).unwrap();
+        assert_eq!(
+            Ok(()),
+            db::aisles::edit_aisle(&mut c, &AUTH, &aisle_id, "renamed", &IfMatch::default())
+        );
+        let changes = store_changes(&mut c, &AUTH, &store_id, revision).unwrap();
+        assert_eq!(3, changes.revision);
+        assert_eq!(false, changes.resync);
+        assert_eq!(None, changes.name);
+        assert_eq!(
+            vec![Aisle::new(
+                aisle_id.to_string(),
+                "renamed".to_owned(),
+                1.,
+                vec![]
+            )],
+            changes.aisles
+        );
+        assert_eq!(
+            vec![ProductChange::new(aisle_id.to_string(), product.clone())],
+            changes.products
+        );
+
+        assert_eq!(
+            Ok(()),
+            db::products::delete_product(&mut c, &AUTH, &product.id(), &IfMatch::default())
+        );
+        assert_eq!(
+            Ok(()),
+            db::stores::edit_store(&mut c, &AUTH, &store_id, "store", &IfMatch::default())
+        );
+        let changes = store_changes(&mut c, &AUTH, &store_id, 3).unwrap();
+        assert_eq!(Some("store".to_owned()), changes.name);
+        assert_eq!(vec![product.id().to_string()], changes.deleted_products);
+        assert!(changes.aisles.is_empty());
+        assert_eq!(
+            Ok(StoreChanges {
+                revision: 5,
+                ..Default::default()
+            }),
+            store_changes(&mut c, &AUTH, &store_id, 5)
+        );
+
+        for _ in 0..db::CHANGE_LOG_LEN {
+            db::aisles::edit_aisle(&mut c, &AUTH, &aisle_id, "renamed", &IfMatch::default())
+                .unwrap();
+        }
+        // the first changes were dropped
+        assert_eq!(
+            Ok(true),
+            store_changes(&mut c, &AUTH, &store_id, 3).map(|c| c.resync)
+        );
+        assert_eq!(
+            Ok(vec![aisle_id.to_string()]),
+            store_changes(&mut c, &AUTH, &store_id, 6).map(|c| c
+                .aisles
+                .iter()
+                .map(|a| a.id().to_string())
+                .collect::<Vec<_>>())
+        );
+
+        assert_eq!(
+            Ok(()),
+            db::stores::delete_store(&mut c, &AUTH, &store_id, &IfMatch::default())
+        );
+        assert_eq!(Ok(()), db::stores::purge_store(&mut c, &store_id));
+        assert_eq!(Ok(false), c.exists(&store_changes_key(&store_id)));
+        assert_eq!(Ok(false), c.exists(&store_revision_key(&store_id)));
+    }
+
+    #[test]
+    fn store_changes_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let revision = s.list_store(&auth, &f.store_id).unwrap().revision();
+            assert_eq!(
+                Ok(StoreChanges {
+                    revision,
+                    ..Default::default()
+                }),
+                s.store_changes(&auth, &f.store_id, revision)
+            );
+            let data = EditProduct::new(Some("product renamed".to_owned()), None, None, None);
+            assert_eq!(
+                Ok(()),
+                s.modify_product(&auth, &data, &f.product_id, &IfMatch::default())
+            );
+            assert_eq!(
+                Ok(vec![f.product_id.to_string()]),
+                s.store_changes(&auth, &f.store_id, revision).map(|c| c
+                    .products
+                    .iter()
+                    .map(|p| p.id().to_string())
+                    .collect())
+            );
+            assert_eq!(
+                Ok(()),
+                s.delete_product(&auth, &f.product_id, &IfMatch::default())
+            );
+            assert_eq!(
+                Ok(vec![f.product_id.to_string()]),
+                s.store_changes(&auth, &f.store_id, revision)
+                    .map(|c| c.deleted_products)
+            );
+        });
+    }
+}
@@ -0,0 +1,112 @@
+#[cfg(not(test))]
+use redis::{Commands, Connection};
+
+#[cfg(test)]
+use fake_redis::FakeConnection as Connection;
+
+use crate::{db, error::*, types::*};
+
+fn event_log_key(user_id: &UserId) -> String {
+    format!("events:{}", **user_id)
+}
+
+fn event_seq_key(user_id: &UserId) -> String {
+    format!("event_seq:{}", **user_id)
+}
+
+// append to the user's change feed, dropping the oldest events past `EVENT_LOG_LEN`
+pub fn log_event(c: &mut Connection, user_id: &UserId, data: &str) -> Result<u64> {
+    let event_log_key = event_log_key(&user_id);
+    let id: u64 = c.incr(&event_seq_key(&user_id), 1)?;
+    c.rpush(&event_log_key, format!("{} {}", id, data))?;
+    c.ltrim(&event_log_key, -(db::EVENT_LOG_LEN as isize), -1)?;
+    Ok(id)
+}
+
+pub fn events_since(
+    c: &mut Connection,
+    auth: &Auth,
+    resume_from: Option<u64>,
+) -> Result<EventFeed> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    let entries: Vec<String> = c.lrange(&event_log_key(&user_id), 0, -1)?;
+    let mut retained = entries
+        .iter()
+        .map(|entry| {
+            let mut parts = entry.splitn(2, ' ');
+            match (parts.next().map(str::parse), parts.next()) {
+                (Some(Ok(id)), Some(data)) => Ok(LoggedEvent::new(id, data.to_owned())),
+                _ => Err(ServerError::new(INTERNAL_ERROR, "Invalid event log entry")),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // concurrent writers may push out of order
+    retained.sort_by_key(|e| e.id);
+    Ok(db::event_feed(user_id, retained, resume_from))
+}
+
+pub fn delete_user_events(c: &mut Connection, user_id: &UserId) -> Result<()> {
+    c.del(&event_log_key(&user_id))?;
+    Ok(c.del(&event_seq_key(&user_id))?)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{tests::*, users::tests::*};
+    use fake_redis::FakeCient as Client;
+
+    #[test]
+    fn event_log_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let token = db::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let user_id = UserId(token.user_id.clone());
+
+        for i in 0..db::EVENT_LOG_LEN + 2 {
+            assert_eq!(Ok(i as u64 + 1), log_event(&mut c, &user_id, "{}"));
+        }
+        let last_id = db::EVENT_LOG_LEN as u64 + 2;
+        let feed = events_since(&mut c, &auth, Some(2)).unwrap();
+        assert_eq!(false, feed.missed);
+        assert_eq!(last_id, feed.last_id);
+        assert_eq!(db::EVENT_LOG_LEN, feed.events.len());
+        assert_eq!(LoggedEvent::new(3, "{}".to_owned()), feed.events[0]);
+        // the first events were dropped
+        let feed = events_since(&mut c, &auth, Some(1)).unwrap();
+        assert_eq!(true, feed.missed);
+        assert!(feed.events.is_empty());
+
+        assert_eq!(Ok(()), delete_user_events(&mut c, &user_id));
+        assert_eq!(Ok(false), c.exists(&event_log_key(&user_id)));
+        assert_eq!(Ok(1), log_event(&mut c, &user_id, "{}"));
+    }
+
+    #[test]
+    fn event_log_storage_test() {
+        for_each_storage(|s| {
+            let token = s.save_user(&gen_user()).unwrap();
+            let auth = Auth(&token.session_token);
+            let user_id = UserId(token.user_id.clone());
+            assert_eq!(Ok(1), s.log_event(&user_id, "{}"));
+            assert_eq!(Ok(2), s.log_event(&user_id, "{\"type\":\"resync\"}"));
+            assert_eq!(
+                Ok(EventFeed::new(
+                    user_id.clone(),
+                    2,
+                    vec![LoggedEvent::new(2, "{\"type\":\"resync\"}".to_owned())],
+                    false
+                )),
+                s.events_since(&auth, Some(1))
+            );
+            // from now on
+            assert_eq!(
+                Ok(EventFeed::new(user_id.clone(), 2, vec![], false)),
+                s.events_since(&auth, None)
+            );
+            // ahead of the log
+            assert_eq!(Ok(true), s.events_since(&auth, Some(3)).map(|f| f.missed));
+        });
+    }
+}
@@ -0,0 +1,126 @@
+#[cfg(not(test))]
+use redis::{Commands, Connection, Pipeline};
+
+#[cfg(test)]
+use fake_redis::{FakeConnection as Connection, FakePipeline as Pipeline};
+
+use serde_json::Value;
+
+use crate::{db, error::*, types::*};
+
+fn store_history_key(store_id: &StoreId) -> String {
+    format!("store_history:{}", **store_id)
+}
+
+// append-only, the history lasts as long as the store
+pub fn record(
+    c: &mut Connection,
+    store_id: &StoreId,
+    user_id: &UserId,
+    action: AuditAction,
+    item_id: &str,
+    before: Value,
+    after: Value,
+) -> Result<()> {
+    let entry = AuditEntry::new(
+        user_id.to_string(),
+        db::timestamp(),
+        action,
+        item_id.to_owned(),
+        before,
+        after,
+    );
+    c.rpush(
+        &store_history_key(&store_id),
+        serde_json::to_string(&entry)?,
+    )?;
+    Ok(())
+}
+
+// the entries are numbered by their position in the list
+pub fn store_history(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    before: Option<u64>,
+    limit: u32,
+) -> Result<StoreHistory> {
+    db::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    let store_history_key = store_history_key(&store_id);
+    let len: u64 = c.llen(&store_history_key)?;
+    let end = before.map_or(len, |before| before.saturating_sub(1).min(len));
+    let start = end.saturating_sub(u64::from(limit));
+    let data: Vec<String> = if start < end {
+        c.lrange(&store_history_key, start as isize, end as isize - 1)?
+    } else {
+        vec![]
+    };
+    let mut entries = data
+        .iter()
+        .zip(start + 1..)
+        .map(|(data, id)| {
+            let mut entry: AuditEntry = serde_json::from_str(data)?;
+            entry.id = id;
+            Ok(entry)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    entries.reverse();
+    let next = if start > 0 { Some(start + 1) } else { None };
+    Ok(StoreHistory::new(entries, next))
+}
+
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_purge_history(pipe: &mut Pipeline, store_id: &StoreId) {
+    pipe.del(&store_history_key(&store_id)).ignore();
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sessions::tests::*, tests::*};
+    use fake_redis::FakeCient as Client;
+
+    pub fn actions(history: &StoreHistory) -> Vec<AuditAction> {
+        history.entries.iter().map(|e| e.action).collect()
+    }
+
+    #[test]
+    fn store_history_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let (aisle_id, product_id) = db::products::tests::save_product_for_test(&mut c);
+        let store_id = db::aisles::get_aisle_store(&mut c, &aisle_id).unwrap();
+        assert_eq!(
+            Ok(()),
+            db::stores::edit_store(&mut c, &AUTH, &store_id, "renamed", &IfMatch::default())
+        );
+        assert_eq!(
+            Ok(()),
+            db::products::delete_product(&mut c, &AUTH, &product_id, &IfMatch::default())
+        );
+
+        let history = store_history(&mut c, &AUTH, &store_id, None, 2).unwrap();
+        assert_eq!(
+            vec![AuditAction::ProductDeleted, AuditAction::StoreRenamed],
+            actions(&history)
+        );
+        let deleted = &history.entries[0];
+        assert_eq!(product_id.to_string(), deleted.item_id);
+        assert_eq!(Value::Null, deleted.after);
+        assert_eq!(Some("product1"), deleted.before["name"].as_str());
+        assert_eq!(Some("MyStore"), history.entries[1].before["name"].as_str());
+        assert_eq!(Some("renamed"), history.entries[1].after["name"].as_str());
+        let next = history.next.unwrap();
+        let history = store_history(&mut c, &AUTH, &store_id, Some(next), 10).unwrap();
+        assert_eq!(
+            vec![
+                AuditAction::ProductCreated,
+                AuditAction::AisleCreated,
+                AuditAction::StoreCreated
+            ],
+            actions(&history)
+        );
+        assert_eq!(None, history.next);
+        assert_eq!(1, history.entries[2].id);
+    }
+}
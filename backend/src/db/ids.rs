@@ -23,7 +23,7 @@ pub fn hash(data: &str, salt: &str) -> String {
     )
 }
 
-fn generate_salt() -> String {
+pub fn generate_salt() -> String {
     if cfg!(test) {
         "00000000".to_string()
     } else {
@@ -32,6 +32,18 @@ fn generate_salt() -> String {
     }
 }
 
+pub fn gen_auth(rng: &mut rand::rngs::ThreadRng) -> String {
+    let mut auth = [0u8; 32];
+    rng.fill(&mut auth[..]);
+    format!("{:x}", HexView::from(&auth))
+}
+
+pub fn gen_invitation_code(rng: &mut rand::rngs::ThreadRng) -> String {
+    let mut code = [0u8; 12];
+    rng.fill(&mut code[..]);
+    format!("{:x}", HexView::from(&code[..]))
+}
+
 fn get_next_id<RV: std::str::FromStr>(
     c: &mut Connection,
     next_key: &str,
@@ -85,6 +97,13 @@ pub fn get_next_product_id() -> ProductId {
     )
 }
 
+pub fn get_next_session_id() -> String {
+    (*Uuid::new_v4()
+        .to_hyphenated_ref()
+        .encode_lower(&mut Uuid::encode_buffer()))
+    .to_string()
+}
+
 #[cfg(test)]
 pub mod tests {
     pub const HASH_1: &str = "26a9dc4bed936c6ad9944f209790626d18f0b797233fd18465ecef1d1fd16686";
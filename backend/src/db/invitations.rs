@@ -0,0 +1,339 @@
+#[cfg(not(test))]
+use redis::{transaction, Commands, Connection, Pipeline};
+
+#[cfg(test)]
+use fake_redis::{transaction, FakeConnection as Connection, FakePipeline as Pipeline};
+
+use crate::{db, error::*, types::*};
+
+const INVITATION_STORE: &str = "store_id";
+const INVITATION_ROLE: &str = "role";
+const INVITATION_EXPIRES: &str = "expires_at";
+const INVITATION_MAX_USES: &str = "max_uses";
+const INVITATION_USES: &str = "uses";
+
+fn invitation_key(code: &str) -> String {
+    format!("invitation:{}", code)
+}
+
+fn store_invitations_key(store_id: &StoreId) -> String {
+    format!("invitations:{}", **store_id)
+}
+
+fn invalid_invitation() -> ServerError {
+    ServerError::new(NOT_FOUND, "Invalid or expired invitation")
+}
+
+// `None` if the invitation doesn't exist or has expired
+fn get_invitation(c: &mut Connection, code: &str) -> Result<Option<Invitation>> {
+    let invitation_key = invitation_key(code);
+    let store_id: Option<String> = c.hget(&invitation_key, INVITATION_STORE)?;
+    let expires_at: Option<i64> = c.hget(&invitation_key, INVITATION_EXPIRES)?;
+    match (store_id, expires_at) {
+        (Some(store_id), Some(expires_at)) if expires_at >= db::timestamp() => {
+            let role: String = c.hget(&invitation_key, INVITATION_ROLE)?;
+            let uses: Option<u32> = c.hget(&invitation_key, INVITATION_USES)?;
+            Ok(Some(Invitation::new(
+                code.to_owned(),
+                store_id,
+                role.parse()?,
+                expires_at,
+                c.hget(&invitation_key, INVITATION_MAX_USES)?,
+                uses.unwrap_or(0),
+            )))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn create_invitation(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    role: Role,
+    ttl: i64,
+    max_uses: u32,
+) -> Result<Invitation> {
+    db::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    if role == Role::Owner {
+        return Err(ServerError::new(
+            PERMISSION_DENIED,
+            "A store can only have one owner",
+        ));
+    }
+    let code = db::ids::gen_invitation_code(&mut rand::thread_rng());
+    let expires_at = db::timestamp() + ttl;
+    let invitation_key = invitation_key(&code);
+    let store_invitations_key = store_invitations_key(&store_id);
+    transaction(c, &[&invitation_key, &store_invitations_key], |c, pipe| {
+        pipe.hset(&invitation_key, INVITATION_STORE, &**store_id)
+            .ignore()
+            .hset(&invitation_key, INVITATION_ROLE, role.to_string())
+            .ignore()
+            .hset(&invitation_key, INVITATION_EXPIRES, expires_at)
+            .ignore()
+            .hset(&invitation_key, INVITATION_MAX_USES, max_uses)
+            .ignore()
+            .hset(&invitation_key, INVITATION_USES, 0)
+            .ignore()
+            .sadd(&store_invitations_key, &code)
+            .query(c)
+    })?;
+    // the expiry date is checked on use, this only cleans up unused invitations
+    c.expire(&invitation_key, ttl as usize)?;
+    Ok(Invitation::new(
+        code,
+        store_id.to_string(),
+        role,
+        expires_at,
+        max_uses,
+        0,
+    ))
+}
+
+pub fn list_invitations(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+) -> Result<Vec<Invitation>> {
+    db::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    let store_invitations_key = store_invitations_key(&store_id);
+    let codes: Vec<String> = c.smembers(&store_invitations_key)?;
+    let mut invitations = vec![];
+    for code in codes {
+        match get_invitation(c, &code)? {
+            Some(invitation) => invitations.push(invitation),
+            None => delete_invitation(c, &store_id, &code)?,
+        }
+    }
+    invitations.sort_by_key(|i| i.expires_at);
+    Ok(invitations)
+}
+
+fn delete_invitation(c: &mut Connection, store_id: &StoreId, code: &str) -> Result<()> {
+    let invitation_key = invitation_key(code);
+    let store_invitations_key = store_invitations_key(&store_id);
+    transaction(c, &[&invitation_key, &store_invitations_key], |c, pipe| {
+        pipe.srem(&store_invitations_key, code)
+            .ignore()
+            .del(&invitation_key)
+            .query(c)
+    })?;
+    Ok(())
+}
+
+pub fn revoke_invitation(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    code: &str,
+) -> Result<()> {
+    db::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    let invitation_store: Option<String> = c.hget(&invitation_key(code), INVITATION_STORE)?;
+    if invitation_store.as_deref() != Some(&**store_id) {
+        return Err(ServerError::new(NOT_FOUND, "Invitation not found"));
+    }
+    delete_invitation(c, &store_id, code)
+}
+
+// join the store, unless already a member in which case the invitation isn't used
+pub fn redeem_invitation(c: &mut Connection, auth: &Auth, code: &str) -> Result<StoreLight> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    let invitation = get_invitation(c, code)?.ok_or_else(invalid_invitation)?;
+    let store_id = StoreId::new(invitation.store_id.clone());
+    if db::stores::get_role(c, &store_id, &user_id)?.is_none() {
+        // whoever gets a use below the limit gets to join
+        let uses: u32 = c.hincr(&invitation_key(code), INVITATION_USES, 1)?;
+        if uses > invitation.max_uses {
+            return Err(invalid_invitation());
+        }
+        if uses == invitation.max_uses {
+            delete_invitation(c, &store_id, code)?;
+        }
+        db::stores::add_member(c, &store_id, &user_id, invitation.role)?;
+    }
+    db::stores::get_store_light(c, &store_id, &user_id)
+}
+
+// purge all invitations to the store
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_purge_invitations(
+    c: &mut Connection,
+    pipe: &mut Pipeline,
+    store_id: &StoreId,
+) -> Result<()> {
+    let store_invitations_key = store_invitations_key(&store_id);
+    let codes: Option<Vec<String>> = c.smembers(&store_invitations_key)?;
+    if let Some(codes) = codes {
+        codes.iter().for_each(|code| {
+            pipe.del(&invitation_key(code)).ignore();
+        });
+        pipe.del(&store_invitations_key).ignore();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sessions::tests::*, stores::tests::*, tests::*, users::tests::*};
+    use fake_redis::FakeCient as Client;
+
+    fn save_member_for_test(c: &mut Connection) -> ConnectionToken {
+        let mut user = gen_user();
+        user.username = "tata".to_owned();
+        db::users::save_user(c, &user).unwrap()
+    }
+
+    #[test]
+    fn redeem_invitation_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let store_id = save_store_for_test(&mut c);
+        let token = save_member_for_test(&mut c);
+        let auth = Auth(&token.session_token);
+        let member_id = UserId(token.user_id.clone());
+
+        assert_eq!(
+            Err(PERMISSION_DENIED),
+            create_invitation(&mut c, &auth, &store_id, Role::Editor, 60, 1).map_err(|e| e.status)
+        );
+        let invitation = create_invitation(&mut c, &AUTH, &store_id, Role::Editor, 60, 1).unwrap();
+        assert_eq!(
+            Ok(true),
+            c.sismember(&store_invitations_key(&store_id), invitation.code())
+        );
+        assert_eq!(
+            Ok(StoreLight::new(
+                STORE_TEST_NAME.to_owned(),
+                store_id.to_string(),
+                Role::Editor
+            )),
+            redeem_invitation(&mut c, &auth, invitation.code())
+        );
+        assert_eq!(
+            Ok(Some(Role::Editor)),
+            db::stores::get_role(&mut c, &store_id, &member_id)
+        );
+        // used up
+        assert_eq!(Ok(false), c.exists(&invitation_key(invitation.code())));
+        assert_eq!(Ok(vec![]), list_invitations(&mut c, &AUTH, &store_id));
+        assert_eq!(
+            Err(NOT_FOUND),
+            redeem_invitation(&mut c, &AUTH, invitation.code()).map_err(|e| e.status)
+        );
+    }
+
+    #[test]
+    fn invitation_limits_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let store_id = save_store_for_test(&mut c);
+        let token = save_member_for_test(&mut c);
+        let auth = Auth(&token.session_token);
+
+        let invitation = create_invitation(&mut c, &AUTH, &store_id, Role::Viewer, 60, 2).unwrap();
+        // members don't use the invitation
+        assert!(redeem_invitation(&mut c, &AUTH, invitation.code()).is_ok());
+        assert_eq!(
+            Ok(vec![Invitation::new(
+                invitation.code().to_owned(),
+                store_id.to_string(),
+                Role::Viewer,
+                invitation.expires_at,
+                2,
+                0
+            )]),
+            list_invitations(&mut c, &AUTH, &store_id)
+        );
+
+        let _: i32 = c
+            .hset(
+                &invitation_key(invitation.code()),
+                INVITATION_EXPIRES,
+                db::timestamp() - 1,
+            )
+            .unwrap();
+        assert_eq!(
+            Err(NOT_FOUND),
+            redeem_invitation(&mut c, &auth, invitation.code()).map_err(|e| e.status)
+        );
+        // expired invitations are cleaned up when listed
+        assert_eq!(Ok(vec![]), list_invitations(&mut c, &AUTH, &store_id));
+        assert_eq!(Ok(false), c.exists(&store_invitations_key(&store_id)));
+
+        let invitation = create_invitation(&mut c, &AUTH, &store_id, Role::Viewer, 60, 2).unwrap();
+        let other_store_id = db::stores::save_store(&mut c, &AUTH, "other").unwrap();
+        assert_eq!(
+            Err(NOT_FOUND),
+            revoke_invitation(&mut c, &AUTH, &other_store_id, invitation.code())
+                .map_err(|e| e.status)
+        );
+        assert_eq!(
+            Ok(()),
+            revoke_invitation(&mut c, &AUTH, &store_id, invitation.code())
+        );
+        assert_eq!(
+            Err(NOT_FOUND),
+            redeem_invitation(&mut c, &auth, invitation.code()).map_err(|e| e.status)
+        );
+
+        create_invitation(&mut c, &AUTH, &store_id, Role::Viewer, 60, 2).unwrap();
+        assert_eq!(
+            Ok(()),
+            db::stores::delete_store(&mut c, &AUTH, &store_id, &IfMatch::default())
+        );
+        assert_eq!(Ok(false), c.exists(&store_invitations_key(&store_id)));
+    }
+
+    #[test]
+    fn invitation_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let member = save_other_user(s, "tata");
+            let member_auth = Auth(&member.session_token);
+            let invitation = s
+                .create_invitation(&auth, &f.store_id, Role::Viewer, 60, 1)
+                .unwrap();
+            assert_eq!(
+                Ok(1),
+                s.list_invitations(&auth, &f.store_id).map(|i| i.len())
+            );
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.list_invitations(&member_auth, &f.store_id)
+                    .map(|_| ())
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Ok(StoreLight::new(
+                    "store".to_owned(),
+                    f.store_id.to_string(),
+                    Role::Viewer
+                )),
+                s.redeem_invitation(&member_auth, invitation.code())
+            );
+            assert!(s.list_store(&member_auth, &f.store_id).is_ok());
+
+            let invitation = s
+                .create_invitation(&auth, &f.store_id, Role::Editor, 60, 1)
+                .unwrap();
+            assert_eq!(
+                Ok(()),
+                s.revoke_invitation(&auth, &f.store_id, invitation.code())
+            );
+            assert_eq!(Ok(vec![]), s.list_invitations(&auth, &f.store_id));
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.redeem_invitation(&member_auth, invitation.code())
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.edit_store(&member_auth, &f.store_id, "member", &IfMatch::default())
+                    .map_err(|e| e.status)
+            );
+        });
+    }
+}
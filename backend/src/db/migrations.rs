@@ -0,0 +1,251 @@
+// Redis keyspace layout, as of the latest schema version:
+//
+// - `next_user_id`, `user_id_salt`: counter and salt used to generate user ids
+// - `users`: hash of normalised username -> user id
+// - `user:{user_id}`: hash with `username`, `email`, `password`, `salt_mail`, `salt_password`
+// - `sessions`: hash of auth token -> user id
+// - `sessions:{user_id}`: set of the user's auth tokens
+// - `session:{auth}`: hash with `id`, `created_at`, `last_used` (seconds since the Unix epoch),
+//   and optionally `user_agent`, `ip`
+// - `reset:{token}`: hash with `user_id`, `expires_at` of a password reset token
+// - `stores:{user_id}`: set of the ids of the stores the user is a member of
+// - `store:{store_id}`: hash with `name`, `owner_id`, `version`
+// - `store_members:{store_id}`: hash of user id -> role (`owner`, `editor` or `viewer`)
+// - `store_revision:{store_id}`: counter of the store's revisions
+// - `store_changes:{store_id}`: list of the latest changes to the store, as `{revision} {item}`,
+//   `item` being `store`, `aisle:{aisle_id}` or `product:{product_id}`
+// - `store_history:{store_id}`: list of the JSON audit entries of the store's mutations,
+//   oldest first
+// - `invitations:{store_id}`: set of the store's invitation codes
+// - `invitation:{code}`: hash with `store_id`, `role`, `expires_at`, `max_uses`, `uses`
+// - `events:{user_id}`: list of the user's latest change events, as `{id} {json}`
+// - `event_seq:{user_id}`: counter of the ids of the user's change events
+// - `op_result:{user_id}:{op_id}`: JSON result of an applied batch operation, expiring
+// - `undo:{user_id}`: list of the JSON inverses of the user's latest mutations, newest last
+// - `aisles_in_store:{store_id}`: set of aisle ids
+// - `aisle:{aisle_id}`: hash with `name`, `sort_weight`, `owner_id`, `store_id`, `version`
+// - `products_in_aisle:{aisle_id}`: set of product ids
+// - `product:{product_id}`: hash with `name`, `quantity`, `unit`, `is_done`, `sort_weight`,
+//   `product_owner`, `aisle`, `version`
+// - `trash:{user_id}`: set of the keys of the stores, aisles and products the user deleted,
+//   their hashes get `trashed_by` and `trashed_at` and are detached from their parent
+// - `trashed_items`: set of the keys of every trashed store, aisle and product
+// - `schema_version`: number of migrations applied to the keyspace
+//
+// To evolve the layout, append a migration to `MIGRATIONS` and update the list above.
+// Migrations are run in order at startup and never modified once released.
+
+use log::*;
+
+#[cfg(not(test))]
+use redis::{Commands, Connection};
+
+#[cfg(test)]
+use fake_redis::FakeConnection as Connection;
+
+use crate::{
+    db,
+    error::{self, Result, ServerError},
+    types,
+};
+
+pub(crate) const SCHEMA_VERSION: &str = "schema_version";
+
+type Migration = fn(&mut Connection) -> Result<()>;
+
+const MIGRATIONS: &[Migration] = &[
+    initial_layout,
+    session_timestamps,
+    session_ids,
+    store_members,
+    entity_versions,
+];
+
+// Version 1: the layout the server used before versioning, nothing to convert
+fn initial_layout(_: &mut Connection) -> Result<()> {
+    Ok(())
+}
+
+// Version 2: sessions expire, existing ones are considered created now
+fn session_timestamps(c: &mut Connection) -> Result<()> {
+    let now = db::timestamp();
+    let auths: Vec<String> = c.hkeys("sessions")?;
+    for auth in auths {
+        let session_key = format!("session:{}", auth);
+        c.hset_multiple(&session_key, &[("created_at", now), ("last_used", now)])?;
+    }
+    Ok(())
+}
+
+// Version 3: sessions get a public id, so they can be listed and revoked
+fn session_ids(c: &mut Connection) -> Result<()> {
+    let auths: Vec<String> = c.hkeys("sessions")?;
+    for auth in auths {
+        let session_key = format!("session:{}", auth);
+        if !c.hexists(&session_key, "id")? {
+            c.hset(&session_key, "id", db::ids::get_next_session_id())?;
+        }
+    }
+    Ok(())
+}
+
+// Version 4: stores can be shared, their owner becomes their first member
+fn store_members(c: &mut Connection) -> Result<()> {
+    let usernames: Vec<String> = c.hkeys("users")?;
+    for username in usernames {
+        let user_id: String = c.hget("users", &username)?;
+        let stores: Vec<String> = c.smembers(&format!("stores:{}", user_id))?;
+        for store_id in stores {
+            c.hset(&format!("store_members:{}", store_id), &user_id, "owner")?;
+        }
+    }
+    Ok(())
+}
+
+// Version 5: stores, aisles and products get a version, bumped by each edit
+fn entity_versions(c: &mut Connection) -> Result<()> {
+    let mut keys = vec![];
+    let usernames: Vec<String> = c.hkeys("users")?;
+    for username in usernames {
+        let user_id: String = c.hget("users", &username)?;
+        let stores: Vec<String> = c.smembers(&format!("stores:{}", user_id))?;
+        for store_id in stores {
+            let aisles: Vec<String> = c.smembers(&format!("aisles_in_store:{}", store_id))?;
+            for aisle_id in aisles {
+                let products: Vec<String> =
+                    c.smembers(&format!("products_in_aisle:{}", aisle_id))?;
+                keys.extend(products.iter().map(|id| format!("product:{}", id)));
+                keys.push(format!("aisle:{}", aisle_id));
+            }
+            keys.push(format!("store:{}", store_id));
+        }
+    }
+    for key in keys {
+        // shared stores are listed by each of their members
+        if !c.hexists(&key, "version")? {
+            c.hset(&key, "version", types::FIRST_VERSION)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn get_schema_version(c: &mut Connection) -> Result<u32> {
+    let version: Option<u32> = c.get(SCHEMA_VERSION)?;
+    Ok(version.unwrap_or(0))
+}
+
+fn run_migrations(c: &mut Connection, migrations: &[Migration]) -> Result<()> {
+    let version = get_schema_version(c)? as usize;
+    if version > migrations.len() {
+        return Err(ServerError::new(
+            error::INTERNAL_ERROR,
+            &format!(
+                "Database schema version {} is newer than this server ({})",
+                version,
+                migrations.len()
+            ),
+        ));
+    }
+    for (i, migration) in migrations.iter().enumerate().skip(version) {
+        info!("Migrating database schema to version {}", i + 1);
+        migration(c)?;
+        c.set(SCHEMA_VERSION, (i + 1) as u32)?;
+    }
+    Ok(())
+}
+
+pub fn migrate(c: &mut Connection) -> Result<()> {
+    run_migrations(c, MIGRATIONS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::*;
+    use fake_redis::FakeCient as Client;
+
+    const MIGRATION_RUNS: &str = "migration_runs";
+
+    fn count_run(c: &mut Connection) -> Result<()> {
+        c.incr(MIGRATION_RUNS, 1)?;
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        assert_eq!(Ok(0), get_schema_version(&mut c));
+        assert_eq!(Ok(()), migrate(&mut c));
+        assert_eq!(Ok(MIGRATIONS.len() as u32), get_schema_version(&mut c));
+        assert_eq!(Ok(()), migrate(&mut c));
+        assert_eq!(Ok(MIGRATIONS.len() as u32), get_schema_version(&mut c));
+    }
+
+    #[test]
+    fn session_migrations_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let _: i32 = c.hset("sessions", "tokenauth", "userid").unwrap();
+        assert_eq!(Ok(()), session_timestamps(&mut c));
+        assert_eq!(Ok(true), c.hexists("session:tokenauth", "created_at"));
+        assert_eq!(Ok(true), c.hexists("session:tokenauth", "last_used"));
+
+        assert_eq!(Ok(()), session_ids(&mut c));
+        let id: String = c.hget("session:tokenauth", "id").unwrap();
+        // already set ids are kept
+        assert_eq!(Ok(()), session_ids(&mut c));
+        assert_eq!(Ok(id), c.hget("session:tokenauth", "id"));
+    }
+
+    #[test]
+    fn store_members_migration_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let _: i32 = c.hset("users", "toto", "userid").unwrap();
+        let _: i32 = c.sadd("stores:userid", "storeid").unwrap();
+        assert_eq!(Ok(()), store_members(&mut c));
+        assert_eq!(
+            Ok("owner".to_owned()),
+            c.hget("store_members:storeid", "userid")
+        );
+    }
+
+    #[test]
+    fn entity_versions_migration_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let _: i32 = c.hset("users", "toto", "userid").unwrap();
+        let _: i32 = c.sadd("stores:userid", "storeid").unwrap();
+        let _: i32 = c.sadd("aisles_in_store:storeid", "aisleid").unwrap();
+        let _: i32 = c.sadd("products_in_aisle:aisleid", "productid").unwrap();
+        let _: i32 = c.hset("store:storeid", "version", 3).unwrap();
+        assert_eq!(Ok(()), entity_versions(&mut c));
+        assert_eq!(Ok(3), c.hget("store:storeid", "version"));
+        assert_eq!(Ok(1), c.hget("aisle:aisleid", "version"));
+        assert_eq!(Ok(1), c.hget("product:productid", "version"));
+    }
+
+    #[test]
+    fn run_migrations_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        assert_eq!(Ok(()), run_migrations(&mut c, &[count_run, count_run]));
+        assert_eq!(Ok(2), get_schema_version(&mut c));
+        assert_eq!(Ok(2), c.get(MIGRATION_RUNS));
+
+        // only the new one is applied
+        assert_eq!(
+            Ok(()),
+            run_migrations(&mut c, &[count_run, count_run, count_run])
+        );
+        assert_eq!(Ok(3), get_schema_version(&mut c));
+        assert_eq!(Ok(3), c.get(MIGRATION_RUNS));
+
+        // a server older than the database refuses to run
+        assert_eq!(
+            Err(error::INTERNAL_ERROR),
+            run_migrations(&mut c, &[count_run]).map_err(|e| e.status)
+        );
+    }
+}
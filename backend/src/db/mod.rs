@@ -1,41 +1,201 @@
 #[cfg(not(test))]
-use redis::{self, Connection};
+use redis::{self, Connection, Pipeline};
 
 #[cfg(test)]
-use fake_redis::FakeConnection as Connection;
+use fake_redis::{FakeConnection as Connection, FakePipeline as Pipeline};
 
 pub mod aisles;
+pub mod backup;
+pub mod changes;
+pub mod events;
+pub mod history;
 pub mod ids;
+pub mod invitations;
+pub mod migrations;
+pub mod op_results;
 pub mod products;
+pub mod redis_storage;
 pub mod sessions;
+pub mod sqlite;
+mod storage;
 pub mod stores;
+pub mod trash;
+pub mod undo;
 pub mod users;
 
+pub use storage::Storage;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use crate::{error::*, types::*};
 
-pub(crate) fn verify_permission(wanted_user_id: &UserId, user_id: &UserId) -> Result<()> {
-    if wanted_user_id != user_id {
-        Err(ServerError::new(
+// number of events kept in each user's change feed
+pub const EVENT_LOG_LEN: usize = 256;
+
+// number of changes kept in each store's revision log
+pub const CHANGE_LOG_LEN: usize = 512;
+
+// number of mutations each user can undo
+pub const UNDO_LEN: usize = 50;
+
+// seconds the result of a batch operation is remembered
+pub const OP_RESULT_TTL: i64 = 24 * 3600;
+
+// seconds a batch operation is held while applied, in case its request never ends it
+pub const OP_PENDING_TTL: i64 = 60;
+
+// seconds since the Unix epoch
+pub fn timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+// `role` is the one the user has in the store, if they are a member
+pub(crate) fn verify_permission(wanted: Role, role: Option<Role>) -> Result<()> {
+    match role {
+        Some(role) if role >= wanted => Ok(()),
+        _ => Err(ServerError::new(
             PERMISSION_DENIED,
             "User does not have permission to edit this resource",
-        ))
-    } else {
-        Ok(())
+        )),
     }
 }
 
 pub(crate) fn verify_permission_auth(
     c: &mut Connection,
     auth: &Auth,
-    user_id: &UserId,
-) -> Result<()> {
-    let wanted_user_id = sessions::get_user_id(c, &auth)?;
-    verify_permission(&wanted_user_id, &user_id)
+    store_id: &StoreId,
+    wanted: Role,
+) -> Result<UserId> {
+    let user_id = sessions::get_user_id(c, &auth)?;
+    verify_permission(wanted, stores::get_role(c, &store_id, &user_id)?)?;
+    Ok(user_id)
+}
+
+// `retained` is the user's event log, in order
+pub(crate) fn event_feed(
+    user_id: UserId,
+    retained: Vec<LoggedEvent>,
+    resume_from: Option<u64>,
+) -> EventFeed {
+    let last_id = retained.last().map_or(0, |e| e.id);
+    match resume_from {
+        Some(from) => {
+            let first_id = retained.first().map_or(last_id + 1, |e| e.id);
+            if from > last_id || from + 1 < first_id {
+                EventFeed::new(user_id, last_id, vec![], true)
+            } else {
+                let events = retained.into_iter().filter(|e| e.id > from).collect();
+                EventFeed::new(user_id, last_id, events, false)
+            }
+        }
+        None => EventFeed::new(user_id, last_id, vec![], false),
+    }
+}
+
+// the items changed after revision `since`, each once,
+// `None` if the log of the store doesn't go back that far
+pub(crate) fn changed_since(
+    revision: u64,
+    mut retained: Vec<(u64, ChangedItem)>,
+    since: u64,
+) -> Option<Vec<ChangedItem>> {
+    retained.sort_by_key(|(revision, _)| *revision);
+    let first = retained
+        .first()
+        .map_or(revision + 1, |(revision, _)| *revision);
+    if since > revision || since + 1 < first {
+        return None;
+    }
+    let mut items: Vec<ChangedItem> = vec![];
+    for (_, item) in retained
+        .into_iter()
+        .filter(|(revision, _)| *revision > since)
+    {
+        if !items.contains(&item) {
+            items.push(item);
+        }
+    }
+    Some(items)
+}
+
+#[cfg(not(test))]
+fn atomic_pipe(_: &Connection) -> Pipeline {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    pipe
 }
 
 #[cfg(test)]
-mod tests {
+fn atomic_pipe(c: &Connection) -> Pipeline {
+    let mut pipe = Pipeline::new(c.db);
+    pipe.atomic();
+    pipe
+}
+
+pub fn change_sort_weight(c: &mut Connection, auth: &Auth, data: &EditWeight) -> Result<()> {
+    let mut pipe = atomic_pipe(c);
+    let mut changes = vec![];
+    if let Some(ref aisles) = data.aisles {
+        for w in aisles {
+            let (store_id, previous) = aisles::edit_aisle_sort_weight(c, &mut pipe, &auth, &w)?;
+            let item = ChangedItem::Aisle(w.id.clone());
+            let weights = (previous, w.sort_weight);
+            changes.push((store_id, item, AuditAction::AisleReordered, &w.id, weights));
+        }
+    }
+    if let Some(ref products) = data.products {
+        for w in products {
+            let (store_id, previous) = products::edit_product_sort_weight(c, &mut pipe, &auth, &w)?;
+            let item = ChangedItem::Product(w.id.clone());
+            let weights = (previous, w.sort_weight);
+            changes.push((
+                store_id,
+                item,
+                AuditAction::ProductReordered,
+                &w.id,
+                weights,
+            ));
+        }
+    }
+    pipe.query(c)?;
+    let user_id = sessions::get_user_id(c, &auth)?;
+    for (store_id, item, action, item_id, (before, after)) in changes {
+        changes::record_change(c, &store_id, item)?;
+        history::record(
+            c,
+            &store_id,
+            &user_id,
+            action,
+            item_id,
+            serde_json::json!({ "sort_weight": before }),
+            serde_json::json!({ "sort_weight": after }),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn flush(c: &mut Connection) -> Result<()> {
+    #[cfg(not(test))]
+    redis::cmd("FLUSHDB").query::<()>(c)?;
+    #[cfg(test)]
+    c.flushdb::<()>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
     use std::sync::atomic::{AtomicI64, Ordering};
+
+    use super::*;
+    use crate::db::{
+        redis_storage::RedisStorage,
+        sessions::tests::TTL,
+        sqlite::{tests::open_for_test, SqliteStorage},
+    };
+    use fake_redis::FakeCient as Client;
+
     static DB_NUM: AtomicI64 = AtomicI64::new(0);
     pub fn get_db_addr() -> String {
         format!(
@@ -43,4 +203,86 @@ mod tests {
             DB_NUM.fetch_add(1, Ordering::SeqCst)
         )
     }
+
+    // Run a `Storage` test against a fresh database of each backend
+    pub fn for_each_storage(test: impl Fn(&mut dyn Storage)) {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let mut s = RedisStorage::new(&mut c, TTL);
+        assert_eq!(Ok(()), s.migrate());
+        test(&mut s);
+        let mut c = open_for_test();
+        test(&mut SqliteStorage::new(&mut c, TTL));
+    }
+
+    // A user owning a store with one aisle holding one product
+    pub struct Fixture {
+        pub token: ConnectionToken,
+        pub user_id: UserId,
+        pub store_id: StoreId,
+        pub aisle_id: AisleId,
+        pub product_id: ProductId,
+    }
+
+    impl Fixture {
+        pub fn new(s: &mut dyn Storage) -> Self {
+            let token = s.save_user(&users::tests::gen_user()).unwrap();
+            let auth = Auth(&token.session_token);
+            let store_id = s.save_store(&auth, "store").unwrap();
+            let aisle_id = s.save_aisle(&auth, &store_id, "aisle").unwrap().id();
+            let product_id = s.save_product(&auth, "product", &aisle_id).unwrap().id();
+            Fixture {
+                user_id: UserId(token.user_id.clone()),
+                token,
+                store_id,
+                aisle_id,
+                product_id,
+            }
+        }
+
+        pub fn auth(&self) -> Auth {
+            Auth(&self.token.session_token)
+        }
+    }
+
+    // Another user, named `username`
+    pub fn save_other_user(s: &mut dyn Storage, username: &str) -> ConnectionToken {
+        let mut user = users::tests::gen_user();
+        user.username = username.to_owned();
+        s.save_user(&user).unwrap()
+    }
+
+    pub fn login_with(s: &mut dyn Storage, password: &str) -> Result<ConnectionToken> {
+        s.login(
+            &AuthInfo {
+                username: "toto".to_owned(),
+                password: password.to_owned(),
+            },
+            &ClientInfo::default(),
+        )
+    }
+
+    #[test]
+    fn change_sort_weight_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let data = EditWeight::new(
+                Some(vec![AisleItemWeight::new(f.aisle_id.to_string(), 3.0)]),
+                Some(vec![ProductItemWeight::new(f.product_id.to_string(), 4.0)]),
+            );
+            assert_eq!(Ok(()), s.change_sort_weight(&auth, &data));
+            let history = s.store_history(&auth, &f.store_id, None, 2).unwrap();
+            assert_eq!(
+                vec![AuditAction::ProductReordered, AuditAction::AisleReordered],
+                history.entries.iter().map(|e| e.action).collect::<Vec<_>>()
+            );
+            // reordering doesn't count as an edit
+            assert_eq!(Ok(FIRST_VERSION), s.aisle_version(&f.aisle_id));
+            assert_eq!(Ok(FIRST_VERSION), s.product_version(&f.product_id));
+            let store = serde_json::to_value(s.list_store(&auth, &f.store_id).unwrap()).unwrap();
+            assert_eq!(3.0, store["aisles"][0]["sort_weight"]);
+            assert_eq!(4.0, store["aisles"][0]["products"][0]["sort_weight"]);
+        });
+    }
 }
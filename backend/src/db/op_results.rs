@@ -0,0 +1,117 @@
+#[cfg(not(test))]
+use redis::{self, Commands, Connection};
+
+#[cfg(test)]
+use fake_redis::FakeConnection as Connection;
+
+use crate::{db, error::*, types::*};
+
+// the value of an operation being applied, a result is never empty
+const PENDING: &str = "";
+
+fn op_result_key(user_id: &UserId, op_id: &str) -> String {
+    format!("op_result:{}:{}", **user_id, op_id)
+}
+
+#[cfg(not(test))]
+fn set_new(c: &mut Connection, key: &str, value: &str, ttl: i64) -> Result<bool> {
+    let set: Option<String> = redis::cmd("SET")
+        .arg(key)
+        .arg(value)
+        .arg("NX")
+        .arg("EX")
+        .arg(ttl)
+        .query(c)?;
+    Ok(set.is_some())
+}
+
+#[cfg(test)]
+fn set_new(c: &mut Connection, key: &str, value: &str, ttl: i64) -> Result<bool> {
+    let set = c.set_nx(key, value)?;
+    if set {
+        c.expire::<()>(key, ttl as usize)?;
+    }
+    Ok(set)
+}
+
+// `None` if the operation was never applied, is being applied or its result has expired
+pub fn get_op_result(c: &mut Connection, auth: &Auth, op_id: &str) -> Result<Option<String>> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    let result: Option<String> = c.get(&op_result_key(&user_id, op_id))?;
+    Ok(result.filter(|result| result != PENDING))
+}
+
+// `false` if another request holds `op_id` or already applied it
+pub fn begin_op(c: &mut Connection, auth: &Auth, op_id: &str) -> Result<bool> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    set_new(
+        c,
+        &op_result_key(&user_id, op_id),
+        PENDING,
+        db::OP_PENDING_TTL,
+    )
+}
+
+// the writes of the operation stay, without `result` it can be applied again
+pub fn end_op(c: &mut Connection, auth: &Auth, op_id: &str, result: Option<&str>) -> Result<()> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    let op_result_key = op_result_key(&user_id, op_id);
+    match result {
+        Some(result) => {
+            c.set(&op_result_key, result)?;
+            Ok(c.expire(&op_result_key, db::OP_RESULT_TTL as usize)?)
+        }
+        None => Ok(c.del(&op_result_key)?),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{tests::*, users::tests::*};
+    use fake_redis::FakeCient as Client;
+
+    #[test]
+    fn op_result_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let token = db::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+
+        assert_eq!(Ok(None), get_op_result(&mut c, &auth, "op1"));
+        assert_eq!(Ok(true), begin_op(&mut c, &auth, "op1"));
+        // held until its end
+        assert_eq!(Ok(false), begin_op(&mut c, &auth, "op1"));
+        assert_eq!(Ok(None), get_op_result(&mut c, &auth, "op1"));
+        assert_eq!(Ok(()), end_op(&mut c, &auth, "op1", Some("{}")));
+        assert_eq!(
+            Ok(Some("{}".to_owned())),
+            get_op_result(&mut c, &auth, "op1")
+        );
+        assert_eq!(Ok(false), begin_op(&mut c, &auth, "op1"));
+        assert_eq!(Ok(None), get_op_result(&mut c, &auth, "op2"));
+        // a failed operation can be applied again
+        assert_eq!(Ok(true), begin_op(&mut c, &auth, "op2"));
+        assert_eq!(Ok(()), end_op(&mut c, &auth, "op2", None));
+        assert_eq!(Ok(true), begin_op(&mut c, &auth, "op2"));
+    }
+
+    #[test]
+    fn op_result_storage_test() {
+        for_each_storage(|s| {
+            let token = s.save_user(&gen_user()).unwrap();
+            let auth = Auth(&token.session_token);
+            assert_eq!(Ok(None), s.op_result(&auth, "op"));
+            assert_eq!(Ok(true), s.begin_op(&auth, "op"));
+            assert_eq!(Ok(()), s.end_op(&auth, "op", Some("{}")));
+            assert_eq!(Ok(Some("{}".to_owned())), s.op_result(&auth, "op"));
+            assert_eq!(Ok(false), s.begin_op(&auth, "op"));
+            // a failed operation can be applied again
+            assert_eq!(Ok(true), s.begin_op(&auth, "failed"));
+            assert_eq!(Ok(()), s.end_op(&auth, "failed", None));
+            assert_eq!(Ok(None), s.op_result(&auth, "failed"));
+            assert_eq!(Ok(true), s.begin_op(&auth, "failed"));
+            assert_eq!(Ok(()), s.end_op(&auth, "failed", Some("{}")));
+        });
+    }
+}
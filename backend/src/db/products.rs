@@ -6,7 +6,9 @@ use redis::{self, transaction, Commands, Connection, Pipeline};
 #[cfg(test)]
 use fake_redis::{transaction, FakeConnection as Connection, FakePipeline as Pipeline};
 
-use crate::{db, error::Result, types::*};
+use serde_json::{json, Value};
+
+use crate::{db, error::*, types::*};
 
 const PROD_NAME: &str = "name";
 const PROD_SORT_WEIGHT: &str = "sort_weight";
@@ -15,6 +17,7 @@ const PROD_OWNER: &str = "product_owner";
 const PROD_QTY: &str = "quantity";
 const PROD_UNIT: &str = "unit";
 const PROD_AISLE: &str = "aisle";
+const PROD_VERSION: &str = "version";
 
 pub fn product_key(id: &ProductId) -> String {
     format!("product:{}", **id)
@@ -24,29 +27,62 @@ pub fn products_in_aisle_key(id: &AisleId) -> String {
     format!("products_in_aisle:{}", **id)
 }
 
-fn get_product_owner(c: &mut Connection, id: &ProductId) -> Result<UserId> {
-    Ok(UserId(c.hget(&product_key(&id), PROD_OWNER)?))
+pub fn get_product_store(c: &mut Connection, id: &ProductId) -> Result<StoreId> {
+    let product_key = product_key(&id);
+    if db::trash::is_trashed(c, &product_key)? {
+        return Err(ServerError::new(NOT_FOUND, "Product not found"));
+    }
+    let aisle_id = AisleId(c.hget(&product_key, PROD_AISLE)?);
+    db::aisles::get_aisle_store(c, &aisle_id)
+}
+
+fn read_product(c: &mut Connection, p: String) -> Result<Product> {
+    let product_key = product_key(&ProductId(p.clone()));
+    let unit: u32 = c.hget(&product_key, PROD_UNIT)?;
+    let state: i32 = c.hget(&product_key, PROD_STATE)?;
+    let state = state != 0;
+    Ok(Product::new(
+        p,
+        c.hget(&product_key, PROD_NAME)?,
+        c.hget(&product_key, PROD_QTY)?,
+        state,
+        Unit::from(unit),
+        c.hget(&product_key, PROD_SORT_WEIGHT)?,
+    )
+    .with_version(c.hget(&product_key, PROD_VERSION)?))
+}
+
+pub fn get_name(c: &mut Connection, product_id: &ProductId) -> Result<String> {
+    Ok(c.hget(&product_key(&product_id), PROD_NAME)?)
+}
+
+pub fn get_version(c: &mut Connection, product_id: &ProductId) -> Result<u64> {
+    let version: Option<u64> = c.hget(&product_key(&product_id), PROD_VERSION)?;
+    version.ok_or_else(|| ServerError::new(NOT_FOUND, "Product not found"))
+}
+
+// `None` if the product doesn't exist or is in a trash
+pub fn get_product(
+    c: &mut Connection,
+    product_id: &ProductId,
+) -> Result<Option<(AisleId, Product)>> {
+    let product_key = product_key(&product_id);
+    if db::trash::is_trashed(c, &product_key)? {
+        return Ok(None);
+    }
+    let aisle_id: Option<String> = c.hget(&product_key, PROD_AISLE)?;
+    match aisle_id {
+        Some(aisle_id) => Ok(Some((
+            AisleId(aisle_id),
+            read_product(c, product_id.to_string())?,
+        ))),
+        None => Ok(None),
+    }
 }
 
 pub fn get_products_in_aisle(c: &mut Connection, aisle_id: &AisleId) -> Result<Vec<Product>> {
     let products: Vec<String> = c.smembers(&products_in_aisle_key(&aisle_id))?;
-    products
-        .into_iter()
-        .map(|p| {
-            let product_key = product_key(&ProductId(p.clone()));
-            let unit: u32 = c.hget(&product_key, PROD_UNIT)?;
-            let state: i32 = c.hget(&product_key, PROD_STATE)?;
-            let state = state != 0;
-            Ok(Product::new(
-                p,
-                c.hget(&product_key, PROD_NAME)?,
-                c.hget(&product_key, PROD_QTY)?,
-                state,
-                Unit::from(unit),
-                c.hget(&product_key, PROD_SORT_WEIGHT)?,
-            ))
-        })
-        .collect()
+    products.into_iter().map(|p| read_product(c, p)).collect()
 }
 
 fn find_max_weight_in_aisle(c: &mut Connection, aisle_id: &AisleId) -> Result<f32> {
@@ -60,9 +96,8 @@ pub fn save_product(
     name: &str,
     aisle_id: &AisleId,
 ) -> Result<Product> {
-    let aisle_owner = db::aisles::get_aisle_owner(c, &aisle_id)?;
-    let user_id = db::sessions::get_user_id(c, &auth)?;
-    db::verify_permission(&user_id, &aisle_owner)?;
+    let store_id = db::aisles::get_aisle_store(c, &aisle_id)?;
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
     let prod_id = db::ids::get_next_product_id();
     let prod_key = product_key(&prod_id);
     let prod_in_aisle_key = products_in_aisle_key(&aisle_id);
@@ -82,9 +117,21 @@ pub fn save_product(
             .ignore()
             .hset(&prod_key, PROD_AISLE, &**aisle_id)
             .ignore()
+            .hset(&prod_key, PROD_VERSION, FIRST_VERSION)
+            .ignore()
             .sadd(&prod_in_aisle_key, &*prod_id)
             .query(c)
     })?;
+    db::changes::record_change(c, &store_id, ChangedItem::Product(prod_id.to_string()))?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::ProductCreated,
+        &prod_id,
+        Value::Null,
+        json!({ "name": name, "aisle_id": aisle_id.to_string() }),
+    )?;
     Ok(Product::new(
         prod_id.to_string(),
         name.to_owned(),
@@ -100,40 +147,383 @@ pub fn modify_product(
     auth: &Auth,
     edit_data: &EditProduct,
     product_id: &ProductId,
+    if_match: &IfMatch,
 ) -> Result<()> {
-    let product_owner = get_product_owner(c, &product_id)?;
-    db::verify_permission_auth(c, &auth, &product_owner)?;
+    let store_id = get_product_store(c, &product_id)?;
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
     let product_key = product_key(&product_id);
-    if let Some(ref new_name) = edit_data.name {
-        c.hset(&product_key, PROD_NAME, new_name)?;
-    }
-    if let Some(qty) = edit_data.quantity {
-        c.hset(&product_key, PROD_QTY, qty)?;
-    }
-    if let Some(is_done) = edit_data.is_done {
-        c.hset(&product_key, PROD_STATE, is_done as i32)?;
-    }
-    if let Some(unit) = &edit_data.unit {
-        c.hset(&product_key, PROD_UNIT, u32::from(unit.clone()))?;
-    }
-    Ok(())
+    let (mut before, mut version) = (edit_data.clone(), 0);
+    transaction(c, &[&product_key], |c, pipe| {
+        let product = read_product(c, product_id.to_string())?;
+        before = edit_data.previous(&product);
+        version = product.version();
+        if if_match.matches(version) {
+            if let Some(ref new_name) = edit_data.name {
+                pipe.hset(&product_key, PROD_NAME, new_name).ignore();
+            }
+            if let Some(qty) = edit_data.quantity {
+                pipe.hset(&product_key, PROD_QTY, qty).ignore();
+            }
+            if let Some(is_done) = edit_data.is_done {
+                pipe.hset(&product_key, PROD_STATE, is_done as i32).ignore();
+            }
+            if let Some(unit) = &edit_data.unit {
+                pipe.hset(&product_key, PROD_UNIT, u32::from(unit.clone()))
+                    .ignore();
+            }
+            pipe.hincr(&product_key, PROD_VERSION, 1).ignore();
+        }
+        pipe.query(c)
+    })?;
+    if_match.verify(version)?;
+    db::changes::record_change(c, &store_id, ChangedItem::Product(product_id.to_string()))?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::ProductModified,
+        &product_id,
+        serde_json::to_value(&before)?,
+        serde_json::to_value(&edit_data)?,
+    )
 }
 
-pub fn delete_product(c: &mut Connection, auth: &Auth, product_id: &ProductId) -> Result<()> {
-    let product_owner = get_product_owner(c, &product_id)?;
-    db::verify_permission_auth(c, &auth, &product_owner)?;
+// into the user's trash
+pub fn delete_product(
+    c: &mut Connection,
+    auth: &Auth,
+    product_id: &ProductId,
+    if_match: &IfMatch,
+) -> Result<()> {
+    let store_id = get_product_store(c, &product_id)?;
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
     let product_key = product_key(&product_id);
     let aisle_id = AisleId(c.hget(&product_key, PROD_AISLE)?);
     let prod_in_aisle_key = products_in_aisle_key(&aisle_id);
+    let mut version = 0;
     transaction(c, &[&product_key, &prod_in_aisle_key], |c, pipe| {
-        pipe.srem(&prod_in_aisle_key, &**product_id)
-            .ignore()
-            .del(&product_key)
-            .query(c)
+        version = c.hget(&product_key, PROD_VERSION)?;
+        if if_match.matches(version) {
+            pipe.srem(&prod_in_aisle_key, &**product_id).ignore();
+            db::trash::transaction_trash(pipe, &product_key, &user_id);
+        }
+        pipe.query(c)
+    })?;
+    if_match.verify(version)?;
+    db::changes::record_change(c, &store_id, ChangedItem::Product(product_id.to_string()))?;
+    let name = get_name(c, &product_id)?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::ProductDeleted,
+        &product_id,
+        json!({ "name": name }),
+        Value::Null,
+    )
+}
+
+// back in its aisle, the user must still be able to edit it
+pub fn restore_product(
+    c: &mut Connection,
+    auth: &Auth,
+    product_id: &ProductId,
+) -> Result<(AisleId, Product)> {
+    let product_key = product_key(&product_id);
+    let user_id = db::trash::verify_in_trash(c, &auth, &product_key)?;
+    let aisle_id = AisleId(c.hget(&product_key, PROD_AISLE)?);
+    let (store_id, _) = db::aisles::get_aisle(c, &aisle_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))?;
+    db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let prod_in_aisle_key = products_in_aisle_key(&aisle_id);
+    transaction(c, &[&product_key, &prod_in_aisle_key], |c, pipe| {
+        pipe.sadd(&prod_in_aisle_key, &**product_id).ignore();
+        db::trash::transaction_restore(pipe, &product_key, &user_id);
+        pipe.query(c)
+    })?;
+    db::changes::record_change(c, &store_id, ChangedItem::Product(product_id.to_string()))?;
+    let name = get_name(c, &product_id)?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::ProductRestored,
+        &product_id,
+        Value::Null,
+        json!({ "name": name }),
+    )?;
+    Ok((aisle_id, read_product(c, product_id.to_string())?))
+}
+
+// delete the product for good
+pub fn purge_product(c: &mut Connection, product_id: &ProductId) -> Result<()> {
+    let product_key = product_key(&product_id);
+    let trashed_by = db::trash::trashed_by(c, &product_key)?;
+    transaction(c, &[&product_key], |c, pipe| {
+        if let Some(user_id) = &trashed_by {
+            db::trash::transaction_discard(pipe, &product_key, &user_id);
+        }
+        pipe.del(&product_key).query(c)
     })?;
     Ok(())
 }
 
+// the products of the aisles whose state changed
+fn set_done(
+    c: &mut Connection,
+    user_id: &UserId,
+    store_id: &StoreId,
+    aisle_ids: &[AisleId],
+    is_done: bool,
+) -> Result<Vec<ProductId>> {
+    let keys: Vec<String> = aisle_ids.iter().map(products_in_aisle_key).collect();
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let mut changed = vec![];
+    transaction(c, &keys, |c, pipe| {
+        changed.clear();
+        for aisle_id in aisle_ids {
+            let products: Vec<String> = c.smembers(&products_in_aisle_key(&aisle_id))?;
+            for p in products {
+                let product_key = product_key(&ProductId(p.clone()));
+                let state: i32 = c.hget(&product_key, PROD_STATE)?;
+                if (state != 0) != is_done {
+                    pipe.hset(&product_key, PROD_STATE, is_done as i32)
+                        .ignore()
+                        .hincr(&product_key, PROD_VERSION, 1)
+                        .ignore();
+                    changed.push(ProductId(p));
+                }
+            }
+        }
+        pipe.query(c)
+    })?;
+    for product_id in &changed {
+        db::changes::record_change(c, &store_id, ChangedItem::Product(product_id.to_string()))?;
+        db::history::record(
+            c,
+            &store_id,
+            &user_id,
+            AuditAction::ProductModified,
+            &product_id,
+            json!({ "is_done": !is_done }),
+            json!({ "is_done": is_done }),
+        )?;
+    }
+    Ok(changed)
+}
+
+// check or uncheck every product of the store, returns the products that changed
+pub fn set_store_done(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    is_done: bool,
+) -> Result<Vec<ProductId>> {
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let aisle_ids = db::aisles::get_aisle_ids(c, &store_id)?;
+    set_done(c, &user_id, &store_id, &aisle_ids, is_done)
+}
+
+// check or uncheck every product of the aisle, returns the products that changed
+pub fn set_aisle_done(
+    c: &mut Connection,
+    auth: &Auth,
+    aisle_id: &AisleId,
+    is_done: bool,
+) -> Result<Vec<ProductId>> {
+    let store_id = db::aisles::get_aisle_store(c, &aisle_id)?;
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    set_done(
+        c,
+        &user_id,
+        &store_id,
+        &[AisleId(aisle_id.to_string())],
+        is_done,
+    )
+}
+
+// check or uncheck the products, which can be in several stores, returns the ones
+// that changed
+pub fn set_products_done(
+    c: &mut Connection,
+    auth: &Auth,
+    product_ids: &[ProductId],
+    is_done: bool,
+) -> Result<Vec<ProductId>> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    let store_ids = product_ids
+        .iter()
+        .map(|product_id| {
+            let store_id = get_product_store(c, &product_id)?;
+            db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+            Ok(store_id)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let keys: Vec<String> = product_ids.iter().map(product_key).collect();
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let mut changed = vec![];
+    transaction(c, &keys, |c, pipe| {
+        changed.clear();
+        for (product_id, store_id) in product_ids.iter().zip(&store_ids) {
+            let product_key = product_key(&product_id);
+            let state: i32 = c.hget(&product_key, PROD_STATE)?;
+            if (state != 0) != is_done {
+                pipe.hset(&product_key, PROD_STATE, is_done as i32)
+                    .ignore()
+                    .hincr(&product_key, PROD_VERSION, 1)
+                    .ignore();
+                changed.push((product_id, store_id));
+            }
+        }
+        pipe.query(c)
+    })?;
+    for (product_id, store_id) in &changed {
+        db::changes::record_change(c, &store_id, ChangedItem::Product(product_id.to_string()))?;
+        db::history::record(
+            c,
+            &store_id,
+            &user_id,
+            AuditAction::ProductModified,
+            &product_id,
+            json!({ "is_done": !is_done }),
+            json!({ "is_done": is_done }),
+        )?;
+    }
+    Ok(changed
+        .into_iter()
+        .map(|(product_id, _)| ProductId(product_id.to_string()))
+        .collect())
+}
+
+// the checked products of the store into the user's trash, returns them
+pub fn delete_done_products(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+) -> Result<Vec<ProductId>> {
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let aisle_ids = db::aisles::get_aisle_ids(c, &store_id)?;
+    let keys: Vec<String> = aisle_ids.iter().map(products_in_aisle_key).collect();
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let mut deleted = vec![];
+    let mut names = vec![];
+    transaction(c, &keys, |c, pipe| {
+        deleted.clear();
+        names.clear();
+        for aisle_id in &aisle_ids {
+            let prod_in_aisle_key = products_in_aisle_key(&aisle_id);
+            let products: Vec<String> = c.smembers(&prod_in_aisle_key)?;
+            for p in products {
+                let product_key = product_key(&ProductId(p.clone()));
+                let state: i32 = c.hget(&product_key, PROD_STATE)?;
+                if state != 0 {
+                    let name: String = c.hget(&product_key, PROD_NAME)?;
+                    names.push(name);
+                    pipe.srem(&prod_in_aisle_key, &p).ignore();
+                    db::trash::transaction_trash(pipe, &product_key, &user_id);
+                    deleted.push(ProductId(p));
+                }
+            }
+        }
+        pipe.query(c)
+    })?;
+    for (product_id, name) in deleted.iter().zip(names) {
+        db::changes::record_change(c, &store_id, ChangedItem::Product(product_id.to_string()))?;
+        db::history::record(
+            c,
+            &store_id,
+            &user_id,
+            AuditAction::ProductDeleted,
+            &product_id,
+            json!({ "name": name }),
+            Value::Null,
+        )?;
+    }
+    Ok(deleted)
+}
+
+// to the end of `aisle_id` unless `sort_weight` is given, the aisle can be in another
+// store
+pub fn move_product(
+    c: &mut Connection,
+    auth: &Auth,
+    product_id: &ProductId,
+    aisle_id: &AisleId,
+    sort_weight: Option<f32>,
+    if_match: &IfMatch,
+) -> Result<Product> {
+    let (from_aisle_id, product) = get_product(c, &product_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Product not found"))?;
+    let (to_store_id, _) = db::aisles::get_aisle(c, &aisle_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))?;
+    let from_store_id = db::aisles::get_aisle_store(c, &from_aisle_id)?;
+    let user_id = db::verify_permission_auth(c, &auth, &from_store_id, Role::Editor)?;
+    db::verify_permission_auth(c, &auth, &to_store_id, Role::Editor)?;
+    let product_key = product_key(&product_id);
+    let from_key = products_in_aisle_key(&from_aisle_id);
+    let to_key = products_in_aisle_key(&aisle_id);
+    let new_sort_weight = match sort_weight {
+        Some(sort_weight) => sort_weight,
+        None => find_max_weight_in_aisle(c, &aisle_id)? + 1f32,
+    };
+    let mut version = 0;
+    transaction(c, &[&product_key, &from_key, &to_key], |c, pipe| {
+        version = c.hget(&product_key, PROD_VERSION)?;
+        if if_match.matches(version) {
+            pipe.srem(&from_key, &**product_id)
+                .ignore()
+                .sadd(&to_key, &**product_id)
+                .ignore()
+                .hset(&product_key, PROD_AISLE, &**aisle_id)
+                .ignore()
+                .hset(&product_key, PROD_SORT_WEIGHT, new_sort_weight)
+                .ignore()
+                .hincr(&product_key, PROD_VERSION, 1)
+                .ignore();
+        }
+        pipe.query(c)
+    })?;
+    if_match.verify(version)?;
+    db::changes::record_change(
+        c,
+        &from_store_id,
+        ChangedItem::Product(product_id.to_string()),
+    )?;
+    if from_store_id != to_store_id {
+        db::changes::record_change(
+            c,
+            &to_store_id,
+            ChangedItem::Product(product_id.to_string()),
+        )?;
+    }
+    let before =
+        json!({ "aisle_id": from_aisle_id.to_string(), "sort_weight": product.sort_weight });
+    let after = json!({ "aisle_id": aisle_id.to_string(), "sort_weight": new_sort_weight });
+    db::history::record(
+        c,
+        &from_store_id,
+        &user_id,
+        AuditAction::ProductMoved,
+        &product_id,
+        before.clone(),
+        after.clone(),
+    )?;
+    if from_store_id != to_store_id {
+        db::history::record(
+            c,
+            &to_store_id,
+            &user_id,
+            AuditAction::ProductMoved,
+            &product_id,
+            before,
+            after,
+        )?;
+    }
+    let mut product = product.with_version(version + 1);
+    product.sort_weight = new_sort_weight;
+    Ok(product)
+}
+
 // purge all products contained in aisle
 // to be used only in a transaction, doesn't execute the `pipe`
 pub fn transaction_purge_products_in_aisle(
@@ -152,19 +542,100 @@ pub fn transaction_purge_products_in_aisle(
     Ok(())
 }
 
+// copy the products of `from` into `to`, owned by `owner_id`
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_copy_products_in_aisle(
+    c: &mut Connection,
+    pipe: &mut Pipeline,
+    from: &AisleId,
+    to: &AisleId,
+    owner_id: &UserId,
+    reset_done: bool,
+) -> Result<()> {
+    let products: Vec<String> = c.smembers(&products_in_aisle_key(&from))?;
+    let to_key = products_in_aisle_key(&to);
+    for p in products {
+        let from_key = product_key(&ProductId(p));
+        let state: i32 = if reset_done {
+            0
+        } else {
+            c.hget(&from_key, PROD_STATE)?
+        };
+        let name: String = c.hget(&from_key, PROD_NAME)?;
+        let qty: u32 = c.hget(&from_key, PROD_QTY)?;
+        let unit: u32 = c.hget(&from_key, PROD_UNIT)?;
+        let sort_weight: f32 = c.hget(&from_key, PROD_SORT_WEIGHT)?;
+        let prod_id = db::ids::get_next_product_id();
+        let prod_key = product_key(&prod_id);
+        pipe.hset(&prod_key, PROD_NAME, name)
+            .ignore()
+            .hset(&prod_key, PROD_QTY, qty)
+            .ignore()
+            .hset(&prod_key, PROD_SORT_WEIGHT, sort_weight)
+            .ignore()
+            .hset(&prod_key, PROD_STATE, state)
+            .ignore()
+            .hset(&prod_key, PROD_OWNER, &**owner_id)
+            .ignore()
+            .hset(&prod_key, PROD_UNIT, unit)
+            .ignore()
+            .hset(&prod_key, PROD_AISLE, &**to)
+            .ignore()
+            .hset(&prod_key, PROD_VERSION, FIRST_VERSION)
+            .ignore()
+            .sadd(&to_key, &*prod_id)
+            .ignore();
+    }
+    Ok(())
+}
+
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_import_products(
+    pipe: &mut Pipeline,
+    aisle_id: &AisleId,
+    owner_id: &UserId,
+    products: &[ExportedProduct],
+) {
+    let aisle_key = products_in_aisle_key(&aisle_id);
+    for product in products {
+        let prod_id = db::ids::get_next_product_id();
+        let prod_key = product_key(&prod_id);
+        pipe.hset(&prod_key, PROD_NAME, &product.name)
+            .ignore()
+            .hset(&prod_key, PROD_QTY, product.quantity)
+            .ignore()
+            .hset(&prod_key, PROD_SORT_WEIGHT, product.sort_weight)
+            .ignore()
+            .hset(&prod_key, PROD_STATE, product.is_done as i32)
+            .ignore()
+            .hset(&prod_key, PROD_OWNER, &**owner_id)
+            .ignore()
+            .hset(&prod_key, PROD_UNIT, u32::from(product.unit.clone()))
+            .ignore()
+            .hset(&prod_key, PROD_AISLE, &**aisle_id)
+            .ignore()
+            .hset(&prod_key, PROD_VERSION, FIRST_VERSION)
+            .ignore()
+            .sadd(&aisle_key, &*prod_id)
+            .ignore();
+    }
+}
+
+// returns the store of the product
 pub fn edit_product_sort_weight(
     c: &mut Connection,
     pipe: &mut Pipeline,
     auth: &Auth,
     data: &ProductItemWeight,
-) -> Result<()> {
+) -> Result<(StoreId, f32)> {
     let product_id = ProductId(data.id.clone());
-    let product_owner = get_product_owner(c, &product_id)?;
-    db::verify_permission_auth(c, &auth, &product_owner)?;
+    let store_id = get_product_store(c, &product_id)?;
+    db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
     let product_key = product_key(&product_id);
+    let previous: f32 = c.hget(&product_key, PROD_SORT_WEIGHT)?;
     pipe.hset(&product_key, PROD_SORT_WEIGHT, data.sort_weight)
         .ignore();
-    Ok(())
+    Ok((store_id, previous))
 }
 
 #[cfg(test)]
@@ -230,7 +701,10 @@ pub mod tests {
         let mut c = client.get_connection().unwrap();
         let (_, product_id) = save_product_for_test(&mut c);
         let data = EditProduct::new(Some(RENAME.to_owned()), Some(2), None, Some(true));
-        assert_eq!(Ok(()), modify_product(&mut c, &AUTH, &data, &product_id));
+        assert_eq!(
+            Ok(()),
+            modify_product(&mut c, &AUTH, &data, &product_id, &IfMatch::default())
+        );
 
         // check DB
         let product_key = product_key(&product_id);
@@ -265,10 +739,108 @@ pub mod tests {
         let mut c = client.get_connection().unwrap();
 
         let (_, p) = save_product_for_test(&mut c);
-        assert_eq!(Ok(()), delete_product(&mut c, &AUTH, &p));
+        assert_eq!(
+            Ok(()),
+            delete_product(&mut c, &AUTH, &p, &IfMatch::default())
+        );
+        assert_eq!(Ok(None), get_product(&mut c, &p));
+        assert_eq!(Ok(true), c.exists(&product_key(&p)));
+
+        assert_eq!(Ok(()), db::trash::purge_trash(&mut c, db::timestamp() + 1));
         assert_eq!(Ok(false), c.exists(&product_key(&p)));
     }
 
+    #[test]
+    fn bulk_done_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let (aisle_id, p1) = save_product_for_test(&mut c);
+        let p2 = add_2nd_product(&mut c, &aisle_id);
+        let store_id = db::aisles::get_aisle_store(&mut c, &aisle_id).unwrap();
+
+        assert_eq!(
+            Ok(2),
+            set_aisle_done(&mut c, &AUTH, &aisle_id, true).map(|p| p.len())
+        );
+        // only the products whose state changes
+        assert_eq!(
+            Ok(0),
+            set_aisle_done(&mut c, &AUTH, &aisle_id, true).map(|p| p.len())
+        );
+        assert_eq!(Ok(FIRST_VERSION + 1), get_version(&mut c, &p1));
+        let data = EditProduct::new(None, None, None, Some(false));
+        assert_eq!(
+            Ok(()),
+            modify_product(&mut c, &AUTH, &data, &p2, &IfMatch::default())
+        );
+
+        assert_eq!(
+            Ok(vec![ProductId(p1.to_string())]),
+            delete_done_products(&mut c, &AUTH, &store_id)
+        );
+        assert_eq!(Ok(true), db::trash::is_trashed(&mut c, &product_key(&p1)));
+        assert_eq!(
+            Ok(false),
+            c.sismember(&products_in_aisle_key(&aisle_id), p1.to_string())
+        );
+        assert_eq!(
+            Ok(ProductId(p1.to_string())),
+            restore_product(&mut c, &AUTH, &p1).map(|(_, p)| p.id())
+        );
+        assert_eq!(
+            Ok(vec![ProductId(p2.to_string())]),
+            set_store_done(&mut c, &AUTH, &store_id, true)
+        );
+        assert_eq!(Ok(1), c.hget(&product_key(&p2), PROD_STATE));
+    }
+
+    #[test]
+    fn move_product_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let (aisle_id, product_id) = save_product_for_test(&mut c);
+        let store_id = db::aisles::get_aisle_store(&mut c, &aisle_id).unwrap();
+        let aisle2 = db::aisles::tests::add_2nd_aisle(&mut c, &store_id);
+        add_2nd_product(&mut c, &aisle2);
+
+        let moved = move_product(
+            &mut c,
+            &AUTH,
+            &product_id,
+            &aisle2,
+            None,
+            &IfMatch::default(),
+        )
+        .unwrap();
+        assert!((moved.sort_weight - 2f32).abs() < std::f32::EPSILON);
+        assert_eq!(FIRST_VERSION + 1, moved.version());
+        assert_eq!(
+            Ok(false),
+            c.sismember(&products_in_aisle_key(&aisle_id), product_id.to_string())
+        );
+        assert_eq!(
+            Ok(true),
+            c.sismember(&products_in_aisle_key(&aisle2), product_id.to_string())
+        );
+        assert_eq!(
+            Ok(aisle2.to_string()),
+            c.hget(&product_key(&product_id), PROD_AISLE)
+        );
+        assert_eq!(
+            Err(NOT_FOUND),
+            move_product(
+                &mut c,
+                &AUTH,
+                &product_id,
+                &AisleId("unknown".to_owned()),
+                None,
+                &IfMatch::default()
+            )
+            .map(|_| ())
+            .map_err(|e| e.status)
+        );
+    }
+
     #[test]
     fn transaction_purge_products_in_aisle_test() {
         let client = Client::open(get_db_addr().as_str()).unwrap();
@@ -292,11 +864,12 @@ pub mod tests {
     fn edit_product_sort_weight_test() {
         let client = Client::open(get_db_addr().as_str()).unwrap();
         let mut c = client.get_connection().unwrap();
-        let (_, product_id) = save_product_for_test(&mut c);
+        let (aisle_id, product_id) = save_product_for_test(&mut c);
+        let store_id = db::aisles::get_aisle_store(&mut c, &aisle_id).unwrap();
         let mut pipe = Pipeline::new(c.db);
         pipe.atomic();
         assert_eq!(
-            Ok(()),
+            Ok((store_id, 1f32)),
             edit_product_sort_weight(
                 &mut c,
                 &mut pipe,
@@ -310,4 +883,86 @@ pub mod tests {
             c.hget(&product_key(&product_id), PROD_SORT_WEIGHT)
         );
     }
+
+    #[test]
+    fn move_product_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let other_store_id = s.save_store(&auth, "other store").unwrap();
+            let other_aisle_id = s
+                .save_aisle(&auth, &other_store_id, "other aisle")
+                .unwrap()
+                .id();
+            let revision = s.list_store(&auth, &f.store_id).unwrap().revision();
+            let moved = s
+                .move_product(
+                    &auth,
+                    &f.product_id,
+                    &other_aisle_id,
+                    None,
+                    &IfMatch::default(),
+                )
+                .unwrap();
+            assert!((moved.sort_weight - 1.0).abs() < std::f32::EPSILON);
+            assert_eq!(FIRST_VERSION + 1, moved.version());
+            assert_eq!(Ok(other_store_id.clone()), s.product_store(&f.product_id));
+            // gone from the source store
+            assert_eq!(
+                Ok(vec![f.product_id.to_string()]),
+                s.store_changes(&auth, &f.store_id, revision)
+                    .map(|c| c.deleted_products)
+            );
+            for store_id in &[&f.store_id, &other_store_id] {
+                let history = s.store_history(&auth, store_id, None, 1).unwrap();
+                assert_eq!(AuditAction::ProductMoved, history.entries[0].action);
+                assert_eq!(f.product_id.to_string(), history.entries[0].item_id);
+            }
+
+            let moved = s
+                .move_product(
+                    &auth,
+                    &f.product_id,
+                    &f.aisle_id,
+                    Some(3.0),
+                    &IfMatch::default(),
+                )
+                .unwrap();
+            assert!((moved.sort_weight - 3.0).abs() < std::f32::EPSILON);
+            assert_eq!(Ok(f.store_id.clone()), s.product_store(&f.product_id));
+        });
+    }
+
+    #[test]
+    fn bulk_done_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let checked_id = s.save_product(&auth, "checked", &f.aisle_id).unwrap().id();
+            assert_eq!(
+                Ok(2),
+                s.set_aisle_done(&auth, &f.aisle_id, true).map(|p| p.len())
+            );
+            // only the products whose state changes
+            assert_eq!(Ok(vec![]), s.set_aisle_done(&auth, &f.aisle_id, true));
+            assert_eq!(
+                Ok(vec![ProductId(f.product_id.to_string())]),
+                s.set_products_done(&auth, &[ProductId(f.product_id.to_string())], false)
+            );
+            assert_eq!(
+                Ok(vec![ProductId(checked_id.to_string())]),
+                s.delete_done_products(&auth, &f.store_id)
+            );
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.product_store(&checked_id).map_err(|e| e.status)
+            );
+            assert_eq!(
+                Ok(vec![ProductId(f.product_id.to_string())]),
+                s.set_store_done(&auth, &f.store_id, true)
+            );
+            assert_eq!(Ok(vec![]), s.set_store_done(&auth, &f.store_id, true));
+            assert_eq!(Ok(FIRST_VERSION + 3), s.product_version(&f.product_id));
+        });
+    }
 }
@@ -0,0 +1,395 @@
+use std::ops::DerefMut;
+
+use derive_new::new;
+
+#[cfg(not(test))]
+use redis::Connection;
+
+#[cfg(test)]
+use fake_redis::FakeConnection as Connection;
+
+use crate::{
+    db::{self, sessions::SessionTtl, Storage},
+    error::{Result, ServerError, NOT_FOUND},
+    types::*,
+};
+
+// `C` is anything giving access to a Redis connection: a pooled connection on
+// the server, a plain `&mut Connection` in tests
+#[derive(new)]
+pub struct RedisStorage<C> {
+    c: C,
+    ttl: SessionTtl,
+}
+
+impl<C: DerefMut<Target = Connection> + Send> Storage for RedisStorage<C> {
+    fn migrate(&mut self) -> Result<()> {
+        db::migrations::migrate(&mut self.c)
+    }
+
+    fn save_user(&mut self, user: &User) -> Result<ConnectionToken> {
+        db::users::save_user(&mut self.c, user)
+    }
+
+    fn login(&mut self, auth_info: &AuthInfo, client: &ClientInfo) -> Result<ConnectionToken> {
+        db::users::login(&mut self.c, auth_info, client, &self.ttl)
+    }
+
+    fn delete_user(&mut self, auth: &Auth, user_id: &UserId) -> Result<()> {
+        db::users::delete_user(&mut self.c, auth, user_id)
+    }
+
+    fn change_password(
+        &mut self,
+        auth: &Auth,
+        user_id: &UserId,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<()> {
+        db::users::change_password(&mut self.c, auth, user_id, old_password, new_password)
+    }
+
+    fn create_reset_token(&mut self, username: &str, email: &str) -> Result<Option<String>> {
+        db::users::create_reset_token(&mut self.c, username, email)
+    }
+
+    fn reset_password(&mut self, token: &str, new_password: &str) -> Result<()> {
+        db::users::reset_password(&mut self.c, token, new_password)
+    }
+
+    fn validate_session(&mut self, auth: &Auth) -> Result<()> {
+        db::sessions::validate_session(&mut self.c, auth, &self.ttl)
+    }
+
+    fn check_session(&mut self, auth: &Auth) -> Result<()> {
+        db::sessions::check_session(&mut self.c, auth, &self.ttl)
+    }
+
+    fn delete_session(&mut self, auth: &Auth, user_id: &UserId) -> Result<()> {
+        db::sessions::delete_session(&mut self.c, auth, user_id)
+    }
+
+    fn list_sessions(&mut self, auth: &Auth) -> Result<Vec<SessionInfo>> {
+        db::sessions::list_sessions(&mut self.c, auth)
+    }
+
+    fn revoke_session(&mut self, auth: &Auth, session_id: &str) -> Result<()> {
+        db::sessions::revoke_session(&mut self.c, auth, session_id)
+    }
+
+    fn revoke_other_sessions(&mut self, auth: &Auth) -> Result<()> {
+        db::sessions::revoke_other_sessions(&mut self.c, auth)
+    }
+
+    fn save_store(&mut self, auth: &Auth, name: &str) -> Result<StoreId> {
+        db::stores::save_store(&mut self.c, auth, name)
+    }
+
+    fn edit_store(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        new_name: &str,
+        if_match: &IfMatch,
+    ) -> Result<()> {
+        db::stores::edit_store(&mut self.c, auth, store_id, new_name, if_match)
+    }
+
+    fn get_all_stores(&mut self, auth: &Auth) -> Result<Vec<StoreLight>> {
+        db::stores::get_all_stores(&mut self.c, auth)
+    }
+
+    fn list_store(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Store> {
+        db::stores::list_store(&mut self.c, auth, store_id)
+    }
+
+    fn store_changes(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        since: u64,
+    ) -> Result<StoreChanges> {
+        db::changes::store_changes(&mut self.c, auth, store_id, since)
+    }
+
+    fn store_history(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        before: Option<u64>,
+        limit: u32,
+    ) -> Result<StoreHistory> {
+        db::history::store_history(&mut self.c, auth, store_id, before, limit)
+    }
+
+    fn import_store(&mut self, auth: &Auth, store: &ExportedStore) -> Result<StoreId> {
+        db::stores::import_store(&mut self.c, auth, store)
+    }
+
+    fn delete_store(&mut self, auth: &Auth, store_id: &StoreId, if_match: &IfMatch) -> Result<()> {
+        db::stores::delete_store(&mut self.c, auth, store_id, if_match)
+    }
+
+    fn duplicate_store(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        name: Option<&str>,
+        reset_done: bool,
+    ) -> Result<StoreId> {
+        db::stores::duplicate_store(&mut self.c, auth, store_id, name, reset_done)
+    }
+
+    fn verify_role(&mut self, auth: &Auth, store_id: &StoreId, role: Role) -> Result<()> {
+        db::verify_permission_auth(&mut self.c, auth, store_id, role).map(|_| ())
+    }
+
+    fn aisle_store(&mut self, aisle_id: &AisleId) -> Result<StoreId> {
+        db::aisles::get_aisle_store(&mut self.c, aisle_id)
+    }
+
+    fn product_store(&mut self, product_id: &ProductId) -> Result<StoreId> {
+        db::products::get_product_store(&mut self.c, product_id)
+    }
+
+    fn store_name(&mut self, store_id: &StoreId) -> Result<String> {
+        db::stores::get_name(&mut self.c, store_id)
+    }
+
+    fn get_aisle(&mut self, aisle_id: &AisleId) -> Result<Aisle> {
+        db::aisles::get_aisle(&mut self.c, aisle_id)?
+            .map(|(_, aisle)| aisle)
+            .ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))
+    }
+
+    fn get_product(&mut self, product_id: &ProductId) -> Result<(AisleId, Product)> {
+        db::products::get_product(&mut self.c, product_id)?
+            .ok_or_else(|| ServerError::new(NOT_FOUND, "Product not found"))
+    }
+
+    fn store_version(&mut self, store_id: &StoreId) -> Result<u64> {
+        db::stores::get_version(&mut self.c, store_id)
+    }
+
+    fn aisle_version(&mut self, aisle_id: &AisleId) -> Result<u64> {
+        db::aisles::get_version(&mut self.c, aisle_id)
+    }
+
+    fn product_version(&mut self, product_id: &ProductId) -> Result<u64> {
+        db::products::get_version(&mut self.c, product_id)
+    }
+
+    fn member_ids(&mut self, store_id: &StoreId) -> Result<Vec<UserId>> {
+        db::stores::get_member_ids(&mut self.c, store_id)
+    }
+
+    fn list_members(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Vec<StoreMember>> {
+        db::stores::list_members(&mut self.c, auth, store_id)
+    }
+
+    fn set_member(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        username: &str,
+        role: Role,
+    ) -> Result<()> {
+        db::stores::set_member(&mut self.c, auth, store_id, username, role)
+    }
+
+    fn remove_member(&mut self, auth: &Auth, store_id: &StoreId, user_id: &UserId) -> Result<()> {
+        db::stores::remove_member(&mut self.c, auth, store_id, user_id)
+    }
+
+    fn create_invitation(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        role: Role,
+        ttl: i64,
+        max_uses: u32,
+    ) -> Result<Invitation> {
+        db::invitations::create_invitation(&mut self.c, auth, store_id, role, ttl, max_uses)
+    }
+
+    fn list_invitations(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Vec<Invitation>> {
+        db::invitations::list_invitations(&mut self.c, auth, store_id)
+    }
+
+    fn revoke_invitation(&mut self, auth: &Auth, store_id: &StoreId, code: &str) -> Result<()> {
+        db::invitations::revoke_invitation(&mut self.c, auth, store_id, code)
+    }
+
+    fn redeem_invitation(&mut self, auth: &Auth, code: &str) -> Result<StoreLight> {
+        db::invitations::redeem_invitation(&mut self.c, auth, code)
+    }
+
+    fn log_event(&mut self, user_id: &UserId, data: &str) -> Result<u64> {
+        db::events::log_event(&mut self.c, user_id, data)
+    }
+
+    fn events_since(&mut self, auth: &Auth, resume_from: Option<u64>) -> Result<EventFeed> {
+        db::events::events_since(&mut self.c, auth, resume_from)
+    }
+
+    fn op_result(&mut self, auth: &Auth, op_id: &str) -> Result<Option<String>> {
+        db::op_results::get_op_result(&mut self.c, auth, op_id)
+    }
+
+    fn begin_op(&mut self, auth: &Auth, op_id: &str) -> Result<bool> {
+        db::op_results::begin_op(&mut self.c, auth, op_id)
+    }
+
+    fn end_op(&mut self, auth: &Auth, op_id: &str, result: Option<&str>) -> Result<()> {
+        db::op_results::end_op(&mut self.c, auth, op_id, result)
+    }
+
+    fn push_undo(&mut self, auth: &Auth, op: &str) -> Result<()> {
+        db::undo::push_undo(&mut self.c, auth, op)
+    }
+
+    fn pop_undo(&mut self, auth: &Auth) -> Result<Option<String>> {
+        db::undo::pop_undo(&mut self.c, auth)
+    }
+
+    fn save_aisle(&mut self, auth: &Auth, store_id: &StoreId, name: &str) -> Result<Aisle> {
+        db::aisles::save_aisle(&mut self.c, auth, store_id, name)
+    }
+
+    fn edit_aisle(
+        &mut self,
+        auth: &Auth,
+        aisle_id: &AisleId,
+        new_name: &str,
+        if_match: &IfMatch,
+    ) -> Result<()> {
+        db::aisles::edit_aisle(&mut self.c, auth, aisle_id, new_name, if_match)
+    }
+
+    fn delete_aisle(&mut self, auth: &Auth, aisle_id: &AisleId, if_match: &IfMatch) -> Result<()> {
+        db::aisles::delete_aisle(&mut self.c, auth, aisle_id, if_match)
+    }
+
+    fn move_aisle(
+        &mut self,
+        auth: &Auth,
+        aisle_id: &AisleId,
+        store_id: &StoreId,
+        sort_weight: Option<f32>,
+        if_match: &IfMatch,
+    ) -> Result<Aisle> {
+        db::aisles::move_aisle(&mut self.c, auth, aisle_id, store_id, sort_weight, if_match)
+    }
+
+    fn save_product(&mut self, auth: &Auth, name: &str, aisle_id: &AisleId) -> Result<Product> {
+        db::products::save_product(&mut self.c, auth, name, aisle_id)
+    }
+
+    fn modify_product(
+        &mut self,
+        auth: &Auth,
+        edit_data: &EditProduct,
+        product_id: &ProductId,
+        if_match: &IfMatch,
+    ) -> Result<()> {
+        db::products::modify_product(&mut self.c, auth, edit_data, product_id, if_match)
+    }
+
+    fn delete_product(
+        &mut self,
+        auth: &Auth,
+        product_id: &ProductId,
+        if_match: &IfMatch,
+    ) -> Result<()> {
+        db::products::delete_product(&mut self.c, auth, product_id, if_match)
+    }
+
+    fn move_product(
+        &mut self,
+        auth: &Auth,
+        product_id: &ProductId,
+        aisle_id: &AisleId,
+        sort_weight: Option<f32>,
+        if_match: &IfMatch,
+    ) -> Result<Product> {
+        db::products::move_product(
+            &mut self.c,
+            auth,
+            product_id,
+            aisle_id,
+            sort_weight,
+            if_match,
+        )
+    }
+
+    fn change_sort_weight(&mut self, auth: &Auth, data: &EditWeight) -> Result<()> {
+        db::change_sort_weight(&mut self.c, auth, data)
+    }
+
+    fn set_store_done(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        is_done: bool,
+    ) -> Result<Vec<ProductId>> {
+        db::products::set_store_done(&mut self.c, auth, store_id, is_done)
+    }
+
+    fn set_aisle_done(
+        &mut self,
+        auth: &Auth,
+        aisle_id: &AisleId,
+        is_done: bool,
+    ) -> Result<Vec<ProductId>> {
+        db::products::set_aisle_done(&mut self.c, auth, aisle_id, is_done)
+    }
+
+    fn set_products_done(
+        &mut self,
+        auth: &Auth,
+        product_ids: &[ProductId],
+        is_done: bool,
+    ) -> Result<Vec<ProductId>> {
+        db::products::set_products_done(&mut self.c, auth, product_ids, is_done)
+    }
+
+    fn delete_done_products(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Vec<ProductId>> {
+        db::products::delete_done_products(&mut self.c, auth, store_id)
+    }
+
+    fn list_trash(&mut self, auth: &Auth) -> Result<Vec<TrashedItem>> {
+        db::trash::list_trash(&mut self.c, auth)
+    }
+
+    fn restore_store(&mut self, auth: &Auth, store_id: &StoreId) -> Result<()> {
+        db::stores::restore_store(&mut self.c, auth, store_id)
+    }
+
+    fn restore_aisle(&mut self, auth: &Auth, aisle_id: &AisleId) -> Result<(StoreId, Aisle)> {
+        db::aisles::restore_aisle(&mut self.c, auth, aisle_id)
+    }
+
+    fn restore_product(
+        &mut self,
+        auth: &Auth,
+        product_id: &ProductId,
+    ) -> Result<(AisleId, Product)> {
+        db::products::restore_product(&mut self.c, auth, product_id)
+    }
+
+    fn purge_trash(&mut self, before: i64) -> Result<()> {
+        db::trash::purge_trash(&mut self.c, before)
+    }
+
+    fn backup(&mut self, with_sessions: bool) -> Result<BackupData> {
+        db::backup::backup(&mut self.c, with_sessions)
+    }
+
+    fn restore(&mut self, data: &BackupData) -> Result<()> {
+        db::backup::restore(&mut self.c, data)
+    }
+
+    fn nuke(&mut self) -> Result<()> {
+        db::flush(&mut self.c)
+    }
+}
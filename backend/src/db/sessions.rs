@@ -4,23 +4,53 @@ use redis::{self, transaction, Commands, Connection};
 #[cfg(test)]
 use fake_redis::{transaction, FakeConnection as Connection};
 
+use derive_new::new;
+
 use crate::{
+    db,
     error::{self, Result, ServerError},
     types::*,
 };
 
 const SESSIONS_LIST: &str = "sessions";
+const SESSION_CREATED: &str = "created_at";
+const SESSION_LAST_USED: &str = "last_used";
+const SESSION_ID: &str = "id";
+const SESSION_USER_AGENT: &str = "user_agent";
+const SESSION_IP: &str = "ip";
+
+// how long a session lives after login, and without being used, in seconds
+#[derive(Clone, Copy, Debug, new)]
+pub struct SessionTtl {
+    pub absolute: i64,
+    pub idle: i64,
+}
+
+impl SessionTtl {
+    pub fn is_expired(&self, created_at: i64, last_used: i64, now: i64) -> bool {
+        now - created_at > self.absolute || now - last_used > self.idle
+    }
+}
 
 fn user_sessions_key(user_id: &UserId) -> String {
     format!("sessions:{}", **user_id)
 }
 
+fn session_key(auth: &str) -> String {
+    format!("session:{}", auth)
+}
+
 pub fn get_user_id(c: &mut Connection, auth: &Auth) -> Result<UserId> {
     let id = c.hget(SESSIONS_LIST, auth.0)?;
     Ok(UserId(id))
 }
 
-pub fn store_session(c: &mut Connection, auth: &str, user_id: &UserId) -> Result<()> {
+pub fn store_session(
+    c: &mut Connection,
+    auth: &str,
+    user_id: &UserId,
+    client: &ClientInfo,
+) -> Result<()> {
     if c.hexists(SESSIONS_LIST, auth)? {
         Err(ServerError::new(
             error::INTERNAL_ERROR,
@@ -28,22 +58,61 @@ pub fn store_session(c: &mut Connection, auth: &str, user_id: &UserId) -> Result
         ))
     } else {
         let user_session_key = user_sessions_key(user_id);
-        transaction(c, &[SESSIONS_LIST, &user_session_key], |c, pipe| {
-            pipe.hset(SESSIONS_LIST, auth, user_id.to_string())
-                .ignore()
-                .sadd(&user_session_key, auth)
-                .query(c)
-        })?;
+        let session_key = session_key(auth);
+        let session_id = db::ids::get_next_session_id();
+        let now = db::timestamp();
+        transaction(
+            c,
+            &[SESSIONS_LIST, &user_session_key, &session_key],
+            |c, pipe| {
+                pipe.hset(SESSIONS_LIST, auth, user_id.to_string())
+                    .ignore()
+                    .sadd(&user_session_key, auth)
+                    .ignore()
+                    .hset(&session_key, SESSION_ID, &session_id)
+                    .ignore()
+                    .hset(&session_key, SESSION_CREATED, now)
+                    .ignore()
+                    .hset(&session_key, SESSION_LAST_USED, now)
+                    .ignore();
+                if let Some(ref user_agent) = client.user_agent {
+                    pipe.hset(&session_key, SESSION_USER_AGENT, user_agent)
+                        .ignore();
+                }
+                if let Some(ref ip) = client.ip {
+                    pipe.hset(&session_key, SESSION_IP, ip).ignore();
+                }
+                pipe.query(c)
+            },
+        )?;
 
         Ok(())
     }
 }
 
-pub fn validate_session(c: &mut Connection, auth: &Auth) -> Result<()> {
+// sessions stored without timestamps are considered expired
+fn is_session_expired(c: &mut Connection, auth: &str, ttl: &SessionTtl, now: i64) -> Result<bool> {
+    let session_key = session_key(auth);
+    let created_at: Option<i64> = c.hget(&session_key, SESSION_CREATED)?;
+    let last_used: Option<i64> = c.hget(&session_key, SESSION_LAST_USED)?;
+    Ok(match (created_at, last_used) {
+        (Some(created_at), Some(last_used)) => ttl.is_expired(created_at, last_used, now),
+        _ => true,
+    })
+}
+
+// the session is valid, without counting it as used
+pub fn check_session(c: &mut Connection, auth: &Auth, ttl: &SessionTtl) -> Result<()> {
     if c.hexists(SESSIONS_LIST, auth.0)? {
         let user_id = get_user_id(c, auth)?;
         if c.sismember(&user_sessions_key(&user_id), auth.0)? {
-            Ok(())
+            let now = db::timestamp();
+            if is_session_expired(c, auth.0, ttl, now)? {
+                delete_session_with_connection(c, auth, &user_id)?;
+                Err(ServerError::new(error::UNAUTHORISED, "Session expired"))
+            } else {
+                Ok(())
+            }
         } else {
             Err(ServerError::new(
                 error::UNAUTHORISED,
@@ -55,20 +124,44 @@ pub fn validate_session(c: &mut Connection, auth: &Auth) -> Result<()> {
     }
 }
 
+pub fn validate_session(c: &mut Connection, auth: &Auth, ttl: &SessionTtl) -> Result<()> {
+    check_session(c, auth, ttl)?;
+    c.hset(&session_key(auth.0), SESSION_LAST_USED, db::timestamp())?;
+    Ok(())
+}
+
 fn delete_session_with_connection(c: &mut Connection, auth: &Auth, user_id: &UserId) -> Result<()> {
     let user_session_key = user_sessions_key(user_id);
+    let session_key = session_key(auth.0);
     Ok(transaction(
         c,
-        &[SESSIONS_LIST, &user_session_key],
+        &[SESSIONS_LIST, &user_session_key, &session_key],
         |c, pipe| {
             pipe.hdel(SESSIONS_LIST, auth.0)
                 .ignore()
                 .srem(&user_session_key, auth.0)
+                .ignore()
+                .del(&session_key)
                 .query(c)
         },
     )?)
 }
 
+pub fn sweep_expired_sessions(
+    c: &mut Connection,
+    user_id: &UserId,
+    ttl: &SessionTtl,
+) -> Result<()> {
+    let now = db::timestamp();
+    let all_user_sessions: Vec<String> = c.smembers(&user_sessions_key(user_id))?;
+    for auth in all_user_sessions {
+        if is_session_expired(c, &auth, ttl, now)? {
+            delete_session_with_connection(c, &Auth(&auth), user_id)?;
+        }
+    }
+    Ok(())
+}
+
 pub fn delete_session(c: &mut Connection, auth: &Auth, wanted_user_id: &UserId) -> Result<()> {
     let user_id = get_user_id(c, auth)?;
     if user_id == *wanted_user_id {
@@ -81,15 +174,64 @@ pub fn delete_session(c: &mut Connection, auth: &Auth, wanted_user_id: &UserId)
     }
 }
 
-pub fn delete_all_user_sessions(c: &mut Connection, auth: &Auth) -> Result<()> {
-    let user_id = UserId(c.hget(SESSIONS_LIST, auth.0)?);
+fn get_session_info(c: &mut Connection, auth: &str, current: bool) -> Result<SessionInfo> {
+    let session_key = session_key(auth);
+    Ok(SessionInfo::new(
+        c.hget(&session_key, SESSION_ID)?,
+        c.hget(&session_key, SESSION_CREATED)?,
+        c.hget(&session_key, SESSION_LAST_USED)?,
+        c.hget(&session_key, SESSION_USER_AGENT)?,
+        c.hget(&session_key, SESSION_IP)?,
+        current,
+    ))
+}
+
+pub fn list_sessions(c: &mut Connection, auth: &Auth) -> Result<Vec<SessionInfo>> {
+    let user_id = get_user_id(c, auth)?;
+    let all_user_sessions: Vec<String> = c.smembers(&user_sessions_key(&user_id))?;
+    let mut sessions = all_user_sessions
+        .iter()
+        .map(|a| get_session_info(c, a, a == auth.0))
+        .collect::<Result<Vec<_>>>()?;
+    sessions.sort_by_key(|s| s.created_at);
+    Ok(sessions)
+}
+
+pub fn revoke_session(c: &mut Connection, auth: &Auth, session_id: &str) -> Result<()> {
+    let user_id = get_user_id(c, auth)?;
+    let all_user_sessions: Vec<String> = c.smembers(&user_sessions_key(&user_id))?;
+    for a in all_user_sessions {
+        let id: Option<String> = c.hget(&session_key(&a), SESSION_ID)?;
+        if id.as_deref() == Some(session_id) {
+            return delete_session_with_connection(c, &Auth(&a), &user_id);
+        }
+    }
+    Err(ServerError::new(error::NOT_FOUND, "Session not found"))
+}
+
+pub fn revoke_other_sessions(c: &mut Connection, auth: &Auth) -> Result<()> {
+    let user_id = get_user_id(c, auth)?;
     let all_user_sessions: Vec<String> = c.smembers(&user_sessions_key(&user_id))?;
     all_user_sessions
         .iter()
+        .filter(|a| *a != auth.0)
         .map(|a| delete_session_with_connection(c, &Auth(a), &user_id))
         .collect()
 }
 
+pub fn delete_all_user_sessions(c: &mut Connection, auth: &Auth) -> Result<()> {
+    let user_id = UserId(c.hget(SESSIONS_LIST, auth.0)?);
+    delete_user_id_sessions(c, &user_id)
+}
+
+pub fn delete_user_id_sessions(c: &mut Connection, user_id: &UserId) -> Result<()> {
+    let all_user_sessions: Vec<String> = c.smembers(&user_sessions_key(user_id))?;
+    all_user_sessions
+        .iter()
+        .map(|a| delete_session_with_connection(c, &Auth(a), user_id))
+        .collect()
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -98,10 +240,17 @@ pub mod tests {
 
     pub const AUTH: Auth = Auth("tokenauth");
     pub const AUTH2: Auth = Auth("anothertokenauth");
+    pub const TTL: SessionTtl = SessionTtl {
+        absolute: 3600,
+        idle: 600,
+    };
 
     pub fn store_session_for_test(c: &mut Connection, auth: &Auth) {
         let user_id = UserId(HASH_1.to_owned());
-        assert_eq!(Ok(()), store_session(c, auth, &user_id));
+        assert_eq!(
+            Ok(()),
+            store_session(c, auth, &user_id, &ClientInfo::default())
+        );
         assert_eq!(Ok(true), c.hexists(SESSIONS_LIST, auth.0));
         assert_eq!(Ok(true), c.sismember(&user_sessions_key(&user_id), auth.0));
         assert_eq!(
@@ -109,7 +258,7 @@ pub mod tests {
                 error::INTERNAL_ERROR,
                 "Auth already exists",
             )),
-            store_session(c, &AUTH, &UserId(HASH_1.to_owned()))
+            store_session(c, &AUTH, &UserId(HASH_1.to_owned()), &ClientInfo::default())
         );
     }
 
@@ -118,10 +267,10 @@ pub mod tests {
         let client = Client::open(get_db_addr().as_str()).unwrap();
         let mut c = client.get_connection().unwrap();
         store_session_for_test(&mut c, &AUTH);
-        assert_eq!(Ok(()), validate_session(&mut c, &AUTH));
+        assert_eq!(Ok(()), validate_session(&mut c, &AUTH, &TTL));
         assert_eq!(
             Err(ServerError::new(error::UNAUTHORISED, "Not logged in")),
-            validate_session(&mut c, &Auth("notpresentauth"))
+            validate_session(&mut c, &Auth("notpresentauth"), &TTL)
         );
         // tamper user sessions list
         let _: i32 = c
@@ -132,10 +281,91 @@ pub mod tests {
                 error::UNAUTHORISED,
                 "x-auth-token does not belong to this user",
             )),
-            validate_session(&mut c, &AUTH)
+            validate_session(&mut c, &AUTH, &TTL)
         );
     }
 
+    #[test]
+    fn session_expiry_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let user_id = UserId(HASH_1.to_owned());
+        store_session_for_test(&mut c, &AUTH);
+        let now = db::timestamp();
+
+        // checking the session doesn't use it
+        let _: i32 = c
+            .hset(&session_key(AUTH.0), SESSION_LAST_USED, now - TTL.idle + 10)
+            .unwrap();
+        assert_eq!(Ok(()), check_session(&mut c, &AUTH, &TTL));
+        assert_eq!(
+            now - TTL.idle + 10,
+            c.hget::<i64>(&session_key(AUTH.0), SESSION_LAST_USED)
+                .unwrap()
+        );
+
+        // using the session pushes the idle expiry back
+        assert_eq!(Ok(()), validate_session(&mut c, &AUTH, &TTL));
+        assert!(
+            c.hget::<i64>(&session_key(AUTH.0), SESSION_LAST_USED)
+                .unwrap()
+                >= now
+        );
+
+        // idle for too long
+        let _: i32 = c
+            .hset(&session_key(AUTH.0), SESSION_LAST_USED, now - TTL.idle - 10)
+            .unwrap();
+        assert_eq!(
+            Err(ServerError::new(error::UNAUTHORISED, "Session expired")),
+            validate_session(&mut c, &AUTH, &TTL)
+        );
+        assert_eq!(Ok(false), c.exists(SESSIONS_LIST));
+        assert_eq!(Ok(false), c.exists(&user_sessions_key(&user_id)));
+        assert_eq!(Ok(false), c.exists(&session_key(AUTH.0)));
+
+        // still in use but too old
+        assert_eq!(
+            Ok(()),
+            store_session(&mut c, AUTH.0, &user_id, &ClientInfo::default())
+        );
+        let _: i32 = c
+            .hset(
+                &session_key(AUTH.0),
+                SESSION_CREATED,
+                now - TTL.absolute - 10,
+            )
+            .unwrap();
+        assert_eq!(
+            Err(ServerError::new(error::UNAUTHORISED, "Session expired")),
+            validate_session(&mut c, &AUTH, &TTL)
+        );
+        assert_eq!(Ok(false), c.exists(&session_key(AUTH.0)));
+    }
+
+    #[test]
+    fn sweep_expired_sessions_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let user_id = UserId(HASH_1.to_owned());
+        store_session_for_test(&mut c, &AUTH);
+        assert_eq!(
+            Ok(()),
+            store_session(&mut c, AUTH2.0, &user_id, &ClientInfo::default())
+        );
+        let _: i32 = c
+            .hset(
+                &session_key(AUTH.0),
+                SESSION_LAST_USED,
+                db::timestamp() - TTL.idle - 10,
+            )
+            .unwrap();
+        assert_eq!(Ok(()), sweep_expired_sessions(&mut c, &user_id, &TTL));
+        assert_eq!(Ok(false), c.hexists(SESSIONS_LIST, AUTH.0));
+        assert_eq!(Ok(false), c.exists(&session_key(AUTH.0)));
+        assert_eq!(Ok(()), validate_session(&mut c, &AUTH2, &TTL));
+    }
+
     #[test]
     fn get_user_id_test() {
         let client = Client::open(get_db_addr().as_str()).unwrap();
@@ -160,6 +390,7 @@ pub mod tests {
             Ok(false),
             c.exists(&user_sessions_key(&UserId(HASH_1.to_owned())))
         );
+        assert_eq!(Ok(false), c.exists(&session_key(AUTH.0)));
     }
 
     #[test]
@@ -168,9 +399,103 @@ pub mod tests {
         let mut c = client.get_connection().unwrap();
         store_session_for_test(&mut c, &AUTH);
         let u = UserId(HASH_1.to_owned());
-        assert_eq!(Ok(()), store_session(&mut c, "AUTH2", &u));
+        assert_eq!(
+            Ok(()),
+            store_session(&mut c, "AUTH2", &u, &ClientInfo::default())
+        );
         assert_eq!(Ok(()), delete_all_user_sessions(&mut c, &AUTH));
         assert_eq!(Ok(false), c.exists(SESSIONS_LIST));
         assert_eq!(Ok(false), c.exists(&user_sessions_key(&u)));
     }
+
+    #[test]
+    fn list_and_revoke_sessions_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let user_id = UserId(HASH_1.to_owned());
+        let client_info =
+            ClientInfo::new(Some("curl/7.68.0".to_owned()), Some("127.0.0.1".to_owned()));
+        assert_eq!(
+            Ok(()),
+            store_session(&mut c, AUTH.0, &user_id, &client_info)
+        );
+        assert_eq!(
+            Ok(()),
+            store_session(&mut c, AUTH2.0, &user_id, &ClientInfo::default())
+        );
+        assert_eq!(
+            Ok(()),
+            store_session(&mut c, "third", &user_id, &ClientInfo::default())
+        );
+
+        let sessions = list_sessions(&mut c, &AUTH).unwrap();
+        assert_eq!(3, sessions.len());
+        let current = sessions.iter().find(|s| s.is_current()).unwrap();
+        assert_eq!(
+            Ok(Some("curl/7.68.0".to_owned())),
+            c.hget(&session_key(AUTH.0), SESSION_USER_AGENT)
+        );
+        assert_eq!(
+            Ok(current.id().to_owned()),
+            c.hget(&session_key(AUTH.0), SESSION_ID)
+        );
+
+        let other_id = c.hget::<String>(&session_key(AUTH2.0), SESSION_ID).unwrap();
+        assert_eq!(Ok(()), revoke_session(&mut c, &AUTH, &other_id));
+        assert_eq!(Ok(false), c.hexists(SESSIONS_LIST, AUTH2.0));
+        assert_eq!(
+            Err(error::NOT_FOUND),
+            revoke_session(&mut c, &AUTH, &other_id).map_err(|e| e.status)
+        );
+
+        assert_eq!(Ok(()), revoke_other_sessions(&mut c, &AUTH));
+        let sessions = list_sessions(&mut c, &AUTH).unwrap();
+        assert_eq!(1, sessions.len());
+        assert!(sessions[0].is_current());
+        assert_eq!(Ok(()), validate_session(&mut c, &AUTH, &TTL));
+    }
+
+    #[test]
+    fn sessions_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let other = login_with(s, "pwd").unwrap();
+            let other_auth = Auth(&other.session_token);
+            assert_eq!(Ok(()), s.validate_session(&other_auth));
+            let third = login_with(s, "pwd").unwrap();
+            let third_auth = Auth(&third.session_token);
+            assert_eq!(Ok(3), s.list_sessions(&auth).map(|s| s.len()));
+            let third_session = s
+                .list_sessions(&third_auth)
+                .unwrap()
+                .into_iter()
+                .find(|s| s.is_current())
+                .unwrap();
+            assert_eq!(Ok(()), s.revoke_session(&auth, third_session.id()));
+            assert_eq!(
+                Err(error::UNAUTHORISED),
+                s.validate_session(&third_auth).map_err(|e| e.status)
+            );
+
+            let fourth = login_with(s, "pwd").unwrap();
+            assert_eq!(Ok(()), s.revoke_other_sessions(&other_auth));
+            assert_eq!(
+                Err(error::UNAUTHORISED),
+                s.validate_session(&Auth(&fourth.session_token))
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(error::UNAUTHORISED),
+                s.validate_session(&auth).map_err(|e| e.status)
+            );
+            assert_eq!(Ok(1), s.list_sessions(&other_auth).map(|s| s.len()));
+
+            assert_eq!(Ok(()), s.delete_session(&other_auth, &f.user_id));
+            assert_eq!(
+                Err(error::UNAUTHORISED),
+                s.validate_session(&other_auth).map_err(|e| e.status)
+            );
+        });
+    }
 }
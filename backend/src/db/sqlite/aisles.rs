@@ -0,0 +1,410 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+
+use crate::{
+    db::{self, sqlite},
+    error::*,
+    types::*,
+};
+
+pub fn get_aisle_store(c: &Connection, aisle_id: &AisleId) -> Result<StoreId> {
+    let store_id: Option<String> = c
+        .query_row(
+            "SELECT store_id FROM aisles WHERE aisle_id = ?1 AND trashed_at IS NULL",
+            params![**aisle_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    store_id
+        .map(StoreId::new)
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))
+}
+
+// `None` if the aisle doesn't exist or is in a trash, it comes without its products
+pub fn get_aisle(c: &Connection, aisle_id: &AisleId) -> Result<Option<(StoreId, Aisle)>> {
+    let aisle = c
+        .query_row(
+            "SELECT store_id, name, sort_weight, version FROM aisles
+             WHERE aisle_id = ?1 AND trashed_at IS NULL",
+            params![**aisle_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, f64>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            },
+        )
+        .optional()?;
+    Ok(aisle.map(|(store_id, name, sort_weight, version)| {
+        (
+            StoreId::new(store_id),
+            Aisle::new(aisle_id.to_string(), name, sort_weight as f32, vec![])
+                .with_version(version as u64),
+        )
+    }))
+}
+
+pub fn get_name(c: &Connection, aisle_id: &AisleId) -> Result<String> {
+    Ok(c.query_row(
+        "SELECT name FROM aisles WHERE aisle_id = ?1",
+        params![**aisle_id],
+        |row| row.get(0),
+    )?)
+}
+
+pub fn get_version(c: &Connection, aisle_id: &AisleId) -> Result<u64> {
+    let version: Option<i64> = c
+        .query_row(
+            "SELECT version FROM aisles WHERE aisle_id = ?1",
+            params![**aisle_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    version
+        .map(|v| v as u64)
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))
+}
+
+pub fn get_aisles_in_store(c: &Connection, store_id: &StoreId) -> Result<Vec<Aisle>> {
+    let mut stmt = c.prepare(
+        "SELECT aisle_id, name, sort_weight, version FROM aisles
+         WHERE store_id = ?1 AND trashed_at IS NULL ORDER BY rowid",
+    )?;
+    let aisles = stmt
+        .query_map(params![**store_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    aisles
+        .into_iter()
+        .map(|(id, name, sort_weight, version)| {
+            let aisle_id = AisleId(id);
+            let products = sqlite::products::get_products_in_aisle(c, &aisle_id)?;
+            Ok(
+                Aisle::new(aisle_id.to_string(), name, sort_weight as f32, products)
+                    .with_version(version as u64),
+            )
+        })
+        .collect()
+}
+
+fn find_max_weight_in_store(c: &Connection, store_id: &StoreId) -> Result<f32> {
+    let max: Option<f64> = c.query_row(
+        "SELECT MAX(sort_weight) FROM aisles WHERE store_id = ?1",
+        params![**store_id],
+        |row| row.get(0),
+    )?;
+    Ok(max.map_or(0f32, |w| w as f32))
+}
+
+pub fn save_aisle(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    name: &str,
+) -> Result<Aisle> {
+    let aisle_id = db::ids::get_next_aisle_id();
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let new_sort_weight = find_max_weight_in_store(c, &store_id)? + 1f32;
+    let tx = c.savepoint()?;
+    tx.execute(
+        "INSERT INTO aisles (aisle_id, store_id, name, sort_weight, owner_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            *aisle_id,
+            **store_id,
+            name,
+            f64::from(new_sort_weight),
+            *user_id
+        ],
+    )?;
+    sqlite::changes::record_change(&tx, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::AisleCreated,
+        &aisle_id,
+        Value::Null,
+        json!({ "name": name }),
+    )?;
+    tx.commit()?;
+    Ok(Aisle::new(
+        aisle_id.to_string(),
+        name.to_owned(),
+        new_sort_weight,
+        vec![],
+    ))
+}
+
+pub fn edit_aisle(
+    c: &mut Connection,
+    auth: &Auth,
+    aisle_id: &AisleId,
+    new_name: &str,
+    if_match: &IfMatch,
+) -> Result<()> {
+    let store_id = get_aisle_store(c, &aisle_id)?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let old_name = get_name(c, &aisle_id)?;
+    let version = get_version(c, &aisle_id)?;
+    if_match.verify(version)?;
+    let tx = c.savepoint()?;
+    sqlite::verify_not_modified(tx.execute(
+        "UPDATE aisles SET name = ?1, version = version + 1
+         WHERE aisle_id = ?2 AND version = ?3",
+        params![new_name, **aisle_id, version as i64],
+    )?)?;
+    sqlite::changes::record_change(&tx, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::AisleRenamed,
+        &aisle_id,
+        json!({ "name": old_name }),
+        json!({ "name": new_name }),
+    )?;
+    Ok(tx.commit()?)
+}
+
+// into the user's trash, with its products
+pub fn delete_aisle(
+    c: &mut Connection,
+    auth: &Auth,
+    aisle_id: &AisleId,
+    if_match: &IfMatch,
+) -> Result<()> {
+    let store_id = get_aisle_store(c, &aisle_id)?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let version = get_version(c, &aisle_id)?;
+    if_match.verify(version)?;
+    let tx = c.savepoint()?;
+    sqlite::verify_not_modified(tx.execute(
+        "UPDATE aisles SET trashed_by = ?1, trashed_at = ?2
+         WHERE aisle_id = ?3 AND version = ?4",
+        params![*user_id, db::timestamp(), **aisle_id, version as i64],
+    )?)?;
+    sqlite::changes::record_change(&tx, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::AisleDeleted,
+        &aisle_id,
+        json!({ "name": get_name(&tx, &aisle_id)? }),
+        Value::Null,
+    )?;
+    Ok(tx.commit()?)
+}
+
+// back in its store, the user must still be able to edit it
+pub fn restore_aisle(
+    c: &mut Connection,
+    auth: &Auth,
+    aisle_id: &AisleId,
+) -> Result<(StoreId, Aisle)> {
+    sqlite::trash::verify_in_trash(c, &auth, TrashedKind::Aisle, &aisle_id)?;
+    let store_id = StoreId::new(c.query_row(
+        "SELECT store_id FROM aisles WHERE aisle_id = ?1",
+        params![**aisle_id],
+        |row| row.get(0),
+    )?);
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let tx = c.savepoint()?;
+    tx.execute(
+        "UPDATE aisles SET trashed_by = NULL, trashed_at = NULL WHERE aisle_id = ?1",
+        params![**aisle_id],
+    )?;
+    let (_, aisle) =
+        get_aisle(&tx, &aisle_id)?.ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))?;
+    let products = sqlite::products::get_products_in_aisle(&tx, &aisle_id)?;
+    sqlite::changes::record_change(&tx, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+    for product in &products {
+        sqlite::changes::record_change(&tx, &store_id, ChangedItem::Product(product.id().0))?;
+    }
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::AisleRestored,
+        &aisle_id,
+        Value::Null,
+        json!({ "name": aisle.name() }),
+    )?;
+    tx.commit()?;
+    Ok((store_id, aisle.with_products(products)))
+}
+
+// to the end of `store_id` unless `sort_weight` is given, with its products
+pub fn move_aisle(
+    c: &mut Connection,
+    auth: &Auth,
+    aisle_id: &AisleId,
+    store_id: &StoreId,
+    sort_weight: Option<f32>,
+    if_match: &IfMatch,
+) -> Result<Aisle> {
+    let (from_store_id, aisle) =
+        get_aisle(c, &aisle_id)?.ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &from_store_id, Role::Editor)?;
+    sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    if_match.verify(aisle.version())?;
+    let tx = c.savepoint()?;
+    let new_sort_weight = match sort_weight {
+        Some(sort_weight) => sort_weight,
+        None => find_max_weight_in_store(&tx, &store_id)? + 1f32,
+    };
+    sqlite::verify_not_modified(tx.execute(
+        "UPDATE aisles SET store_id = ?1, sort_weight = ?2, version = version + 1
+         WHERE aisle_id = ?3 AND version = ?4",
+        params![
+            **store_id,
+            f64::from(new_sort_weight),
+            **aisle_id,
+            aisle.version() as i64
+        ],
+    )?)?;
+    let products = sqlite::products::get_products_in_aisle(&tx, &aisle_id)?;
+    sqlite::changes::record_change(
+        &tx,
+        &from_store_id,
+        ChangedItem::Aisle(aisle_id.to_string()),
+    )?;
+    if from_store_id != *store_id {
+        sqlite::changes::record_change(&tx, &store_id, ChangedItem::Aisle(aisle_id.to_string()))?;
+        for product in &products {
+            sqlite::changes::record_change(&tx, &store_id, ChangedItem::Product(product.id().0))?;
+        }
+    }
+    let before = json!({
+        "store_id": from_store_id.to_string(),
+        "sort_weight": aisle.sort_weight,
+    });
+    let after = json!({ "store_id": store_id.to_string(), "sort_weight": new_sort_weight });
+    sqlite::history::record(
+        &tx,
+        &from_store_id,
+        &user_id,
+        AuditAction::AisleMoved,
+        &aisle_id,
+        before.clone(),
+        after.clone(),
+    )?;
+    if from_store_id != *store_id {
+        sqlite::history::record(
+            &tx,
+            &store_id,
+            &user_id,
+            AuditAction::AisleMoved,
+            &aisle_id,
+            before,
+            after,
+        )?;
+    }
+    tx.commit()?;
+    let version = aisle.version() + 1;
+    let mut aisle = aisle.with_version(version).with_products(products);
+    aisle.sort_weight = new_sort_weight;
+    Ok(aisle)
+}
+
+pub fn edit_aisle_sort_weight(c: &Connection, auth: &Auth, data: &AisleItemWeight) -> Result<()> {
+    let aisle_id = AisleId(data.id.clone());
+    let (store_id, aisle) =
+        get_aisle(c, &aisle_id)?.ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    c.execute(
+        "UPDATE aisles SET sort_weight = ?1 WHERE aisle_id = ?2",
+        params![f64::from(data.sort_weight), *aisle_id],
+    )?;
+    sqlite::changes::record_change(c, &store_id, ChangedItem::Aisle(data.id.clone()))?;
+    sqlite::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::AisleReordered,
+        &aisle_id,
+        json!({ "sort_weight": aisle.sort_weight }),
+        json!({ "sort_weight": data.sort_weight }),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sqlite::tests::*, users::tests::*};
+
+    #[test]
+    fn save_aisle_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = sqlite::stores::save_store(&mut c, &auth, "store").unwrap();
+        let a1 = save_aisle(&mut c, &auth, &store_id, "aisle1").unwrap();
+        let a2 = save_aisle(&mut c, &auth, &store_id, "aisle2").unwrap();
+        assert!((a1.sort_weight - 1f32).abs() < std::f32::EPSILON);
+        assert!((a2.sort_weight - 2f32).abs() < std::f32::EPSILON);
+        assert_eq!(Ok(vec![a1, a2]), get_aisles_in_store(&c, &store_id));
+    }
+
+    #[test]
+    fn delete_aisle_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = sqlite::stores::save_store(&mut c, &auth, "store").unwrap();
+        let aisle_id = save_aisle(&mut c, &auth, &store_id, "aisle").unwrap().id();
+        sqlite::products::save_product(&mut c, &auth, "product", &aisle_id).unwrap();
+        assert_eq!(
+            Ok(()),
+            delete_aisle(&mut c, &auth, &aisle_id, &IfMatch::default())
+        );
+        assert_eq!(Ok(vec![]), get_aisles_in_store(&c, &store_id));
+        assert_eq!(Ok(None), get_aisle(&c, &aisle_id));
+
+        assert_eq!(
+            Ok(()),
+            sqlite::trash::purge_trash(&mut c, db::timestamp() + 1)
+        );
+        assert_eq!(
+            Ok(vec![]),
+            sqlite::products::get_products_in_aisle(&c, &aisle_id)
+        );
+    }
+
+    #[test]
+    fn move_aisle_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = sqlite::stores::save_store(&mut c, &auth, "store").unwrap();
+        let aisle_id = save_aisle(&mut c, &auth, &store_id, "aisle").unwrap().id();
+        let product = sqlite::products::save_product(&mut c, &auth, "product", &aisle_id).unwrap();
+        let store2 = sqlite::stores::save_store(&mut c, &auth, "store2").unwrap();
+        save_aisle(&mut c, &auth, &store2, "aisle2").unwrap();
+
+        let moved =
+            move_aisle(&mut c, &auth, &aisle_id, &store2, None, &IfMatch::default()).unwrap();
+        assert!((moved.sort_weight - 2f32).abs() < std::f32::EPSILON);
+        assert_eq!(FIRST_VERSION + 1, moved.version());
+        assert_eq!(Ok(vec![]), get_aisles_in_store(&c, &store_id));
+        assert_eq!(
+            Ok(store2.clone()),
+            sqlite::products::get_product_store(&c, &product.id())
+        );
+        let changes = sqlite::changes::store_changes(&c, &auth, &store2, 1).unwrap();
+        assert_eq!(vec![moved.clone()], changes.aisles);
+        assert_eq!(
+            vec![ProductChange::new(aisle_id.to_string(), product)],
+            changes.products
+        );
+    }
+}
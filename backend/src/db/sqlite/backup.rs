@@ -0,0 +1,222 @@
+use rusqlite::{params, types::Value as SqlValue, types::ValueRef, Connection, NO_PARAMS};
+use serde_json::{Number, Value};
+
+use crate::{
+    db::sqlite,
+    error::{self, Result, ServerError},
+    types::*,
+};
+
+fn table_exists(c: &Connection, table: &str) -> Result<bool> {
+    let count: i64 = c.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        params![table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn table_columns(c: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut stmt = c.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map(NO_PARAMS, |row| row.get(1))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(columns)
+}
+
+fn to_json(value: ValueRef) -> Result<Value> {
+    Ok(match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(f) => Number::from_f64(f).map(Value::Number).ok_or_else(|| {
+            ServerError::new(error::INTERNAL_ERROR, &format!("Invalid number: {}", f))
+        })?,
+        ValueRef::Text(t) => Value::from(
+            std::str::from_utf8(t)
+                .map_err(|e| ServerError::new(error::INTERNAL_ERROR, &e.to_string()))?,
+        ),
+        ValueRef::Blob(_) => {
+            return Err(ServerError::new(
+                error::INTERNAL_ERROR,
+                "Binary values are not supported",
+            ))
+        }
+    })
+}
+
+fn from_json(value: &Value) -> Result<SqlValue> {
+    Ok(match value {
+        Value::Null => SqlValue::Null,
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        v => {
+            return Err(ServerError::new(
+                error::INTERNAL_ERROR,
+                &format!("Unsupported value: {}", v),
+            ))
+        }
+    })
+}
+
+// left out of a backup without the sessions
+const SESSION_TABLES: &[&str] = &["sessions", "password_resets"];
+
+// the tables of the schema version the database is at, in insertion order
+pub fn backup(c: &Connection, with_sessions: bool) -> Result<BackupData> {
+    let mut tables = vec![];
+    for table in sqlite::TABLES {
+        if (!with_sessions && SESSION_TABLES.contains(table)) || !table_exists(c, table)? {
+            continue;
+        }
+        let mut stmt = c.prepare(&format!("SELECT * FROM {} ORDER BY rowid", table))?;
+        let columns: Vec<String> = stmt.column_names().into_iter().map(str::to_owned).collect();
+        let mut rows = stmt.query(NO_PARAMS)?;
+        let mut values = vec![];
+        while let Some(row) = rows.next()? {
+            values.push(
+                (0..columns.len())
+                    .map(|i| to_json(row.get_raw(i)))
+                    .collect::<Result<Vec<_>>>()?,
+            );
+        }
+        tables.push(SqliteTable::new((*table).to_owned(), columns, values));
+    }
+    Ok(BackupData::Sqlite {
+        schema_version: sqlite::get_schema_version(c)?,
+        tables,
+    })
+}
+
+// the database is migrated to the backup's schema version, it is up to the caller to
+// migrate it further
+pub fn restore(c: &mut Connection, data: &BackupData) -> Result<()> {
+    let (schema_version, tables) = match data {
+        BackupData::Sqlite {
+            schema_version,
+            tables,
+        } => (*schema_version, tables),
+        _ => {
+            return Err(ServerError::new(
+                error::INTERNAL_ERROR,
+                "The backup is not one of a SQLite database",
+            ))
+        }
+    };
+    if sqlite::get_schema_version(c)? > schema_version {
+        return Err(ServerError::new(
+            error::INTERNAL_ERROR,
+            "The database to restore into has a newer schema than the backup",
+        ));
+    }
+    sqlite::migrate_to(c, schema_version)?;
+    for table in sqlite::TABLES {
+        if table_exists(c, table)? {
+            let count: i64 = c.query_row(
+                &format!("SELECT COUNT(*) FROM {}", table),
+                NO_PARAMS,
+                |row| row.get(0),
+            )?;
+            if count > 0 {
+                return Err(ServerError::new(
+                    error::INTERNAL_ERROR,
+                    "The database to restore into is not empty",
+                ));
+            }
+        }
+    }
+
+    let tx = c.transaction()?;
+    for table in tables {
+        if !sqlite::TABLES.contains(&table.name.as_str()) {
+            return Err(ServerError::new(
+                error::INTERNAL_ERROR,
+                &format!("Unknown table: {}", table.name),
+            ));
+        }
+        let known = table_columns(&tx, &table.name)?;
+        if let Some(column) = table.columns.iter().find(|c| !known.contains(c)) {
+            return Err(ServerError::new(
+                error::INTERNAL_ERROR,
+                &format!("Unknown column: {}.{}", table.name, column),
+            ));
+        }
+        let mut stmt = tx.prepare(&format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            table.name,
+            table.columns.join(", "),
+            vec!["?"; table.columns.len()].join(", ")
+        ))?;
+        for row in &table.rows {
+            let values = row.iter().map(from_json).collect::<Result<Vec<_>>>()?;
+            stmt.execute(&values)?;
+        }
+    }
+    Ok(tx.commit()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        sessions::tests::TTL, sqlite::tests::open_for_test, sqlite::SqliteStorage, users, Storage,
+    };
+
+    #[test]
+    fn backup_restore_test() {
+        let mut c = open_for_test();
+        let mut s = SqliteStorage::new(&mut c, TTL);
+        let user = users::tests::gen_user();
+        let token = s.save_user(&user).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = s.save_store(&auth, "my store").unwrap();
+        let aisle_id = s.save_aisle(&auth, &store_id, "aisle").unwrap().id();
+        s.save_product(&auth, "product", &aisle_id).unwrap();
+        s.create_reset_token(&user.username, &user.email).unwrap();
+
+        let full = s.backup(true).unwrap();
+        let data = s.backup(false).unwrap();
+        assert_ne!(full, data);
+        match &data {
+            BackupData::Sqlite { tables, .. } => assert!(tables
+                .iter()
+                .all(|t| !SESSION_TABLES.contains(&t.name.as_str()))),
+            _ => panic!("not a SQLite backup"),
+        }
+        assert_eq!(
+            Err(error::INTERNAL_ERROR),
+            s.restore(&data).map_err(|e| e.status)
+        );
+
+        // a new file, not migrated yet
+        let mut target = Connection::open_in_memory().unwrap();
+        let mut t = SqliteStorage::new(&mut target, TTL);
+        assert_eq!(Ok(()), t.restore(&full));
+        assert_eq!(Ok(()), t.migrate());
+        assert_eq!(Ok(full), t.backup(true));
+        assert_eq!(Ok(()), t.validate_session(&auth));
+        assert_eq!(
+            Ok("my store".to_owned()),
+            t.list_store(&auth, &store_id).map(|s| s.name().to_owned())
+        );
+
+        let mut tampered = s.backup(true).unwrap();
+        if let BackupData::Sqlite { tables, .. } = &mut tampered {
+            tables[0].columns[0] = "x) SELECT 1; --".to_owned();
+        }
+        let mut target = Connection::open_in_memory().unwrap();
+        assert_eq!(
+            Err(error::INTERNAL_ERROR),
+            restore(&mut target, &tampered).map_err(|e| e.status)
+        );
+
+        let redis = BackupData::Redis { keys: vec![] };
+        let mut target = open_for_test();
+        assert_eq!(
+            Err(error::INTERNAL_ERROR),
+            restore(&mut target, &redis).map_err(|e| e.status)
+        );
+    }
+}
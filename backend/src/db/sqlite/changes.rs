@@ -0,0 +1,138 @@
+use rusqlite::{params, Connection};
+
+use crate::{
+    db::{self, sqlite},
+    error::*,
+    types::*,
+};
+
+// bump the revision of the store, dropping the oldest changes past `CHANGE_LOG_LEN`
+pub fn record_change(c: &Connection, store_id: &StoreId, item: ChangedItem) -> Result<()> {
+    c.execute(
+        "INSERT INTO store_changes (store_id, revision, item)
+         SELECT ?1, COALESCE(MAX(revision), 0) + 1, ?2 FROM store_changes WHERE store_id = ?1",
+        params![**store_id, item.to_string()],
+    )?;
+    c.execute(
+        "DELETE FROM store_changes WHERE store_id = ?1 AND revision <=
+         (SELECT MAX(revision) FROM store_changes WHERE store_id = ?1) - ?2",
+        params![**store_id, db::CHANGE_LOG_LEN as i64],
+    )?;
+    Ok(())
+}
+
+pub fn get_revision(c: &Connection, store_id: &StoreId) -> Result<u64> {
+    let revision: i64 = c.query_row(
+        "SELECT COALESCE(MAX(revision), 0) FROM store_changes WHERE store_id = ?1",
+        params![**store_id],
+        |row| row.get(0),
+    )?;
+    Ok(revision as u64)
+}
+
+pub fn store_changes(
+    c: &Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    since: u64,
+) -> Result<StoreChanges> {
+    sqlite::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    let revision = get_revision(c, &store_id)?;
+    let mut stmt = c.prepare("SELECT revision, item FROM store_changes WHERE store_id = ?1")?;
+    let retained = stmt
+        .query_map(params![**store_id], |row| {
+            Ok((row.get::<_, i64>(0)? as u64, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let retained = retained
+        .into_iter()
+        .map(|(revision, item)| Ok((revision, item.parse()?)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut changes = StoreChanges {
+        revision,
+        ..Default::default()
+    };
+    let items = match db::changed_since(revision, retained, since) {
+        Some(items) => items,
+        None => {
+            changes.resync = true;
+            return Ok(changes);
+        }
+    };
+    for item in items {
+        match item {
+            ChangedItem::Store => changes.name = Some(sqlite::stores::get_name(c, &store_id)?),
+            ChangedItem::Aisle(id) => match sqlite::aisles::get_aisle(c, &AisleId(id.clone()))? {
+                Some((aisle_store_id, aisle)) if aisle_store_id == *store_id => {
+                    changes.aisles.push(aisle)
+                }
+                _ => changes.deleted_aisles.push(id),
+            },
+            ChangedItem::Product(id) => {
+                match sqlite::products::get_product(c, &ProductId(id.clone()))? {
+                    // the aisle may be in a trash
+                    Some((aisle_id, product))
+                        if sqlite::aisles::get_aisle(c, &aisle_id)?
+                            .map_or(false, |(aisle_store_id, _)| aisle_store_id == *store_id) =>
+                    {
+                        changes
+                            .products
+                            .push(ProductChange::new(aisle_id.to_string(), product))
+                    }
+                    _ => changes.deleted_products.push(id),
+                }
+            }
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sqlite::tests::*, users::tests::*};
+
+    #[test]
+    fn store_changes_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = sqlite::stores::save_store(&mut c, &auth, "store").unwrap();
+        assert_eq!(Ok(0), get_revision(&c, &store_id));
+        let aisle = sqlite::aisles::save_aisle(&mut c, &auth, &store_id, "aisle").unwrap();
+        let product =
+            sqlite::products::save_product(&mut c, &auth, "product", &aisle.id()).unwrap();
+
+        let changes = store_changes(&c, &auth, &store_id, 0).unwrap();
+        assert_eq!(2, changes.revision);
+        assert_eq!(vec![aisle.clone()], changes.aisles);
+        assert_eq!(
+            vec![ProductChange::new(aisle.id().to_string(), product.clone())],
+            changes.products
+        );
+        assert_eq!(
+            Ok(()),
+            sqlite::aisles::delete_aisle(&mut c, &auth, &aisle.id(), &IfMatch::default())
+        );
+        let changes = store_changes(&c, &auth, &store_id, 0).unwrap();
+        assert_eq!(vec![aisle.id().to_string()], changes.deleted_aisles);
+        assert_eq!(vec![product.id().to_string()], changes.deleted_products);
+        assert_eq!(
+            Ok(3),
+            sqlite::stores::list_store(&c, &auth, &store_id).map(|s| s.revision())
+        );
+
+        for _ in 0..db::CHANGE_LOG_LEN {
+            sqlite::stores::edit_store(&mut c, &auth, &store_id, "renamed", &IfMatch::default())
+                .unwrap();
+        }
+        assert_eq!(
+            Ok(true),
+            store_changes(&c, &auth, &store_id, 2).map(|c| c.resync)
+        );
+        assert_eq!(
+            Ok(Some("renamed".to_owned())),
+            store_changes(&c, &auth, &store_id, 3).map(|c| c.name)
+        );
+    }
+}
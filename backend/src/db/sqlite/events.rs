@@ -0,0 +1,64 @@
+use rusqlite::{params, Connection};
+
+use crate::{
+    db::{self, sqlite},
+    error::*,
+    types::*,
+};
+
+// append to the user's change feed, dropping the oldest events past `EVENT_LOG_LEN`
+pub fn log_event(c: &mut Connection, user_id: &UserId, data: &str) -> Result<u64> {
+    let tx = c.savepoint()?;
+    // SQLite integers are signed
+    let id: i64 = tx.query_row(
+        "SELECT COALESCE(MAX(event_id), 0) + 1 FROM event_log WHERE user_id = ?1",
+        params![**user_id],
+        |row| row.get(0),
+    )?;
+    tx.execute(
+        "INSERT INTO event_log (user_id, event_id, data) VALUES (?1, ?2, ?3)",
+        params![**user_id, id, data],
+    )?;
+    tx.execute(
+        "DELETE FROM event_log WHERE user_id = ?1 AND event_id <= ?2",
+        params![**user_id, id - db::EVENT_LOG_LEN as i64],
+    )?;
+    tx.commit()?;
+    Ok(id as u64)
+}
+
+pub fn events_since(c: &Connection, auth: &Auth, resume_from: Option<u64>) -> Result<EventFeed> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let mut stmt =
+        c.prepare("SELECT event_id, data FROM event_log WHERE user_id = ?1 ORDER BY event_id")?;
+    let retained = stmt
+        .query_map(params![*user_id], |row| {
+            Ok(LoggedEvent::new(row.get::<_, i64>(0)? as u64, row.get(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(db::event_feed(user_id, retained, resume_from))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sqlite::tests::*, users::tests::*};
+
+    #[test]
+    fn event_log_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let user_id = UserId(token.user_id.clone());
+
+        for i in 0..db::EVENT_LOG_LEN + 2 {
+            assert_eq!(Ok(i as u64 + 1), log_event(&mut c, &user_id, "{}"));
+        }
+        let feed = events_since(&c, &auth, Some(2)).unwrap();
+        assert_eq!(false, feed.missed);
+        assert_eq!(db::EVENT_LOG_LEN as u64 + 2, feed.last_id);
+        assert_eq!(db::EVENT_LOG_LEN, feed.events.len());
+        let feed = events_since(&c, &auth, Some(1)).unwrap();
+        assert_eq!(true, feed.missed);
+    }
+}
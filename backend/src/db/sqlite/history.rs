@@ -0,0 +1,110 @@
+use rusqlite::{params, Connection};
+use serde_json::Value;
+
+use crate::{
+    db::{self, sqlite},
+    error::*,
+    types::*,
+};
+
+// append-only, the history lasts as long as the store
+pub fn record(
+    c: &Connection,
+    store_id: &StoreId,
+    user_id: &UserId,
+    action: AuditAction,
+    item_id: &str,
+    before: Value,
+    after: Value,
+) -> Result<()> {
+    let entry = AuditEntry::new(
+        user_id.to_string(),
+        db::timestamp(),
+        action,
+        item_id.to_owned(),
+        before,
+        after,
+    );
+    c.execute(
+        "INSERT INTO store_history (store_id, entry_id, data)
+         SELECT ?1, COALESCE(MAX(entry_id), 0) + 1, ?2 FROM store_history WHERE store_id = ?1",
+        params![**store_id, serde_json::to_string(&entry)?],
+    )?;
+    Ok(())
+}
+
+pub fn store_history(
+    c: &Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    before: Option<u64>,
+    limit: u32,
+) -> Result<StoreHistory> {
+    sqlite::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    let before = before.map_or(i64::MAX, |before| before as i64);
+    let mut stmt = c.prepare(
+        "SELECT entry_id, data FROM store_history WHERE store_id = ?1 AND entry_id < ?2
+         ORDER BY entry_id DESC LIMIT ?3",
+    )?;
+    let rows = stmt
+        .query_map(params![**store_id, before, limit], |row| {
+            Ok((row.get::<_, i64>(0)? as u64, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let entries = rows
+        .into_iter()
+        .map(|(id, data)| {
+            let mut entry: AuditEntry = serde_json::from_str(&data)?;
+            entry.id = id;
+            Ok(entry)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let next = match entries.last() {
+        Some(oldest) if oldest.id > 1 => Some(oldest.id),
+        _ => None,
+    };
+    Ok(StoreHistory::new(entries, next))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{history::tests::actions, sqlite::tests::*, users::tests::*};
+
+    #[test]
+    fn store_history_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = sqlite::stores::save_store(&mut c, &auth, "store").unwrap();
+        let aisle = sqlite::aisles::save_aisle(&mut c, &auth, &store_id, "aisle").unwrap();
+        let edit = EditProduct::new(None, Some(3), None, None);
+        let product = sqlite::products::save_product(&mut c, &auth, "milk", &aisle.id()).unwrap();
+        assert_eq!(
+            Ok(()),
+            sqlite::products::modify_product(
+                &mut c,
+                &auth,
+                &edit,
+                &product.id(),
+                &IfMatch::default()
+            )
+        );
+
+        let history = store_history(&c, &auth, &store_id, None, 1).unwrap();
+        assert_eq!(vec![AuditAction::ProductModified], actions(&history));
+        assert_eq!(Some(1), history.entries[0].before["quantity"].as_u64());
+        assert_eq!(Some(3), history.entries[0].after["quantity"].as_u64());
+        assert_eq!(Some(4), history.next);
+        let history = store_history(&c, &auth, &store_id, history.next, 10).unwrap();
+        assert_eq!(
+            vec![
+                AuditAction::ProductCreated,
+                AuditAction::AisleCreated,
+                AuditAction::StoreCreated
+            ],
+            actions(&history)
+        );
+        assert_eq!(None, history.next);
+    }
+}
@@ -0,0 +1,183 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    db::{self, sqlite},
+    error::*,
+    types::*,
+};
+
+fn invalid_invitation() -> ServerError {
+    ServerError::new(NOT_FOUND, "Invalid or expired invitation")
+}
+
+pub fn create_invitation(
+    c: &Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    role: Role,
+    ttl: i64,
+    max_uses: u32,
+) -> Result<Invitation> {
+    sqlite::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    if role == Role::Owner {
+        return Err(ServerError::new(
+            PERMISSION_DENIED,
+            "A store can only have one owner",
+        ));
+    }
+    let code = db::ids::gen_invitation_code(&mut rand::thread_rng());
+    let expires_at = db::timestamp() + ttl;
+    c.execute(
+        "INSERT INTO invitations (code, store_id, role, expires_at, max_uses, uses)
+         VALUES (?1, ?2, ?3, ?4, ?5, 0)",
+        params![code, **store_id, role.to_string(), expires_at, max_uses],
+    )?;
+    Ok(Invitation::new(
+        code,
+        store_id.to_string(),
+        role,
+        expires_at,
+        max_uses,
+        0,
+    ))
+}
+
+pub fn list_invitations(
+    c: &Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+) -> Result<Vec<Invitation>> {
+    sqlite::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    c.execute(
+        "DELETE FROM invitations WHERE store_id = ?1 AND expires_at < ?2",
+        params![**store_id, db::timestamp()],
+    )?;
+    let mut stmt = c.prepare(
+        "SELECT code, role, expires_at, max_uses, uses FROM invitations
+         WHERE store_id = ?1 ORDER BY expires_at",
+    )?;
+    let invitations = stmt
+        .query_map(params![**store_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, u32>(3)?,
+                row.get::<_, u32>(4)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    invitations
+        .into_iter()
+        .map(|(code, role, expires_at, max_uses, uses)| {
+            Ok(Invitation::new(
+                code,
+                store_id.to_string(),
+                role.parse()?,
+                expires_at,
+                max_uses,
+                uses,
+            ))
+        })
+        .collect()
+}
+
+pub fn revoke_invitation(
+    c: &Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    code: &str,
+) -> Result<()> {
+    sqlite::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    let deleted = c.execute(
+        "DELETE FROM invitations WHERE code = ?1 AND store_id = ?2",
+        params![code, **store_id],
+    )?;
+    if deleted == 0 {
+        Err(ServerError::new(NOT_FOUND, "Invitation not found"))
+    } else {
+        Ok(())
+    }
+}
+
+// join the store, unless already a member in which case the invitation isn't used
+pub fn redeem_invitation(c: &mut Connection, auth: &Auth, code: &str) -> Result<StoreLight> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let tx = c.savepoint()?;
+    let invitation: Option<(String, String, u32, u32)> = tx
+        .query_row(
+            "SELECT store_id, role, max_uses, uses FROM invitations
+             WHERE code = ?1 AND expires_at >= ?2",
+            params![code, db::timestamp()],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()?;
+    let (store_id, role, max_uses, uses) = invitation.ok_or_else(invalid_invitation)?;
+    let store_id = StoreId::new(store_id);
+    if sqlite::stores::get_role(&tx, &store_id, &user_id)?.is_none() {
+        if uses >= max_uses {
+            return Err(invalid_invitation());
+        }
+        if uses + 1 == max_uses {
+            tx.execute("DELETE FROM invitations WHERE code = ?1", params![code])?;
+        } else {
+            tx.execute(
+                "UPDATE invitations SET uses = uses + 1 WHERE code = ?1",
+                params![code],
+            )?;
+        }
+        sqlite::stores::add_member(&tx, &store_id, &user_id, role.parse()?)?;
+    }
+    let store = sqlite::stores::get_store_light(&tx, &store_id, &user_id)?;
+    tx.commit()?;
+    Ok(store)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sqlite::tests::*, users::tests::*};
+
+    #[test]
+    fn redeem_invitation_test() {
+        let mut c = open_for_test();
+        let owner = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let owner_auth = Auth(&owner.session_token);
+        let store_id = sqlite::stores::save_store(&mut c, &owner_auth, "store").unwrap();
+        let mut user = gen_user();
+        user.username = "tata".to_string();
+        let token = sqlite::users::save_user(&mut c, &user).unwrap();
+        let auth = Auth(&token.session_token);
+
+        let invitation =
+            create_invitation(&c, &owner_auth, &store_id, Role::Viewer, 60, 1).unwrap();
+        assert_eq!(
+            Ok(StoreLight::new(
+                "store".to_owned(),
+                store_id.to_string(),
+                Role::Viewer
+            )),
+            redeem_invitation(&mut c, &auth, invitation.code())
+        );
+        assert_eq!(Ok(vec![]), list_invitations(&c, &owner_auth, &store_id));
+
+        let invitation =
+            create_invitation(&c, &owner_auth, &store_id, Role::Viewer, -1, 1).unwrap();
+        let mut user = gen_user();
+        user.username = "titi".to_string();
+        let token = sqlite::users::save_user(&mut c, &user).unwrap();
+        assert_eq!(
+            Err(NOT_FOUND),
+            redeem_invitation(&mut c, &Auth(&token.session_token), invitation.code())
+                .map_err(|e| e.status)
+        );
+        assert_eq!(
+            Ok(()),
+            revoke_invitation(&c, &owner_auth, &store_id, invitation.code())
+        );
+        assert_eq!(
+            Err(NOT_FOUND),
+            revoke_invitation(&c, &owner_auth, &store_id, invitation.code()).map_err(|e| e.status)
+        );
+    }
+}
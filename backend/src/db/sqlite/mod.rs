@@ -0,0 +1,700 @@
+use std::ops::DerefMut;
+
+use derive_new::new;
+use log::*;
+use rusqlite::{Connection, NO_PARAMS};
+
+use crate::{
+    db::{self, sessions::SessionTtl, Storage},
+    error::{self, Result, ServerError},
+    types::*,
+};
+
+pub mod aisles;
+pub mod backup;
+pub mod changes;
+pub mod events;
+pub mod history;
+pub mod invitations;
+pub mod op_results;
+pub mod products;
+pub mod sessions;
+pub mod stores;
+pub mod trash;
+pub mod undo;
+pub mod users;
+
+// Each entry upgrades the schema by one version, the current version being
+// stored in `PRAGMA user_version`. Never modify a released entry, append a new one.
+const MIGRATIONS: &[&str] = &[
+    SCHEMA_V1, SCHEMA_V2, SCHEMA_V3, SCHEMA_V4, SCHEMA_V5, SCHEMA_V6, SCHEMA_V7, SCHEMA_V8,
+    SCHEMA_V9, SCHEMA_V10, SCHEMA_V11, SCHEMA_V12, SCHEMA_V13,
+];
+
+const SCHEMA_V1: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS users (
+        user_id TEXT PRIMARY KEY,
+        username TEXT NOT NULL,
+        norm_username TEXT NOT NULL UNIQUE,
+        email TEXT NOT NULL,
+        password TEXT NOT NULL,
+        salt_mail TEXT NOT NULL,
+        salt_password TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        auth TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS sessions_user ON sessions (user_id);
+    CREATE TABLE IF NOT EXISTS stores (
+        store_id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        owner_id TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS stores_owner ON stores (owner_id);
+    CREATE TABLE IF NOT EXISTS aisles (
+        aisle_id TEXT PRIMARY KEY,
+        store_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        sort_weight REAL NOT NULL,
+        owner_id TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS aisles_store ON aisles (store_id);
+    CREATE TABLE IF NOT EXISTS products (
+        product_id TEXT PRIMARY KEY,
+        aisle_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        quantity INTEGER NOT NULL,
+        is_done INTEGER NOT NULL,
+        unit INTEGER NOT NULL,
+        sort_weight REAL NOT NULL,
+        owner_id TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS products_aisle ON products (aisle_id);
+";
+
+// sessions expire, existing ones are considered created now
+const SCHEMA_V2: &str = "
+    ALTER TABLE sessions ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE sessions ADD COLUMN last_used INTEGER NOT NULL DEFAULT 0;
+    UPDATE sessions SET
+        created_at = CAST(strftime('%s', 'now') AS INTEGER),
+        last_used = CAST(strftime('%s', 'now') AS INTEGER);
+";
+
+// sessions get a public id, so they can be listed and revoked, and remember the client
+const SCHEMA_V3: &str = "
+    ALTER TABLE sessions ADD COLUMN session_id TEXT NOT NULL DEFAULT '';
+    ALTER TABLE sessions ADD COLUMN user_agent TEXT;
+    ALTER TABLE sessions ADD COLUMN ip TEXT;
+    UPDATE sessions SET session_id = lower(hex(randomblob(16)));
+";
+
+const SCHEMA_V4: &str = "
+    CREATE TABLE password_resets (
+        token TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+";
+
+// stores can be shared, their owner becomes their first member
+const SCHEMA_V5: &str = "
+    CREATE TABLE store_members (
+        store_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        role TEXT NOT NULL,
+        PRIMARY KEY (store_id, user_id)
+    );
+    CREATE INDEX store_members_user ON store_members (user_id);
+    INSERT INTO store_members (store_id, user_id, role)
+        SELECT store_id, owner_id, 'owner' FROM stores;
+";
+
+const SCHEMA_V6: &str = "
+    CREATE TABLE invitations (
+        code TEXT PRIMARY KEY,
+        store_id TEXT NOT NULL,
+        role TEXT NOT NULL,
+        expires_at INTEGER NOT NULL,
+        max_uses INTEGER NOT NULL,
+        uses INTEGER NOT NULL
+    );
+    CREATE INDEX invitations_store ON invitations (store_id);
+";
+
+const SCHEMA_V7: &str = "
+    CREATE TABLE event_log (
+        user_id TEXT NOT NULL,
+        event_id INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (user_id, event_id)
+    );
+";
+
+const SCHEMA_V8: &str = "
+    CREATE TABLE store_changes (
+        store_id TEXT NOT NULL,
+        revision INTEGER NOT NULL,
+        item TEXT NOT NULL,
+        PRIMARY KEY (store_id, revision)
+    );
+";
+
+const SCHEMA_V9: &str = "
+    CREATE TABLE op_results (
+        user_id TEXT NOT NULL,
+        op_id TEXT NOT NULL,
+        result TEXT NOT NULL,
+        expires_at INTEGER NOT NULL,
+        PRIMARY KEY (user_id, op_id)
+    );
+";
+
+// stores, aisles and products get a version, bumped by each edit
+const SCHEMA_V10: &str = "
+    ALTER TABLE stores ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE aisles ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE products ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+";
+
+// deleted stores, aisles and products stay in the trash of the user who deleted them
+const SCHEMA_V11: &str = "
+    ALTER TABLE stores ADD COLUMN trashed_by TEXT;
+    ALTER TABLE stores ADD COLUMN trashed_at INTEGER;
+    ALTER TABLE aisles ADD COLUMN trashed_by TEXT;
+    ALTER TABLE aisles ADD COLUMN trashed_at INTEGER;
+    ALTER TABLE products ADD COLUMN trashed_by TEXT;
+    ALTER TABLE products ADD COLUMN trashed_at INTEGER;
+    CREATE INDEX stores_trash ON stores (trashed_by);
+    CREATE INDEX aisles_trash ON aisles (trashed_by);
+    CREATE INDEX products_trash ON products (trashed_by);
+";
+
+const SCHEMA_V12: &str = "
+    CREATE TABLE undo_history (
+        user_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        op TEXT NOT NULL,
+        PRIMARY KEY (user_id, position)
+    );
+";
+
+const SCHEMA_V13: &str = "
+    CREATE TABLE store_history (
+        store_id TEXT NOT NULL,
+        entry_id INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (store_id, entry_id)
+    );
+";
+
+pub(crate) const TABLES: &[&str] = &[
+    "meta",
+    "users",
+    "sessions",
+    "password_resets",
+    "stores",
+    "store_members",
+    "invitations",
+    "store_changes",
+    "event_log",
+    "op_results",
+    "undo_history",
+    "store_history",
+    "aisles",
+    "products",
+];
+
+pub fn get_schema_version(c: &Connection) -> Result<u32> {
+    Ok(c.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?)
+}
+
+pub fn migrate(c: &mut Connection) -> Result<()> {
+    migrate_to(c, MIGRATIONS.len() as u32)
+}
+
+// only up to the schema version `target`, to restore a backup made by an older server
+pub fn migrate_to(c: &mut Connection, target: u32) -> Result<()> {
+    let version = get_schema_version(c)? as usize;
+    let newest = version.max(target as usize);
+    if newest > MIGRATIONS.len() {
+        return Err(ServerError::new(
+            error::INTERNAL_ERROR,
+            &format!(
+                "Database schema version {} is newer than this server ({})",
+                newest,
+                MIGRATIONS.len()
+            ),
+        ));
+    }
+    for (i, migration) in MIGRATIONS
+        .iter()
+        .enumerate()
+        .take(target as usize)
+        .skip(version)
+    {
+        info!("Migrating database schema to version {}", i + 1);
+        let tx = c.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1))?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+pub fn flush(c: &mut Connection) -> Result<()> {
+    let tx = c.transaction()?;
+    for table in TABLES {
+        tx.execute(&format!("DELETE FROM {}", table), NO_PARAMS)?;
+    }
+    Ok(tx.commit()?)
+}
+
+pub fn change_sort_weight(c: &mut Connection, auth: &Auth, data: &EditWeight) -> Result<()> {
+    let tx = c.savepoint()?;
+    if let Some(ref aisles) = data.aisles {
+        aisles
+            .iter()
+            .try_for_each(|w| aisles::edit_aisle_sort_weight(&tx, &auth, &w))?;
+    }
+    if let Some(ref products) = data.products {
+        products
+            .iter()
+            .try_for_each(|w| products::edit_product_sort_weight(&tx, &auth, &w))?;
+    }
+    Ok(tx.commit()?)
+}
+
+// `C` is anything giving access to a SQLite connection: a pooled connection on
+// the server, a plain `&mut Connection` in tests
+#[derive(new)]
+pub struct SqliteStorage<C> {
+    c: C,
+    ttl: SessionTtl,
+}
+
+impl<C: DerefMut<Target = Connection> + Send> Storage for SqliteStorage<C> {
+    fn migrate(&mut self) -> Result<()> {
+        migrate(&mut self.c)
+    }
+
+    fn save_user(&mut self, user: &User) -> Result<ConnectionToken> {
+        users::save_user(&mut self.c, user)
+    }
+
+    fn login(&mut self, auth_info: &AuthInfo, client: &ClientInfo) -> Result<ConnectionToken> {
+        users::login(&mut self.c, auth_info, client, &self.ttl)
+    }
+
+    fn delete_user(&mut self, auth: &Auth, user_id: &UserId) -> Result<()> {
+        users::delete_user(&mut self.c, auth, user_id)
+    }
+
+    fn change_password(
+        &mut self,
+        auth: &Auth,
+        user_id: &UserId,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<()> {
+        users::change_password(&self.c, auth, user_id, old_password, new_password)
+    }
+
+    fn create_reset_token(&mut self, username: &str, email: &str) -> Result<Option<String>> {
+        users::create_reset_token(&self.c, username, email)
+    }
+
+    fn reset_password(&mut self, token: &str, new_password: &str) -> Result<()> {
+        users::reset_password(&mut self.c, token, new_password)
+    }
+
+    fn validate_session(&mut self, auth: &Auth) -> Result<()> {
+        sessions::validate_session(&self.c, auth, &self.ttl)
+    }
+
+    fn check_session(&mut self, auth: &Auth) -> Result<()> {
+        sessions::check_session(&self.c, auth, &self.ttl)
+    }
+
+    fn delete_session(&mut self, auth: &Auth, user_id: &UserId) -> Result<()> {
+        sessions::delete_session(&self.c, auth, user_id)
+    }
+
+    fn list_sessions(&mut self, auth: &Auth) -> Result<Vec<SessionInfo>> {
+        sessions::list_sessions(&self.c, auth)
+    }
+
+    fn revoke_session(&mut self, auth: &Auth, session_id: &str) -> Result<()> {
+        sessions::revoke_session(&self.c, auth, session_id)
+    }
+
+    fn revoke_other_sessions(&mut self, auth: &Auth) -> Result<()> {
+        sessions::revoke_other_sessions(&self.c, auth)
+    }
+
+    fn save_store(&mut self, auth: &Auth, name: &str) -> Result<StoreId> {
+        stores::save_store(&mut self.c, auth, name)
+    }
+
+    fn edit_store(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        new_name: &str,
+        if_match: &IfMatch,
+    ) -> Result<()> {
+        stores::edit_store(&mut self.c, auth, store_id, new_name, if_match)
+    }
+
+    fn get_all_stores(&mut self, auth: &Auth) -> Result<Vec<StoreLight>> {
+        stores::get_all_stores(&self.c, auth)
+    }
+
+    fn list_store(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Store> {
+        stores::list_store(&self.c, auth, store_id)
+    }
+
+    fn store_changes(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        since: u64,
+    ) -> Result<StoreChanges> {
+        changes::store_changes(&self.c, auth, store_id, since)
+    }
+
+    fn store_history(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        before: Option<u64>,
+        limit: u32,
+    ) -> Result<StoreHistory> {
+        history::store_history(&self.c, auth, store_id, before, limit)
+    }
+
+    fn import_store(&mut self, auth: &Auth, store: &ExportedStore) -> Result<StoreId> {
+        stores::import_store(&mut self.c, auth, store)
+    }
+
+    fn delete_store(&mut self, auth: &Auth, store_id: &StoreId, if_match: &IfMatch) -> Result<()> {
+        stores::delete_store(&mut self.c, auth, store_id, if_match)
+    }
+
+    fn duplicate_store(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        name: Option<&str>,
+        reset_done: bool,
+    ) -> Result<StoreId> {
+        stores::duplicate_store(&mut self.c, auth, store_id, name, reset_done)
+    }
+
+    fn verify_role(&mut self, auth: &Auth, store_id: &StoreId, role: Role) -> Result<()> {
+        verify_permission_auth(&self.c, auth, store_id, role).map(|_| ())
+    }
+
+    fn aisle_store(&mut self, aisle_id: &AisleId) -> Result<StoreId> {
+        aisles::get_aisle_store(&self.c, aisle_id)
+    }
+
+    fn product_store(&mut self, product_id: &ProductId) -> Result<StoreId> {
+        products::get_product_store(&self.c, product_id)
+    }
+
+    fn store_name(&mut self, store_id: &StoreId) -> Result<String> {
+        stores::get_name(&self.c, store_id)
+    }
+
+    fn get_aisle(&mut self, aisle_id: &AisleId) -> Result<Aisle> {
+        aisles::get_aisle(&self.c, aisle_id)?
+            .map(|(_, aisle)| aisle)
+            .ok_or_else(|| ServerError::new(error::NOT_FOUND, "Aisle not found"))
+    }
+
+    fn get_product(&mut self, product_id: &ProductId) -> Result<(AisleId, Product)> {
+        products::get_product(&self.c, product_id)?
+            .ok_or_else(|| ServerError::new(error::NOT_FOUND, "Product not found"))
+    }
+
+    fn store_version(&mut self, store_id: &StoreId) -> Result<u64> {
+        stores::get_version(&self.c, store_id)
+    }
+
+    fn aisle_version(&mut self, aisle_id: &AisleId) -> Result<u64> {
+        aisles::get_version(&self.c, aisle_id)
+    }
+
+    fn product_version(&mut self, product_id: &ProductId) -> Result<u64> {
+        products::get_version(&self.c, product_id)
+    }
+
+    fn member_ids(&mut self, store_id: &StoreId) -> Result<Vec<UserId>> {
+        stores::get_member_ids(&self.c, store_id)
+    }
+
+    fn list_members(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Vec<StoreMember>> {
+        stores::list_members(&self.c, auth, store_id)
+    }
+
+    fn set_member(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        username: &str,
+        role: Role,
+    ) -> Result<()> {
+        stores::set_member(&self.c, auth, store_id, username, role)
+    }
+
+    fn remove_member(&mut self, auth: &Auth, store_id: &StoreId, user_id: &UserId) -> Result<()> {
+        stores::remove_member(&self.c, auth, store_id, user_id)
+    }
+
+    fn create_invitation(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        role: Role,
+        ttl: i64,
+        max_uses: u32,
+    ) -> Result<Invitation> {
+        invitations::create_invitation(&self.c, auth, store_id, role, ttl, max_uses)
+    }
+
+    fn list_invitations(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Vec<Invitation>> {
+        invitations::list_invitations(&self.c, auth, store_id)
+    }
+
+    fn revoke_invitation(&mut self, auth: &Auth, store_id: &StoreId, code: &str) -> Result<()> {
+        invitations::revoke_invitation(&self.c, auth, store_id, code)
+    }
+
+    fn redeem_invitation(&mut self, auth: &Auth, code: &str) -> Result<StoreLight> {
+        invitations::redeem_invitation(&mut self.c, auth, code)
+    }
+
+    fn log_event(&mut self, user_id: &UserId, data: &str) -> Result<u64> {
+        events::log_event(&mut self.c, user_id, data)
+    }
+
+    fn events_since(&mut self, auth: &Auth, resume_from: Option<u64>) -> Result<EventFeed> {
+        events::events_since(&self.c, auth, resume_from)
+    }
+
+    fn op_result(&mut self, auth: &Auth, op_id: &str) -> Result<Option<String>> {
+        op_results::get_op_result(&self.c, auth, op_id)
+    }
+
+    fn begin_op(&mut self, auth: &Auth, op_id: &str) -> Result<bool> {
+        op_results::begin_op(&self.c, auth, op_id)
+    }
+
+    fn end_op(&mut self, auth: &Auth, op_id: &str, result: Option<&str>) -> Result<()> {
+        op_results::end_op(&self.c, auth, op_id, result)
+    }
+
+    fn push_undo(&mut self, auth: &Auth, op: &str) -> Result<()> {
+        undo::push_undo(&mut self.c, auth, op)
+    }
+
+    fn pop_undo(&mut self, auth: &Auth) -> Result<Option<String>> {
+        undo::pop_undo(&mut self.c, auth)
+    }
+
+    fn save_aisle(&mut self, auth: &Auth, store_id: &StoreId, name: &str) -> Result<Aisle> {
+        aisles::save_aisle(&mut self.c, auth, store_id, name)
+    }
+
+    fn edit_aisle(
+        &mut self,
+        auth: &Auth,
+        aisle_id: &AisleId,
+        new_name: &str,
+        if_match: &IfMatch,
+    ) -> Result<()> {
+        aisles::edit_aisle(&mut self.c, auth, aisle_id, new_name, if_match)
+    }
+
+    fn delete_aisle(&mut self, auth: &Auth, aisle_id: &AisleId, if_match: &IfMatch) -> Result<()> {
+        aisles::delete_aisle(&mut self.c, auth, aisle_id, if_match)
+    }
+
+    fn move_aisle(
+        &mut self,
+        auth: &Auth,
+        aisle_id: &AisleId,
+        store_id: &StoreId,
+        sort_weight: Option<f32>,
+        if_match: &IfMatch,
+    ) -> Result<Aisle> {
+        aisles::move_aisle(&mut self.c, auth, aisle_id, store_id, sort_weight, if_match)
+    }
+
+    fn save_product(&mut self, auth: &Auth, name: &str, aisle_id: &AisleId) -> Result<Product> {
+        products::save_product(&mut self.c, auth, name, aisle_id)
+    }
+
+    fn modify_product(
+        &mut self,
+        auth: &Auth,
+        edit_data: &EditProduct,
+        product_id: &ProductId,
+        if_match: &IfMatch,
+    ) -> Result<()> {
+        products::modify_product(&mut self.c, auth, edit_data, product_id, if_match)
+    }
+
+    fn delete_product(
+        &mut self,
+        auth: &Auth,
+        product_id: &ProductId,
+        if_match: &IfMatch,
+    ) -> Result<()> {
+        products::delete_product(&mut self.c, auth, product_id, if_match)
+    }
+
+    fn move_product(
+        &mut self,
+        auth: &Auth,
+        product_id: &ProductId,
+        aisle_id: &AisleId,
+        sort_weight: Option<f32>,
+        if_match: &IfMatch,
+    ) -> Result<Product> {
+        products::move_product(
+            &mut self.c,
+            auth,
+            product_id,
+            aisle_id,
+            sort_weight,
+            if_match,
+        )
+    }
+
+    fn change_sort_weight(&mut self, auth: &Auth, data: &EditWeight) -> Result<()> {
+        change_sort_weight(&mut self.c, auth, data)
+    }
+
+    fn set_store_done(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        is_done: bool,
+    ) -> Result<Vec<ProductId>> {
+        products::set_store_done(&mut self.c, auth, store_id, is_done)
+    }
+
+    fn set_aisle_done(
+        &mut self,
+        auth: &Auth,
+        aisle_id: &AisleId,
+        is_done: bool,
+    ) -> Result<Vec<ProductId>> {
+        products::set_aisle_done(&mut self.c, auth, aisle_id, is_done)
+    }
+
+    fn set_products_done(
+        &mut self,
+        auth: &Auth,
+        product_ids: &[ProductId],
+        is_done: bool,
+    ) -> Result<Vec<ProductId>> {
+        products::set_products_done(&mut self.c, auth, product_ids, is_done)
+    }
+
+    fn delete_done_products(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Vec<ProductId>> {
+        products::delete_done_products(&mut self.c, auth, store_id)
+    }
+
+    fn list_trash(&mut self, auth: &Auth) -> Result<Vec<TrashedItem>> {
+        trash::list_trash(&self.c, auth)
+    }
+
+    fn restore_store(&mut self, auth: &Auth, store_id: &StoreId) -> Result<()> {
+        stores::restore_store(&self.c, auth, store_id)
+    }
+
+    fn restore_aisle(&mut self, auth: &Auth, aisle_id: &AisleId) -> Result<(StoreId, Aisle)> {
+        aisles::restore_aisle(&mut self.c, auth, aisle_id)
+    }
+
+    fn restore_product(
+        &mut self,
+        auth: &Auth,
+        product_id: &ProductId,
+    ) -> Result<(AisleId, Product)> {
+        products::restore_product(&mut self.c, auth, product_id)
+    }
+
+    fn purge_trash(&mut self, before: i64) -> Result<()> {
+        trash::purge_trash(&mut self.c, before)
+    }
+
+    fn backup(&mut self, with_sessions: bool) -> Result<BackupData> {
+        backup::backup(&self.c, with_sessions)
+    }
+
+    fn restore(&mut self, data: &BackupData) -> Result<()> {
+        backup::restore(&mut self.c, data)
+    }
+
+    fn nuke(&mut self) -> Result<()> {
+        flush(&mut self.c)
+    }
+}
+
+pub(crate) fn verify_permission_auth(
+    c: &Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    wanted: Role,
+) -> Result<UserId> {
+    let user_id = sessions::get_user_id(c, &auth)?;
+    db::verify_permission(wanted, stores::get_role(c, &store_id, &user_id)?)?;
+    Ok(user_id)
+}
+
+// `updated` is the number of rows changed by an update guarded by the version the item
+// had when read, none if it was modified in between
+pub(crate) fn verify_not_modified(updated: usize) -> Result<()> {
+    if updated == 0 {
+        Err(ServerError::new(
+            error::VERSION_MISMATCH,
+            "The item was modified since it was read",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub fn open_for_test() -> Connection {
+        let mut c = Connection::open_in_memory().unwrap();
+        assert_eq!(Ok(()), migrate(&mut c));
+        c
+    }
+
+    #[test]
+    fn migrate_test() {
+        let mut c = open_for_test();
+        assert_eq!(Ok(MIGRATIONS.len() as u32), get_schema_version(&c));
+        assert_eq!(Ok(()), migrate(&mut c));
+        assert_eq!(Ok(MIGRATIONS.len() as u32), get_schema_version(&c));
+
+        c.execute_batch("PRAGMA user_version = 1000").unwrap();
+        assert_eq!(
+            Err(error::INTERNAL_ERROR),
+            migrate(&mut c).map_err(|e| e.status)
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    db::{self, sqlite},
+    error::*,
+    types::*,
+};
+
+// `None` if the operation was never applied or its result has expired
+pub fn get_op_result(c: &Connection, auth: &Auth, op_id: &str) -> Result<Option<String>> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    Ok(c.query_row(
+        "SELECT result FROM op_results WHERE user_id = ?1 AND op_id = ?2 AND expires_at >= ?3",
+        params![*user_id, op_id, db::timestamp()],
+        |row| row.get(0),
+    )
+    .optional()?)
+}
+
+// Opens the savepoint `batch_op`, which holds the writes of the operation and its result
+// until `end_op`. `false` if another request already applied `op_id`.
+pub fn begin_op(c: &Connection, auth: &Auth, op_id: &str) -> Result<bool> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    c.execute_batch("SAVEPOINT batch_op")?;
+    let reserved = reserve(c, &user_id, op_id);
+    if reserved.as_ref().map_or(true, |reserved| !reserved) {
+        rollback(c)?;
+    }
+    reserved
+}
+
+fn reserve(c: &Connection, user_id: &UserId, op_id: &str) -> Result<bool> {
+    let now = db::timestamp();
+    c.execute(
+        "DELETE FROM op_results WHERE user_id = ?1 AND expires_at < ?2",
+        params![**user_id, now],
+    )?;
+    // the result is set by `end_op`, in the same transaction
+    let reserved = c.execute(
+        "INSERT OR IGNORE INTO op_results (user_id, op_id, result, expires_at)
+         VALUES (?1, ?2, '', ?3)",
+        params![**user_id, op_id, now + db::OP_PENDING_TTL],
+    )?;
+    Ok(reserved == 1)
+}
+
+// commits the writes of the operation with `result`, or drops them all without it
+pub fn end_op(c: &Connection, auth: &Auth, op_id: &str, result: Option<&str>) -> Result<()> {
+    if let Some(result) = result {
+        let saved = save_result(c, auth, op_id, result);
+        if saved.is_err() {
+            rollback(c)?;
+        }
+        return saved;
+    }
+    rollback(c)
+}
+
+fn rollback(c: &Connection) -> Result<()> {
+    Ok(c.execute_batch("ROLLBACK TO batch_op; RELEASE batch_op")?)
+}
+
+fn save_result(c: &Connection, auth: &Auth, op_id: &str, result: &str) -> Result<()> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    c.execute(
+        "UPDATE op_results SET result = ?1, expires_at = ?2 WHERE user_id = ?3 AND op_id = ?4",
+        params![result, db::timestamp() + db::OP_RESULT_TTL, *user_id, op_id],
+    )?;
+    Ok(c.execute_batch("RELEASE batch_op")?)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sqlite::tests::*, users::tests::*};
+
+    #[test]
+    fn op_result_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+
+        assert_eq!(Ok(None), get_op_result(&c, &auth, "op1"));
+        assert_eq!(Ok(true), begin_op(&c, &auth, "op1"));
+        assert_eq!(Ok(()), end_op(&c, &auth, "op1", Some("{}")));
+        assert_eq!(Ok(Some("{}".to_owned())), get_op_result(&c, &auth, "op1"));
+        assert_eq!(Ok(false), begin_op(&c, &auth, "op1"));
+        // the writes of a failed operation are dropped
+        assert_eq!(Ok(true), begin_op(&c, &auth, "op2"));
+        sqlite::undo::push_undo(&mut c, &auth, "op").unwrap();
+        assert_eq!(Ok(()), end_op(&c, &auth, "op2", None));
+        assert_eq!(Ok(None), sqlite::undo::pop_undo(&mut c, &auth));
+        assert_eq!(Ok(None), get_op_result(&c, &auth, "op2"));
+        // expired results are ignored, then cleaned up
+        c.execute("UPDATE op_results SET expires_at = 0", params![])
+            .unwrap();
+        assert_eq!(Ok(None), get_op_result(&c, &auth, "op1"));
+        assert_eq!(Ok(true), begin_op(&c, &auth, "op2"));
+        assert_eq!(Ok(()), end_op(&c, &auth, "op2", Some("{}")));
+        let remaining: u32 = c
+            .query_row("SELECT COUNT(*) FROM op_results", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(1, remaining);
+    }
+
+    #[test]
+    fn pending_op_expires_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let expires_at = |c: &Connection| -> i64 {
+            c.query_row("SELECT expires_at FROM op_results", params![], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+
+        // held shortly while applied, a crashed request doesn't hold it for long
+        assert_eq!(Ok(true), begin_op(&c, &auth, "op"));
+        assert!(expires_at(&c) <= db::timestamp() + db::OP_PENDING_TTL);
+        assert_eq!(Ok(()), end_op(&c, &auth, "op", Some("{}")));
+        assert!(expires_at(&c) > db::timestamp() + db::OP_PENDING_TTL);
+    }
+}
@@ -0,0 +1,588 @@
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+use serde_json::{json, Value};
+
+use crate::{
+    db::{self, sqlite},
+    error::*,
+    types::*,
+};
+
+pub fn get_product_store(c: &Connection, id: &ProductId) -> Result<StoreId> {
+    let store_id: Option<String> = c
+        .query_row(
+            "SELECT a.store_id FROM products p JOIN aisles a ON a.aisle_id = p.aisle_id
+             WHERE p.product_id = ?1 AND p.trashed_at IS NULL AND a.trashed_at IS NULL",
+            params![**id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    store_id
+        .map(StoreId::new)
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Product not found"))
+}
+
+fn read_product(row: &rusqlite::Row) -> rusqlite::Result<Product> {
+    Ok(Product::new(
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        Unit::from(row.get::<_, u32>(4)?),
+        row.get::<_, f64>(5)? as f32,
+    )
+    .with_version(row.get::<_, i64>(6)? as u64))
+}
+
+pub fn get_name(c: &Connection, product_id: &ProductId) -> Result<String> {
+    Ok(c.query_row(
+        "SELECT name FROM products WHERE product_id = ?1",
+        params![**product_id],
+        |row| row.get(0),
+    )?)
+}
+
+pub fn get_version(c: &Connection, product_id: &ProductId) -> Result<u64> {
+    let version: Option<i64> = c
+        .query_row(
+            "SELECT version FROM products WHERE product_id = ?1",
+            params![**product_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    version
+        .map(|v| v as u64)
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Product not found"))
+}
+
+// `None` if the product doesn't exist or is in a trash
+pub fn get_product(c: &Connection, product_id: &ProductId) -> Result<Option<(AisleId, Product)>> {
+    let product = c
+        .query_row(
+            "SELECT product_id, name, quantity, is_done, unit, sort_weight, version, aisle_id
+             FROM products WHERE product_id = ?1 AND trashed_at IS NULL",
+            params![**product_id],
+            |row| Ok((AisleId(row.get(7)?), read_product(row)?)),
+        )
+        .optional()?;
+    Ok(product)
+}
+
+pub fn get_products_in_aisle(c: &Connection, aisle_id: &AisleId) -> Result<Vec<Product>> {
+    let mut stmt = c.prepare(
+        "SELECT product_id, name, quantity, is_done, unit, sort_weight, version
+         FROM products WHERE aisle_id = ?1 AND trashed_at IS NULL ORDER BY rowid",
+    )?;
+    let products = stmt
+        .query_map(params![**aisle_id], read_product)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(products)
+}
+
+fn find_max_weight_in_aisle(c: &Connection, aisle_id: &AisleId) -> Result<f32> {
+    let max: Option<f64> = c.query_row(
+        "SELECT MAX(sort_weight) FROM products WHERE aisle_id = ?1",
+        params![**aisle_id],
+        |row| row.get(0),
+    )?;
+    Ok(max.map_or(0f32, |w| w as f32))
+}
+
+pub fn save_product(
+    c: &mut Connection,
+    auth: &Auth,
+    name: &str,
+    aisle_id: &AisleId,
+) -> Result<Product> {
+    let store_id = sqlite::aisles::get_aisle_store(c, &aisle_id)?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let prod_id = db::ids::get_next_product_id();
+    let new_sort_weight = find_max_weight_in_aisle(c, &aisle_id)? + 1f32;
+    let tx = c.savepoint()?;
+    tx.execute(
+        "INSERT INTO products
+         (product_id, aisle_id, name, quantity, is_done, unit, sort_weight, owner_id)
+         VALUES (?1, ?2, ?3, 1, 0, ?4, ?5, ?6)",
+        params![
+            *prod_id,
+            **aisle_id,
+            name,
+            u32::from(Unit::Unit),
+            f64::from(new_sort_weight),
+            *user_id
+        ],
+    )?;
+    sqlite::changes::record_change(&tx, &store_id, ChangedItem::Product(prod_id.to_string()))?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::ProductCreated,
+        &prod_id,
+        Value::Null,
+        json!({ "name": name, "aisle_id": aisle_id.to_string() }),
+    )?;
+    tx.commit()?;
+    Ok(Product::new(
+        prod_id.to_string(),
+        name.to_owned(),
+        1,
+        false,
+        Unit::Unit,
+        new_sort_weight,
+    ))
+}
+
+pub fn modify_product(
+    c: &mut Connection,
+    auth: &Auth,
+    edit_data: &EditProduct,
+    product_id: &ProductId,
+    if_match: &IfMatch,
+) -> Result<()> {
+    let (_, product) = get_product(c, &product_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Product not found"))?;
+    let store_id = get_product_store(c, &product_id)?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    if_match.verify(product.version())?;
+    let tx = c.savepoint()?;
+    sqlite::verify_not_modified(tx.execute(
+        "UPDATE products SET version = version + 1 WHERE product_id = ?1 AND version = ?2",
+        params![**product_id, product.version() as i64],
+    )?)?;
+    if let Some(ref new_name) = edit_data.name {
+        tx.execute(
+            "UPDATE products SET name = ?1 WHERE product_id = ?2",
+            params![new_name, **product_id],
+        )?;
+    }
+    if let Some(qty) = edit_data.quantity {
+        tx.execute(
+            "UPDATE products SET quantity = ?1 WHERE product_id = ?2",
+            params![qty, **product_id],
+        )?;
+    }
+    if let Some(is_done) = edit_data.is_done {
+        tx.execute(
+            "UPDATE products SET is_done = ?1 WHERE product_id = ?2",
+            params![is_done, **product_id],
+        )?;
+    }
+    if let Some(unit) = &edit_data.unit {
+        tx.execute(
+            "UPDATE products SET unit = ?1 WHERE product_id = ?2",
+            params![u32::from(unit.clone()), **product_id],
+        )?;
+    }
+    sqlite::changes::record_change(&tx, &store_id, ChangedItem::Product(product_id.to_string()))?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::ProductModified,
+        &product_id,
+        serde_json::to_value(&edit_data.previous(&product))?,
+        serde_json::to_value(&edit_data)?,
+    )?;
+    Ok(tx.commit()?)
+}
+
+// into the user's trash
+pub fn delete_product(
+    c: &mut Connection,
+    auth: &Auth,
+    product_id: &ProductId,
+    if_match: &IfMatch,
+) -> Result<()> {
+    let store_id = get_product_store(c, &product_id)?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let version = get_version(c, &product_id)?;
+    if_match.verify(version)?;
+    let tx = c.savepoint()?;
+    sqlite::verify_not_modified(tx.execute(
+        "UPDATE products SET trashed_by = ?1, trashed_at = ?2
+         WHERE product_id = ?3 AND version = ?4",
+        params![*user_id, db::timestamp(), **product_id, version as i64],
+    )?)?;
+    sqlite::changes::record_change(&tx, &store_id, ChangedItem::Product(product_id.to_string()))?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::ProductDeleted,
+        &product_id,
+        json!({ "name": get_name(&tx, &product_id)? }),
+        Value::Null,
+    )?;
+    Ok(tx.commit()?)
+}
+
+// back in its aisle, the user must still be able to edit it
+pub fn restore_product(
+    c: &mut Connection,
+    auth: &Auth,
+    product_id: &ProductId,
+) -> Result<(AisleId, Product)> {
+    sqlite::trash::verify_in_trash(c, &auth, TrashedKind::Product, &product_id)?;
+    let aisle_id = AisleId(c.query_row(
+        "SELECT aisle_id FROM products WHERE product_id = ?1",
+        params![**product_id],
+        |row| row.get(0),
+    )?);
+    let (store_id, _) = sqlite::aisles::get_aisle(c, &aisle_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let tx = c.savepoint()?;
+    tx.execute(
+        "UPDATE products SET trashed_by = NULL, trashed_at = NULL WHERE product_id = ?1",
+        params![**product_id],
+    )?;
+    sqlite::changes::record_change(&tx, &store_id, ChangedItem::Product(product_id.to_string()))?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::ProductRestored,
+        &product_id,
+        Value::Null,
+        json!({ "name": get_name(&tx, &product_id)? }),
+    )?;
+    let product = get_product(&tx, &product_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Product not found"))?;
+    tx.commit()?;
+    Ok(product)
+}
+
+fn select_product_ids(c: &Connection, sql: &str, params: &[&dyn ToSql]) -> Result<Vec<ProductId>> {
+    let mut stmt = c.prepare(sql)?;
+    let product_ids = stmt
+        .query_map(params, |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(product_ids.into_iter().map(ProductId).collect())
+}
+
+fn set_done(
+    c: &Connection,
+    user_id: &UserId,
+    store_id: &StoreId,
+    product_ids: &[ProductId],
+    is_done: bool,
+) -> Result<()> {
+    for product_id in product_ids {
+        c.execute(
+            "UPDATE products SET is_done = ?1, version = version + 1 WHERE product_id = ?2",
+            params![is_done, **product_id],
+        )?;
+        sqlite::changes::record_change(c, &store_id, ChangedItem::Product(product_id.to_string()))?;
+        sqlite::history::record(
+            c,
+            &store_id,
+            &user_id,
+            AuditAction::ProductModified,
+            &product_id,
+            json!({ "is_done": !is_done }),
+            json!({ "is_done": is_done }),
+        )?;
+    }
+    Ok(())
+}
+
+// check or uncheck every product of the store, returns the products that changed
+pub fn set_store_done(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    is_done: bool,
+) -> Result<Vec<ProductId>> {
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let tx = c.savepoint()?;
+    let changed = select_product_ids(
+        &tx,
+        "SELECT p.product_id FROM products p JOIN aisles a ON a.aisle_id = p.aisle_id
+         WHERE a.store_id = ?1 AND p.is_done != ?2
+         AND a.trashed_at IS NULL AND p.trashed_at IS NULL ORDER BY p.rowid",
+        params![**store_id, is_done],
+    )?;
+    set_done(&tx, &user_id, &store_id, &changed, is_done)?;
+    tx.commit()?;
+    Ok(changed)
+}
+
+// check or uncheck every product of the aisle, returns the products that changed
+pub fn set_aisle_done(
+    c: &mut Connection,
+    auth: &Auth,
+    aisle_id: &AisleId,
+    is_done: bool,
+) -> Result<Vec<ProductId>> {
+    let store_id = sqlite::aisles::get_aisle_store(c, &aisle_id)?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let tx = c.savepoint()?;
+    let changed = select_product_ids(
+        &tx,
+        "SELECT product_id FROM products
+         WHERE aisle_id = ?1 AND is_done != ?2 AND trashed_at IS NULL ORDER BY rowid",
+        params![**aisle_id, is_done],
+    )?;
+    set_done(&tx, &user_id, &store_id, &changed, is_done)?;
+    tx.commit()?;
+    Ok(changed)
+}
+
+// check or uncheck the products, which can be in several stores, returns the ones
+// that changed
+pub fn set_products_done(
+    c: &mut Connection,
+    auth: &Auth,
+    product_ids: &[ProductId],
+    is_done: bool,
+) -> Result<Vec<ProductId>> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let store_ids = product_ids
+        .iter()
+        .map(|product_id| {
+            let store_id = get_product_store(c, &product_id)?;
+            sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+            Ok(store_id)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let tx = c.savepoint()?;
+    let mut changed = vec![];
+    for (product_id, store_id) in product_ids.iter().zip(&store_ids) {
+        let product_id = ProductId(product_id.to_string());
+        let state: bool = tx.query_row(
+            "SELECT is_done FROM products WHERE product_id = ?1",
+            params![*product_id],
+            |row| row.get(0),
+        )?;
+        if state != is_done {
+            set_done(
+                &tx,
+                &user_id,
+                &store_id,
+                std::slice::from_ref(&product_id),
+                is_done,
+            )?;
+            changed.push(product_id);
+        }
+    }
+    tx.commit()?;
+    Ok(changed)
+}
+
+// the checked products of the store into the user's trash, returns them
+pub fn delete_done_products(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+) -> Result<Vec<ProductId>> {
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let tx = c.savepoint()?;
+    let deleted = select_product_ids(
+        &tx,
+        "SELECT p.product_id FROM products p JOIN aisles a ON a.aisle_id = p.aisle_id
+         WHERE a.store_id = ?1 AND p.is_done
+         AND a.trashed_at IS NULL AND p.trashed_at IS NULL ORDER BY p.rowid",
+        params![**store_id],
+    )?;
+    let names = deleted
+        .iter()
+        .map(|product_id| get_name(&tx, &product_id))
+        .collect::<Result<Vec<_>>>()?;
+    tx.execute(
+        "UPDATE products SET trashed_by = ?1, trashed_at = ?2
+         WHERE is_done AND trashed_at IS NULL AND aisle_id IN
+         (SELECT aisle_id FROM aisles WHERE store_id = ?3 AND trashed_at IS NULL)",
+        params![*user_id, db::timestamp(), **store_id],
+    )?;
+    for (product_id, name) in deleted.iter().zip(names) {
+        sqlite::changes::record_change(
+            &tx,
+            &store_id,
+            ChangedItem::Product(product_id.to_string()),
+        )?;
+        sqlite::history::record(
+            &tx,
+            &store_id,
+            &user_id,
+            AuditAction::ProductDeleted,
+            &product_id,
+            json!({ "name": name }),
+            Value::Null,
+        )?;
+    }
+    tx.commit()?;
+    Ok(deleted)
+}
+
+// to the end of `aisle_id` unless `sort_weight` is given, the aisle can be in another
+// store
+pub fn move_product(
+    c: &mut Connection,
+    auth: &Auth,
+    product_id: &ProductId,
+    aisle_id: &AisleId,
+    sort_weight: Option<f32>,
+    if_match: &IfMatch,
+) -> Result<Product> {
+    let (from_aisle_id, product) = get_product(c, &product_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Product not found"))?;
+    let (to_store_id, _) = sqlite::aisles::get_aisle(c, &aisle_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Aisle not found"))?;
+    let from_store_id = sqlite::aisles::get_aisle_store(c, &from_aisle_id)?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &from_store_id, Role::Editor)?;
+    sqlite::verify_permission_auth(c, &auth, &to_store_id, Role::Editor)?;
+    if_match.verify(product.version())?;
+    let tx = c.savepoint()?;
+    let new_sort_weight = match sort_weight {
+        Some(sort_weight) => sort_weight,
+        None => find_max_weight_in_aisle(&tx, &aisle_id)? + 1f32,
+    };
+    sqlite::verify_not_modified(tx.execute(
+        "UPDATE products SET aisle_id = ?1, sort_weight = ?2, version = version + 1
+         WHERE product_id = ?3 AND version = ?4",
+        params![
+            **aisle_id,
+            f64::from(new_sort_weight),
+            **product_id,
+            product.version() as i64
+        ],
+    )?)?;
+    sqlite::changes::record_change(
+        &tx,
+        &from_store_id,
+        ChangedItem::Product(product_id.to_string()),
+    )?;
+    if from_store_id != to_store_id {
+        sqlite::changes::record_change(
+            &tx,
+            &to_store_id,
+            ChangedItem::Product(product_id.to_string()),
+        )?;
+    }
+    let before =
+        json!({ "aisle_id": from_aisle_id.to_string(), "sort_weight": product.sort_weight });
+    let after = json!({ "aisle_id": aisle_id.to_string(), "sort_weight": new_sort_weight });
+    sqlite::history::record(
+        &tx,
+        &from_store_id,
+        &user_id,
+        AuditAction::ProductMoved,
+        &product_id,
+        before.clone(),
+        after.clone(),
+    )?;
+    if from_store_id != to_store_id {
+        sqlite::history::record(
+            &tx,
+            &to_store_id,
+            &user_id,
+            AuditAction::ProductMoved,
+            &product_id,
+            before,
+            after,
+        )?;
+    }
+    tx.commit()?;
+    let version = product.version() + 1;
+    let mut product = product.with_version(version);
+    product.sort_weight = new_sort_weight;
+    Ok(product)
+}
+
+pub fn edit_product_sort_weight(
+    c: &Connection,
+    auth: &Auth,
+    data: &ProductItemWeight,
+) -> Result<()> {
+    let product_id = ProductId(data.id.clone());
+    let (_, product) = get_product(c, &product_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Product not found"))?;
+    let store_id = get_product_store(c, &product_id)?;
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    c.execute(
+        "UPDATE products SET sort_weight = ?1 WHERE product_id = ?2",
+        params![f64::from(data.sort_weight), *product_id],
+    )?;
+    sqlite::changes::record_change(c, &store_id, ChangedItem::Product(data.id.clone()))?;
+    sqlite::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::ProductReordered,
+        &product_id,
+        json!({ "sort_weight": product.sort_weight }),
+        json!({ "sort_weight": data.sort_weight }),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sqlite::tests::*, users::tests::*};
+
+    #[test]
+    fn modify_product_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = sqlite::stores::save_store(&mut c, &auth, "store").unwrap();
+        let aisle_id = sqlite::aisles::save_aisle(&mut c, &auth, &store_id, "aisle")
+            .unwrap()
+            .id();
+        let product_id = save_product(&mut c, &auth, "product", &aisle_id)
+            .unwrap()
+            .id();
+        let data = EditProduct::new(None, Some(3), Some(Unit::Ml), Some(true));
+        assert_eq!(
+            Ok(()),
+            modify_product(&mut c, &auth, &data, &product_id, &IfMatch::default())
+        );
+        let (qty, unit, is_done): (u32, u32, bool) = c
+            .query_row(
+                "SELECT quantity, unit, is_done FROM products WHERE product_id = ?1",
+                params![*product_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(3, qty);
+        assert_eq!(Unit::Ml, Unit::from(unit));
+        assert!(is_done);
+    }
+
+    #[test]
+    fn move_product_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = sqlite::stores::save_store(&mut c, &auth, "store").unwrap();
+        let aisle_id = sqlite::aisles::save_aisle(&mut c, &auth, &store_id, "aisle")
+            .unwrap()
+            .id();
+        let product_id = save_product(&mut c, &auth, "product", &aisle_id)
+            .unwrap()
+            .id();
+        let store2 = sqlite::stores::save_store(&mut c, &auth, "store2").unwrap();
+        let aisle2 = sqlite::aisles::save_aisle(&mut c, &auth, &store2, "aisle2")
+            .unwrap()
+            .id();
+        save_product(&mut c, &auth, "product2", &aisle2).unwrap();
+
+        let moved = move_product(
+            &mut c,
+            &auth,
+            &product_id,
+            &aisle2,
+            None,
+            &IfMatch::default(),
+        )
+        .unwrap();
+        assert!((moved.sort_weight - 2f32).abs() < std::f32::EPSILON);
+        assert_eq!(FIRST_VERSION + 1, moved.version());
+        assert_eq!(Ok(vec![]), get_products_in_aisle(&c, &aisle_id));
+        assert_eq!(Ok(store2), get_product_store(&c, &product_id));
+        assert_eq!(
+            Ok(vec![product_id.to_string()]),
+            sqlite::changes::store_changes(&c, &auth, &store_id, 0).map(|c| c.deleted_products)
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    db::{self, sessions::SessionTtl},
+    error::{self, Result, ServerError},
+    types::*,
+};
+
+pub fn get_user_id(c: &Connection, auth: &Auth) -> Result<UserId> {
+    let id = c.query_row(
+        "SELECT user_id FROM sessions WHERE auth = ?1",
+        params![auth.0],
+        |row| row.get(0),
+    )?;
+    Ok(UserId(id))
+}
+
+pub fn store_session(
+    c: &Connection,
+    auth: &str,
+    user_id: &UserId,
+    client: &ClientInfo,
+) -> Result<()> {
+    let inserted = c.execute(
+        "INSERT OR IGNORE INTO sessions
+         (auth, user_id, created_at, last_used, session_id, user_agent, ip)
+         VALUES (?1, ?2, ?3, ?3, ?4, ?5, ?6)",
+        params![
+            auth,
+            **user_id,
+            db::timestamp(),
+            db::ids::get_next_session_id(),
+            client.user_agent,
+            client.ip
+        ],
+    )?;
+    if inserted == 0 {
+        Err(ServerError::new(
+            error::INTERNAL_ERROR,
+            "Auth already exists",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// the session is valid, without counting it as used
+pub fn check_session(c: &Connection, auth: &Auth, ttl: &SessionTtl) -> Result<()> {
+    let times: Option<(i64, i64)> = c
+        .query_row(
+            "SELECT created_at, last_used FROM sessions WHERE auth = ?1",
+            params![auth.0],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let now = db::timestamp();
+    match times {
+        Some((created_at, last_used)) if ttl.is_expired(created_at, last_used, now) => {
+            c.execute("DELETE FROM sessions WHERE auth = ?1", params![auth.0])?;
+            Err(ServerError::new(error::UNAUTHORISED, "Session expired"))
+        }
+        Some(_) => Ok(()),
+        None => Err(ServerError::new(error::UNAUTHORISED, "Not logged in")),
+    }
+}
+
+pub fn validate_session(c: &Connection, auth: &Auth, ttl: &SessionTtl) -> Result<()> {
+    check_session(c, auth, ttl)?;
+    c.execute(
+        "UPDATE sessions SET last_used = ?1 WHERE auth = ?2",
+        params![db::timestamp(), auth.0],
+    )?;
+    Ok(())
+}
+
+pub fn sweep_expired_sessions(c: &Connection, user_id: &UserId, ttl: &SessionTtl) -> Result<()> {
+    c.execute(
+        "DELETE FROM sessions
+         WHERE user_id = ?1 AND (?2 - created_at > ?3 OR ?2 - last_used > ?4)",
+        params![**user_id, db::timestamp(), ttl.absolute, ttl.idle],
+    )?;
+    Ok(())
+}
+
+pub fn delete_session(c: &Connection, auth: &Auth, wanted_user_id: &UserId) -> Result<()> {
+    let user_id = get_user_id(c, auth)?;
+    if user_id == *wanted_user_id {
+        c.execute("DELETE FROM sessions WHERE auth = ?1", params![auth.0])?;
+        Ok(())
+    } else {
+        Err(ServerError::new(
+            error::UNAUTHORISED,
+            "x-auth-token does not belong to this user",
+        ))
+    }
+}
+
+pub fn list_sessions(c: &Connection, auth: &Auth) -> Result<Vec<SessionInfo>> {
+    let user_id = get_user_id(c, auth)?;
+    let mut stmt = c.prepare(
+        "SELECT session_id, created_at, last_used, user_agent, ip, auth FROM sessions
+         WHERE user_id = ?1 ORDER BY created_at",
+    )?;
+    let sessions = stmt
+        .query_map(params![*user_id], |row| {
+            Ok(SessionInfo::new(
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get::<_, String>(5)? == auth.0,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(sessions)
+}
+
+pub fn revoke_session(c: &Connection, auth: &Auth, session_id: &str) -> Result<()> {
+    let user_id = get_user_id(c, auth)?;
+    let deleted = c.execute(
+        "DELETE FROM sessions WHERE user_id = ?1 AND session_id = ?2",
+        params![*user_id, session_id],
+    )?;
+    if deleted == 0 {
+        Err(ServerError::new(error::NOT_FOUND, "Session not found"))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn revoke_other_sessions(c: &Connection, auth: &Auth) -> Result<()> {
+    let user_id = get_user_id(c, auth)?;
+    c.execute(
+        "DELETE FROM sessions WHERE user_id = ?1 AND auth != ?2",
+        params![*user_id, auth.0],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{ids::tests::*, sessions::tests::*, sqlite::tests::*};
+
+    fn set_session_time(c: &Connection, auth: &Auth, column: &str, time: i64) {
+        c.execute(
+            &format!("UPDATE sessions SET {} = ?1 WHERE auth = ?2", column),
+            params![time, auth.0],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn store_session_test() {
+        let c = open_for_test();
+        let user_id = UserId(HASH_1.to_owned());
+        assert_eq!(
+            Ok(()),
+            store_session(&c, &AUTH, &user_id, &ClientInfo::default())
+        );
+        assert_eq!(
+            Err(ServerError::new(
+                error::INTERNAL_ERROR,
+                "Auth already exists",
+            )),
+            store_session(&c, &AUTH, &user_id, &ClientInfo::default())
+        );
+        assert_eq!(Ok(user_id), get_user_id(&c, &AUTH));
+    }
+
+    #[test]
+    fn delete_session_test() {
+        let c = open_for_test();
+        let user_id = UserId(HASH_1.to_owned());
+        assert_eq!(
+            Ok(()),
+            store_session(&c, &AUTH, &user_id, &ClientInfo::default())
+        );
+        assert_eq!(
+            Ok(()),
+            store_session(&c, &AUTH2, &user_id, &ClientInfo::default())
+        );
+        assert_eq!(
+            Err(error::UNAUTHORISED),
+            delete_session(&c, &AUTH, &UserId(HASH_2.to_owned())).map_err(|e| e.status)
+        );
+        assert_eq!(Ok(()), delete_session(&c, &AUTH, &user_id));
+        assert_eq!(
+            Err(ServerError::new(error::UNAUTHORISED, "Not logged in")),
+            validate_session(&c, &AUTH, &TTL)
+        );
+        assert_eq!(Ok(()), validate_session(&c, &AUTH2, &TTL));
+    }
+
+    #[test]
+    fn session_expiry_test() {
+        let c = open_for_test();
+        let user_id = UserId(HASH_1.to_owned());
+        assert_eq!(
+            Ok(()),
+            store_session(&c, &AUTH, &user_id, &ClientInfo::default())
+        );
+        let now = db::timestamp();
+
+        // checking the session doesn't use it
+        set_session_time(&c, &AUTH, "last_used", now - TTL.idle + 10);
+        assert_eq!(Ok(()), check_session(&c, &AUTH, &TTL));
+        assert_eq!(
+            now - TTL.idle + 10,
+            c.query_row(
+                "SELECT last_used FROM sessions WHERE auth = ?1",
+                params![AUTH.0],
+                |row| row.get::<_, i64>(0)
+            )
+            .unwrap()
+        );
+
+        // using the session pushes the idle expiry back
+        assert_eq!(Ok(()), validate_session(&c, &AUTH, &TTL));
+        set_session_time(&c, &AUTH, "created_at", now - TTL.idle - 10);
+        assert_eq!(Ok(()), validate_session(&c, &AUTH, &TTL));
+
+        set_session_time(&c, &AUTH, "last_used", now - TTL.idle - 10);
+        assert_eq!(
+            Err(ServerError::new(error::UNAUTHORISED, "Session expired")),
+            validate_session(&c, &AUTH, &TTL)
+        );
+        assert_eq!(
+            Err(ServerError::new(error::UNAUTHORISED, "Not logged in")),
+            validate_session(&c, &AUTH, &TTL)
+        );
+
+        assert_eq!(
+            Ok(()),
+            store_session(&c, &AUTH, &user_id, &ClientInfo::default())
+        );
+        set_session_time(&c, &AUTH, "created_at", now - TTL.absolute - 10);
+        assert_eq!(
+            Err(ServerError::new(error::UNAUTHORISED, "Session expired")),
+            validate_session(&c, &AUTH, &TTL)
+        );
+    }
+
+    #[test]
+    fn sweep_expired_sessions_test() {
+        let c = open_for_test();
+        let user_id = UserId(HASH_1.to_owned());
+        assert_eq!(
+            Ok(()),
+            store_session(&c, &AUTH, &user_id, &ClientInfo::default())
+        );
+        assert_eq!(
+            Ok(()),
+            store_session(&c, &AUTH2, &user_id, &ClientInfo::default())
+        );
+        set_session_time(&c, &AUTH, "last_used", db::timestamp() - TTL.idle - 10);
+        assert_eq!(Ok(()), sweep_expired_sessions(&c, &user_id, &TTL));
+        assert_eq!(
+            Err(ServerError::new(error::UNAUTHORISED, "Not logged in")),
+            validate_session(&c, &AUTH, &TTL)
+        );
+        assert_eq!(Ok(()), validate_session(&c, &AUTH2, &TTL));
+    }
+    #[test]
+    fn list_and_revoke_sessions_test() {
+        let c = open_for_test();
+        let user_id = UserId(HASH_1.to_owned());
+        let client_info =
+            ClientInfo::new(Some("curl/7.68.0".to_owned()), Some("127.0.0.1".to_owned()));
+        assert_eq!(Ok(()), store_session(&c, &AUTH, &user_id, &client_info));
+        assert_eq!(
+            Ok(()),
+            store_session(&c, &AUTH2, &user_id, &ClientInfo::default())
+        );
+        assert_eq!(
+            Ok(()),
+            store_session(&c, "third", &user_id, &ClientInfo::default())
+        );
+
+        let sessions = list_sessions(&c, &AUTH).unwrap();
+        assert_eq!(3, sessions.len());
+        assert_eq!(1, sessions.iter().filter(|s| s.is_current()).count());
+        let (user_agent, ip): (Option<String>, Option<String>) = c
+            .query_row(
+                "SELECT user_agent, ip FROM sessions WHERE auth = ?1",
+                params![AUTH.0],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(client_info.user_agent, user_agent);
+        assert_eq!(client_info.ip, ip);
+
+        let other_id = sessions.iter().find(|s| !s.is_current()).unwrap().id();
+        assert_eq!(Ok(()), revoke_session(&c, &AUTH, other_id));
+        assert_eq!(
+            Err(error::NOT_FOUND),
+            revoke_session(&c, &AUTH, other_id).map_err(|e| e.status)
+        );
+
+        assert_eq!(Ok(()), revoke_other_sessions(&c, &AUTH));
+        let sessions = list_sessions(&c, &AUTH).unwrap();
+        assert_eq!(1, sessions.len());
+        assert!(sessions[0].is_current());
+        assert_eq!(Ok(()), validate_session(&c, &AUTH, &TTL));
+    }
+}
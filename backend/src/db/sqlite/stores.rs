@@ -0,0 +1,563 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+
+use crate::{
+    db::{self, sqlite},
+    error::*,
+    types::*,
+};
+
+// `None` for everyone while the store is in a trash
+pub fn get_role(c: &Connection, store_id: &StoreId, user_id: &UserId) -> Result<Option<Role>> {
+    let role: Option<String> = c
+        .query_row(
+            "SELECT m.role FROM store_members m JOIN stores s ON s.store_id = m.store_id
+             WHERE m.store_id = ?1 AND m.user_id = ?2 AND s.trashed_at IS NULL",
+            params![**store_id, **user_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    role.map(|r| r.parse()).transpose()
+}
+
+pub fn get_member_ids(c: &Connection, store_id: &StoreId) -> Result<Vec<UserId>> {
+    let mut stmt = c.prepare("SELECT user_id FROM store_members WHERE store_id = ?1")?;
+    let user_ids = stmt
+        .query_map(params![**store_id], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(user_ids.into_iter().map(UserId).collect())
+}
+
+pub fn list_store(c: &Connection, auth: &Auth, store_id: &StoreId) -> Result<Store> {
+    sqlite::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    // read first, changes made while reading the tree will be sent again
+    let revision = sqlite::changes::get_revision(c, &store_id)?;
+    Ok(Store::new(
+        store_id.to_string(),
+        get_name(c, &store_id)?,
+        sqlite::aisles::get_aisles_in_store(c, &store_id)?,
+    )
+    .at_revision(revision)
+    .with_version(get_version(c, &store_id)?))
+}
+
+pub fn get_name(c: &Connection, store_id: &StoreId) -> Result<String> {
+    let name: Option<String> = c
+        .query_row(
+            "SELECT name FROM stores WHERE store_id = ?1",
+            params![**store_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    name.ok_or_else(|| ServerError::new(NOT_FOUND, "Store not found"))
+}
+
+pub fn get_version(c: &Connection, store_id: &StoreId) -> Result<u64> {
+    let version: Option<i64> = c
+        .query_row(
+            "SELECT version FROM stores WHERE store_id = ?1",
+            params![**store_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    version
+        .map(|v| v as u64)
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Store not found"))
+}
+
+fn insert_store(c: &Connection, store_id: &StoreId, user_id: &UserId, name: &str) -> Result<()> {
+    c.execute(
+        "INSERT INTO stores (store_id, name, owner_id) VALUES (?1, ?2, ?3)",
+        params![**store_id, name, **user_id],
+    )?;
+    c.execute(
+        "INSERT INTO store_members (store_id, user_id, role) VALUES (?1, ?2, ?3)",
+        params![**store_id, **user_id, Role::Owner.to_string()],
+    )?;
+    Ok(())
+}
+
+pub fn save_store(c: &mut Connection, auth: &Auth, name: &str) -> Result<StoreId> {
+    let store_id = db::ids::get_next_store_id();
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let tx = c.savepoint()?;
+    insert_store(&tx, &store_id, &user_id, name)?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::StoreCreated,
+        &store_id,
+        Value::Null,
+        json!({ "name": name }),
+    )?;
+    tx.commit()?;
+    Ok(store_id)
+}
+
+// a new store owned by the user, with a copy of the aisles and products of `store_id`
+pub fn duplicate_store(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    name: Option<&str>,
+    reset_done: bool,
+) -> Result<StoreId> {
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => get_name(c, &store_id)?,
+    };
+    let new_store_id = db::ids::get_next_store_id();
+    let tx = c.savepoint()?;
+    {
+        insert_store(&tx, &new_store_id, &user_id, &name)?;
+        let mut aisles_stmt = tx.prepare(
+            "SELECT aisle_id, name, sort_weight FROM aisles
+             WHERE store_id = ?1 AND trashed_at IS NULL ORDER BY rowid",
+        )?;
+        let mut products_stmt = tx.prepare(
+            "SELECT product_id FROM products
+             WHERE aisle_id = ?1 AND trashed_at IS NULL ORDER BY rowid",
+        )?;
+        let aisles = aisles_stmt
+            .query_map(params![**store_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, f64>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (aisle_id, aisle_name, sort_weight) in aisles {
+            let new_aisle_id = db::ids::get_next_aisle_id();
+            tx.execute(
+                "INSERT INTO aisles (aisle_id, store_id, name, sort_weight, owner_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    *new_aisle_id,
+                    *new_store_id,
+                    aisle_name,
+                    sort_weight,
+                    *user_id
+                ],
+            )?;
+            let product_ids = products_stmt
+                .query_map(params![aisle_id], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            for product_id in product_ids {
+                tx.execute(
+                    "INSERT INTO products
+                     (product_id, aisle_id, name, quantity, is_done, unit, sort_weight, owner_id)
+                     SELECT ?1, ?2, name, quantity, is_done AND NOT ?3, unit, sort_weight, ?4
+                     FROM products WHERE product_id = ?5",
+                    params![
+                        *db::ids::get_next_product_id(),
+                        *new_aisle_id,
+                        reset_done,
+                        *user_id,
+                        product_id
+                    ],
+                )?;
+            }
+        }
+    }
+    sqlite::history::record(
+        &tx,
+        &new_store_id,
+        &user_id,
+        AuditAction::StoreCreated,
+        &new_store_id,
+        Value::Null,
+        json!({ "name": name, "copied_from": store_id.to_string() }),
+    )?;
+    tx.commit()?;
+    Ok(new_store_id)
+}
+
+// a new store owned by the user, with the aisles and products of `store` under new ids
+pub fn import_store(c: &mut Connection, auth: &Auth, store: &ExportedStore) -> Result<StoreId> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let store_id = db::ids::get_next_store_id();
+    let tx = c.savepoint()?;
+    insert_store(&tx, &store_id, &user_id, &store.name)?;
+    for aisle in &store.aisles {
+        let aisle_id = db::ids::get_next_aisle_id();
+        tx.execute(
+            "INSERT INTO aisles (aisle_id, store_id, name, sort_weight, owner_id)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                *aisle_id,
+                *store_id,
+                aisle.name,
+                f64::from(aisle.sort_weight),
+                *user_id
+            ],
+        )?;
+        for product in &aisle.products {
+            tx.execute(
+                "INSERT INTO products
+                 (product_id, aisle_id, name, quantity, is_done, unit, sort_weight, owner_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    *db::ids::get_next_product_id(),
+                    *aisle_id,
+                    product.name,
+                    product.quantity,
+                    product.is_done,
+                    u32::from(product.unit.clone()),
+                    f64::from(product.sort_weight),
+                    *user_id
+                ],
+            )?;
+        }
+    }
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::StoreCreated,
+        &store_id,
+        Value::Null,
+        json!({ "name": store.name, "imported": true }),
+    )?;
+    tx.commit()?;
+    Ok(store_id)
+}
+
+pub fn edit_store(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    new_name: &str,
+    if_match: &IfMatch,
+) -> Result<()> {
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let old_name = get_name(c, &store_id)?;
+    let version = get_version(c, &store_id)?;
+    if_match.verify(version)?;
+    let tx = c.savepoint()?;
+    sqlite::verify_not_modified(tx.execute(
+        "UPDATE stores SET name = ?1, version = version + 1
+         WHERE store_id = ?2 AND version = ?3",
+        params![new_name, **store_id, version as i64],
+    )?)?;
+    sqlite::changes::record_change(&tx, &store_id, ChangedItem::Store)?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::StoreRenamed,
+        &store_id,
+        json!({ "name": old_name }),
+        json!({ "name": new_name }),
+    )?;
+    Ok(tx.commit()?)
+}
+
+pub fn get_store_light(c: &Connection, store_id: &StoreId, user_id: &UserId) -> Result<StoreLight> {
+    let (name, role): (String, String) = c.query_row(
+        "SELECT s.name, m.role FROM stores s
+         JOIN store_members m ON m.store_id = s.store_id
+         WHERE s.store_id = ?1 AND m.user_id = ?2",
+        params![**store_id, **user_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(StoreLight::new(name, store_id.to_string(), role.parse()?))
+}
+
+pub fn get_all_stores(c: &Connection, auth: &Auth) -> Result<Vec<StoreLight>> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let mut stmt = c.prepare(
+        "SELECT s.name, s.store_id, m.role FROM stores s
+         JOIN store_members m ON m.store_id = s.store_id
+         WHERE m.user_id = ?1 AND s.trashed_at IS NULL ORDER BY s.rowid",
+    )?;
+    let stores = stmt
+        .query_map(params![*user_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    stores
+        .into_iter()
+        .map(|(name, store_id, role)| Ok(StoreLight::new(name, store_id, role.parse()?)))
+        .collect()
+}
+
+// into the owner's trash, its invitations are revoked
+pub fn delete_store(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    if_match: &IfMatch,
+) -> Result<()> {
+    let user_id = sqlite::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    let version = get_version(c, &store_id)?;
+    if_match.verify(version)?;
+    let tx = c.savepoint()?;
+    sqlite::verify_not_modified(tx.execute(
+        "UPDATE stores SET trashed_by = ?1, trashed_at = ?2
+         WHERE store_id = ?3 AND version = ?4",
+        params![*user_id, db::timestamp(), **store_id, version as i64],
+    )?)?;
+    tx.execute(
+        "DELETE FROM invitations WHERE store_id = ?1",
+        params![**store_id],
+    )?;
+    sqlite::history::record(
+        &tx,
+        &store_id,
+        &user_id,
+        AuditAction::StoreDeleted,
+        &store_id,
+        json!({ "name": get_name(&tx, &store_id)? }),
+        Value::Null,
+    )?;
+    Ok(tx.commit()?)
+}
+
+pub fn restore_store(c: &Connection, auth: &Auth, store_id: &StoreId) -> Result<()> {
+    let user_id = sqlite::trash::verify_in_trash(c, &auth, TrashedKind::Store, &store_id)?;
+    c.execute(
+        "UPDATE stores SET trashed_by = NULL, trashed_at = NULL WHERE store_id = ?1",
+        params![**store_id],
+    )?;
+    sqlite::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::StoreRestored,
+        &store_id,
+        Value::Null,
+        json!({ "name": get_name(c, &store_id)? }),
+    )
+}
+
+// delete the store and everything it contains, to be used in a transaction
+pub fn purge_store(c: &Connection, store_id: &StoreId) -> Result<()> {
+    c.execute(
+        "DELETE FROM products WHERE aisle_id IN
+         (SELECT aisle_id FROM aisles WHERE store_id = ?1)",
+        params![**store_id],
+    )?;
+    c.execute(
+        "DELETE FROM aisles WHERE store_id = ?1",
+        params![**store_id],
+    )?;
+    c.execute(
+        "DELETE FROM store_members WHERE store_id = ?1",
+        params![**store_id],
+    )?;
+    c.execute(
+        "DELETE FROM invitations WHERE store_id = ?1",
+        params![**store_id],
+    )?;
+    c.execute(
+        "DELETE FROM store_changes WHERE store_id = ?1",
+        params![**store_id],
+    )?;
+    c.execute(
+        "DELETE FROM store_history WHERE store_id = ?1",
+        params![**store_id],
+    )?;
+    c.execute(
+        "DELETE FROM stores WHERE store_id = ?1",
+        params![**store_id],
+    )?;
+    Ok(())
+}
+
+// delete the stores owned by the user, the ones in their trash, and leave the ones shared
+// with them
+pub fn delete_all_user_stores(c: &mut Connection, auth: &Auth) -> Result<()> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let stores = {
+        let mut stmt =
+            c.prepare("SELECT store_id FROM store_members WHERE user_id = ?1 AND role = ?2")?;
+        let ids = stmt
+            .query_map(params![*user_id, Role::Owner.to_string()], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        ids
+    };
+    let tx = c.savepoint()?;
+    for store_id in stores {
+        purge_store(&tx, &StoreId::new(store_id))?;
+    }
+    sqlite::trash::purge_user_trash(&tx, &user_id)?;
+    tx.execute(
+        "DELETE FROM store_members WHERE user_id = ?1",
+        params![*user_id],
+    )?;
+    Ok(tx.commit()?)
+}
+
+pub fn list_members(c: &Connection, auth: &Auth, store_id: &StoreId) -> Result<Vec<StoreMember>> {
+    sqlite::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    let mut stmt = c.prepare(
+        "SELECT m.user_id, u.username, m.role FROM store_members m
+         JOIN users u ON u.user_id = m.user_id
+         WHERE m.store_id = ?1",
+    )?;
+    let members = stmt
+        .query_map(params![**store_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut members = members
+        .into_iter()
+        .map(|(user_id, username, role)| Ok((role.parse::<Role>()?, username, user_id)))
+        .collect::<Result<Vec<_>>>()?;
+    // by decreasing rights, then by name
+    members.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    Ok(members
+        .into_iter()
+        .map(|(role, username, user_id)| StoreMember::new(user_id, username, role))
+        .collect())
+}
+
+pub fn set_member(
+    c: &Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    username: &str,
+    role: Role,
+) -> Result<()> {
+    sqlite::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    if role == Role::Owner {
+        return Err(ServerError::new(
+            PERMISSION_DENIED,
+            "A store can only have one owner",
+        ));
+    }
+    let user_id: String = c
+        .query_row(
+            "SELECT user_id FROM users WHERE norm_username = ?1",
+            params![username.to_lowercase()],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "User not found"))?;
+    let user_id = UserId(user_id);
+    if get_role(c, &store_id, &user_id)? == Some(Role::Owner) {
+        return Err(ServerError::new(
+            PERMISSION_DENIED,
+            "The store owner's role cannot be changed",
+        ));
+    }
+    add_member(c, &store_id, &user_id, role)
+}
+
+pub fn add_member(c: &Connection, store_id: &StoreId, user_id: &UserId, role: Role) -> Result<()> {
+    c.execute(
+        "INSERT INTO store_members (store_id, user_id, role) VALUES (?1, ?2, ?3)
+         ON CONFLICT (store_id, user_id) DO UPDATE SET role = excluded.role",
+        params![**store_id, **user_id, role.to_string()],
+    )?;
+    Ok(())
+}
+
+// the owner can remove anyone but themselves, other members can leave the store
+pub fn remove_member(
+    c: &Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    user_id: &UserId,
+) -> Result<()> {
+    let requester_id = sqlite::sessions::get_user_id(c, &auth)?;
+    if requester_id != *user_id {
+        sqlite::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    }
+    match get_role(c, &store_id, &user_id)? {
+        None => Err(ServerError::new(NOT_FOUND, "Member not found")),
+        Some(Role::Owner) => Err(ServerError::new(
+            PERMISSION_DENIED,
+            "The store owner cannot be removed",
+        )),
+        Some(_) => {
+            c.execute(
+                "DELETE FROM store_members WHERE store_id = ?1 AND user_id = ?2",
+                params![**store_id, **user_id],
+            )?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sqlite::tests::*, users::tests::*};
+
+    #[test]
+    fn delete_store_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = save_store(&mut c, &auth, "store").unwrap();
+        let aisle = sqlite::aisles::save_aisle(&mut c, &auth, &store_id, "aisle").unwrap();
+        sqlite::products::save_product(&mut c, &auth, "product", &aisle.id()).unwrap();
+
+        assert_eq!(
+            Ok(()),
+            delete_store(&mut c, &auth, &store_id, &IfMatch::default())
+        );
+        assert_eq!(Ok(vec![]), get_all_stores(&c, &auth));
+
+        assert_eq!(
+            Ok(()),
+            sqlite::trash::purge_trash(&mut c, db::timestamp() + 1)
+        );
+        for table in &["stores", "aisles", "products"] {
+            let count: u32 = c
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {}", table),
+                    rusqlite::NO_PARAMS,
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(0, count);
+        }
+    }
+
+    #[test]
+    fn store_permission_test() {
+        let mut c = open_for_test();
+        let owner = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let store_id = save_store(&mut c, &Auth(&owner.session_token), "store").unwrap();
+        let mut user = gen_user();
+        user.username = "tata".to_string();
+        let token = sqlite::users::save_user(&mut c, &user).unwrap();
+        let auth = Auth(&token.session_token);
+        assert_eq!(
+            Err(crate::error::PERMISSION_DENIED),
+            edit_store(&mut c, &auth, &store_id, "hacked", &IfMatch::default())
+                .map_err(|e| e.status)
+        );
+        assert_eq!(Ok(vec![]), get_all_stores(&c, &auth));
+
+        let owner_auth = Auth(&owner.session_token);
+        assert_eq!(
+            Ok(()),
+            set_member(&c, &owner_auth, &store_id, "tata", Role::Viewer)
+        );
+        assert_eq!(
+            Err(crate::error::PERMISSION_DENIED),
+            edit_store(&mut c, &auth, &store_id, "hacked", &IfMatch::default())
+                .map_err(|e| e.status)
+        );
+        // a member deleting their account only leaves the store
+        assert_eq!(Ok(()), delete_all_user_stores(&mut c, &auth));
+        assert_eq!(
+            Ok(1),
+            list_members(&c, &owner_auth, &store_id).map(|m| m.len())
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+
+use crate::{db::sqlite, error::*, types::*};
+
+// only the user who deleted an item can restore it
+pub fn verify_in_trash(c: &Connection, auth: &Auth, kind: TrashedKind, id: &str) -> Result<UserId> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let sql = match kind {
+        TrashedKind::Store => "SELECT trashed_by FROM stores WHERE store_id = ?1",
+        TrashedKind::Aisle => "SELECT trashed_by FROM aisles WHERE aisle_id = ?1",
+        TrashedKind::Product => "SELECT trashed_by FROM products WHERE product_id = ?1",
+    };
+    let trashed_by: Option<Option<String>> =
+        c.query_row(sql, params![id], |row| row.get(0)).optional()?;
+    if trashed_by.flatten().as_deref() == Some(&**user_id) {
+        Ok(user_id)
+    } else {
+        Err(ServerError::new(NOT_FOUND, "Item not found in the trash"))
+    }
+}
+
+// the latest deleted first
+pub fn list_trash(c: &Connection, auth: &Auth) -> Result<Vec<TrashedItem>> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let mut stmt = c.prepare(
+        "SELECT 'store', store_id, name, trashed_at FROM stores WHERE trashed_by = ?1
+         UNION ALL
+         SELECT 'aisle', aisle_id, name, trashed_at FROM aisles WHERE trashed_by = ?1
+         UNION ALL
+         SELECT 'product', product_id, name, trashed_at FROM products WHERE trashed_by = ?1
+         ORDER BY 4 DESC",
+    )?;
+    let items = stmt
+        .query_map(params![*user_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    items
+        .into_iter()
+        .map(|(kind, id, name, trashed_at)| {
+            Ok(TrashedItem::new(kind.parse()?, id, name, trashed_at))
+        })
+        .collect()
+}
+
+// `condition` selects the trashed rows of the stores, aisles and products tables
+fn purge(c: &Connection, condition: &str, param: &dyn ToSql) -> Result<()> {
+    let store_ids = {
+        let mut stmt = c.prepare(&format!("SELECT store_id FROM stores WHERE {}", condition))?;
+        let ids = stmt
+            .query_map(&[param], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        ids
+    };
+    for store_id in store_ids {
+        sqlite::stores::purge_store(c, &StoreId::new(store_id))?;
+    }
+    c.execute(
+        &format!(
+            "DELETE FROM products WHERE aisle_id IN (SELECT aisle_id FROM aisles WHERE {})",
+            condition
+        ),
+        &[param],
+    )?;
+    c.execute(&format!("DELETE FROM aisles WHERE {}", condition), &[param])?;
+    c.execute(
+        &format!("DELETE FROM products WHERE {}", condition),
+        &[param],
+    )?;
+    Ok(())
+}
+
+// `before` in seconds since the Unix epoch
+pub fn purge_trash(c: &mut Connection, before: i64) -> Result<()> {
+    let tx = c.savepoint()?;
+    purge(&tx, "trashed_at < ?1", &before)?;
+    Ok(tx.commit()?)
+}
+
+// to be used in a transaction
+pub fn purge_user_trash(c: &Connection, user_id: &UserId) -> Result<()> {
+    purge(c, "trashed_by = ?1", &**user_id)
+}
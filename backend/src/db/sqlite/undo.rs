@@ -0,0 +1,75 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    db::{self, sqlite},
+    error::*,
+    types::*,
+};
+
+// on top of the user's undo history, dropping the oldest past `UNDO_LEN`
+pub fn push_undo(c: &mut Connection, auth: &Auth, op: &str) -> Result<()> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let tx = c.savepoint()?;
+    let position: i64 = tx.query_row(
+        "SELECT COALESCE(MAX(position), 0) + 1 FROM undo_history WHERE user_id = ?1",
+        params![*user_id],
+        |row| row.get(0),
+    )?;
+    tx.execute(
+        "INSERT INTO undo_history (user_id, position, op) VALUES (?1, ?2, ?3)",
+        params![*user_id, position, op],
+    )?;
+    tx.execute(
+        "DELETE FROM undo_history WHERE user_id = ?1 AND position <= ?2",
+        params![*user_id, position - db::UNDO_LEN as i64],
+    )?;
+    Ok(tx.commit()?)
+}
+
+pub fn pop_undo(c: &mut Connection, auth: &Auth) -> Result<Option<String>> {
+    let user_id = sqlite::sessions::get_user_id(c, &auth)?;
+    let tx = c.savepoint()?;
+    let latest: Option<(i64, String)> = tx
+        .query_row(
+            "SELECT position, op FROM undo_history WHERE user_id = ?1
+             ORDER BY position DESC LIMIT 1",
+            params![*user_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    if let Some((position, _)) = latest {
+        tx.execute(
+            "DELETE FROM undo_history WHERE user_id = ?1 AND position = ?2",
+            params![*user_id, position],
+        )?;
+    }
+    tx.commit()?;
+    Ok(latest.map(|(_, op)| op))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{sqlite::tests::*, users::tests::*};
+    use rusqlite::NO_PARAMS;
+
+    #[test]
+    fn undo_test() {
+        let mut c = open_for_test();
+        let token = sqlite::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+
+        assert_eq!(Ok(None), pop_undo(&mut c, &auth));
+        for i in 0..db::UNDO_LEN + 1 {
+            assert_eq!(Ok(()), push_undo(&mut c, &auth, &i.to_string()));
+        }
+        assert_eq!(Ok(Some(db::UNDO_LEN.to_string())), pop_undo(&mut c, &auth));
+        let count: i64 = c
+            .query_row("SELECT COUNT(*) FROM undo_history", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        // the oldest was dropped
+        assert_eq!(db::UNDO_LEN as i64 - 1, count);
+    }
+}
@@ -0,0 +1,348 @@
+use rand::{self, Rng};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    db::{self, sqlite},
+    error::{self, *},
+    types::*,
+};
+
+const NEXT_USER_ID: &str = "next_user_id";
+
+const USER_ID_SALT: &str = "user_id_salt";
+
+// same id scheme as the Redis backend: hash of a counter with a per-database salt
+fn get_next_user_id(c: &Connection) -> Result<UserId> {
+    c.execute(
+        "INSERT INTO meta (key, value) VALUES (?1, 1)
+         ON CONFLICT (key) DO UPDATE SET value = value + 1",
+        params![NEXT_USER_ID],
+    )?;
+    let id: u32 = c.query_row(
+        "SELECT value FROM meta WHERE key = ?1",
+        params![NEXT_USER_ID],
+        |row| row.get(0),
+    )?;
+    let salt: Option<String> = c
+        .query_row(
+            "SELECT value FROM meta WHERE key = ?1",
+            params![USER_ID_SALT],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let salt = match salt {
+        Some(salt) => salt,
+        None => {
+            let s = db::ids::generate_salt();
+            c.execute(
+                "INSERT INTO meta (key, value) VALUES (?1, ?2)",
+                params![USER_ID_SALT, s],
+            )?;
+            s
+        }
+    };
+    Ok(UserId(db::ids::hash(&id.to_string(), &salt)))
+}
+
+pub fn save_user(c: &mut Connection, user: &User) -> Result<ConnectionToken> {
+    let norm_username = user.username.to_lowercase();
+    let tx = c.savepoint()?;
+    let exists: Option<String> = tx
+        .query_row(
+            "SELECT user_id FROM users WHERE norm_username = ?1",
+            params![norm_username],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if exists.is_some() {
+        Err(ServerError::new(
+            error::USERNAME_TAKEN,
+            &format!("Username {} is not available.", &user.username),
+        ))
+    } else {
+        let mut rng = rand::thread_rng();
+        let salt_mail = rng.gen::<u64>().to_string();
+        let salt_pwd = rng.gen::<u64>().to_string();
+        let hashed_pwd = db::ids::hash(&user.password, &salt_pwd);
+        let hashed_mail = db::ids::hash(&user.email, &salt_mail);
+        let user_id = get_next_user_id(&tx)?;
+        tx.execute(
+            "INSERT INTO users
+             (user_id, username, norm_username, email, password, salt_mail, salt_password)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                *user_id,
+                user.username,
+                norm_username,
+                hashed_mail,
+                hashed_pwd,
+                salt_mail,
+                salt_pwd
+            ],
+        )?;
+        let auth = db::ids::gen_auth(&mut rng);
+        sqlite::sessions::store_session(&tx, &auth, &user_id, &ClientInfo::default())?;
+        tx.commit()?;
+        Ok(ConnectionToken::new(auth, user_id.to_string()))
+    }
+}
+
+pub fn delete_user(c: &mut Connection, auth: &Auth, wanted_user_id: &UserId) -> Result<()> {
+    let user_id = sqlite::sessions::get_user_id(c, auth)?;
+    if user_id == *wanted_user_id {
+        sqlite::stores::delete_all_user_stores(c, &auth)?;
+        let tx = c.savepoint()?;
+        tx.execute("DELETE FROM sessions WHERE user_id = ?1", params![*user_id])?;
+        tx.execute(
+            "DELETE FROM password_resets WHERE user_id = ?1",
+            params![*user_id],
+        )?;
+        tx.execute(
+            "DELETE FROM event_log WHERE user_id = ?1",
+            params![*user_id],
+        )?;
+        tx.execute(
+            "DELETE FROM op_results WHERE user_id = ?1",
+            params![*user_id],
+        )?;
+        tx.execute(
+            "DELETE FROM undo_history WHERE user_id = ?1",
+            params![*user_id],
+        )?;
+        tx.execute("DELETE FROM users WHERE user_id = ?1", params![*user_id])?;
+        Ok(tx.commit()?)
+    } else {
+        Err(ServerError::new(
+            error::UNAUTHORISED,
+            "x-auth-token does not belong to this user",
+        ))
+    }
+}
+
+fn check_password(c: &Connection, user_id: &UserId, password: &str) -> Result<bool> {
+    let (salt_pwd, stored_pwd): (String, String) = c.query_row(
+        "SELECT salt_password, password FROM users WHERE user_id = ?1",
+        params![**user_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(db::ids::hash(password, &salt_pwd) == stored_pwd)
+}
+
+pub fn change_password(
+    c: &Connection,
+    auth: &Auth,
+    wanted_user_id: &UserId,
+    old_password: &str,
+    new_password: &str,
+) -> Result<()> {
+    let user_id = sqlite::sessions::get_user_id(c, auth)?;
+    if user_id != *wanted_user_id {
+        Err(ServerError::new(
+            error::UNAUTHORISED,
+            "x-auth-token does not belong to this user",
+        ))
+    } else if !check_password(c, &user_id, old_password)? {
+        Err(ServerError::new(
+            error::INVALID_USER_OR_PWD,
+            "Invalid password",
+        ))
+    } else {
+        set_password(c, &user_id, new_password)
+    }
+}
+
+fn set_password(c: &Connection, user_id: &UserId, password: &str) -> Result<()> {
+    let salt_pwd = rand::thread_rng().gen::<u64>().to_string();
+    let hashed_pwd = db::ids::hash(password, &salt_pwd);
+    c.execute(
+        "UPDATE users SET password = ?1, salt_password = ?2 WHERE user_id = ?3",
+        params![hashed_pwd, salt_pwd, **user_id],
+    )?;
+    Ok(())
+}
+
+pub fn create_reset_token(c: &Connection, username: &str, email: &str) -> Result<Option<String>> {
+    let user: Option<(String, String, String)> = c
+        .query_row(
+            "SELECT user_id, salt_mail, email FROM users WHERE norm_username = ?1",
+            params![username.to_lowercase()],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    match user {
+        Some((user_id, salt_mail, stored_mail))
+            if db::ids::hash(email, &salt_mail) == stored_mail =>
+        {
+            let now = db::timestamp();
+            c.execute(
+                "DELETE FROM password_resets WHERE expires_at < ?1",
+                params![now],
+            )?;
+            let token = db::ids::gen_auth(&mut rand::thread_rng());
+            c.execute(
+                "INSERT INTO password_resets (token, user_id, expires_at) VALUES (?1, ?2, ?3)",
+                params![token, user_id, now + db::users::RESET_TOKEN_TTL],
+            )?;
+            Ok(Some(token))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn reset_password(c: &mut Connection, token: &str, new_password: &str) -> Result<()> {
+    let tx = c.savepoint()?;
+    let reset: Option<(String, i64)> = tx
+        .query_row(
+            "SELECT user_id, expires_at FROM password_resets WHERE token = ?1",
+            params![token],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    tx.execute(
+        "DELETE FROM password_resets WHERE token = ?1",
+        params![token],
+    )?;
+    match reset {
+        Some((user_id, expires_at)) if expires_at >= db::timestamp() => {
+            let user_id = UserId(user_id);
+            set_password(&tx, &user_id, new_password)?;
+            tx.execute("DELETE FROM sessions WHERE user_id = ?1", params![*user_id])?;
+            Ok(tx.commit()?)
+        }
+        _ => {
+            tx.commit()?;
+            Err(ServerError::new(
+                error::UNAUTHORISED,
+                "Invalid or expired reset token",
+            ))
+        }
+    }
+}
+
+pub fn login(
+    c: &mut Connection,
+    auth_info: &AuthInfo,
+    client: &ClientInfo,
+    ttl: &db::sessions::SessionTtl,
+) -> Result<ConnectionToken> {
+    let (user_id, salt_pwd, stored_pwd): (String, String, String) = c
+        .query_row(
+            "SELECT user_id, salt_password, password FROM users WHERE norm_username = ?1",
+            params![auth_info.username.to_lowercase()],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .map_err(|_| ServerError::new(error::INVALID_USER_OR_PWD, "Invalid usename or password"))?;
+    let hashed_pwd = db::ids::hash(&auth_info.password, &salt_pwd);
+    if hashed_pwd == stored_pwd {
+        let user_id = UserId(user_id);
+        sqlite::sessions::sweep_expired_sessions(c, &user_id, ttl)?;
+        let mut rng = rand::thread_rng();
+        let auth = db::ids::gen_auth(&mut rng);
+        sqlite::sessions::store_session(c, &auth, &user_id, client)?;
+        Ok(ConnectionToken::new(auth, user_id.to_string()))
+    } else {
+        Err(ServerError::new(
+            error::INVALID_USER_OR_PWD,
+            "Invalid usename or password",
+        ))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::{ids::tests::*, sqlite::tests::*, users::tests::*};
+
+    fn count(c: &Connection, sql: &str) -> u32 {
+        c.query_row(sql, rusqlite::NO_PARAMS, |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn save_user_test() {
+        let mut c = open_for_test();
+        let token = save_user(&mut c, &gen_user()).unwrap();
+        assert_eq!(HASH_1, token.user_id);
+        assert_eq!(1, count(&c, "SELECT COUNT(*) FROM users"));
+        assert_eq!(1, count(&c, "SELECT COUNT(*) FROM sessions"));
+
+        let mut user = gen_user();
+        user.username = "ToTo".to_string(); // username uniqueness should be case insensitive
+        assert_eq!(
+            Err(error::USERNAME_TAKEN),
+            save_user(&mut c, &user).map(|_| ()).map_err(|e| e.status)
+        );
+        user.username = "tata".to_string();
+        assert_eq!(HASH_2, save_user(&mut c, &user).unwrap().user_id);
+    }
+
+    #[test]
+    fn delete_user_test() {
+        let mut c = open_for_test();
+        let token = save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        sqlite::stores::save_store(&mut c, &auth, "store").unwrap();
+        assert_eq!(
+            Ok(()),
+            delete_user(&mut c, &auth, &UserId(HASH_1.to_owned()))
+        );
+        assert_eq!(0, count(&c, "SELECT COUNT(*) FROM users"));
+        assert_eq!(0, count(&c, "SELECT COUNT(*) FROM sessions"));
+        assert_eq!(0, count(&c, "SELECT COUNT(*) FROM stores"));
+    }
+
+    #[test]
+    fn change_password_test() {
+        let mut c = open_for_test();
+        let token = save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let user_id = UserId(token.user_id.clone());
+        assert_eq!(
+            Err(error::INVALID_USER_OR_PWD),
+            change_password(&c, &auth, &user_id, "wrong", "newpwd").map_err(|e| e.status)
+        );
+        assert_eq!(
+            Err(error::UNAUTHORISED),
+            change_password(&c, &auth, &UserId(HASH_2.to_owned()), "pwd", "newpwd")
+                .map_err(|e| e.status)
+        );
+        assert_eq!(
+            Ok(()),
+            change_password(&c, &auth, &user_id, "pwd", "newpwd")
+        );
+        assert_eq!(Ok(false), check_password(&c, &user_id, "pwd"));
+        assert_eq!(Ok(true), check_password(&c, &user_id, "newpwd"));
+    }
+
+    #[test]
+    fn reset_password_test() {
+        let mut c = open_for_test();
+        let token = save_user(&mut c, &gen_user()).unwrap();
+        let user_id = UserId(token.user_id.clone());
+        assert_eq!(Ok(None), create_reset_token(&c, "toto", "other@m.com"));
+        assert_eq!(Ok(None), create_reset_token(&c, "tata", "m@m.com"));
+
+        let reset_token = create_reset_token(&c, "ToTo", "m@m.com").unwrap().unwrap();
+        assert_eq!(Ok(()), reset_password(&mut c, &reset_token, "newpwd"));
+        assert_eq!(Ok(true), check_password(&c, &user_id, "newpwd"));
+        assert_eq!(0, count(&c, "SELECT COUNT(*) FROM sessions"));
+        // single use
+        assert_eq!(
+            Err(error::UNAUTHORISED),
+            reset_password(&mut c, &reset_token, "otherpwd").map_err(|e| e.status)
+        );
+
+        let reset_token = create_reset_token(&c, "toto", "m@m.com").unwrap().unwrap();
+        c.execute(
+            "UPDATE password_resets SET expires_at = ?1",
+            params![db::timestamp() - 1],
+        )
+        .unwrap();
+        assert_eq!(
+            Err(error::UNAUTHORISED),
+            reset_password(&mut c, &reset_token, "otherpwd").map_err(|e| e.status)
+        );
+        assert_eq!(Ok(true), check_password(&c, &user_id, "newpwd"));
+        assert_eq!(0, count(&c, "SELECT COUNT(*) FROM password_resets"));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{error::Result, types::*};
+
+/// Operations the endpoints need from a database backend
+pub trait Storage: Send {
+    // bring the database schema up to date, called once at startup
+    fn migrate(&mut self) -> Result<()>;
+
+    fn save_user(&mut self, user: &User) -> Result<ConnectionToken>;
+    fn login(&mut self, auth_info: &AuthInfo, client: &ClientInfo) -> Result<ConnectionToken>;
+    fn delete_user(&mut self, auth: &Auth, user_id: &UserId) -> Result<()>;
+    fn change_password(
+        &mut self,
+        auth: &Auth,
+        user_id: &UserId,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<()>;
+    // `None` when the username and email do not match an account
+    fn create_reset_token(&mut self, username: &str, email: &str) -> Result<Option<String>>;
+    // the token can be used once, and logs the user out everywhere
+    fn reset_password(&mut self, token: &str, new_password: &str) -> Result<()>;
+
+    fn validate_session(&mut self, auth: &Auth) -> Result<()>;
+    // like `validate_session`, for the periodic checks of an open connection that
+    // shouldn't keep an idle session alive
+    fn check_session(&mut self, auth: &Auth) -> Result<()>;
+    fn delete_session(&mut self, auth: &Auth, user_id: &UserId) -> Result<()>;
+    fn list_sessions(&mut self, auth: &Auth) -> Result<Vec<SessionInfo>>;
+    fn revoke_session(&mut self, auth: &Auth, session_id: &str) -> Result<()>;
+    // revoke every session of the user except the one `auth` belongs to
+    fn revoke_other_sessions(&mut self, auth: &Auth) -> Result<()>;
+
+    fn save_store(&mut self, auth: &Auth, name: &str) -> Result<StoreId>;
+    // the edits and deletions of stores, aisles and products are refused when `if_match`
+    // doesn't accept the version of the item
+    fn edit_store(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        new_name: &str,
+        if_match: &IfMatch,
+    ) -> Result<()>;
+    fn get_all_stores(&mut self, auth: &Auth) -> Result<Vec<StoreLight>>;
+    fn list_store(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Store>;
+    // what changed after the revision `since` of the store
+    fn store_changes(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        since: u64,
+    ) -> Result<StoreChanges>;
+    // the audit entries of the store older than the entry `before`, newest first
+    fn store_history(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        before: Option<u64>,
+        limit: u32,
+    ) -> Result<StoreHistory>;
+    // a new store owned by the user, with the aisles and products of `store` under new ids
+    fn import_store(&mut self, auth: &Auth, store: &ExportedStore) -> Result<StoreId>;
+    // into the owner's trash
+    fn delete_store(&mut self, auth: &Auth, store_id: &StoreId, if_match: &IfMatch) -> Result<()>;
+    // a copy of the aisles and products in a new store owned by the user, named like the
+    // original unless `name` is given
+    fn duplicate_store(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        name: Option<&str>,
+        reset_done: bool,
+    ) -> Result<StoreId>;
+    // the user needs at least `role` in the store
+    fn verify_role(&mut self, auth: &Auth, store_id: &StoreId, role: Role) -> Result<()>;
+    fn aisle_store(&mut self, aisle_id: &AisleId) -> Result<StoreId>;
+    fn product_store(&mut self, product_id: &ProductId) -> Result<StoreId>;
+    fn store_name(&mut self, store_id: &StoreId) -> Result<String>;
+    // without its products, `NOT_FOUND` once in a trash
+    fn get_aisle(&mut self, aisle_id: &AisleId) -> Result<Aisle>;
+    // with the aisle it is in, `NOT_FOUND` once in a trash
+    fn get_product(&mut self, product_id: &ProductId) -> Result<(AisleId, Product)>;
+    // bumped by each edit of the item, reordering doesn't change it
+    fn store_version(&mut self, store_id: &StoreId) -> Result<u64>;
+    fn aisle_version(&mut self, aisle_id: &AisleId) -> Result<u64>;
+    fn product_version(&mut self, product_id: &ProductId) -> Result<u64>;
+    fn member_ids(&mut self, store_id: &StoreId) -> Result<Vec<UserId>>;
+    fn list_members(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Vec<StoreMember>>;
+    // `role` can't be `Owner`, a store has a single owner
+    fn set_member(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        username: &str,
+        role: Role,
+    ) -> Result<()>;
+    fn remove_member(&mut self, auth: &Auth, store_id: &StoreId, user_id: &UserId) -> Result<()>;
+
+    // `ttl` in seconds
+    fn create_invitation(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        role: Role,
+        ttl: i64,
+        max_uses: u32,
+    ) -> Result<Invitation>;
+    fn list_invitations(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Vec<Invitation>>;
+    fn revoke_invitation(&mut self, auth: &Auth, store_id: &StoreId, code: &str) -> Result<()>;
+    fn redeem_invitation(&mut self, auth: &Auth, code: &str) -> Result<StoreLight>;
+
+    // append to the user's change feed, returns the id of the event
+    fn log_event(&mut self, user_id: &UserId, data: &str) -> Result<u64>;
+    // `resume_from` is the id of the last event the client received
+    fn events_since(&mut self, auth: &Auth, resume_from: Option<u64>) -> Result<EventFeed>;
+
+    // the serialised result of an already applied batch operation
+    fn op_result(&mut self, auth: &Auth, op_id: &str) -> Result<Option<String>>;
+    // Holds `op_id` for the request until `end_op`, at most `OP_PENDING_TTL` seconds,
+    // `false` if another request holds it or already applied it. With SQLite the writes
+    // in between are a single transaction.
+    fn begin_op(&mut self, auth: &Auth, op_id: &str) -> Result<bool>;
+    // `result` is kept `OP_RESULT_TTL` seconds, so a replayed batch isn't applied twice.
+    // Without it `op_id` can be applied again, and SQLite drops the writes.
+    fn end_op(&mut self, auth: &Auth, op_id: &str, result: Option<&str>) -> Result<()>;
+
+    // the serialised inverse of a mutation of the user, the oldest are dropped past
+    // `UNDO_LEN`
+    fn push_undo(&mut self, auth: &Auth, op: &str) -> Result<()>;
+    // the latest pushed, taken out of the user's history
+    fn pop_undo(&mut self, auth: &Auth) -> Result<Option<String>>;
+
+    fn save_aisle(&mut self, auth: &Auth, store_id: &StoreId, name: &str) -> Result<Aisle>;
+    fn edit_aisle(
+        &mut self,
+        auth: &Auth,
+        aisle_id: &AisleId,
+        new_name: &str,
+        if_match: &IfMatch,
+    ) -> Result<()>;
+    // into the user's trash, with its products
+    fn delete_aisle(&mut self, auth: &Auth, aisle_id: &AisleId, if_match: &IfMatch) -> Result<()>;
+    // to the end of `store_id` unless `sort_weight` is given, with its products, the
+    // user edits both stores
+    fn move_aisle(
+        &mut self,
+        auth: &Auth,
+        aisle_id: &AisleId,
+        store_id: &StoreId,
+        sort_weight: Option<f32>,
+        if_match: &IfMatch,
+    ) -> Result<Aisle>;
+
+    fn save_product(&mut self, auth: &Auth, name: &str, aisle_id: &AisleId) -> Result<Product>;
+    fn modify_product(
+        &mut self,
+        auth: &Auth,
+        edit_data: &EditProduct,
+        product_id: &ProductId,
+        if_match: &IfMatch,
+    ) -> Result<()>;
+    // into the user's trash
+    fn delete_product(
+        &mut self,
+        auth: &Auth,
+        product_id: &ProductId,
+        if_match: &IfMatch,
+    ) -> Result<()>;
+    // to the end of `aisle_id` unless `sort_weight` is given, the user edits the stores
+    // of both aisles
+    fn move_product(
+        &mut self,
+        auth: &Auth,
+        product_id: &ProductId,
+        aisle_id: &AisleId,
+        sort_weight: Option<f32>,
+        if_match: &IfMatch,
+    ) -> Result<Product>;
+
+    // all the weights are applied at once, or none if one of them is not permitted
+    fn change_sort_weight(&mut self, auth: &Auth, data: &EditWeight) -> Result<()>;
+
+    // check or uncheck every product at once, returns those whose state changed
+    fn set_store_done(
+        &mut self,
+        auth: &Auth,
+        store_id: &StoreId,
+        is_done: bool,
+    ) -> Result<Vec<ProductId>>;
+    fn set_aisle_done(
+        &mut self,
+        auth: &Auth,
+        aisle_id: &AisleId,
+        is_done: bool,
+    ) -> Result<Vec<ProductId>>;
+    // all at once, the products can be in several stores
+    fn set_products_done(
+        &mut self,
+        auth: &Auth,
+        product_ids: &[ProductId],
+        is_done: bool,
+    ) -> Result<Vec<ProductId>>;
+    // into the user's trash, returns the deleted products
+    fn delete_done_products(&mut self, auth: &Auth, store_id: &StoreId) -> Result<Vec<ProductId>>;
+
+    // what the user deleted, the latest first
+    fn list_trash(&mut self, auth: &Auth) -> Result<Vec<TrashedItem>>;
+    // only from the user's trash, aisles and products need their parent to still be there
+    fn restore_store(&mut self, auth: &Auth, store_id: &StoreId) -> Result<()>;
+    fn restore_aisle(&mut self, auth: &Auth, aisle_id: &AisleId) -> Result<(StoreId, Aisle)>;
+    fn restore_product(
+        &mut self,
+        auth: &Auth,
+        product_id: &ProductId,
+    ) -> Result<(AisleId, Product)>;
+    // delete for good what was trashed before `before`, in seconds since the Unix epoch
+    fn purge_trash(&mut self, before: i64) -> Result<()>;
+
+    // the whole database, the sessions and password resets only if `with_sessions`
+    fn backup(&mut self, with_sessions: bool) -> Result<BackupData>;
+    // into an empty database, from a backup of the same backend
+    fn restore(&mut self, data: &BackupData) -> Result<()>;
+
+    // wipe the whole database
+    fn nuke(&mut self) -> Result<()>;
+}
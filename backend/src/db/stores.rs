@@ -1,35 +1,55 @@
 #[cfg(not(test))]
-use redis::{transaction, Commands, Connection};
+use redis::{transaction, Commands, Connection, Pipeline};
 
 #[cfg(test)]
-use fake_redis::{transaction, FakeConnection as Connection};
+use fake_redis::{transaction, FakeConnection as Connection, FakePipeline as Pipeline};
 
-use crate::{db, error::Result, types::*};
+use serde_json::{json, Value};
+
+use crate::{db, error::*, types::*};
 
 const STORE_NAME: &str = "name";
 const STORE_OWNER: &str = "owner_id";
+const STORE_VERSION: &str = "version";
 
 fn store_key(id: &StoreId) -> String {
     format!("store:{}", **id)
 }
 
+fn store_members_key(id: &StoreId) -> String {
+    format!("store_members:{}", **id)
+}
+
 fn user_stores_list_key(user_id: &UserId) -> String {
     format!("stores:{}", **user_id)
 }
 
-pub fn get_store_owner(c: &mut Connection, store_id: &StoreId) -> Result<UserId> {
-    Ok(UserId(c.hget(&store_key(&store_id), STORE_OWNER)?))
+// `None` for everyone while the store is in a trash
+pub fn get_role(c: &mut Connection, store_id: &StoreId, user_id: &UserId) -> Result<Option<Role>> {
+    if db::trash::is_trashed(c, &store_key(&store_id))? {
+        return Ok(None);
+    }
+    let role: Option<String> = c.hget(&store_members_key(&store_id), &**user_id)?;
+    role.map(|r| r.parse()).transpose()
+}
+
+pub fn get_member_ids(c: &mut Connection, store_id: &StoreId) -> Result<Vec<UserId>> {
+    let user_ids: Vec<String> = c.hkeys(&store_members_key(&store_id))?;
+    Ok(user_ids.into_iter().map(UserId).collect())
 }
 
 pub fn list_store(c: &mut Connection, auth: &Auth, store_id: &StoreId) -> Result<Store> {
-    let user_id = db::sessions::get_user_id(c, &auth)?;
+    db::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    // read first, changes made while reading the tree will be sent again
+    let revision = db::changes::get_revision(c, &store_id)?;
     let store_key = store_key(&store_id);
-    db::verify_permission(&user_id, &get_store_owner(c, &store_id)?)?;
     Ok(Store::new(
         store_id.to_string(),
         c.hget(&store_key, STORE_NAME)?,
         db::aisles::get_aisles_in_store(c, &store_id)?,
-    ))
+    )
+    .at_revision(revision)
+    .with_version(c.hget(&store_key, STORE_VERSION)?))
 }
 
 pub fn save_store(c: &mut Connection, auth: &Auth, name: &str) -> Result<StoreId> {
@@ -38,50 +58,250 @@ pub fn save_store(c: &mut Connection, auth: &Auth, name: &str) -> Result<StoreId
     let store_key = store_key(&store_id);
     let user_stores_key = user_stores_list_key(&user_id);
     transaction(c, &[&store_key, &user_stores_key], |c, pipe| {
-        pipe.hset(&store_key, STORE_NAME, name)
-            .ignore()
-            .hset(&store_key, STORE_OWNER, user_id.to_string())
-            .ignore()
-            .sadd(&user_stores_key, store_id.to_string())
-            .query(c)
+        transaction_create_store(pipe, &store_id, &user_id, name).query(c)
     })?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::StoreCreated,
+        &store_id,
+        Value::Null,
+        json!({ "name": name }),
+    )?;
+    Ok(store_id)
+}
 
+// a new store owned by the user, with a copy of the aisles and products of `store_id`
+pub fn duplicate_store(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    name: Option<&str>,
+    reset_done: bool,
+) -> Result<StoreId> {
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => get_name(c, &store_id)?,
+    };
+    let new_store_id = db::ids::get_next_store_id();
+    let store_key = store_key(&new_store_id);
+    let user_stores_key = user_stores_list_key(&user_id);
+    transaction(c, &[&store_key, &user_stores_key], |c, mut pipe| {
+        db::aisles::transaction_copy_aisles_in_store(
+            c,
+            &mut pipe,
+            &store_id,
+            &new_store_id,
+            &user_id,
+            reset_done,
+        )?;
+        transaction_create_store(pipe, &new_store_id, &user_id, &name).query(c)
+    })?;
+    db::history::record(
+        c,
+        &new_store_id,
+        &user_id,
+        AuditAction::StoreCreated,
+        &new_store_id,
+        Value::Null,
+        json!({ "name": name, "copied_from": store_id.to_string() }),
+    )?;
+    Ok(new_store_id)
+}
+
+// a new store owned by the user, with the aisles and products of `store` under new ids
+pub fn import_store(c: &mut Connection, auth: &Auth, store: &ExportedStore) -> Result<StoreId> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    let store_id = db::ids::get_next_store_id();
+    let store_key = store_key(&store_id);
+    let user_stores_key = user_stores_list_key(&user_id);
+    transaction(c, &[&store_key, &user_stores_key], |c, pipe| {
+        db::aisles::transaction_import_aisles(pipe, &store_id, &user_id, &store.aisles);
+        transaction_create_store(pipe, &store_id, &user_id, &store.name).query(c)
+    })?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::StoreCreated,
+        &store_id,
+        Value::Null,
+        json!({ "name": store.name, "imported": true }),
+    )?;
     Ok(store_id)
 }
 
+// to be used only in a transaction, doesn't execute the `pipe`
+fn transaction_create_store<'a>(
+    pipe: &'a mut Pipeline,
+    store_id: &StoreId,
+    user_id: &UserId,
+    name: &str,
+) -> &'a mut Pipeline {
+    let store_key = store_key(&store_id);
+    pipe.hset(&store_key, STORE_NAME, name)
+        .ignore()
+        .hset(&store_key, STORE_OWNER, user_id.to_string())
+        .ignore()
+        .hset(&store_key, STORE_VERSION, FIRST_VERSION)
+        .ignore()
+        .hset(
+            &store_members_key(&store_id),
+            &**user_id,
+            Role::Owner.to_string(),
+        )
+        .ignore()
+        .sadd(&user_stores_list_key(&user_id), store_id.to_string())
+}
+
 pub fn edit_store(
     c: &mut Connection,
     auth: &Auth,
     store_id: &StoreId,
     new_name: &str,
+    if_match: &IfMatch,
 ) -> Result<()> {
-    let owner_id = get_store_owner(c, &store_id)?;
-    db::verify_permission_auth(c, &auth, &owner_id)?;
-    Ok(c.hset(&store_key(&store_id), STORE_NAME, new_name)?)
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Editor)?;
+    let store_key = store_key(&store_id);
+    let (mut old_name, mut version) = (String::new(), 0);
+    transaction(c, &[&store_key], |c, pipe| {
+        old_name = c.hget(&store_key, STORE_NAME)?;
+        version = c.hget(&store_key, STORE_VERSION)?;
+        if if_match.matches(version) {
+            pipe.hset(&store_key, STORE_NAME, new_name)
+                .ignore()
+                .hincr(&store_key, STORE_VERSION, 1)
+                .ignore();
+        }
+        pipe.query(c)
+    })?;
+    if_match.verify(version)?;
+    db::changes::record_change(c, &store_id, ChangedItem::Store)?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::StoreRenamed,
+        &store_id,
+        json!({ "name": old_name }),
+        json!({ "name": new_name }),
+    )
+}
+
+pub fn get_name(c: &mut Connection, store_id: &StoreId) -> Result<String> {
+    let name: Option<String> = c.hget(&store_key(&store_id), STORE_NAME)?;
+    name.ok_or_else(|| ServerError::new(NOT_FOUND, "Store not found"))
+}
+
+pub fn get_version(c: &mut Connection, store_id: &StoreId) -> Result<u64> {
+    let version: Option<u64> = c.hget(&store_key(&store_id), STORE_VERSION)?;
+    version.ok_or_else(|| ServerError::new(NOT_FOUND, "Store not found"))
+}
+
+pub fn get_store_light(
+    c: &mut Connection,
+    store_id: &StoreId,
+    user_id: &UserId,
+) -> Result<StoreLight> {
+    let name = get_name(c, &store_id)?;
+    let role = get_role(c, &store_id, &user_id)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "Store member not found"))?;
+    Ok(StoreLight::new(name, store_id.to_string(), role))
 }
 
 pub fn get_all_stores(c: &mut Connection, auth: &Auth) -> Result<Vec<StoreLight>> {
     let user_id = db::sessions::get_user_id(c, &auth)?;
     let all_store_ids: Vec<String> = c.smembers(&user_stores_list_key(&user_id))?;
-    Ok(all_store_ids
+    all_store_ids
         .into_iter()
-        .map(|id| {
-            let name: String = c
-                .hget(&store_key(&StoreId::new(id.to_owned())), STORE_NAME)
-                .expect("Db is corrupted? Should have a store name.");
-            StoreLight::new(name, id)
-        })
-        .collect())
+        .map(|id| get_store_light(c, &StoreId::new(id), &user_id))
+        .collect()
 }
 
-pub fn delete_store(c: &mut Connection, auth: &Auth, store_id: &StoreId) -> Result<()> {
-    let owner_id = get_store_owner(c, &store_id)?;
-    db::verify_permission_auth(c, &auth, &owner_id)?;
+// into the owner's trash, its invitations are revoked
+pub fn delete_store(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    if_match: &IfMatch,
+) -> Result<()> {
+    let user_id = db::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
     let store_key = store_key(&store_id);
-    let user_stores_key = user_stores_list_key(&owner_id);
-    transaction(c, &[&store_key, &user_stores_key], |c, mut pipe| {
+    let store_members_key = store_members_key(&store_id);
+    let mut version = 0;
+    transaction(c, &[&store_key, &store_members_key], |c, mut pipe| {
+        version = c.hget(&store_key, STORE_VERSION)?;
+        if if_match.matches(version) {
+            db::invitations::transaction_purge_invitations(c, &mut pipe, &store_id)?;
+            let members: Vec<String> = c.hkeys(&store_members_key)?;
+            for member in members {
+                pipe.srem(&user_stores_list_key(&UserId(member)), store_id.to_string())
+                    .ignore();
+            }
+            db::trash::transaction_trash(pipe, &store_key, &user_id);
+        }
+        pipe.query(c)
+    })?;
+    if_match.verify(version)?;
+    let name = get_name(c, &store_id)?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::StoreDeleted,
+        &store_id,
+        json!({ "name": name }),
+        Value::Null,
+    )
+}
+
+// back in the lists of its members
+pub fn restore_store(c: &mut Connection, auth: &Auth, store_id: &StoreId) -> Result<()> {
+    let store_key = store_key(&store_id);
+    let user_id = db::trash::verify_in_trash(c, &auth, &store_key)?;
+    let store_members_key = store_members_key(&store_id);
+    transaction(c, &[&store_key, &store_members_key], |c, pipe| {
+        let members: Vec<String> = c.hkeys(&store_members_key)?;
+        for member in members {
+            pipe.sadd(&user_stores_list_key(&UserId(member)), store_id.to_string())
+                .ignore();
+        }
+        db::trash::transaction_restore(pipe, &store_key, &user_id);
+        pipe.query(c)
+    })?;
+    let name = get_name(c, &store_id)?;
+    db::history::record(
+        c,
+        &store_id,
+        &user_id,
+        AuditAction::StoreRestored,
+        &store_id,
+        Value::Null,
+        json!({ "name": name }),
+    )
+}
+
+// delete the store for good, with its aisles, products, invitations and history
+pub fn purge_store(c: &mut Connection, store_id: &StoreId) -> Result<()> {
+    let store_key = store_key(&store_id);
+    let store_members_key = store_members_key(&store_id);
+    let trashed_by = db::trash::trashed_by(c, &store_key)?;
+    transaction(c, &[&store_key, &store_members_key], |c, mut pipe| {
         db::aisles::transaction_purge_aisles_in_store(c, &mut pipe, &store_id)?;
-        pipe.srem(&user_stores_key, store_id.to_string())
+        db::invitations::transaction_purge_invitations(c, &mut pipe, &store_id)?;
+        db::changes::transaction_purge_changes(&mut pipe, &store_id);
+        db::history::transaction_purge_history(&mut pipe, &store_id);
+        let members: Vec<String> = c.hkeys(&store_members_key)?;
+        for member in members {
+            pipe.srem(&user_stores_list_key(&UserId(member)), store_id.to_string())
+                .ignore();
+        }
+        if let Some(user_id) = &trashed_by {
+            db::trash::transaction_discard(pipe, &store_key, &user_id);
+        }
+        pipe.del(&store_members_key)
             .ignore()
             .del(&store_key)
             .query(c)
@@ -89,18 +309,129 @@ pub fn delete_store(c: &mut Connection, auth: &Auth, store_id: &StoreId) -> Resu
     Ok(())
 }
 
+fn leave_store(c: &mut Connection, store_id: &StoreId, user_id: &UserId) -> Result<()> {
+    let store_members_key = store_members_key(&store_id);
+    let user_stores_key = user_stores_list_key(&user_id);
+    transaction(c, &[&store_members_key, &user_stores_key], |c, pipe| {
+        pipe.hdel(&store_members_key, &**user_id)
+            .ignore()
+            .srem(&user_stores_key, store_id.to_string())
+            .query(c)
+    })?;
+    Ok(())
+}
+
+// delete the stores owned by the user, the ones in their trash, and leave the ones shared
+// with them
 pub fn delete_all_user_stores(c: &mut Connection, auth: &Auth) -> Result<()> {
     let user_id = db::sessions::get_user_id(c, &auth)?;
     let user_stores_key = user_stores_list_key(&user_id);
     let stores: Option<Vec<String>> = c.smembers(&user_stores_key)?;
     if let Some(stores) = stores {
         for store_id in stores {
-            delete_store(c, &auth, &StoreId::new(store_id))?;
+            let store_id = StoreId::new(store_id);
+            if get_role(c, &store_id, &user_id)? == Some(Role::Owner) {
+                purge_store(c, &store_id)?;
+            } else {
+                leave_store(c, &store_id, &user_id)?;
+            }
         }
     }
+    db::trash::purge_user_trash(c, &user_id)
+}
+
+pub fn list_members(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+) -> Result<Vec<StoreMember>> {
+    db::verify_permission_auth(c, &auth, &store_id, Role::Viewer)?;
+    let user_ids: Vec<String> = c.hkeys(&store_members_key(&store_id))?;
+    let mut members = user_ids
+        .into_iter()
+        .filter_map(|id| {
+            let user_id = UserId(id);
+            // the member may have left since the ids were read
+            match get_role(c, &store_id, &user_id) {
+                Ok(Some(role)) => {
+                    Some(db::users::get_username(c, &user_id).map(|name| (role, name, user_id)))
+                }
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // by decreasing rights, then by name
+    members.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    Ok(members
+        .into_iter()
+        .map(|(role, username, user_id)| StoreMember::new(user_id.to_string(), username, role))
+        .collect())
+}
+
+pub fn set_member(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    username: &str,
+    role: Role,
+) -> Result<()> {
+    db::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    if role == Role::Owner {
+        return Err(ServerError::new(
+            PERMISSION_DENIED,
+            "A store can only have one owner",
+        ));
+    }
+    let user_id = db::users::get_user_id_by_name(c, username)?
+        .ok_or_else(|| ServerError::new(NOT_FOUND, "User not found"))?;
+    if get_role(c, &store_id, &user_id)? == Some(Role::Owner) {
+        return Err(ServerError::new(
+            PERMISSION_DENIED,
+            "The store owner's role cannot be changed",
+        ));
+    }
+    add_member(c, &store_id, &user_id, role)
+}
+
+pub fn add_member(
+    c: &mut Connection,
+    store_id: &StoreId,
+    user_id: &UserId,
+    role: Role,
+) -> Result<()> {
+    let store_members_key = store_members_key(&store_id);
+    let user_stores_key = user_stores_list_key(&user_id);
+    transaction(c, &[&store_members_key, &user_stores_key], |c, pipe| {
+        pipe.hset(&store_members_key, &**user_id, role.to_string())
+            .ignore()
+            .sadd(&user_stores_key, store_id.to_string())
+            .query(c)
+    })?;
     Ok(())
 }
 
+// the owner can remove anyone but themselves, other members can leave the store
+pub fn remove_member(
+    c: &mut Connection,
+    auth: &Auth,
+    store_id: &StoreId,
+    user_id: &UserId,
+) -> Result<()> {
+    let requester_id = db::sessions::get_user_id(c, &auth)?;
+    if requester_id != *user_id {
+        db::verify_permission_auth(c, &auth, &store_id, Role::Owner)?;
+    }
+    match get_role(c, &store_id, &user_id)? {
+        None => Err(ServerError::new(NOT_FOUND, "Member not found")),
+        Some(Role::Owner) => Err(ServerError::new(
+            PERMISSION_DENIED,
+            "The store owner cannot be removed",
+        )),
+        Some(_) => leave_store(c, &store_id, &user_id),
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -144,7 +475,16 @@ pub mod tests {
         let client = Client::open(get_db_addr().as_str()).unwrap();
         let mut c = client.get_connection().unwrap();
         let store_id = save_store_for_test(&mut c);
-        assert_eq!(Ok(()), edit_store(&mut c, &AUTH, &store_id, NEW_STORE_NAME));
+        assert_eq!(
+            Ok(()),
+            edit_store(
+                &mut c,
+                &AUTH,
+                &store_id,
+                NEW_STORE_NAME,
+                &IfMatch::default()
+            )
+        );
         let store_key = store_key(&store_id);
         assert_eq!(
             Ok(NEW_STORE_NAME.to_owned()),
@@ -160,12 +500,73 @@ pub mod tests {
         let store_id2 = save_store(&mut c, &AUTH, NEW_STORE_NAME).unwrap();
 
         let expected_stores = vec![
-            StoreLight::new(STORE_TEST_NAME.to_owned(), store_id.to_string()),
-            StoreLight::new(NEW_STORE_NAME.to_owned(), store_id2.to_string()),
+            StoreLight::new(
+                STORE_TEST_NAME.to_owned(),
+                store_id.to_string(),
+                Role::Owner,
+            ),
+            StoreLight::new(
+                NEW_STORE_NAME.to_owned(),
+                store_id2.to_string(),
+                Role::Owner,
+            ),
         ];
         assert_eq!(Ok(expected_stores), get_all_stores(&mut c, &AUTH));
     }
 
+    #[test]
+    fn shared_store_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let store_id = save_store_for_test(&mut c);
+        let mut user = gen_user();
+        user.username = "tata".to_owned();
+        let token = db::users::save_user(&mut c, &user).unwrap();
+        let auth = Auth(&token.session_token);
+        let member_id = UserId(token.user_id.clone());
+
+        assert_eq!(
+            Ok(()),
+            set_member(&mut c, &AUTH, &store_id, "TATA", Role::Editor)
+        );
+        assert_eq!(
+            Ok(Some(Role::Editor)),
+            get_role(&mut c, &store_id, &member_id)
+        );
+        assert_eq!(
+            Ok(true),
+            c.sismember(&user_stores_list_key(&member_id), store_id.to_string())
+        );
+        assert_eq!(
+            Ok(()),
+            edit_store(
+                &mut c,
+                &auth,
+                &store_id,
+                NEW_STORE_NAME,
+                &IfMatch::default()
+            )
+        );
+        assert_eq!(
+            Err(NOT_FOUND),
+            set_member(&mut c, &AUTH, &store_id, "titi", Role::Viewer).map_err(|e| e.status)
+        );
+        assert_eq!(
+            Err(PERMISSION_DENIED),
+            set_member(&mut c, &AUTH, &store_id, "toto", Role::Viewer).map_err(|e| e.status)
+        );
+        assert_eq!(
+            Err(PERMISSION_DENIED),
+            remove_member(&mut c, &AUTH, &store_id, &UserId(HASH_1.to_owned()))
+                .map_err(|e| e.status)
+        );
+
+        // a member deleting their account only leaves the store
+        assert_eq!(Ok(()), db::users::delete_user(&mut c, &auth, &member_id));
+        assert_eq!(Ok(None), get_role(&mut c, &store_id, &member_id));
+        assert_eq!(Ok(true), c.exists(&store_key(&store_id)));
+    }
+
     #[test]
     fn list_store_test() {
         let client = Client::open(get_db_addr().as_str()).unwrap();
@@ -216,6 +617,26 @@ pub mod tests {
         assert_eq!(Ok(expected), list_store(&mut c, &AUTH, &store_id));
     }
 
+    #[test]
+    fn duplicate_store_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let store_id = db::aisles::tests::get_aisles_in_store_for_test(&mut c);
+
+        let copy_id = duplicate_store(&mut c, &AUTH, &store_id, None, true).unwrap();
+        assert_ne!(store_id, copy_id);
+        assert_eq!(
+            list_store(&mut c, &AUTH, &store_id),
+            list_store(&mut c, &AUTH, &copy_id)
+        );
+        let user_id = UserId(HASH_1.to_owned());
+        assert_eq!(Ok(Some(Role::Owner)), get_role(&mut c, &copy_id, &user_id));
+        assert_eq!(
+            Ok(true),
+            c.sismember(&user_stores_list_key(&user_id), copy_id.to_string())
+        );
+    }
+
     #[test]
     fn delete_store_test() {
         let client = Client::open(get_db_addr().as_str()).unwrap();
@@ -225,13 +646,24 @@ pub mod tests {
         let aid2 = db::aisles::tests::add_2nd_aisle(&mut c, &store_id);
         let (p1, p2, p3) = db::aisles::tests::fill_aisles(&mut c, &aisle_id, &aid2);
 
-        assert_eq!(Ok(()), delete_store(&mut c, &AUTH, &store_id));
+        assert_eq!(
+            Ok(()),
+            delete_store(&mut c, &AUTH, &store_id, &IfMatch::default())
+        );
         assert_eq!(
             Ok(false),
             c.sismember(&user_stores_list_key(&UserId(HASH_1.to_owned())), 1u32)
         );
+        assert_eq!(
+            Ok(None),
+            get_role(&mut c, &store_id, &UserId(HASH_1.to_owned()))
+        );
+        assert_eq!(Ok(true), c.exists(&store_key(&store_id)));
+
+        assert_eq!(Ok(()), db::trash::purge_trash(&mut c, db::timestamp() + 1));
 
         assert_eq!(Ok(false), c.exists(&store_key(&store_id)));
+        assert_eq!(Ok(false), c.exists(&store_members_key(&store_id)));
         assert_eq!(
             Ok(false),
             c.exists(&db::aisles::tests::aisles_in_store_key(&store_id))
@@ -262,4 +694,350 @@ pub mod tests {
         );
         assert_eq!(Ok(false), c.exists(&db::aisles::tests::aisle_key(&aid2)));
     }
+
+    #[test]
+    fn store_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            assert_eq!(
+                Ok(()),
+                s.edit_store(&auth, &f.store_id, "store renamed", &IfMatch::default())
+            );
+            assert_eq!(
+                Ok(vec![StoreLight::new(
+                    "store renamed".to_owned(),
+                    f.store_id.to_string(),
+                    Role::Owner
+                )]),
+                s.get_all_stores(&auth)
+            );
+            assert_eq!(Ok("store renamed".to_owned()), s.store_name(&f.store_id));
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.store_name(&StoreId::new("unknown".to_owned()))
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(Ok(()), s.verify_role(&auth, &f.store_id, Role::Owner));
+
+            assert_eq!(
+                Ok(()),
+                s.edit_aisle(&auth, &f.aisle_id, "aisle renamed", &IfMatch::default())
+            );
+            let data = EditProduct::new(Some("product renamed".to_owned()), Some(2), None, None);
+            assert_eq!(
+                Ok(()),
+                s.modify_product(&auth, &data, &f.product_id, &IfMatch::default())
+            );
+            assert_eq!(Ok(f.store_id.clone()), s.aisle_store(&f.aisle_id));
+            assert_eq!(Ok(f.store_id.clone()), s.product_store(&f.product_id));
+            assert_eq!(
+                Ok("aisle renamed".to_owned()),
+                s.get_aisle(&f.aisle_id)
+                    .map(|aisle| aisle.name().to_owned())
+            );
+            assert_eq!(
+                Ok((f.aisle_id.to_string(), f.product_id.to_string())),
+                s.get_product(&f.product_id)
+                    .map(|(aisle_id, product)| (aisle_id.to_string(), product.id().to_string()))
+            );
+            assert_eq!(
+                Ok(Store::new(
+                    f.store_id.to_string(),
+                    "store renamed".to_owned(),
+                    vec![Aisle::new(
+                        f.aisle_id.to_string(),
+                        "aisle renamed".to_owned(),
+                        1.0,
+                        vec![Product::new(
+                            f.product_id.to_string(),
+                            "product renamed".to_owned(),
+                            2,
+                            false,
+                            Unit::Unit,
+                            1.0
+                        )]
+                    )]
+                )),
+                s.list_store(&auth, &f.store_id)
+            );
+        });
+    }
+
+    #[test]
+    fn delete_store_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let member = save_other_user(s, "tata");
+            let member_auth = Auth(&member.session_token);
+            assert_eq!(
+                Ok(()),
+                s.set_member(&auth, &f.store_id, "tata", Role::Viewer)
+            );
+
+            assert_eq!(
+                Ok(()),
+                s.delete_product(&auth, &f.product_id, &IfMatch::default())
+            );
+            assert_eq!(
+                Ok(()),
+                s.delete_aisle(&auth, &f.aisle_id, &IfMatch::default())
+            );
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.product_store(&f.product_id).map_err(|e| e.status)
+            );
+            assert_eq!(
+                Ok(()),
+                s.delete_store(&auth, &f.store_id, &IfMatch::default())
+            );
+            // for every member
+            assert_eq!(Ok(vec![]), s.get_all_stores(&auth));
+            assert_eq!(Ok(vec![]), s.get_all_stores(&member_auth));
+        });
+    }
+
+    #[test]
+    fn members_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let member = save_other_user(s, "tata");
+            let member_auth = Auth(&member.session_token);
+            let member_id = UserId(member.user_id.clone());
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.list_store(&member_auth, &f.store_id)
+                    .map(|_| ())
+                    .map_err(|e| e.status)
+            );
+
+            assert_eq!(
+                Ok(()),
+                s.set_member(&auth, &f.store_id, "tata", Role::Viewer)
+            );
+            assert_eq!(
+                Ok(vec![StoreLight::new(
+                    "store".to_owned(),
+                    f.store_id.to_string(),
+                    Role::Viewer
+                )]),
+                s.get_all_stores(&member_auth)
+            );
+            assert!(s.list_store(&member_auth, &f.store_id).is_ok());
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.verify_role(&member_auth, &f.store_id, Role::Editor)
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.edit_aisle(&member_auth, &f.aisle_id, "viewer", &IfMatch::default())
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.set_store_done(&member_auth, &f.store_id, true)
+                    .map(|_| ())
+                    .map_err(|e| e.status)
+            );
+
+            assert_eq!(
+                Ok(()),
+                s.set_member(&auth, &f.store_id, "tata", Role::Editor)
+            );
+            let data = EditProduct::new(None, Some(2), None, None);
+            assert_eq!(
+                Ok(()),
+                s.modify_product(&member_auth, &data, &f.product_id, &IfMatch::default())
+            );
+            // not a member of the destination
+            let other_store_id = s.save_store(&auth, "other store").unwrap();
+            let other_aisle_id = s
+                .save_aisle(&auth, &other_store_id, "other aisle")
+                .unwrap()
+                .id();
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.move_product(
+                    &member_auth,
+                    &f.product_id,
+                    &other_aisle_id,
+                    None,
+                    &IfMatch::default()
+                )
+                .map(|_| ())
+                .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.delete_store(&member_auth, &f.store_id, &IfMatch::default())
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.set_member(&member_auth, &f.store_id, "tata", Role::Viewer)
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Ok(vec![
+                    StoreMember::new(f.user_id.to_string(), "toto".to_owned(), Role::Owner),
+                    StoreMember::new(member_id.to_string(), "tata".to_owned(), Role::Editor),
+                ]),
+                s.list_members(&member_auth, &f.store_id)
+            );
+            assert_eq!(Ok(2), s.member_ids(&f.store_id).map(|m| m.len()));
+
+            assert_eq!(
+                Err(PERMISSION_DENIED),
+                s.remove_member(&member_auth, &f.store_id, &f.user_id)
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Ok(()),
+                s.remove_member(&member_auth, &f.store_id, &member_id)
+            );
+            assert_eq!(Ok(vec![]), s.get_all_stores(&member_auth));
+        });
+    }
+
+    #[test]
+    fn if_match_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            assert_eq!(
+                Ok(()),
+                s.edit_store(&auth, &f.store_id, "store renamed", &IfMatch::default())
+            );
+            assert_eq!(
+                Ok(()),
+                s.edit_aisle(&auth, &f.aisle_id, "aisle renamed", &IfMatch::default())
+            );
+            let data = EditProduct::new(Some("product renamed".to_owned()), None, None, None);
+            assert_eq!(
+                Ok(()),
+                s.modify_product(&auth, &data, &f.product_id, &IfMatch::default())
+            );
+            assert_eq!(Ok(FIRST_VERSION + 1), s.store_version(&f.store_id));
+            assert_eq!(Ok(FIRST_VERSION + 1), s.aisle_version(&f.aisle_id));
+            assert_eq!(Ok(FIRST_VERSION + 1), s.product_version(&f.product_id));
+            assert_eq!(
+                Ok(FIRST_VERSION + 1),
+                s.list_store(&auth, &f.store_id).map(|s| s.version())
+            );
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.aisle_version(&AisleId("unknown".to_owned()))
+                    .map_err(|e| e.status)
+            );
+
+            // nothing is written when the versions don't match
+            let stale = etag(FIRST_VERSION);
+            let stale = IfMatch(Some(&stale));
+            assert_eq!(
+                Err(VERSION_MISMATCH),
+                s.edit_store(&auth, &f.store_id, "stale", &stale)
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(VERSION_MISMATCH),
+                s.delete_aisle(&auth, &f.aisle_id, &stale)
+                    .map_err(|e| e.status)
+            );
+            let data = EditProduct::new(Some("stale".to_owned()), None, None, None);
+            assert_eq!(
+                Err(VERSION_MISMATCH),
+                s.modify_product(&auth, &data, &f.product_id, &stale)
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(VERSION_MISMATCH),
+                s.move_product(&auth, &f.product_id, &f.aisle_id, None, &stale)
+                    .map(|_| ())
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(Ok(FIRST_VERSION + 1), s.store_version(&f.store_id));
+            assert_eq!(Ok(FIRST_VERSION + 1), s.aisle_version(&f.aisle_id));
+            assert_eq!(Ok(FIRST_VERSION + 1), s.product_version(&f.product_id));
+            assert_eq!(Ok("store renamed".to_owned()), s.store_name(&f.store_id));
+        });
+    }
+
+    #[test]
+    fn duplicate_store_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let data = EditProduct::new(None, Some(2), Some(Unit::Gram), Some(true));
+            assert_eq!(
+                Ok(()),
+                s.modify_product(&auth, &data, &f.product_id, &IfMatch::default())
+            );
+            let copy_id = s
+                .duplicate_store(&auth, &f.store_id, Some("copy"), true)
+                .unwrap();
+            assert_eq!(Ok(()), s.verify_role(&auth, &copy_id, Role::Owner));
+            let original = serde_json::to_value(s.list_store(&auth, &f.store_id).unwrap()).unwrap();
+            let copy = serde_json::to_value(s.list_store(&auth, &copy_id).unwrap()).unwrap();
+            assert_eq!("copy", copy["name"]);
+            let (aisle, aisle_copy) = (&original["aisles"][0], &copy["aisles"][0]);
+            assert_ne!(aisle["aisle_id"], aisle_copy["aisle_id"]);
+            assert_eq!(aisle["name"], aisle_copy["name"]);
+            assert_eq!(aisle["sort_weight"], aisle_copy["sort_weight"]);
+            let (product, product_copy) = (&aisle["products"][0], &aisle_copy["products"][0]);
+            assert_ne!(product["product_id"], product_copy["product_id"]);
+            for field in &["name", "quantity", "unit", "sort_weight"] {
+                assert_eq!(product[field], product_copy[field]);
+            }
+            assert_eq!(true, product["is_done"]);
+            assert_eq!(false, product_copy["is_done"]);
+
+            // viewers can use the store as a template
+            let member = save_other_user(s, "tata");
+            let member_auth = Auth(&member.session_token);
+            assert_eq!(
+                Ok(()),
+                s.set_member(&auth, &f.store_id, "tata", Role::Viewer)
+            );
+            let copy_id = s
+                .duplicate_store(&member_auth, &f.store_id, None, false)
+                .unwrap();
+            assert_eq!(Ok(()), s.verify_role(&member_auth, &copy_id, Role::Owner));
+            assert_eq!(
+                s.list_store(&member_auth, &f.store_id),
+                s.list_store(&member_auth, &copy_id)
+            );
+        });
+    }
+
+    #[test]
+    fn import_store_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let data = EditProduct::new(None, Some(2), Some(Unit::Gram), Some(true));
+            assert_eq!(
+                Ok(()),
+                s.modify_product(&auth, &data, &f.product_id, &IfMatch::default())
+            );
+            // an export imported back is the same tree under new ids
+            let export = ExportedStore::from(s.list_store(&auth, &f.store_id).unwrap());
+            let import_id = s.import_store(&auth, &export).unwrap();
+            assert_ne!(f.store_id, import_id);
+            assert_eq!(Ok(()), s.verify_role(&auth, &import_id, Role::Owner));
+            let original = serde_json::to_value(s.list_store(&auth, &f.store_id).unwrap()).unwrap();
+            let import = serde_json::to_value(s.list_store(&auth, &import_id).unwrap()).unwrap();
+            assert_eq!(original["name"], import["name"]);
+            let (aisle, aisle_import) = (&original["aisles"][0], &import["aisles"][0]);
+            assert_ne!(aisle["aisle_id"], aisle_import["aisle_id"]);
+            assert_eq!(aisle["sort_weight"], aisle_import["sort_weight"]);
+            let (product, product_import) = (&aisle["products"][0], &aisle_import["products"][0]);
+            assert_ne!(product["product_id"], product_import["product_id"]);
+            for field in &["name", "quantity", "unit", "sort_weight", "is_done"] {
+                assert_eq!(product[field], product_import[field]);
+            }
+        });
+    }
 }
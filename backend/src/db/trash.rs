@@ -0,0 +1,258 @@
+#[cfg(not(test))]
+use redis::{Commands, Connection, Pipeline};
+
+#[cfg(test)]
+use fake_redis::{FakeConnection as Connection, FakePipeline as Pipeline};
+
+use crate::{db, error::*, types::*};
+
+const TRASHED_BY: &str = "trashed_by";
+const TRASHED_AT: &str = "trashed_at";
+// every trashed item of every user, for the purge
+const TRASHED_ITEMS: &str = "trashed_items";
+
+fn user_trash_key(user_id: &UserId) -> String {
+    format!("trash:{}", **user_id)
+}
+
+// the entries are the keys of the trashed hashes: `{kind}:{id}`
+fn parse_entry(key: &str) -> Result<(TrashedKind, String)> {
+    let mut parts = key.splitn(2, ':');
+    match (parts.next().map(str::parse), parts.next()) {
+        (Some(Ok(kind)), Some(id)) => Ok((kind, id.to_owned())),
+        _ => Err(ServerError::new(INTERNAL_ERROR, "Invalid trash entry")),
+    }
+}
+
+pub fn is_trashed(c: &mut Connection, key: &str) -> Result<bool> {
+    Ok(c.hexists(key, TRASHED_AT)?)
+}
+
+// `None` if the item isn't in a trash
+pub fn trashed_by(c: &mut Connection, key: &str) -> Result<Option<UserId>> {
+    let user_id: Option<String> = c.hget(key, TRASHED_BY)?;
+    Ok(user_id.map(UserId))
+}
+
+// only the user who deleted an item can restore it
+pub fn verify_in_trash(c: &mut Connection, auth: &Auth, key: &str) -> Result<UserId> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    if c.sismember(&user_trash_key(&user_id), key)? {
+        Ok(user_id)
+    } else {
+        Err(ServerError::new(NOT_FOUND, "Item not found in the trash"))
+    }
+}
+
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_trash(pipe: &mut Pipeline, key: &str, user_id: &UserId) {
+    pipe.hset(key, TRASHED_BY, &**user_id)
+        .ignore()
+        .hset(key, TRASHED_AT, db::timestamp())
+        .ignore()
+        .sadd(&user_trash_key(&user_id), key)
+        .ignore()
+        .sadd(TRASHED_ITEMS, key)
+        .ignore();
+}
+
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_restore(pipe: &mut Pipeline, key: &str, user_id: &UserId) {
+    pipe.hdel(key, TRASHED_BY)
+        .ignore()
+        .hdel(key, TRASHED_AT)
+        .ignore();
+    transaction_discard(pipe, key, &user_id);
+}
+
+// forget the entry of a purged item
+// to be used only in a transaction, doesn't execute the `pipe`
+pub fn transaction_discard(pipe: &mut Pipeline, key: &str, user_id: &UserId) {
+    pipe.srem(&user_trash_key(&user_id), key)
+        .ignore()
+        .srem(TRASHED_ITEMS, key)
+        .ignore();
+}
+
+// the latest deleted first
+pub fn list_trash(c: &mut Connection, auth: &Auth) -> Result<Vec<TrashedItem>> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    let keys: Vec<String> = c.smembers(&user_trash_key(&user_id))?;
+    let mut items = keys
+        .into_iter()
+        .map(|key| {
+            let (kind, id) = parse_entry(&key)?;
+            let name = match kind {
+                TrashedKind::Store => db::stores::get_name(c, &StoreId::new(id.clone()))?,
+                TrashedKind::Aisle => db::aisles::get_name(c, &AisleId(id.clone()))?,
+                TrashedKind::Product => db::products::get_name(c, &ProductId(id.clone()))?,
+            };
+            let trashed_at: i64 = c.hget(&key, TRASHED_AT)?;
+            Ok(TrashedItem::new(kind, id, name, trashed_at))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    items.sort_by(|a, b| b.trashed_at.cmp(&a.trashed_at));
+    Ok(items)
+}
+
+fn purge(c: &mut Connection, key: &str) -> Result<()> {
+    match parse_entry(key)? {
+        (TrashedKind::Store, id) => db::stores::purge_store(c, &StoreId::new(id)),
+        (TrashedKind::Aisle, id) => db::aisles::purge_aisle(c, &AisleId(id)),
+        (TrashedKind::Product, id) => db::products::purge_product(c, &ProductId(id)),
+    }
+}
+
+// `before` in seconds since the Unix epoch
+pub fn purge_trash(c: &mut Connection, before: i64) -> Result<()> {
+    let keys: Vec<String> = c.smembers(TRASHED_ITEMS)?;
+    for key in keys {
+        let trashed_at: Option<i64> = c.hget(&key, TRASHED_AT)?;
+        if trashed_at.map_or(false, |t| t < before) {
+            purge(c, &key)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn purge_user_trash(c: &mut Connection, user_id: &UserId) -> Result<()> {
+    let keys: Vec<String> = c.smembers(&user_trash_key(&user_id))?;
+    keys.iter().try_for_each(|key| purge(c, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{sessions::tests::*, tests::*};
+    use fake_redis::FakeCient as Client;
+
+    #[test]
+    fn trash_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let (store_id, aisle_id) = db::aisles::tests::save_aisle_for_test(&mut c);
+        let aisle2 = db::aisles::tests::add_2nd_aisle(&mut c, &store_id);
+        let (p1, _, p3) = db::aisles::tests::fill_aisles(&mut c, &aisle_id, &aisle2);
+
+        assert_eq!(
+            Ok(()),
+            db::products::delete_product(&mut c, &AUTH, &p1, &IfMatch::default())
+        );
+        assert_eq!(
+            Ok(()),
+            db::aisles::delete_aisle(&mut c, &AUTH, &aisle2, &IfMatch::default())
+        );
+        assert_eq!(Ok(2), list_trash(&mut c, &AUTH).map(|t| t.len()));
+        assert_eq!(Ok(None), db::products::get_product(&mut c, &p1));
+        // the products come back with their aisle
+        assert!(db::aisles::restore_aisle(&mut c, &AUTH, &aisle2).is_ok());
+        assert_eq!(
+            Ok(vec![p3.to_string()]),
+            db::products::get_products_in_aisle(&mut c, &aisle2)
+                .map(|p| p.iter().map(|p| p.id().to_string()).collect::<Vec<_>>())
+        );
+
+        assert_eq!(Ok(()), purge_trash(&mut c, db::timestamp() + 1));
+        assert_eq!(Ok(false), c.exists(&db::products::product_key(&p1)));
+        assert_eq!(Ok(true), c.exists(&db::products::product_key(&p3)));
+        assert_eq!(Ok(vec![]), list_trash(&mut c, &AUTH));
+        let items: Vec<String> = c.smembers(TRASHED_ITEMS).unwrap();
+        assert!(items.is_empty());
+    }
+
+    #[test]
+    fn trash_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            let copy_id = s.duplicate_store(&auth, &f.store_id, None, false).unwrap();
+            assert_eq!(Ok(()), s.delete_store(&auth, &copy_id, &IfMatch::default()));
+            let trashed_aisle_id = s.save_aisle(&auth, &f.store_id, "trashed").unwrap().id();
+            let trashed_id = s
+                .save_product(&auth, "trashed", &trashed_aisle_id)
+                .unwrap()
+                .id();
+            assert_eq!(
+                Ok(()),
+                s.delete_aisle(&auth, &trashed_aisle_id, &IfMatch::default())
+            );
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.product_store(&trashed_id).map_err(|e| e.status)
+            );
+            assert_eq!(
+                Ok(()),
+                s.delete_product(&auth, &f.product_id, &IfMatch::default())
+            );
+            let trash = s.list_trash(&auth).unwrap();
+            assert_eq!(3, trash.len());
+            assert!(trash
+                .iter()
+                .any(|i| i.kind == TrashedKind::Product && i.id == *f.product_id));
+            assert!(trash
+                .iter()
+                .any(|i| i.kind == TrashedKind::Aisle && i.id == *trashed_aisle_id));
+            assert!(trash
+                .iter()
+                .any(|i| i.kind == TrashedKind::Store && i.id == *copy_id));
+
+            // with its products
+            let (store_id, aisle) = s.restore_aisle(&auth, &trashed_aisle_id).unwrap();
+            assert_eq!(f.store_id, store_id);
+            assert_eq!(
+                *trashed_id,
+                serde_json::to_value(aisle).unwrap()["products"][0]["product_id"]
+            );
+            assert_eq!(
+                Ok(f.aisle_id.to_string()),
+                s.restore_product(&auth, &f.product_id)
+                    .map(|(aisle_id, _)| aisle_id.to_string())
+            );
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.restore_product(&auth, &f.product_id)
+                    .map(|_| ())
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(Ok(()), s.restore_store(&auth, &copy_id));
+            assert_eq!(Ok(()), s.verify_role(&auth, &copy_id, Role::Owner));
+            assert_eq!(Ok(vec![]), s.list_trash(&auth));
+
+            // only from their own trash
+            let member = save_other_user(s, "tata");
+            let member_auth = Auth(&member.session_token);
+            assert_eq!(
+                Ok(()),
+                s.set_member(&auth, &f.store_id, "tata", Role::Viewer)
+            );
+            let copy_id = s
+                .duplicate_store(&member_auth, &f.store_id, None, false)
+                .unwrap();
+            assert_eq!(
+                Ok(()),
+                s.delete_store(&member_auth, &copy_id, &IfMatch::default())
+            );
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.restore_store(&auth, &copy_id).map_err(|e| e.status)
+            );
+
+            assert_eq!(
+                Ok(()),
+                s.delete_aisle(&auth, &trashed_aisle_id, &IfMatch::default())
+            );
+            // nothing is old enough
+            assert_eq!(Ok(()), s.purge_trash(db::timestamp() - 60));
+            assert_eq!(Ok(1), s.list_trash(&auth).map(|t| t.len()));
+            assert_eq!(Ok(()), s.purge_trash(db::timestamp() + 1));
+            assert_eq!(Ok(vec![]), s.list_trash(&auth));
+            assert_eq!(Ok(vec![]), s.list_trash(&member_auth));
+            assert_eq!(
+                Err(NOT_FOUND),
+                s.restore_aisle(&auth, &trashed_aisle_id)
+                    .map(|_| ())
+                    .map_err(|e| e.status)
+            );
+        });
+    }
+}
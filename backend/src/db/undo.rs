@@ -0,0 +1,73 @@
+#[cfg(not(test))]
+use redis::{Commands, Connection};
+
+#[cfg(test)]
+use fake_redis::FakeConnection as Connection;
+
+use crate::{db, error::*, types::*};
+
+fn undo_key(user_id: &UserId) -> String {
+    format!("undo:{}", **user_id)
+}
+
+// on top of the user's undo history, dropping the oldest past `UNDO_LEN`
+pub fn push_undo(c: &mut Connection, auth: &Auth, op: &str) -> Result<()> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    let undo_key = undo_key(&user_id);
+    c.rpush(&undo_key, op)?;
+    Ok(c.ltrim(&undo_key, -(db::UNDO_LEN as isize), -1)?)
+}
+
+pub fn pop_undo(c: &mut Connection, auth: &Auth) -> Result<Option<String>> {
+    let user_id = db::sessions::get_user_id(c, &auth)?;
+    Ok(c.rpop(&undo_key(&user_id))?)
+}
+
+pub fn delete_user_undo(c: &mut Connection, user_id: &UserId) -> Result<()> {
+    Ok(c.del(&undo_key(&user_id))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{tests::*, users::tests::*};
+    use fake_redis::FakeCient as Client;
+
+    #[test]
+    fn undo_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let token = db::users::save_user(&mut c, &gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+
+        assert_eq!(Ok(None), pop_undo(&mut c, &auth));
+        for i in 0..db::UNDO_LEN + 1 {
+            assert_eq!(Ok(()), push_undo(&mut c, &auth, &i.to_string()));
+        }
+        assert_eq!(Ok(Some(db::UNDO_LEN.to_string())), pop_undo(&mut c, &auth));
+        for _ in 1..db::UNDO_LEN {
+            assert!(pop_undo(&mut c, &auth).unwrap().is_some());
+        }
+        // the oldest was dropped
+        assert_eq!(Ok(None), pop_undo(&mut c, &auth));
+
+        assert_eq!(Ok(()), push_undo(&mut c, &auth, "{}"));
+        let user_id = UserId(token.user_id.clone());
+        assert_eq!(Ok(()), delete_user_undo(&mut c, &user_id));
+        assert_eq!(Ok(false), c.exists(&undo_key(&user_id)));
+    }
+
+    #[test]
+    fn undo_storage_test() {
+        for_each_storage(|s| {
+            let token = s.save_user(&gen_user()).unwrap();
+            let auth = Auth(&token.session_token);
+            assert_eq!(Ok(None), s.pop_undo(&auth));
+            assert_eq!(Ok(()), s.push_undo(&auth, "1"));
+            assert_eq!(Ok(()), s.push_undo(&auth, "2"));
+            assert_eq!(Ok(Some("2".to_owned())), s.pop_undo(&auth));
+            assert_eq!(Ok(Some("1".to_owned())), s.pop_undo(&auth));
+            assert_eq!(Ok(None), s.pop_undo(&auth));
+        });
+    }
+}
@@ -1,4 +1,3 @@
-use hex_view::HexView;
 use rand::{self, Rng};
 
 #[cfg(test)]
@@ -18,15 +17,18 @@ const USER_SALT_M: &str = "salt_mail";
 const USER_SALT_P: &str = "salt_password";
 const USER_NAME: &str = "username";
 const USERS_LIST: &str = "users";
+const RESET_USER: &str = "user_id";
+const RESET_EXPIRES: &str = "expires_at";
+
+// validity of a password reset token, in seconds
+pub const RESET_TOKEN_TTL: i64 = 3600;
 
 fn user_key(user_id: &UserId) -> String {
     format!("user:{}", **user_id)
 }
 
-fn gen_auth(rng: &mut rand::rngs::ThreadRng) -> String {
-    let mut auth = [0u8; 32];
-    rng.fill(&mut auth[..]);
-    format!("{:x}", HexView::from(&auth))
+fn reset_key(token: &str) -> String {
+    format!("reset:{}", token)
 }
 
 pub fn save_user(c: &mut Connection, user: &User) -> Result<ConnectionToken> {
@@ -54,12 +56,21 @@ pub fn save_user(c: &mut Connection, user: &User) -> Result<ConnectionToken> {
             ],
         )?;
         c.hset(USERS_LIST, &norm_username, user_id.to_string())?;
-        let auth = gen_auth(&mut rng);
-        db::sessions::store_session(c, &auth, &user_id)?;
+        let auth = db::ids::gen_auth(&mut rng);
+        db::sessions::store_session(c, &auth, &user_id, &ClientInfo::default())?;
         Ok(ConnectionToken::new(auth, user_id.to_string()))
     }
 }
 
+pub fn get_user_id_by_name(c: &mut Connection, username: &str) -> Result<Option<UserId>> {
+    let user_id: Option<String> = c.hget(USERS_LIST, &username.to_lowercase())?;
+    Ok(user_id.map(UserId))
+}
+
+pub fn get_username(c: &mut Connection, user_id: &UserId) -> Result<String> {
+    Ok(c.hget(&user_key(&user_id), USER_NAME)?)
+}
+
 pub fn delete_user(c: &mut Connection, auth: &Auth, wanted_user_id: &UserId) -> Result<()> {
     let user_id = db::sessions::get_user_id(c, auth)?;
     if user_id == *wanted_user_id {
@@ -68,6 +79,8 @@ pub fn delete_user(c: &mut Connection, auth: &Auth, wanted_user_id: &UserId) ->
         db::stores::delete_all_user_stores(c, &auth)?;
         c.hdel(USERS_LIST, &username.to_lowercase())?;
         db::sessions::delete_all_user_sessions(c, auth)?;
+        db::events::delete_user_events(c, &user_id)?;
+        db::undo::delete_user_undo(c, &user_id)?;
         Ok(c.del(&user_key)?)
     } else {
         Err(ServerError::new(
@@ -77,21 +90,120 @@ pub fn delete_user(c: &mut Connection, auth: &Auth, wanted_user_id: &UserId) ->
     }
 }
 
-pub fn login(c: &mut Connection, auth_info: &AuthInfo) -> Result<ConnectionToken> {
+fn check_password(c: &mut Connection, user_id: &UserId, password: &str) -> Result<bool> {
+    let user_key = user_key(&user_id);
+    let salt_pwd: String = c.hget(&user_key, USER_SALT_P)?;
+    let stored_pwd: String = c.hget(&user_key, USER_PWD)?;
+    Ok(db::ids::hash(password, &salt_pwd) == stored_pwd)
+}
+
+pub fn change_password(
+    c: &mut Connection,
+    auth: &Auth,
+    wanted_user_id: &UserId,
+    old_password: &str,
+    new_password: &str,
+) -> Result<()> {
+    let user_id = db::sessions::get_user_id(c, auth)?;
+    if user_id != *wanted_user_id {
+        Err(ServerError::new(
+            error::UNAUTHORISED,
+            "x-auth-token does not belong to this user",
+        ))
+    } else if !check_password(c, &user_id, old_password)? {
+        Err(ServerError::new(
+            error::INVALID_USER_OR_PWD,
+            "Invalid password",
+        ))
+    } else {
+        set_password(c, &user_id, new_password)
+    }
+}
+
+fn set_password(c: &mut Connection, user_id: &UserId, password: &str) -> Result<()> {
+    let salt_pwd = rand::thread_rng().gen::<u64>().to_string();
+    let hashed_pwd = db::ids::hash(password, &salt_pwd);
+    Ok(c.hset_multiple(
+        &user_key(&user_id),
+        &[(USER_PWD, &hashed_pwd), (USER_SALT_P, &salt_pwd)],
+    )?)
+}
+
+pub fn create_reset_token(
+    c: &mut Connection,
+    username: &str,
+    email: &str,
+) -> Result<Option<String>> {
+    let user_id = match get_user_id_by_name(c, username)? {
+        Some(user_id) => user_id,
+        None => return Ok(None),
+    };
+    let user_key = user_key(&user_id);
+    let salt_mail: String = c.hget(&user_key, USER_SALT_M)?;
+    let stored_mail: String = c.hget(&user_key, USER_MAIL)?;
+    if db::ids::hash(email, &salt_mail) != stored_mail {
+        return Ok(None);
+    }
+    let token = db::ids::gen_auth(&mut rand::thread_rng());
+    let reset_key = reset_key(&token);
+    c.hset_multiple(
+        &reset_key,
+        &[
+            (RESET_USER, user_id.to_string()),
+            (
+                RESET_EXPIRES,
+                (db::timestamp() + RESET_TOKEN_TTL).to_string(),
+            ),
+        ],
+    )?;
+    // the expiry date is checked on use, this only cleans up unused tokens
+    c.expire(&reset_key, RESET_TOKEN_TTL as usize)?;
+    Ok(Some(token))
+}
+
+pub fn reset_password(c: &mut Connection, token: &str, new_password: &str) -> Result<()> {
+    let reset_key = reset_key(token);
+    let user_id: Option<String> = c.hget(&reset_key, RESET_USER)?;
+    let expires_at: Option<i64> = c.hget(&reset_key, RESET_EXPIRES)?;
+    // whoever deletes the token gets to use it
+    let deleted: i32 = c.del(&reset_key)?;
+    match (user_id, expires_at) {
+        (Some(user_id), Some(expires_at)) if deleted == 1 && expires_at >= db::timestamp() => {
+            let user_id = UserId(user_id);
+            if c.exists(&user_key(&user_id))? {
+                set_password(c, &user_id, new_password)?;
+                db::sessions::delete_user_id_sessions(c, &user_id)
+            } else {
+                Err(ServerError::new(
+                    error::UNAUTHORISED,
+                    "Invalid or expired reset token",
+                ))
+            }
+        }
+        _ => Err(ServerError::new(
+            error::UNAUTHORISED,
+            "Invalid or expired reset token",
+        )),
+    }
+}
+
+pub fn login(
+    c: &mut Connection,
+    auth_info: &AuthInfo,
+    client: &ClientInfo,
+    ttl: &db::sessions::SessionTtl,
+) -> Result<ConnectionToken> {
     let user_id = UserId(
         c.hget(USERS_LIST, &auth_info.username.to_lowercase())
             .map_err(|_| {
                 ServerError::new(error::INVALID_USER_OR_PWD, "Invalid usename or password")
             })?,
     );
-    let user_key = user_key(&user_id);
-    let salt_pwd: String = c.hget(&user_key, USER_SALT_P)?;
-    let stored_pwd: String = c.hget(&user_key, USER_PWD)?;
-    let hashed_pwd = db::ids::hash(&auth_info.password, &salt_pwd);
-    if hashed_pwd == stored_pwd {
+    if check_password(c, &user_id, &auth_info.password)? {
+        db::sessions::sweep_expired_sessions(c, &user_id, ttl)?;
         let mut rng = rand::thread_rng();
-        let auth = gen_auth(&mut rng);
-        db::sessions::store_session(c, &auth, &user_id)?;
+        let auth = db::ids::gen_auth(&mut rng);
+        db::sessions::store_session(c, &auth, &user_id, client)?;
         Ok(ConnectionToken::new(auth, user_id.to_string()))
     } else {
         Err(ServerError::new(
@@ -104,7 +216,7 @@ pub fn login(c: &mut Connection, auth_info: &AuthInfo) -> Result<ConnectionToken
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::db::{ids::tests::*, tests::*};
+    use crate::db::{ids::tests::*, sessions::tests::TTL, tests::*};
     use fake_redis::FakeCient as Client;
 
     pub fn gen_user() -> User {
@@ -176,7 +288,7 @@ pub mod tests {
             username: "toto".to_string(),
             password: "pwd".to_string(),
         };
-        let res = login(&mut c, &login_data);
+        let res = login(&mut c, &login_data, &ClientInfo::default(), &TTL);
         if res.is_err() {
             dbg!(&res);
         }
@@ -186,7 +298,7 @@ pub mod tests {
             username: "toto".to_string(),
             password: "pwdb".to_string(),
         };
-        let res = login(&mut c, &login_data);
+        let res = login(&mut c, &login_data, &ClientInfo::default(), &TTL);
         if res.is_ok() {
             dbg!(&res);
         }
@@ -196,7 +308,7 @@ pub mod tests {
             username: "tato".to_string(),
             password: "pwd".to_string(),
         };
-        let res = login(&mut c, &login_data);
+        let res = login(&mut c, &login_data, &ClientInfo::default(), &TTL);
         if res.is_ok() {
             dbg!(&res);
         }
@@ -236,4 +348,154 @@ pub mod tests {
         assert_eq!(Ok(true), c.exists(&format!("user:{}", HASH_2)));
         assert_eq!(Ok(false), c.exists(&format!("user:{}", HASH_3)));
     }
+
+    #[test]
+    fn change_password_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let token = store_user_for_test(&mut c);
+        let auth = Auth(&token.session_token);
+        let user_id = UserId(token.user_id.clone());
+        let old_salt: String = c.hget(&user_key(&user_id), USER_SALT_P).unwrap();
+        assert_eq!(
+            Err(error::INVALID_USER_OR_PWD),
+            change_password(&mut c, &auth, &user_id, "wrong", "newpwd").map_err(|e| e.status)
+        );
+        assert_eq!(
+            Err(error::UNAUTHORISED),
+            change_password(&mut c, &auth, &UserId(HASH_2.to_owned()), "pwd", "newpwd")
+                .map_err(|e| e.status)
+        );
+        assert_eq!(
+            Ok(()),
+            change_password(&mut c, &auth, &user_id, "pwd", "newpwd")
+        );
+        assert_ne!(
+            Ok(old_salt),
+            c.hget::<String>(&user_key(&user_id), USER_SALT_P)
+        );
+        assert_eq!(Ok(false), check_password(&mut c, &user_id, "pwd"));
+        assert_eq!(Ok(true), check_password(&mut c, &user_id, "newpwd"));
+    }
+
+    #[test]
+    fn reset_password_test() {
+        let client = Client::open(get_db_addr().as_str()).unwrap();
+        let mut c = client.get_connection().unwrap();
+        let token = store_user_for_test(&mut c);
+        let user_id = UserId(token.user_id.clone());
+        assert_eq!(Ok(None), create_reset_token(&mut c, "toto", "other@m.com"));
+        assert_eq!(Ok(None), create_reset_token(&mut c, "tata", "m@m.com"));
+
+        let reset_token = create_reset_token(&mut c, "ToTo", "m@m.com")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Ok(HASH_1.to_owned()),
+            c.hget(&reset_key(&reset_token), RESET_USER)
+        );
+        assert_eq!(Ok(()), reset_password(&mut c, &reset_token, "newpwd"));
+        assert_eq!(Ok(true), check_password(&mut c, &user_id, "newpwd"));
+        assert_eq!(Ok(false), c.exists(&format!("sessions:{}", HASH_1)));
+        // single use
+        assert_eq!(
+            Err(error::UNAUTHORISED),
+            reset_password(&mut c, &reset_token, "otherpwd").map_err(|e| e.status)
+        );
+
+        let reset_token = create_reset_token(&mut c, "toto", "m@m.com")
+            .unwrap()
+            .unwrap();
+        let _: i32 = c
+            .hset(&reset_key(&reset_token), RESET_EXPIRES, db::timestamp() - 1)
+            .unwrap();
+        assert_eq!(
+            Err(error::UNAUTHORISED),
+            reset_password(&mut c, &reset_token, "otherpwd").map_err(|e| e.status)
+        );
+        assert_eq!(Ok(true), check_password(&mut c, &user_id, "newpwd"));
+    }
+
+    #[test]
+    fn user_storage_test() {
+        for_each_storage(|s| {
+            let token = s.save_user(&gen_user()).unwrap();
+            assert_eq!(Ok(()), s.validate_session(&Auth(&token.session_token)));
+            assert_eq!(
+                Err(USERNAME_TAKEN),
+                s.save_user(&gen_user()).map(|_| ()).map_err(|e| e.status)
+            );
+            let token = login_with(s, "pwd").unwrap();
+            assert_eq!(Ok(()), s.validate_session(&Auth(&token.session_token)));
+            assert_eq!(
+                Err(INVALID_USER_OR_PWD),
+                login_with(s, "wrong").map(|_| ()).map_err(|e| e.status)
+            );
+        });
+    }
+
+    #[test]
+    fn delete_user_storage_test() {
+        for_each_storage(|s| {
+            let f = Fixture::new(s);
+            let auth = f.auth();
+            assert_eq!(Ok(()), s.delete_user(&auth, &f.user_id));
+            assert_eq!(
+                Err(UNAUTHORISED),
+                s.validate_session(&auth).map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(INVALID_USER_OR_PWD),
+                login_with(s, "pwd").map(|_| ()).map_err(|e| e.status)
+            );
+            // the name is free again
+            assert!(s.save_user(&gen_user()).is_ok());
+        });
+    }
+
+    #[test]
+    fn change_password_storage_test() {
+        for_each_storage(|s| {
+            let token = s.save_user(&gen_user()).unwrap();
+            let auth = Auth(&token.session_token);
+            let user_id = UserId(token.user_id.clone());
+            assert_eq!(
+                Ok(()),
+                s.change_password(&auth, &user_id, "pwd", "new password")
+            );
+            assert_eq!(
+                Err(INVALID_USER_OR_PWD),
+                s.change_password(&auth, &user_id, "pwd", "new password")
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(INVALID_USER_OR_PWD),
+                login_with(s, "pwd").map(|_| ()).map_err(|e| e.status)
+            );
+            assert_eq!(Ok(()), login_with(s, "new password").map(|_| ()));
+        });
+    }
+
+    #[test]
+    fn reset_password_storage_test() {
+        for_each_storage(|s| {
+            let token = s.save_user(&gen_user()).unwrap();
+            let auth = Auth(&token.session_token);
+            assert_eq!(Ok(None), s.create_reset_token("toto", "wrong@m.com"));
+            assert_eq!(Ok(None), s.create_reset_token("unknown", "m@m.com"));
+            let reset_token = s.create_reset_token("toto", "m@m.com").unwrap().unwrap();
+            assert_eq!(Ok(()), s.reset_password(&reset_token, "reset password"));
+            // every session is closed
+            assert_eq!(
+                Err(UNAUTHORISED),
+                s.validate_session(&auth).map_err(|e| e.status)
+            );
+            assert_eq!(
+                Err(UNAUTHORISED),
+                s.reset_password(&reset_token, "again")
+                    .map_err(|e| e.status)
+            );
+            assert_eq!(Ok(()), login_with(s, "reset password").map(|_| ()));
+        });
+    }
 }
@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+
+use crate::{
+    db::Storage,
+    endpoints::{undo, INVALID_PARAMS},
+    error::*,
+    types::*,
+};
+
+// the stores the user owns, the shared ones are their owner's to export
+pub async fn export_account(auth: String, s: &mut dyn Storage) -> Result<AccountExport> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let mut stores = vec![];
+    for store in s.get_all_stores(&auth)? {
+        if store.role() == Role::Owner {
+            stores.push(ExportedStore::from(s.list_store(&auth, &store.id())?));
+        }
+    }
+    Ok(AccountExport::new(EXPORT_VERSION, stores))
+}
+
+// a store named like one the user already has is left out and reported
+pub async fn import_account(
+    auth: String,
+    data: &AccountExport,
+    s: &mut dyn Storage,
+) -> Result<ImportReport> {
+    if data.version != EXPORT_VERSION {
+        return Err(ServerError::new(
+            INVALID_PARAMS,
+            &format!("Unsupported export version {}", data.version),
+        ));
+    }
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let mut names: HashSet<String> = s
+        .get_all_stores(&auth)?
+        .iter()
+        .map(|store| store.name().to_owned())
+        .collect();
+    let mut imported = vec![];
+    let mut conflicts = vec![];
+    for store in &data.stores {
+        if !names.insert(store.name.clone()) {
+            conflicts.push(store.name.clone());
+            continue;
+        }
+        let store_id = s.import_store(&auth, store)?;
+        let version = s.store_version(&store_id);
+        undo::record_at(s, &auth, version, |version| UndoOp::DeleteStore {
+            store_id: store_id.to_string(),
+            version,
+        });
+        imported.push(StoreLight::new(
+            store.name.clone(),
+            store_id.to_string(),
+            Role::Owner,
+        ));
+    }
+    Ok(ImportReport::new(imported, conflicts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sessions::tests::TTL;
+    use crate::db::{sqlite::tests::open_for_test, sqlite::SqliteStorage, users::tests::*};
+    use crate::endpoints::store;
+
+    #[tokio::test]
+    async fn export_import_test() {
+        let mut c = open_for_test();
+        let mut s = SqliteStorage::new(&mut c, TTL);
+        let token = s.save_user(&gen_user()).unwrap();
+        let auth = token.session_token.clone();
+        let mut user = gen_user();
+        user.username = "other".to_owned();
+        let other = s.save_user(&user).unwrap().session_token;
+        let name_data = |name: &str| NameData {
+            name: name.to_owned(),
+        };
+        store::create_store(auth.clone(), &name_data("store"), &mut s)
+            .await
+            .unwrap();
+        store::create_store(other.clone(), &name_data("shared"), &mut s)
+            .await
+            .unwrap();
+
+        let export = export_account(auth.clone(), &mut s).await.unwrap();
+        assert_eq!(1, export.stores.len());
+        let json = serde_json::to_string(&export).unwrap();
+        let export: AccountExport = serde_json::from_str(&json).unwrap();
+        // the user already has it
+        assert_eq!(
+            Ok(ImportReport::new(vec![], vec!["store".to_owned()])),
+            import_account(auth.clone(), &export, &mut s).await
+        );
+        let report = import_account(other.clone(), &export, &mut s).await;
+        let report = serde_json::to_value(report.unwrap()).unwrap();
+        assert_eq!("store", report["imported"][0]["name"]);
+        assert_eq!(0, report["conflicts"].as_array().unwrap().len());
+        assert_eq!(Ok(2), s.get_all_stores(&Auth(&other)).map(|s| s.len()));
+
+        let unknown = AccountExport::new(EXPORT_VERSION + 1, vec![]);
+        assert_eq!(
+            Err(INVALID_PARAMS),
+            import_account(other, &unknown, &mut s)
+                .await
+                .map_err(|e| e.status)
+        );
+    }
+}
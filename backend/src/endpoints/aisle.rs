@@ -1,35 +1,198 @@
-use crate::{db, error::Result, types::*};
-
-#[cfg(not(test))]
-use redis::Connection;
-
-#[cfg(test)]
-use fake_redis::FakeConnection as Connection;
+use crate::{
+    db::Storage,
+    endpoints::undo,
+    error::Result,
+    events::{EventBus, StoreEvent},
+    types::*,
+};
 
 pub async fn create_aisle(
     auth: String,
     store_id: String,
     data: &NameData,
-    c: &mut Connection,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<Aisle> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let store_id = StoreId::new(store_id);
+    let aisle = s.save_aisle(&auth, &store_id, &data.name)?;
+    undo::record(
+        s,
+        &auth,
+        UndoOp::DeleteAisle {
+            aisle_id: aisle.id().to_string(),
+            version: aisle.version(),
+        },
+    );
+    events.publish(
+        s,
+        &store_id,
+        StoreEvent::AisleCreated {
+            aisle: aisle.clone(),
+        },
+    );
+    Ok(aisle)
+}
+
+// the aisle comes back with its products
+pub async fn restore_aisle(
+    auth: String,
+    aisle_id: String,
+    s: &mut dyn Storage,
+    events: &EventBus,
 ) -> Result<Aisle> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::aisles::save_aisle(c, &auth, &StoreId::new(store_id), &data.name)
+    s.validate_session(&auth)?;
+    let (store_id, aisle) = s.restore_aisle(&auth, &AisleId(aisle_id))?;
+    undo::record(
+        s,
+        &auth,
+        UndoOp::DeleteAisle {
+            aisle_id: aisle.id().to_string(),
+            version: aisle.version(),
+        },
+    );
+    events.publish(
+        s,
+        &store_id,
+        StoreEvent::AisleCreated {
+            aisle: aisle.clone(),
+        },
+    );
+    Ok(aisle)
 }
 
 pub async fn rename_aisle(
     auth: String,
     aisle_id: String,
     data: &NameData,
-    c: &mut Connection,
+    if_match: Option<String>,
+    s: &mut dyn Storage,
+    events: &EventBus,
 ) -> Result<()> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::aisles::edit_aisle(c, &auth, &AisleId(aisle_id), &data.name)
+    s.validate_session(&auth)?;
+    let aisle_id = AisleId(aisle_id);
+    let store_id = s.aisle_store(&aisle_id)?;
+    let previous = s.get_aisle(&aisle_id)?;
+    s.edit_aisle(&auth, &aisle_id, &data.name, &IfMatch(if_match.as_deref()))?;
+    let version = s.aisle_version(&aisle_id);
+    undo::record_at(s, &auth, version, |version| UndoOp::RenameAisle {
+        aisle_id: aisle_id.to_string(),
+        name: previous.name().to_owned(),
+        version,
+    });
+    events.publish(
+        s,
+        &store_id,
+        StoreEvent::AisleRenamed {
+            aisle_id: aisle_id.to_string(),
+            name: data.name.to_owned(),
+        },
+    );
+    Ok(())
 }
 
-pub async fn delete_aisle(auth: String, aisle_id: String, c: &mut Connection) -> Result<()> {
+pub async fn move_aisle(
+    auth: String,
+    aisle_id: String,
+    data: &MoveAisle,
+    if_match: Option<String>,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<Aisle> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let aisle_id = AisleId(aisle_id);
+    let from_store_id = s.aisle_store(&aisle_id)?;
+    let to_store_id = StoreId::new(data.store_id.clone());
+    let previous = s.get_aisle(&aisle_id)?;
+    let aisle = s.move_aisle(
+        &auth,
+        &aisle_id,
+        &to_store_id,
+        None,
+        &IfMatch(if_match.as_deref()),
+    )?;
+    undo::record(
+        s,
+        &auth,
+        UndoOp::MoveAisle {
+            aisle_id: aisle_id.to_string(),
+            store_id: from_store_id.to_string(),
+            sort_weight: previous.sort_weight,
+            version: aisle.version(),
+        },
+    );
+    publish_move(s, events, &from_store_id, &to_store_id, &aisle);
+    Ok(aisle)
+}
+
+pub(super) fn publish_move(
+    s: &mut dyn Storage,
+    events: &EventBus,
+    from_store_id: &StoreId,
+    to_store_id: &StoreId,
+    aisle: &Aisle,
+) {
+    let aisle_id = aisle.id();
+    if from_store_id == to_store_id {
+        // only its place changed
+        events.publish(
+            s,
+            to_store_id,
+            StoreEvent::SortWeightChanged {
+                aisles: vec![AisleItemWeight::new(
+                    aisle_id.to_string(),
+                    aisle.sort_weight,
+                )],
+                products: vec![],
+            },
+        );
+    } else {
+        events.publish(
+            s,
+            from_store_id,
+            StoreEvent::AisleDeleted {
+                aisle_id: aisle_id.to_string(),
+            },
+        );
+        events.publish(
+            s,
+            to_store_id,
+            StoreEvent::AisleCreated {
+                aisle: aisle.clone(),
+            },
+        );
+    }
+}
+
+pub async fn delete_aisle(
+    auth: String,
+    aisle_id: String,
+    if_match: Option<String>,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<()> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::aisles::delete_aisle(c, &auth, &AisleId(aisle_id))
+    s.validate_session(&auth)?;
+    let aisle_id = AisleId(aisle_id);
+    let store_id = s.aisle_store(&aisle_id)?;
+    s.delete_aisle(&auth, &aisle_id, &IfMatch(if_match.as_deref()))?;
+    undo::record(
+        s,
+        &auth,
+        UndoOp::RestoreAisle {
+            aisle_id: aisle_id.to_string(),
+        },
+    );
+    events.publish(
+        s,
+        &store_id,
+        StoreEvent::AisleDeleted {
+            aisle_id: aisle_id.to_string(),
+        },
+    );
+    Ok(())
 }
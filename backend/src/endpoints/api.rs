@@ -0,0 +1,147 @@
+// The routes of the API, served by `routes.rs` on their method and path and described by
+// `openapi.rs`
+
+pub struct ApiRoute {
+    pub method: &'static str,
+    // relative to `/api/v1`, parameters as `{name}`
+    pub path: &'static str,
+}
+
+const fn route(method: &'static str, path: &'static str) -> ApiRoute {
+    ApiRoute { method, path }
+}
+
+impl ApiRoute {
+    // `path` is what follows the version in the path of a request
+    pub fn matches(&self, path: &str) -> bool {
+        let mut pattern = self.path.split('/').filter(|part| !part.is_empty());
+        let mut parts = path.split('/').filter(|part| !part.is_empty());
+        loop {
+            match (pattern.next(), parts.next()) {
+                (None, None) => return true,
+                (Some(p), Some(part)) if p.starts_with('{') || p == part => continue,
+                _ => return false,
+            }
+        }
+    }
+}
+
+pub const NUKE: ApiRoute = route("post", "/nuke");
+pub const CREATE_USER: ApiRoute = route("post", "/user");
+pub const LOGIN: ApiRoute = route("post", "/login");
+pub const LOGOUT: ApiRoute = route("post", "/logout/{user_id}");
+pub const REQUEST_PASSWORD_RESET: ApiRoute = route("post", "/password_reset");
+pub const RESET_PASSWORD: ApiRoute = route("post", "/password_reset/{token}");
+pub const LIST_SESSIONS: ApiRoute = route("get", "/session");
+pub const REVOKE_SESSION: ApiRoute = route("delete", "/session/{session_id}");
+pub const REVOKE_OTHER_SESSIONS: ApiRoute = route("delete", "/session");
+pub const DELETE_USER: ApiRoute = route("delete", "/user/{user_id}");
+pub const CHANGE_PASSWORD: ApiRoute = route("put", "/user/{user_id}/password");
+pub const CREATE_STORE: ApiRoute = route("post", "/store");
+pub const DUPLICATE_STORE: ApiRoute = route("post", "/store/{store_id}/duplicate");
+pub const EDIT_STORE: ApiRoute = route("put", "/store/{store_id}");
+pub const CREATE_AISLE: ApiRoute = route("post", "/store/{store_id}/aisle");
+pub const EDIT_AISLE: ApiRoute = route("put", "/aisle/{aisle_id}");
+pub const CREATE_PRODUCT: ApiRoute = route("post", "/aisle/{aisle_id}/product");
+pub const EDIT_PRODUCT: ApiRoute = route("put", "/product/{product_id}");
+pub const MOVE_PRODUCT: ApiRoute = route("post", "/product/{product_id}/move");
+pub const MOVE_AISLE: ApiRoute = route("post", "/aisle/{aisle_id}/move");
+pub const GET_ALL_STORES: ApiRoute = route("get", "/store");
+pub const LIST_STORE: ApiRoute = route("get", "/store/{store_id}");
+pub const STORE_CHANGES: ApiRoute = route("get", "/store/{store_id}/changes");
+pub const STORE_HISTORY: ApiRoute = route("get", "/store/{store_id}/history");
+pub const WATCH_STORE: ApiRoute = route("get", "/store/{store_id}/ws");
+pub const USER_FEED: ApiRoute = route("get", "/feed");
+pub const CREATE_INVITATION: ApiRoute = route("post", "/store/{store_id}/invitation");
+pub const LIST_INVITATIONS: ApiRoute = route("get", "/store/{store_id}/invitation");
+pub const REVOKE_INVITATION: ApiRoute = route("delete", "/store/{store_id}/invitation/{code}");
+pub const REDEEM_INVITATION: ApiRoute = route("post", "/invitation/{code}");
+pub const LIST_MEMBERS: ApiRoute = route("get", "/store/{store_id}/member");
+pub const SET_MEMBER: ApiRoute = route("put", "/store/{store_id}/member");
+pub const REMOVE_MEMBER: ApiRoute = route("delete", "/store/{store_id}/member/{user_id}");
+pub const DELETE_PRODUCT: ApiRoute = route("delete", "/product/{product_id}");
+pub const DELETE_AISLE: ApiRoute = route("delete", "/aisle/{aisle_id}");
+pub const DELETE_STORE: ApiRoute = route("delete", "/store/{store_id}");
+pub const UNDO: ApiRoute = route("post", "/undo");
+pub const LIST_TRASH: ApiRoute = route("get", "/trash");
+pub const RESTORE_STORE: ApiRoute = route("post", "/store/{store_id}/restore");
+pub const RESTORE_AISLE: ApiRoute = route("post", "/aisle/{aisle_id}/restore");
+pub const RESTORE_PRODUCT: ApiRoute = route("post", "/product/{product_id}/restore");
+pub const SET_STORE_DONE: ApiRoute = route("put", "/store/{store_id}/done");
+pub const SET_AISLE_DONE: ApiRoute = route("put", "/aisle/{aisle_id}/done");
+pub const CLEAR_DONE_PRODUCTS: ApiRoute = route("post", "/store/{store_id}/clear_done");
+pub const CHANGE_SORT_WEIGHT: ApiRoute = route("put", "/sort_weight");
+pub const APPLY_BATCH: ApiRoute = route("post", "/batch");
+pub const EXPORT_ACCOUNT: ApiRoute = route("get", "/export");
+pub const IMPORT_ACCOUNT: ApiRoute = route("post", "/import");
+pub const OPENAPI_DOC: ApiRoute = route("get", "/openapi.json");
+
+// checked against the OpenAPI document
+#[cfg(test)]
+pub const ALL: &[ApiRoute] = &[
+    NUKE,
+    CREATE_USER,
+    LOGIN,
+    LOGOUT,
+    REQUEST_PASSWORD_RESET,
+    RESET_PASSWORD,
+    LIST_SESSIONS,
+    REVOKE_SESSION,
+    REVOKE_OTHER_SESSIONS,
+    DELETE_USER,
+    CHANGE_PASSWORD,
+    CREATE_STORE,
+    DUPLICATE_STORE,
+    EDIT_STORE,
+    CREATE_AISLE,
+    EDIT_AISLE,
+    CREATE_PRODUCT,
+    EDIT_PRODUCT,
+    MOVE_PRODUCT,
+    MOVE_AISLE,
+    GET_ALL_STORES,
+    LIST_STORE,
+    STORE_CHANGES,
+    STORE_HISTORY,
+    WATCH_STORE,
+    USER_FEED,
+    CREATE_INVITATION,
+    LIST_INVITATIONS,
+    REVOKE_INVITATION,
+    REDEEM_INVITATION,
+    LIST_MEMBERS,
+    SET_MEMBER,
+    REMOVE_MEMBER,
+    DELETE_PRODUCT,
+    DELETE_AISLE,
+    DELETE_STORE,
+    UNDO,
+    LIST_TRASH,
+    RESTORE_STORE,
+    RESTORE_AISLE,
+    RESTORE_PRODUCT,
+    SET_STORE_DONE,
+    SET_AISLE_DONE,
+    CLEAR_DONE_PRODUCTS,
+    CHANGE_SORT_WEIGHT,
+    APPLY_BATCH,
+    EXPORT_ACCOUNT,
+    IMPORT_ACCOUNT,
+    OPENAPI_DOC,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_test() {
+        assert!(LIST_STORE.matches("store/123"));
+        assert!(LIST_STORE.matches("store/123/"));
+        assert!(!LIST_STORE.matches("store"));
+        assert!(!LIST_STORE.matches("store/123/aisle"));
+        assert!(REVOKE_INVITATION.matches("store/123/invitation/abc"));
+        assert!(!REVOKE_INVITATION.matches("store/123/member/abc"));
+        assert!(GET_ALL_STORES.matches("store"));
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::Value;
+
+use crate::{
+    db::Storage,
+    endpoints::{aisle, misc, product, store, INVALID_PARAMS},
+    error::*,
+    events::EventBus,
+    types::*,
+};
+
+const MAX_BATCH_LEN: usize = 100;
+
+// Operations are applied in order, each one atomically, and the first failure skips the
+// rest. Applied operations are remembered by `op_id`, so a client replaying a batch after
+// a dropped response gets their results back instead of applying them again. An
+// operation still held by another request fails, it is never applied twice. The events
+// of an operation are published once it is committed.
+pub async fn apply_batch(
+    auth: String,
+    batch: &Batch,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<BatchResults> {
+    let session = Auth(&auth);
+    s.validate_session(&session)?;
+    validate_batch(batch)?;
+    let mut created: HashMap<&str, String> = HashMap::new();
+    let mut results = Vec::with_capacity(batch.operations.len());
+    let mut failed = false;
+    for op in &batch.operations {
+        let status = if failed {
+            OpStatus::Skipped
+        } else if let Some(result) = s.op_result(&session, &op.op_id)? {
+            OpStatus::Applied {
+                result: serde_json::from_str(&result)?,
+                replayed: true,
+            }
+        } else if !s.begin_op(&session, &op.op_id)? {
+            failed = true;
+            let e = ServerError::new(
+                OP_IN_PROGRESS,
+                "The operation is applied by another request",
+            );
+            OpStatus::Failed {
+                http_status: e.status.as_u16(),
+                error: e,
+            }
+        } else {
+            let held = events.hold();
+            let applied = apply_operation(auth.clone(), &op.operation, &created, s, &held).await;
+            let result = applied.as_ref().ok().map(Value::to_string);
+            // a result that can't be saved fails the operation: SQLite dropped its writes,
+            // Redis keeps `op_id` held so that a retry doesn't apply it again
+            let ended = s.end_op(&session, &op.op_id, result.as_deref());
+            match applied.and_then(|result| ended.map(|_| result)) {
+                Ok(result) => {
+                    held.release(s);
+                    OpStatus::Applied {
+                        result,
+                        replayed: false,
+                    }
+                }
+                Err(e) => {
+                    failed = true;
+                    OpStatus::Failed {
+                        http_status: e.status.as_u16(),
+                        error: e,
+                    }
+                }
+            }
+        };
+        if let OpStatus::Applied { result, .. } = &status {
+            if let Some(id) = created_id(result) {
+                created.insert(&op.op_id, id);
+            }
+        }
+        results.push(OpResult::new(op.op_id.clone(), status));
+    }
+    Ok(BatchResults::new(results))
+}
+
+fn validate_batch(batch: &Batch) -> Result<()> {
+    if batch.operations.len() > MAX_BATCH_LEN {
+        return Err(ServerError::new(
+            INVALID_PARAMS,
+            &format!("A batch can't have more than {} operations", MAX_BATCH_LEN),
+        ));
+    }
+    let mut op_ids = HashSet::new();
+    for op in &batch.operations {
+        if op.op_id.is_empty() || !op_ids.insert(op.op_id.as_str()) {
+            return Err(ServerError::new(
+                INVALID_PARAMS,
+                &format!("Invalid or duplicated op_id \"{}\"", op.op_id),
+            )
+            .on_field("op_id"));
+        }
+    }
+    Ok(())
+}
+
+// the id of the store, aisle or product created by an operation
+fn created_id(result: &Value) -> Option<String> {
+    ["store_id", "aisle_id", "product_id"]
+        .iter()
+        .find_map(|field| result.get(field).and_then(Value::as_str))
+        .map(str::to_owned)
+}
+
+fn resolve(id: &str, created: &HashMap<&str, String>) -> Result<String> {
+    if id.starts_with('$') {
+        created.get(&id[1..]).cloned().ok_or_else(|| {
+            ServerError::new(
+                INVALID_PARAMS,
+                &format!("{} does not refer to an earlier creation", id),
+            )
+        })
+    } else {
+        Ok(id.to_owned())
+    }
+}
+
+async fn apply_operation(
+    auth: String,
+    operation: &Operation,
+    created: &HashMap<&str, String>,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<Value> {
+    let name_data = |name: &str| NameData {
+        name: name.to_owned(),
+    };
+    let result = match operation {
+        Operation::CreateStore { name } => {
+            serde_json::to_value(store::create_store(auth, &name_data(name), s).await?)?
+        }
+        Operation::EditStore { store_id, name } => {
+            let store_id = resolve(store_id, created)?;
+            store::edit_store(auth, store_id, &name_data(name), None, s, events).await?;
+            Value::Null
+        }
+        Operation::DeleteStore { store_id } => {
+            store::delete_store(auth, resolve(store_id, created)?, None, s, events).await?;
+            Value::Null
+        }
+        Operation::CreateAisle { store_id, name } => {
+            let store_id = resolve(store_id, created)?;
+            serde_json::to_value(
+                aisle::create_aisle(auth, store_id, &name_data(name), s, events).await?,
+            )?
+        }
+        Operation::EditAisle { aisle_id, name } => {
+            let aisle_id = resolve(aisle_id, created)?;
+            aisle::rename_aisle(auth, aisle_id, &name_data(name), None, s, events).await?;
+            Value::Null
+        }
+        Operation::DeleteAisle { aisle_id } => {
+            aisle::delete_aisle(auth, resolve(aisle_id, created)?, None, s, events).await?;
+            Value::Null
+        }
+        Operation::CreateProduct { aisle_id, name } => {
+            let aisle_id = resolve(aisle_id, created)?;
+            serde_json::to_value(
+                product::create_product(auth, aisle_id, &name_data(name), s, events).await?,
+            )?
+        }
+        Operation::EditProduct {
+            product_id,
+            changes,
+        } => {
+            let product_id = resolve(product_id, created)?;
+            product::edit_product(auth, product_id, changes, None, s, events).await?;
+            Value::Null
+        }
+        Operation::DeleteProduct { product_id } => {
+            product::delete_product(auth, resolve(product_id, created)?, None, s, events).await?;
+            Value::Null
+        }
+        Operation::ChangeSortWeight { weights } => {
+            let aisles = weights
+                .aisles
+                .as_ref()
+                .map(|aisles| {
+                    aisles
+                        .iter()
+                        .map(|w| {
+                            Ok(AisleItemWeight::new(
+                                resolve(&w.id, created)?,
+                                w.sort_weight,
+                            ))
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+                .transpose()?;
+            let products = weights
+                .products
+                .as_ref()
+                .map(|products| {
+                    products
+                        .iter()
+                        .map(|w| {
+                            Ok(ProductItemWeight::new(
+                                resolve(&w.id, created)?,
+                                w.sort_weight,
+                            ))
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+                .transpose()?;
+            misc::change_sort_weight(auth, &EditWeight::new(aisles, products), s, events).await?;
+            Value::Null
+        }
+    };
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sessions::tests::TTL;
+    use crate::db::{sqlite::tests::open_for_test, sqlite::SqliteStorage, users::tests::*};
+
+    fn batch(json: &str) -> Batch {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn apply_batch_test() {
+        let mut c = open_for_test();
+        let mut s = SqliteStorage::new(&mut c, TTL);
+        let events = EventBus::default();
+        let token = s.save_user(&gen_user()).unwrap();
+        let auth = token.session_token.clone();
+
+        let ops = batch(
+            r#"{"operations": [
+                {"op_id": "s", "op": "create_store", "name": "store"},
+                {"op_id": "a", "op": "create_aisle", "store_id": "$s", "name": "aisle"},
+                {"op_id": "p", "op": "create_product", "aisle_id": "$a", "name": "milk"},
+                {"op_id": "e", "op": "edit_product", "product_id": "$p", "quantity": 2},
+                {"op_id": "w", "op": "change_sort_weight", "aisles": [{"id": "$a", "sort_weight": 3.0}]}
+            ]}"#,
+        );
+        let results = apply_batch(auth.clone(), &ops, &mut s, &events)
+            .await
+            .unwrap();
+        let json = serde_json::to_value(&results).unwrap();
+        let statuses = |json: &Value| {
+            json["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["status"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["applied"; 5], statuses(&json));
+        let store_id = json["results"][0]["result"]["store_id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        let store = s
+            .list_store(&Auth(&auth), &StoreId::new(store_id.clone()))
+            .unwrap();
+        let store = serde_json::to_value(&store).unwrap();
+        assert_eq!(3.0, store["aisles"][0]["sort_weight"]);
+        assert_eq!(2, store["aisles"][0]["products"][0]["quantity"]);
+
+        // replaying after a dropped response doesn't create anything twice
+        let replayed = apply_batch(auth.clone(), &ops, &mut s, &events)
+            .await
+            .unwrap();
+        let replayed = serde_json::to_value(&replayed).unwrap();
+        assert_eq!(
+            json["results"][2]["result"],
+            replayed["results"][2]["result"]
+        );
+        assert_eq!(true, replayed["results"][2]["replayed"]);
+        assert_eq!(Ok(1), s.get_all_stores(&Auth(&auth)).map(|s| s.len()));
+
+        // the first failure skips the rest
+        let ops = batch(
+            r#"{"operations": [
+                {"op_id": "r", "op": "edit_store", "store_id": "unknown", "name": "x"},
+                {"op_id": "d", "op": "delete_aisle", "aisle_id": "$nothing"}
+            ]}"#,
+        );
+        let results = apply_batch(auth.clone(), &ops, &mut s, &events)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec!["failed", "skipped"],
+            statuses(&serde_json::to_value(&results).unwrap())
+        );
+        // failed operations can be retried
+        assert_eq!(Ok(None), s.op_result(&Auth(&auth), "r"));
+
+        // not while another request holds them
+        assert_eq!(Ok(true), s.begin_op(&Auth(&auth), "h"));
+        let ops = batch(r#"{"operations": [{"op_id": "h", "op": "create_store", "name": "x"}]}"#);
+        let results = apply_batch(auth.clone(), &ops, &mut s, &events)
+            .await
+            .unwrap();
+        let json = serde_json::to_value(&results).unwrap();
+        assert_eq!(vec!["failed"], statuses(&json));
+        assert_eq!(
+            OP_IN_PROGRESS.as_u16(),
+            json["results"][0]["http_status"].as_u64().unwrap() as u16
+        );
+        assert_eq!(Ok(()), s.end_op(&Auth(&auth), "h", None));
+        assert_eq!(Ok(1), s.get_all_stores(&Auth(&auth)).map(|s| s.len()));
+    }
+
+    #[tokio::test]
+    async fn invalid_batch_test() {
+        let mut c = open_for_test();
+        let mut s = SqliteStorage::new(&mut c, TTL);
+        let events = EventBus::default();
+        let token = s.save_user(&gen_user()).unwrap();
+        let auth = token.session_token.clone();
+
+        let ops = batch(
+            r#"{"operations": [
+                {"op_id": "1", "op": "create_store", "name": "a"},
+                {"op_id": "1", "op": "create_store", "name": "b"}
+            ]}"#,
+        );
+        assert_eq!(
+            Err(INVALID_PARAMS),
+            apply_batch(auth.clone(), &ops, &mut s, &events)
+                .await
+                .map_err(|e| e.status)
+        );
+        let ops =
+            batch(r#"{"operations": [{"op_id": "1", "op": "delete_aisle", "aisle_id": "$2"}]}"#);
+        let results = apply_batch(auth.clone(), &ops, &mut s, &events)
+            .await
+            .unwrap();
+        assert_eq!(
+            BatchResults::new(vec![OpResult::new(
+                "1".to_owned(),
+                OpStatus::Failed {
+                    http_status: INVALID_PARAMS.as_u16(),
+                    error: ServerError::new(
+                        INVALID_PARAMS,
+                        "$2 does not refer to an earlier creation"
+                    ),
+                }
+            )]),
+            results
+        );
+        assert_eq!(Ok(0), s.get_all_stores(&Auth(&auth)).map(|s| s.len()));
+    }
+}
@@ -0,0 +1,204 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{stream, SinkExt, Stream, StreamExt};
+use log::*;
+use tokio::{
+    sync::broadcast::{self, RecvError},
+    time::{self, Instant},
+};
+use warp::{
+    sse::{self, ServerSentEvent},
+    ws::{Message, WebSocket},
+};
+
+use crate::{
+    db::Storage,
+    endpoints::store,
+    error::Result,
+    events::{Event, StoreEvent},
+    types::*,
+};
+
+// how often a watcher's session and role are checked again
+#[cfg(not(test))]
+const ACCESS_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+#[cfg(test)]
+const ACCESS_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+// Forward the changes of the store until the client leaves, the store is deleted or the
+// client can't view the store anymore: its session ended or it was removed from the store.
+pub async fn stream_store_events<F>(
+    ws: WebSocket,
+    auth: String,
+    store_id: StoreId,
+    mut rx: broadcast::Receiver<Arc<Event>>,
+    new_storage: F,
+) where
+    F: Fn() -> Result<Box<dyn Storage>> + Send,
+{
+    let (mut tx, mut client) = ws.split();
+    let mut access_check = time::interval_at(
+        Instant::now() + ACCESS_CHECK_INTERVAL,
+        ACCESS_CHECK_INTERVAL,
+    );
+    loop {
+        let event = tokio::select! {
+            _ = access_check.tick() => {
+                let access = match new_storage() {
+                    Ok(mut s) => store::check_watcher(auth.clone(), &store_id, &mut *s).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = access {
+                    debug!("Store {} watcher lost its access: {}", *store_id, e.msg);
+                    break;
+                }
+                continue;
+            },
+            msg = client.next() => match msg {
+                // the client isn't expected to send anything, only closing matters
+                Some(Ok(msg)) if !msg.is_close() => continue,
+                _ => break,
+            },
+            event = rx.recv() => match event {
+                Ok(event) if event.store_id == *store_id => event,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("Store {} watcher missed {} events", *store_id, missed);
+                    Arc::new(Event::new(store_id.to_string(), StoreEvent::Resync))
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        let msg = match serde_json::to_string(&*event) {
+            Ok(json) => Message::text(json),
+            Err(e) => {
+                error!("Cannot serialize event: {}", e);
+                break;
+            }
+        };
+        if tx.send(msg).await.is_err() {
+            break;
+        }
+        if let StoreEvent::StoreDeleted = event.change {
+            break;
+        }
+    }
+    let _ = tx.close().await;
+}
+
+pub async fn open_feed(
+    auth: String,
+    resume_from: Option<u64>,
+    s: &mut dyn Storage,
+) -> Result<EventFeed> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.events_since(&auth, resume_from)
+}
+
+// The logged events the client missed, then the live ones as they come, until its session
+// ends.
+pub fn user_event_stream<F>(
+    auth: String,
+    feed: EventFeed,
+    rx: broadcast::Receiver<Arc<Event>>,
+    new_storage: F,
+) -> impl Stream<Item = std::result::Result<impl ServerSentEvent, Infallible>>
+where
+    F: Fn() -> Result<Box<dyn Storage>> + Send + Sync + 'static,
+{
+    let EventFeed {
+        user_id,
+        last_id,
+        events,
+        missed,
+    } = feed;
+    let backlog = if missed {
+        let resync = serde_json::to_string(&StoreEvent::Resync).unwrap_or_default();
+        vec![(last_id, resync)]
+    } else {
+        events.into_iter().map(|e| (e.id, e.data)).collect()
+    };
+    let new_storage = Arc::new(new_storage);
+    let access_check = time::interval_at(
+        Instant::now() + ACCESS_CHECK_INTERVAL,
+        ACCESS_CHECK_INTERVAL,
+    );
+    let live = stream::unfold((rx, access_check), move |(mut rx, mut access_check)| {
+        let user_id = user_id.clone();
+        let auth = auth.clone();
+        let new_storage = new_storage.clone();
+        async move {
+            loop {
+                tokio::select! {
+                    _ = access_check.tick() => {
+                        let access = new_storage()
+                            .and_then(|mut s| s.check_session(&Auth(&auth)));
+                        if let Err(e) = access {
+                            debug!("Feed of user {} lost its session: {}", user_id.0, e.msg);
+                            return None;
+                        }
+                    },
+                    event = rx.recv() => match event {
+                        // the older ones were already in the log
+                        Ok(event) => match event.id_for(&user_id) {
+                            Some(id) if id > last_id => match serde_json::to_string(&*event) {
+                                Ok(data) => return Some(((id, data), (rx, access_check))),
+                                Err(e) => error!("Cannot serialize event: {}", e),
+                            },
+                            _ => continue,
+                        },
+                        // the client reconnects with its `Last-Event-ID` and catches up from the
+                        // log
+                        Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => return None,
+                    },
+                }
+            }
+        }
+    });
+    stream::iter(backlog)
+        .chain(live)
+        .map(|(id, data)| Ok((sse::id(id.to_string()), sse::data(data))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        redis_storage::RedisStorage,
+        sessions::tests::TTL,
+        tests::{get_db_addr, Fixture},
+    };
+    use crate::events::EventBus;
+    use fake_redis::FakeCient as Client;
+
+    #[tokio::test]
+    async fn user_event_stream_test() {
+        let addr = get_db_addr();
+        let new_storage = move || -> Result<Box<dyn Storage>> {
+            let client = Client::open(addr.as_str())?;
+            Ok(Box::new(RedisStorage::new(
+                Box::new(client.get_connection()?),
+                TTL,
+            )))
+        };
+        let mut s = new_storage().unwrap();
+        assert_eq!(Ok(()), s.migrate());
+        let f = Fixture::new(&mut *s);
+        let auth = f.token.session_token.clone();
+        let events = EventBus::default();
+        let rx = events.subscribe();
+        let feed = open_feed(auth.clone(), None, &mut *s).await.unwrap();
+        let mut stream = Box::pin(user_event_stream(auth.clone(), feed, rx, new_storage));
+
+        events.publish(&mut *s, &f.store_id, StoreEvent::StoreRestored);
+        assert!(stream.next().await.is_some());
+
+        // closed at the next check once the session ends
+        assert_eq!(Ok(()), s.delete_session(&Auth(&auth), &f.user_id));
+        let next = time::timeout(Duration::from_secs(1), stream.next()).await;
+        assert!(matches!(next, Ok(None)));
+    }
+}
@@ -1,56 +1,95 @@
-use crate::{db, endpoints::INVALID_PARAMS, error, types::*};
+use std::collections::HashMap;
 
-#[cfg(not(test))]
-use redis::Connection;
+use crate::{
+    db::Storage,
+    endpoints::{undo, INVALID_PARAMS},
+    error,
+    events::{EventBus, StoreEvent},
+    types::*,
+};
 
-#[cfg(test)]
-use fake_redis::FakeConnection as Connection;
+type WeightsByStore = HashMap<String, (Vec<AisleItemWeight>, Vec<ProductItemWeight>)>;
+
+// the items can come from several stores, each gets its own event
+pub(super) fn weights_by_store(
+    s: &mut dyn Storage,
+    data: &EditWeight,
+) -> error::Result<WeightsByStore> {
+    let mut changes = WeightsByStore::new();
+    for w in data.aisles.iter().flatten() {
+        let store_id = s.aisle_store(&AisleId(w.id.clone()))?;
+        changes
+            .entry(store_id.to_string())
+            .or_default()
+            .0
+            .push(w.clone());
+    }
+    for w in data.products.iter().flatten() {
+        let store_id = s.product_store(&ProductId(w.id.clone()))?;
+        changes
+            .entry(store_id.to_string())
+            .or_default()
+            .1
+            .push(w.clone());
+    }
+    Ok(changes)
+}
+
+pub(super) fn publish_weights(s: &mut dyn Storage, events: &EventBus, changes: WeightsByStore) {
+    for (store_id, (aisles, products)) in changes {
+        events.publish(
+            s,
+            &StoreId::new(store_id),
+            StoreEvent::SortWeightChanged { aisles, products },
+        );
+    }
+}
+
+// the weights the changes overwrite
+fn previous_weights(s: &mut dyn Storage, data: &EditWeight) -> error::Result<UndoOp> {
+    let mut previous_aisles = vec![];
+    for w in data.aisles.iter().flatten() {
+        let aisle = s.get_aisle(&AisleId(w.id.clone()))?;
+        previous_aisles.push(AisleItemWeight::new(w.id.clone(), aisle.sort_weight));
+    }
+    let mut previous_products = vec![];
+    for w in data.products.iter().flatten() {
+        let (_, product) = s.get_product(&ProductId(w.id.clone()))?;
+        previous_products.push(ProductItemWeight::new(w.id.clone(), product.sort_weight));
+    }
+    Ok(UndoOp::ChangeSortWeight {
+        aisles: previous_aisles,
+        products: previous_products,
+    })
+}
 
 pub async fn change_sort_weight(
     auth: String,
     data: &EditWeight,
-    c: &mut Connection,
+    s: &mut dyn Storage,
+    events: &EventBus,
 ) -> error::Result<()> {
     if !data.has_at_least_a_field() {
-        Err(error::ServerError::new(
-            INVALID_PARAMS,
-            "At least a field must be present",
-        ))
+        Err(
+            error::ServerError::new(INVALID_PARAMS, "At least a field must be present")
+                .with_code(error::ErrorCode::MissingField),
+        )
     } else {
         let auth = Auth(&auth);
-        let mut pipe = redis::pipe();
-        pipe.atomic();
-        if let Some(ref aisles) = data.aisles {
-            aisles
-                .iter()
-                .try_for_each(|w| db::aisles::edit_aisle_sort_weight(c, &mut pipe, &auth, &w))?;
-        }
-        if let Some(ref products) = data.products {
-            products.iter().try_for_each(|w| {
-                db::products::edit_product_sort_weight(c, &mut pipe, &auth, &w)
-            })?;
-        }
-        pipe.query(c)?;
+        s.validate_session(&auth)?;
+        let changes = weights_by_store(s, data)?;
+        let previous = previous_weights(s, &data)?;
+        s.change_sort_weight(&auth, &data)?;
+        undo::record(s, &auth, previous);
+        publish_weights(s, events, changes);
         Ok(())
     }
 }
 
 // Reset the DB, only available in debug compilation
-#[cfg(not(test))]
-pub async fn nuke(c: &mut Connection) -> Result<impl warp::reply::Reply, warp::reject::Rejection> {
+pub async fn nuke(s: &mut dyn Storage) -> Result<impl warp::reply::Reply, warp::reject::Rejection> {
     if cfg!(debug_assertions) {
-        redis::cmd("FLUSHDB")
-            .query::<()>(c)
-            .expect("error on flush");
-        Ok(warp::reply())
-    } else {
-        Err(warp::reject::not_found())
-    }
-}
-
-#[cfg(test)]
-pub async fn nuke(_: &mut Connection) -> Result<impl warp::reply::Reply, warp::reject::Rejection> {
-    if false {
+        s.nuke().expect("error on flush");
         Ok(warp::reply())
     } else {
         Err(warp::reject::not_found())
@@ -1,11 +1,17 @@
 use warp::http::StatusCode;
 
+pub mod account;
 pub mod aisle;
+pub mod api;
+pub mod batch;
+pub mod feed;
 pub mod misc;
 pub mod product;
+#[cfg(not(test))]
 pub mod routes;
 pub mod session;
 pub mod store;
+pub mod undo;
 pub mod user;
 
-const INVALID_PARAMS: StatusCode = StatusCode::PRECONDITION_FAILED;
+const INVALID_PARAMS: StatusCode = StatusCode::UNPROCESSABLE_ENTITY;
@@ -1,42 +1,306 @@
-use crate::{db, endpoints::INVALID_PARAMS, error::*, types::*};
-
-#[cfg(not(test))]
-use redis::Connection;
-
-#[cfg(test)]
-use fake_redis::FakeConnection as Connection;
+use crate::{
+    db::Storage,
+    endpoints::{undo, INVALID_PARAMS},
+    error::*,
+    events::{EventBus, StoreEvent},
+    types::*,
+};
 
 pub async fn create_product(
     auth: String,
     aisle_id: String,
     data: &NameData,
-    c: &mut Connection,
+    s: &mut dyn Storage,
+    events: &EventBus,
 ) -> Result<Product> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::products::save_product(c, &auth, &data.name, &AisleId(aisle_id))
+    s.validate_session(&auth)?;
+    let aisle_id = AisleId(aisle_id);
+    let store_id = s.aisle_store(&aisle_id)?;
+    let product = s.save_product(&auth, &data.name, &aisle_id)?;
+    undo::record(
+        s,
+        &auth,
+        UndoOp::DeleteProduct {
+            product_id: product.id().to_string(),
+            version: product.version(),
+        },
+    );
+    events.publish(
+        s,
+        &store_id,
+        StoreEvent::ProductCreated {
+            aisle_id: aisle_id.to_string(),
+            product: product.clone(),
+        },
+    );
+    Ok(product)
+}
+
+pub async fn restore_product(
+    auth: String,
+    product_id: String,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<Product> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let (aisle_id, product) = s.restore_product(&auth, &ProductId(product_id))?;
+    undo::record(
+        s,
+        &auth,
+        UndoOp::DeleteProduct {
+            product_id: product.id().to_string(),
+            version: product.version(),
+        },
+    );
+    let store_id = s.aisle_store(&aisle_id)?;
+    events.publish(
+        s,
+        &store_id,
+        StoreEvent::ProductCreated {
+            aisle_id: aisle_id.to_string(),
+            product: product.clone(),
+        },
+    );
+    Ok(product)
 }
 
 pub async fn edit_product(
     auth: String,
     product_id: String,
     data: &EditProduct,
-    c: &mut Connection,
+    if_match: Option<String>,
+    s: &mut dyn Storage,
+    events: &EventBus,
 ) -> Result<()> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
+    s.validate_session(&auth)?;
     if !data.has_at_least_a_field() {
-        Err(ServerError::new(
-            INVALID_PARAMS,
-            "At least a field must be present",
-        ))
+        Err(
+            ServerError::new(INVALID_PARAMS, "At least a field must be present")
+                .with_code(ErrorCode::MissingField),
+        )
+    } else {
+        let product_id = ProductId(product_id);
+        let store_id = s.product_store(&product_id)?;
+        let (_, previous) = s.get_product(&product_id)?;
+        s.modify_product(&auth, &data, &product_id, &IfMatch(if_match.as_deref()))?;
+        let version = s.product_version(&product_id);
+        undo::record_at(s, &auth, version, |version| UndoOp::EditProduct {
+            product_id: product_id.to_string(),
+            changes: data.previous(&previous),
+            version,
+        });
+        events.publish(
+            s,
+            &store_id,
+            StoreEvent::ProductModified {
+                product_id: product_id.to_string(),
+                changes: data.clone(),
+            },
+        );
+        Ok(())
+    }
+}
+
+pub async fn move_product(
+    auth: String,
+    product_id: String,
+    data: &MoveProduct,
+    if_match: Option<String>,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<Product> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let product_id = ProductId(product_id);
+    let from_store_id = s.product_store(&product_id)?;
+    let aisle_id = AisleId(data.aisle_id.clone());
+    let (previous_aisle_id, previous) = s.get_product(&product_id)?;
+    let product = s.move_product(
+        &auth,
+        &product_id,
+        &aisle_id,
+        None,
+        &IfMatch(if_match.as_deref()),
+    )?;
+    undo::record(
+        s,
+        &auth,
+        UndoOp::MoveProduct {
+            product_id: product_id.to_string(),
+            aisle_id: previous_aisle_id.to_string(),
+            sort_weight: previous.sort_weight,
+            version: product.version(),
+        },
+    );
+    let to_store_id = s.aisle_store(&aisle_id)?;
+    publish_move(s, events, &from_store_id, &to_store_id, &aisle_id, &product);
+    Ok(product)
+}
+
+pub(super) fn publish_move(
+    s: &mut dyn Storage,
+    events: &EventBus,
+    from_store_id: &StoreId,
+    to_store_id: &StoreId,
+    aisle_id: &AisleId,
+    product: &Product,
+) {
+    let product_id = product.id();
+    if from_store_id == to_store_id {
+        events.publish(
+            s,
+            to_store_id,
+            StoreEvent::ProductMoved {
+                product_id: product_id.to_string(),
+                aisle_id: aisle_id.to_string(),
+                sort_weight: product.sort_weight,
+            },
+        );
     } else {
-        db::products::modify_product(c, &auth, &data, &ProductId(product_id))
+        events.publish(
+            s,
+            from_store_id,
+            StoreEvent::ProductDeleted {
+                product_id: product_id.to_string(),
+            },
+        );
+        events.publish(
+            s,
+            to_store_id,
+            StoreEvent::ProductCreated {
+                aisle_id: aisle_id.to_string(),
+                product: product.clone(),
+            },
+        );
+    }
+}
+
+pub async fn delete_product(
+    auth: String,
+    product_id: String,
+    if_match: Option<String>,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<()> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let product_id = ProductId(product_id);
+    let store_id = s.product_store(&product_id)?;
+    s.delete_product(&auth, &product_id, &IfMatch(if_match.as_deref()))?;
+    undo::record(
+        s,
+        &auth,
+        UndoOp::RestoreProduct {
+            product_id: product_id.to_string(),
+        },
+    );
+    events.publish(
+        s,
+        &store_id,
+        StoreEvent::ProductDeleted {
+            product_id: product_id.to_string(),
+        },
+    );
+    Ok(())
+}
+
+// the products were in the other state before
+fn record_done(s: &mut dyn Storage, auth: &Auth, changed: &[ProductId], is_done: bool) {
+    if changed.is_empty() {
+        return;
     }
+    undo::record(
+        s,
+        auth,
+        UndoOp::SetDone {
+            product_ids: changed.iter().map(|id| id.to_string()).collect(),
+            is_done: !is_done,
+        },
+    );
+}
+
+pub(super) fn publish_done(
+    s: &mut dyn Storage,
+    events: &EventBus,
+    store_id: &StoreId,
+    changed: Vec<ProductId>,
+    is_done: bool,
+) {
+    if changed.is_empty() {
+        return;
+    }
+    events.publish(
+        s,
+        &store_id,
+        StoreEvent::ProductsModified {
+            product_ids: changed.iter().map(|id| id.to_string()).collect(),
+            changes: EditProduct::new(None, None, None, Some(is_done)),
+        },
+    );
+}
+
+pub async fn set_store_done(
+    auth: String,
+    store_id: String,
+    data: &DoneState,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<()> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let store_id = StoreId::new(store_id);
+    let changed = s.set_store_done(&auth, &store_id, data.is_done)?;
+    record_done(s, &auth, &changed, data.is_done);
+    publish_done(s, events, &store_id, changed, data.is_done);
+    Ok(())
 }
 
-pub async fn delete_product(auth: String, product_id: String, c: &mut Connection) -> Result<()> {
+pub async fn set_aisle_done(
+    auth: String,
+    aisle_id: String,
+    data: &DoneState,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<()> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::products::delete_product(c, &auth, &ProductId(product_id))
+    s.validate_session(&auth)?;
+    let aisle_id = AisleId(aisle_id);
+    let store_id = s.aisle_store(&aisle_id)?;
+    let changed = s.set_aisle_done(&auth, &aisle_id, data.is_done)?;
+    record_done(s, &auth, &changed, data.is_done);
+    publish_done(s, events, &store_id, changed, data.is_done);
+    Ok(())
+}
+
+// the products go to the user's trash, to be restored from there
+pub async fn clear_done_products(
+    auth: String,
+    store_id: String,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<()> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let store_id = StoreId::new(store_id);
+    let cleared = s.delete_done_products(&auth, &store_id)?;
+    if !cleared.is_empty() {
+        undo::record(
+            s,
+            &auth,
+            UndoOp::RestoreProducts {
+                product_ids: cleared.iter().map(|id| id.to_string()).collect(),
+            },
+        );
+        events.publish(
+            s,
+            &store_id,
+            StoreEvent::ProductsDeleted {
+                product_ids: cleared.iter().map(|id| id.to_string()).collect(),
+            },
+        );
+    }
+    Ok(())
 }
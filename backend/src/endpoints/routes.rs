@@ -1,18 +1,140 @@
 use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 use log::*;
 use r2d2_redis::RedisConnectionManager;
-use warp::{self, path, Filter, Rejection, Reply};
+use r2d2_sqlite::SqliteConnectionManager;
+use warp::{
+    self,
+    filters::BoxedFilter,
+    path,
+    path::{FullPath, Peek},
+    sse, Filter, Rejection, Reply,
+};
 
-use crate::{cli::*, endpoints::*, error, types::*};
+use crate::{
+    cli::*,
+    db::{self, redis_storage::RedisStorage, sessions::SessionTtl, sqlite::SqliteStorage, Storage},
+    endpoints::{api::ApiRoute, *},
+    error,
+    events::EventBus,
+    mailer::{Mailer, SmtpMailer, SpoolMailer},
+    openapi,
+    types::*,
+};
 
 const HEADER_AUTH: &str = "x-auth-token";
+const HEADER_IF_MATCH: &str = "if-match";
+const HEADER_ETAG: &str = "etag";
+const HEADER_DEPRECATION: &str = "deprecation";
+const HEADER_LINK: &str = "link";
 const DEFAULT_DB_PORT: u32 = 6379;
 const DEFAULT_DB_HOST: &str = "redis://127.0.0.1";
+const DEFAULT_DB_FILE: &str = "efficio.db";
+const SQLITE_BUSY_TIMEOUT: u64 = 5;
+const DEFAULT_MAIL_SPOOL: &str = "mail_spool";
+const DEFAULT_MAIL_FROM: &str = "noreply@efficio.local";
+const SMTP_PASSWORD_VAR: &str = "EFFICIO_SMTP_PASSWORD";
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
 
-type PooledConnection = r2d2::PooledConnection<r2d2_redis::RedisConnectionManager>;
+type DbStorage = Box<dyn Storage>;
+// opens a storage on a connection of the pool
+type NewStorage = Arc<dyn Fn() -> error::Result<DbStorage> + Send + Sync>;
 
-pub async fn start_server(opt: &Opt) -> error::Result<()> {
+fn session_ttl(opt: &Opt) -> SessionTtl {
+    SessionTtl::new(
+        i64::from(opt.session_ttl) * 3600,
+        i64::from(opt.session_idle_ttl) * 3600,
+    )
+}
+
+fn mailer(opt: &Opt) -> Arc<dyn Mailer> {
+    match opt.smtp_host {
+        Some(ref host) => {
+            info!("Sending mails through {}", host);
+            let credentials = opt.smtp_user.as_ref().map(|user| {
+                (
+                    user.to_owned(),
+                    env::var(SMTP_PASSWORD_VAR).unwrap_or_default(),
+                )
+            });
+            let from = match opt.mail_from {
+                Some(ref from) => from,
+                _ => DEFAULT_MAIL_FROM,
+            };
+            Arc::new(SmtpMailer::new(
+                host.to_owned(),
+                credentials,
+                from.to_owned(),
+            ))
+        }
+        None => {
+            let dir = match opt.mail_spool {
+                Some(ref dir) => dir,
+                _ => DEFAULT_MAIL_SPOOL,
+            };
+            info!("Spooling mails to {}", dir);
+            Arc::new(SpoolMailer::new(PathBuf::from(dir)))
+        }
+    }
+}
+
+// removes the items which stayed in the trash longer than the retention
+fn spawn_trash_purge<F>(opt: &Opt, new_storage: F)
+where
+    F: Fn() -> error::Result<DbStorage> + Send + 'static,
+{
+    let retention = i64::from(opt.trash_retention) * 24 * 3600;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) =
+                new_storage().and_then(|mut s| s.purge_trash(db::timestamp() - retention))
+            {
+                error!("Cannot purge the trash: {}", e.msg);
+            }
+        }
+    });
+}
+
+// the method and path of the route, whose parameters the filters after it extract
+fn on(route: &'static ApiRoute) -> BoxedFilter<()> {
+    let method = match route.method {
+        "get" => warp::get().boxed(),
+        "post" => warp::post().boxed(),
+        "put" => warp::put().boxed(),
+        "delete" => warp::delete().boxed(),
+        method => panic!("Unsupported method {}", method),
+    };
+    method
+        .and(warp::path::peek())
+        .and_then(move |rest: Peek| async move {
+            if route.matches(rest.as_str()) {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+        .boxed()
+}
+
+// user agent and address of the client, recorded with its session
+fn client_info() -> BoxedFilter<(ClientInfo,)> {
+    warp::header::optional::<String>("user-agent")
+        .and(warp::addr::remote())
+        .map(|user_agent, addr: Option<SocketAddr>| {
+            ClientInfo::new(user_agent, addr.map(|a| a.ip().to_string()))
+        })
+        .boxed()
+}
+
+fn redis_addr(opt: &Opt) -> String {
     let db_host = match opt.db_host {
         Some(ref host) => host,
         _ => DEFAULT_DB_HOST,
@@ -22,80 +144,244 @@ pub async fn start_server(opt: &Opt) -> error::Result<()> {
         _ => DEFAULT_DB_PORT,
     };
     let db_num: u32 = if cfg!(debug_assertions) { 0 } else { 1 };
-    let redis_addr = format!("{}:{}/{}", db_host, db_port, db_num);
+    format!("{}:{}/{}", db_host, db_port, db_num)
+}
+
+fn db_file(opt: &Opt) -> &str {
+    match opt.db_file {
+        Some(ref file) => file,
+        _ => DEFAULT_DB_FILE,
+    }
+}
+
+// a single connection to the database, not migrated, for the maintenance commands
+pub fn open_storage(opt: &Opt) -> error::Result<DbStorage> {
+    let ttl = session_ttl(opt);
+    Ok(match opt.db_backend {
+        Backend::Redis => {
+            let redis_addr = redis_addr(opt);
+            info!("DB address: {}", redis_addr);
+            let client = redis::Client::open(redis_addr.as_str())?;
+            Box::new(RedisStorage::new(Box::new(client.get_connection()?), ttl))
+        }
+        Backend::Sqlite => {
+            info!("DB file: {}", db_file(opt));
+            let c = rusqlite::Connection::open(db_file(opt))?;
+            c.busy_timeout(Duration::from_secs(SQLITE_BUSY_TIMEOUT))?;
+            Box::new(SqliteStorage::new(Box::new(c), ttl))
+        }
+    })
+}
+
+fn redis_storage(opt: &Opt) -> error::Result<NewStorage> {
+    let redis_addr = redis_addr(opt);
 
     info!("DB address: {}", redis_addr);
     let manager = RedisConnectionManager::new(redis_addr.as_str())?;
     debug!("Creating db connection pool");
     let pool = r2d2::Pool::builder().max_size(15).build(manager)?;
+    let ttl = session_ttl(opt);
+    RedisStorage::new(pool.get()?, ttl).migrate()?;
+    Ok(Arc::new(move || {
+        Ok(Box::new(RedisStorage::new(pool.get()?, ttl)) as DbStorage)
+    }))
+}
+
+fn sqlite_storage(opt: &Opt) -> error::Result<NewStorage> {
+    let db_file = db_file(opt);
+
+    info!("DB file: {}", db_file);
+    let manager = SqliteConnectionManager::file(db_file)
+        .with_init(|c| c.busy_timeout(Duration::from_secs(SQLITE_BUSY_TIMEOUT)));
+    debug!("Creating db connection pool");
+    let pool = r2d2::Pool::builder().max_size(15).build(manager)?;
+    let ttl = session_ttl(opt);
+    SqliteStorage::new(pool.get()?, ttl).migrate()?;
+    Ok(Arc::new(move || {
+        Ok(Box::new(SqliteStorage::new(pool.get()?, ttl)) as DbStorage)
+    }))
+}
 
-    let get_connection = warp::any()
+fn storage_filter(new_storage: NewStorage) -> BoxedFilter<(DbStorage,)> {
+    warp::any()
         .and_then(move || {
-            let pool = pool.clone();
-            async move {
-                match pool.get() {
-                    Ok(c) => Ok(c),
-                    Err(e) => Err(warp::reject::custom(error::ServerError::from(e))),
-                }
-            }
+            let new_storage = new_storage.clone();
+            async move { new_storage().map_err(warp::reject::custom) }
         })
-        .boxed();
-    let get_connection = move || get_connection.clone();
+        .boxed()
+}
+
+pub async fn start_server(opt: &Opt) -> error::Result<()> {
+    let new_storage = match opt.db_backend {
+        Backend::Redis => redis_storage(&opt)?,
+        Backend::Sqlite => sqlite_storage(&opt)?,
+    };
+    let purge_storage = new_storage.clone();
+    spawn_trash_purge(&opt, move || purge_storage());
+    let storage = storage_filter(new_storage.clone());
+    let get_storage = move || storage.clone();
+    // for the long-lived requests, which open a storage only when they need one
+    let get_new_storage = move || {
+        let new_storage = new_storage.clone();
+        warp::any().map(move || new_storage.clone())
+    };
+    let mailer = mailer(&opt);
+    let get_mailer = move || {
+        let mailer = mailer.clone();
+        warp::any().map(move || mailer.clone())
+    };
+    let events = EventBus::default();
+    let get_events = move || {
+        let events = events.clone();
+        warp::any().map(move || events.clone())
+    };
 
     // POST /nuke
-    let nuke = warp::path("nuke")
+    let nuke = on(&api::NUKE)
+        .and(warp::path("nuke"))
         .and(warp::path::end())
-        .and(get_connection())
-        .and_then(move |mut c: PooledConnection| async move { misc::nuke(&mut *c).await });
+        .and(get_storage())
+        .and_then(move |mut s: DbStorage| async move { misc::nuke(&mut *s).await });
 
     // POST /user
-    let create_user = warp::path("user")
+    let create_user = on(&api::CREATE_USER)
+        .and(warp::path("user"))
         .and(warp::path::end())
         .and(warp::body::json())
-        .and(get_connection())
-        .and_then(move |user: User, mut c: PooledConnection| async move {
-            user::create_user(&user, &mut *c)
+        .and(get_storage())
+        .and_then(move |user: User, mut s: DbStorage| async move {
+            user::create_user(&user, &mut *s)
                 .await
                 .map(|token| warp::reply::json(&token))
                 .map_err(warp::reject::custom)
         });
 
     // POST /login
-    let login = warp::path("login")
+    let login = on(&api::LOGIN)
+        .and(warp::path("login"))
         .and(warp::path::end())
         .and(warp::body::json())
-        .and(get_connection())
+        .and(client_info())
+        .and(get_storage())
         .and_then(
-            move |auth_info: AuthInfo, mut c: PooledConnection| async move {
-                session::login(&auth_info, &mut *c)
+            move |auth_info: AuthInfo, client: ClientInfo, mut s: DbStorage| async move {
+                session::login(&auth_info, &client, &mut *s)
                     .await
                     .map(|token| warp::reply::json(&token))
                     .map_err(warp::reject::custom)
             },
         );
 
-    // POST /logout
-    let logout = path!("logout" / String)
+    // POST /logout/<id>
+    let logout = on(&api::LOGOUT)
+        .and(path!("logout" / String))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(
+            move |id: String, auth: String, mut s: DbStorage| async move {
+                session::logout(&auth, &id, &mut *s)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // POST /password_reset
+    let request_password_reset = on(&api::REQUEST_PASSWORD_RESET)
+        .and(warp::path("password_reset"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(get_storage())
+        .and(get_mailer())
+        .and_then(
+            move |data: PasswordResetRequest, mut s: DbStorage, mailer: Arc<dyn Mailer>| async move {
+                user::request_password_reset(&data, &mut *s, &*mailer)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // POST /password_reset/<token>
+    let reset_password = on(&api::RESET_PASSWORD)
+        .and(path!("password_reset" / String))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(get_storage())
+        .and_then(
+            move |token: String, data: NewPassword, mut s: DbStorage| async move {
+                user::reset_password(&token, &data, &mut *s)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // GET /session
+    let list_sessions = on(&api::LIST_SESSIONS)
+        .and(warp::path("session"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(move |auth, mut s: DbStorage| async move {
+            session::list_sessions(auth, &mut *s)
+                .await
+                .map(|sessions| warp::reply::json(&sessions))
+                .map_err(warp::reject::custom)
+        });
+
+    // DELETE /session/<id>
+    let revoke_session = on(&api::REVOKE_SESSION)
+        .and(path!("session" / String))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(move |session_id, auth, mut s: DbStorage| async move {
+            session::revoke_session(auth, session_id, &mut *s)
+                .await
+                .map(|()| warp::reply())
+                .map_err(warp::reject::custom)
+        });
+
+    // DELETE /session, revokes all the sessions but the current one
+    let revoke_other_sessions = on(&api::REVOKE_OTHER_SESSIONS)
+        .and(warp::path("session"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(move |auth, mut s: DbStorage| async move {
+            session::revoke_other_sessions(auth, &mut *s)
+                .await
+                .map(|()| warp::reply())
+                .map_err(warp::reject::custom)
+        });
+
+    // DELETE /user/<id>
+    let delete_user = on(&api::DELETE_USER)
+        .and(path!("user" / String))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
-        .and(get_connection())
+        .and(get_storage())
         .and_then(
-            move |id: String, auth: String, mut c: PooledConnection| async move {
-                session::logout(&auth, &id, &mut *c)
+            move |id: String, auth: String, mut s: DbStorage| async move {
+                user::delete_user(&auth, &id, &mut *s)
                     .await
                     .map(|()| warp::reply())
                     .map_err(warp::reject::custom)
             },
         );
 
-    // DELETE /user
-    let delete_user = path!("user" / String)
+    // PUT /user/<id>/password
+    let change_password = on(&api::CHANGE_PASSWORD)
+        .and(path!("user" / String / "password"))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
-        .and(get_connection())
+        .and(warp::body::json())
+        .and(get_storage())
         .and_then(
-            move |id: String, auth: String, mut c: PooledConnection| async move {
-                user::delete_user(&auth, &id, &mut *c)
+            move |id: String, auth: String, data: ChangePassword, mut s: DbStorage| async move {
+                user::change_password(&auth, &id, &data, &mut *s)
                     .await
                     .map(|()| warp::reply())
                     .map_err(warp::reject::custom)
@@ -103,29 +389,64 @@ pub async fn start_server(opt: &Opt) -> error::Result<()> {
         );
 
     // POST /store
-    let create_store = warp::path("store")
+    let create_store = on(&api::CREATE_STORE)
+        .and(warp::path("store"))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
         .and(warp::body::json())
-        .and(get_connection())
+        .and(get_storage())
+        .and_then(move |auth, data: NameData, mut s: DbStorage| async move {
+            store::create_store(auth, &data, &mut *s)
+                .await
+                .map(|store_id| {
+                    warp::reply::with_header(
+                        warp::reply::json(&store_id),
+                        HEADER_ETAG,
+                        etag(FIRST_VERSION),
+                    )
+                })
+                .map_err(warp::reject::custom)
+        });
+
+    // POST /store/<id>/duplicate
+    let duplicate_store = on(&api::DUPLICATE_STORE)
+        .and(path!("store" / String / "duplicate"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::body::json())
+        .and(get_storage())
         .and_then(
-            move |auth, data: NameData, mut c: PooledConnection| async move {
-                store::create_store(auth, &data, &mut *c)
+            move |store_id, auth, data: DuplicateStore, mut s: DbStorage| async move {
+                store::duplicate_store(auth, store_id, &data, &mut *s)
                     .await
-                    .map(|store_id| warp::reply::json(&store_id))
+                    .map(|store_id| {
+                        warp::reply::with_header(
+                            warp::reply::json(&store_id),
+                            HEADER_ETAG,
+                            etag(FIRST_VERSION),
+                        )
+                    })
                     .map_err(warp::reject::custom)
             },
         );
 
     // PUT /store/{id}
-    let edit_store = path!("store" / String)
+    let edit_store = on(&api::EDIT_STORE)
+        .and(path!("store" / String))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
         .and(warp::body::json())
-        .and(get_connection())
+        .and(warp::header::optional::<String>(HEADER_IF_MATCH))
+        .and(get_storage())
+        .and(get_events())
         .and_then(
-            move |id, auth, data: NameData, mut c: PooledConnection| async move {
-                store::edit_store(auth, id, &data, &mut *c)
+            move |id,
+                  auth,
+                  data: NameData,
+                  if_match,
+                  mut s: DbStorage,
+                  events: EventBus| async move {
+                store::edit_store(auth, id, &data, if_match, &mut *s, &events)
                     .await
                     .map(|()| warp::reply())
                     .map_err(warp::reject::custom)
@@ -133,97 +454,395 @@ pub async fn start_server(opt: &Opt) -> error::Result<()> {
         );
 
     // POST /store/<id>/aisle
-    let create_aisle = path!("store" / String / "aisle")
+    let create_aisle = on(&api::CREATE_AISLE)
+        .and(path!("store" / String / "aisle"))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
         .and(warp::body::json())
-        .and(get_connection())
+        .and(get_storage())
+        .and(get_events())
         .and_then(
-            move |store_id, auth, data: NameData, mut c: PooledConnection| async move {
-                aisle::create_aisle(auth, store_id, &data, &mut *c)
+            move |store_id, auth, data: NameData, mut s: DbStorage, events: EventBus| async move {
+                aisle::create_aisle(auth, store_id, &data, &mut *s, &events)
                     .await
-                    .map(|aisle| warp::reply::json(&aisle))
+                    .map(|aisle| {
+                        warp::reply::with_header(
+                            warp::reply::json(&aisle),
+                            HEADER_ETAG,
+                            etag(aisle.version()),
+                        )
+                    })
                     .map_err(warp::reject::custom)
             },
         );
 
     // PUT /aisle/<id>
-    let edit_aisle = path!("aisle" / String)
+    // PUT /aisle/<id>
+    let edit_aisle =
+        on(&api::EDIT_AISLE)
+            .and(path!("aisle" / String))
+            .and(warp::path::end())
+            .and(warp::header::<String>(HEADER_AUTH))
+            .and(warp::body::json())
+            .and(warp::header::optional::<String>(HEADER_IF_MATCH))
+            .and(get_storage())
+            .and(get_events())
+            .and_then(
+                move |aisle_id,
+                      auth,
+                      data: NameData,
+                      if_match,
+                      mut s: DbStorage,
+                      events: EventBus| async move {
+                    aisle::rename_aisle(auth, aisle_id, &data, if_match, &mut *s, &events)
+                        .await
+                        .map(|()| warp::reply())
+                        .map_err(warp::reject::custom)
+                },
+            );
+
+    // POST /aisle/<id>/product
+    let create_product = on(&api::CREATE_PRODUCT)
+        .and(path!("aisle" / String / "product"))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
         .and(warp::body::json())
-        .and(get_connection())
+        .and(get_storage())
+        .and(get_events())
         .and_then(
-            move |aisle_id, auth, data: NameData, mut c: PooledConnection| async move {
-                aisle::rename_aisle(auth, aisle_id, &data, &mut *c)
+            move |aisle_id, auth, data: NameData, mut s: DbStorage, events: EventBus| async move {
+                product::create_product(auth, aisle_id, &data, &mut *s, &events)
                     .await
-                    .map(|()| warp::reply())
+                    .map(|product| {
+                        warp::reply::with_header(
+                            warp::reply::json(&product),
+                            HEADER_ETAG,
+                            etag(product.version()),
+                        )
+                    })
                     .map_err(warp::reject::custom)
             },
         );
 
-    // POST /aisle/<id>/product
-    let create_product = path!("aisle" / String / "product")
+    // PUT /product/<id>
+    let edit_product = on(&api::EDIT_PRODUCT)
+        .and(path!("product" / String))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
         .and(warp::body::json())
-        .and(get_connection())
+        .and(warp::header::optional::<String>(HEADER_IF_MATCH))
+        .and(get_storage())
+        .and(get_events())
         .and_then(
-            move |aisle_id, auth, data: NameData, mut c: PooledConnection| async move {
-                product::create_product(auth, aisle_id, &data, &mut *c)
+            move |product_id,
+                  auth,
+                  data: EditProduct,
+                  if_match,
+                  mut s: DbStorage,
+                  events: EventBus| async move {
+                product::edit_product(auth, product_id, &data, if_match, &mut *s, &events)
                     .await
-                    .map(|product| warp::reply::json(&product))
+                    .map(|()| warp::reply())
                     .map_err(warp::reject::custom)
             },
         );
 
-    // PUT /product/<id>
-    let edit_product = path!("product" / String)
+    // POST /product/<id>/move
+    let move_product = on(&api::MOVE_PRODUCT)
+        .and(path!("product" / String / "move"))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
         .and(warp::body::json())
-        .and(get_connection())
+        .and(warp::header::optional::<String>(HEADER_IF_MATCH))
+        .and(get_storage())
+        .and(get_events())
         .and_then(
-            move |product_id, auth, data: EditProduct, mut c: PooledConnection| async move {
-                product::edit_product(auth, product_id, &data, &mut *c)
+            move |product_id,
+                  auth,
+                  data: MoveProduct,
+                  if_match,
+                  mut s: DbStorage,
+                  events: EventBus| async move {
+                product::move_product(auth, product_id, &data, if_match, &mut *s, &events)
                     .await
-                    .map(|()| warp::reply())
+                    .map(|product| {
+                        warp::reply::with_header(
+                            warp::reply::json(&product),
+                            HEADER_ETAG,
+                            etag(product.version()),
+                        )
+                    })
                     .map_err(warp::reject::custom)
             },
         );
 
+    // POST /aisle/<id>/move
+    // POST /aisle/<id>/move
+    let move_aisle =
+        on(&api::MOVE_AISLE)
+            .and(path!("aisle" / String / "move"))
+            .and(warp::path::end())
+            .and(warp::header::<String>(HEADER_AUTH))
+            .and(warp::body::json())
+            .and(warp::header::optional::<String>(HEADER_IF_MATCH))
+            .and(get_storage())
+            .and(get_events())
+            .and_then(
+                move |aisle_id,
+                      auth,
+                      data: MoveAisle,
+                      if_match,
+                      mut s: DbStorage,
+                      events: EventBus| async move {
+                    aisle::move_aisle(auth, aisle_id, &data, if_match, &mut *s, &events)
+                        .await
+                        .map(|aisle| {
+                            warp::reply::with_header(
+                                warp::reply::json(&aisle),
+                                HEADER_ETAG,
+                                etag(aisle.version()),
+                            )
+                        })
+                        .map_err(warp::reject::custom)
+                },
+            );
+
     // GET /store
-    let get_all_stores = warp::path("store")
+    let get_all_stores = on(&api::GET_ALL_STORES)
+        .and(warp::path("store"))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
-        .and(get_connection())
-        .and_then(move |auth, mut c: PooledConnection| async move {
-            store::list_stores(auth, &mut *c)
+        .and(get_storage())
+        .and_then(move |auth, mut s: DbStorage| async move {
+            store::list_stores(auth, &mut *s)
                 .await
                 .map(|stores| warp::reply::json(&stores))
                 .map_err(warp::reject::custom)
         });
 
     // GET /store/<id>
-    let list_store = path!("store" / String)
+    let list_store = on(&api::LIST_STORE)
+        .and(path!("store" / String))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(move |store_id, auth, mut s: DbStorage| async move {
+            store::list_store(auth, store_id, &mut *s)
+                .await
+                .map(|store| {
+                    warp::reply::with_header(
+                        warp::reply::json(&store),
+                        HEADER_ETAG,
+                        etag(store.version()),
+                    )
+                })
+                .map_err(warp::reject::custom)
+        });
+
+    // GET /store/<id>/changes?since=<revision>
+    let store_changes = on(&api::STORE_CHANGES)
+        .and(path!("store" / String / "changes"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::query::<ChangesQuery>())
+        .and(get_storage())
+        .and_then(
+            move |store_id, auth, query: ChangesQuery, mut s: DbStorage| async move {
+                store::store_changes(auth, store_id, &query, &mut *s)
+                    .await
+                    .map(|changes| warp::reply::json(&changes))
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // GET /store/<id>/history?before=<id>&limit=<n>
+    let store_history = on(&api::STORE_HISTORY)
+        .and(path!("store" / String / "history"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::query::<HistoryQuery>())
+        .and(get_storage())
+        .and_then(
+            move |store_id, auth, query: HistoryQuery, mut s: DbStorage| async move {
+                store::store_history(auth, store_id, &query, &mut *s)
+                    .await
+                    .map(|history| warp::reply::json(&history))
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // GET /store/<id>/ws
+    let watch_store = on(&api::WATCH_STORE)
+        .and(path!("store" / String / "ws"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and(warp::ws())
+        .and(get_events())
+        .and(get_new_storage())
+        .and_then(
+            move |store_id,
+                  auth: String,
+                  mut s: DbStorage,
+                  ws: warp::ws::Ws,
+                  events: EventBus,
+                  new_storage: NewStorage| async move {
+                let store_id = StoreId::new(store_id);
+                store::watch_store(auth.clone(), &store_id, &mut *s)
+                    .await
+                    .map(|()| {
+                        // subscribe before upgrading so no change is missed in between
+                        let rx = events.subscribe();
+                        ws.on_upgrade(move |socket| {
+                            // the session and role are checked again while it's open
+                            feed::stream_store_events(socket, auth, store_id, rx, move || {
+                                new_storage()
+                            })
+                        })
+                    })
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // GET /feed, resumes after the event in the `Last-Event-ID` header
+    let user_feed = on(&api::USER_FEED)
+        .and(warp::path("feed"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(sse::last_event_id::<u64>())
+        .and(get_storage())
+        .and(get_events())
+        .and(get_new_storage())
+        .and_then(
+            move |auth: String,
+                  resume_from,
+                  mut s: DbStorage,
+                  events: EventBus,
+                  new_storage: NewStorage| async move {
+                // subscribe before reading the log so no change is missed in between
+                let rx = events.subscribe();
+                feed::open_feed(auth.clone(), resume_from, &mut *s)
+                    .await
+                    .map(|feed| {
+                        // the session is checked again while it's open
+                        let stream =
+                            feed::user_event_stream(auth, feed, rx, move || new_storage());
+                        sse::reply(sse::keep_alive().stream(stream))
+                    })
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // POST /store/<id>/invitation
+    let create_invitation = on(&api::CREATE_INVITATION)
+        .and(path!("store" / String / "invitation"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::body::json())
+        .and(get_storage())
+        .and_then(
+            move |store_id, auth, data: NewInvitation, mut s: DbStorage| async move {
+                store::create_invitation(auth, store_id, &data, &mut *s)
+                    .await
+                    .map(|invitation| warp::reply::json(&invitation))
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // GET /store/<id>/invitation
+    let list_invitations = on(&api::LIST_INVITATIONS)
+        .and(path!("store" / String / "invitation"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(move |store_id, auth, mut s: DbStorage| async move {
+            store::list_invitations(auth, store_id, &mut *s)
+                .await
+                .map(|invitations| warp::reply::json(&invitations))
+                .map_err(warp::reject::custom)
+        });
+
+    // DELETE /store/<id>/invitation/<code>
+    let revoke_invitation = on(&api::REVOKE_INVITATION)
+        .and(path!("store" / String / "invitation" / String))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
-        .and(get_connection())
-        .and_then(move |store_id, auth, mut c: PooledConnection| async move {
-            store::list_store(auth, store_id, &mut *c)
+        .and(get_storage())
+        .and_then(move |store_id, code, auth, mut s: DbStorage| async move {
+            store::revoke_invitation(auth, store_id, code, &mut *s)
+                .await
+                .map(|()| warp::reply())
+                .map_err(warp::reject::custom)
+        });
+
+    // POST /invitation/<code>
+    let redeem_invitation = on(&api::REDEEM_INVITATION)
+        .and(path!("invitation" / String))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(move |code, auth, mut s: DbStorage| async move {
+            store::redeem_invitation(auth, code, &mut *s)
                 .await
                 .map(|store| warp::reply::json(&store))
                 .map_err(warp::reject::custom)
         });
 
+    // GET /store/<id>/member
+    let list_members = on(&api::LIST_MEMBERS)
+        .and(path!("store" / String / "member"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(move |store_id, auth, mut s: DbStorage| async move {
+            store::list_members(auth, store_id, &mut *s)
+                .await
+                .map(|members| warp::reply::json(&members))
+                .map_err(warp::reject::custom)
+        });
+
+    // PUT /store/<id>/member
+    let set_member = on(&api::SET_MEMBER)
+        .and(path!("store" / String / "member"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::body::json())
+        .and(get_storage())
+        .and_then(
+            move |store_id, auth, data: EditMember, mut s: DbStorage| async move {
+                store::set_member(auth, store_id, &data, &mut *s)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // DELETE /store/<id>/member/<user_id>
+    let remove_member = on(&api::REMOVE_MEMBER)
+        .and(path!("store" / String / "member" / String))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(
+            move |store_id, user_id, auth, mut s: DbStorage| async move {
+                store::remove_member(auth, store_id, user_id, &mut *s)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
     // DELETE /product/<id>
-    let delete_product = path!("product" / String)
+    let delete_product = on(&api::DELETE_PRODUCT)
+        .and(path!("product" / String))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
-        .and(get_connection())
+        .and(warp::header::optional::<String>(HEADER_IF_MATCH))
+        .and(get_storage())
+        .and(get_events())
         .and_then(
-            move |product_id, auth, mut c: PooledConnection| async move {
-                product::delete_product(auth, product_id, &mut *c)
+            move |product_id, auth, if_match, mut s: DbStorage, events: EventBus| async move {
+                product::delete_product(auth, product_id, if_match, &mut *s, &events)
                     .await
                     .map(|()| warp::reply())
                     .map_err(warp::reject::custom)
@@ -231,89 +850,384 @@ pub async fn start_server(opt: &Opt) -> error::Result<()> {
         );
 
     // DELETE /aisle/<id>
-    let delete_aisle = path!("aisle" / String)
+    let delete_aisle = on(&api::DELETE_AISLE)
+        .and(path!("aisle" / String))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
-        .and(get_connection())
-        .and_then(move |aisle_id, auth, mut c: PooledConnection| async move {
-            aisle::delete_aisle(auth, aisle_id, &mut *c)
+        .and(warp::header::optional::<String>(HEADER_IF_MATCH))
+        .and(get_storage())
+        .and(get_events())
+        .and_then(
+            move |aisle_id, auth, if_match, mut s: DbStorage, events: EventBus| async move {
+                aisle::delete_aisle(auth, aisle_id, if_match, &mut *s, &events)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // DELETE /store/<id>
+    let delete_store = on(&api::DELETE_STORE)
+        .and(path!("store" / String))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::header::optional::<String>(HEADER_IF_MATCH))
+        .and(get_storage())
+        .and(get_events())
+        .and_then(
+            move |store_id, auth, if_match, mut s: DbStorage, events: EventBus| async move {
+                store::delete_store(auth, store_id, if_match, &mut *s, &events)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // POST /undo, reverts the latest change of the user
+    let undo = on(&api::UNDO)
+        .and(warp::path("undo"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and(get_events())
+        .and_then(move |auth, mut s: DbStorage, events: EventBus| async move {
+            undo::undo(auth, &mut *s, &events)
                 .await
                 .map(|()| warp::reply())
                 .map_err(warp::reject::custom)
         });
 
-    // DELETE /store/<id>
-    let delete_store = path!("store" / String)
+    // GET /trash
+    let list_trash = on(&api::LIST_TRASH)
+        .and(warp::path("trash"))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
-        .and(get_connection())
-        .and_then(move |store_id, auth, mut c: PooledConnection| async move {
-            store::delete_store(auth, store_id, &mut *c)
+        .and(get_storage())
+        .and_then(move |auth, mut s: DbStorage| async move {
+            store::list_trash(auth, &mut *s)
                 .await
-                .map(|()| warp::reply())
+                .map(|trash| warp::reply::json(&trash))
                 .map_err(warp::reject::custom)
         });
 
+    // POST /store/<id>/restore
+    let restore_store = on(&api::RESTORE_STORE)
+        .and(path!("store" / String / "restore"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and(get_events())
+        .and_then(
+            move |store_id, auth, mut s: DbStorage, events: EventBus| async move {
+                store::restore_store(auth, store_id, &mut *s, &events)
+                    .await
+                    .map(|store| {
+                        warp::reply::with_header(
+                            warp::reply::json(&store),
+                            HEADER_ETAG,
+                            etag(store.version()),
+                        )
+                    })
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // POST /aisle/<id>/restore
+    let restore_aisle = on(&api::RESTORE_AISLE)
+        .and(path!("aisle" / String / "restore"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and(get_events())
+        .and_then(
+            move |aisle_id, auth, mut s: DbStorage, events: EventBus| async move {
+                aisle::restore_aisle(auth, aisle_id, &mut *s, &events)
+                    .await
+                    .map(|aisle| {
+                        warp::reply::with_header(
+                            warp::reply::json(&aisle),
+                            HEADER_ETAG,
+                            etag(aisle.version()),
+                        )
+                    })
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // POST /product/<id>/restore
+    let restore_product = on(&api::RESTORE_PRODUCT)
+        .and(path!("product" / String / "restore"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and(get_events())
+        .and_then(
+            move |product_id, auth, mut s: DbStorage, events: EventBus| async move {
+                product::restore_product(auth, product_id, &mut *s, &events)
+                    .await
+                    .map(|product| {
+                        warp::reply::with_header(
+                            warp::reply::json(&product),
+                            HEADER_ETAG,
+                            etag(product.version()),
+                        )
+                    })
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // PUT /store/<id>/done, checks or unchecks all the products
+    let set_store_done = on(&api::SET_STORE_DONE)
+        .and(path!("store" / String / "done"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::body::json())
+        .and(get_storage())
+        .and(get_events())
+        .and_then(
+            move |store_id, auth, data: DoneState, mut s: DbStorage, events: EventBus| async move {
+                product::set_store_done(auth, store_id, &data, &mut *s, &events)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // PUT /aisle/<id>/done
+    let set_aisle_done = on(&api::SET_AISLE_DONE)
+        .and(path!("aisle" / String / "done"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::body::json())
+        .and(get_storage())
+        .and(get_events())
+        .and_then(
+            move |aisle_id, auth, data: DoneState, mut s: DbStorage, events: EventBus| async move {
+                product::set_aisle_done(auth, aisle_id, &data, &mut *s, &events)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // POST /store/<id>/clear_done, deletes the checked products
+    let clear_done_products = on(&api::CLEAR_DONE_PRODUCTS)
+        .and(path!("store" / String / "clear_done"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and(get_events())
+        .and_then(
+            move |store_id, auth, mut s: DbStorage, events: EventBus| async move {
+                product::clear_done_products(auth, store_id, &mut *s, &events)
+                    .await
+                    .map(|()| warp::reply())
+                    .map_err(warp::reject::custom)
+            },
+        );
+
     // PUT /sort_weight
-    let change_sort_weight = warp::path("sort_weight")
+    let change_sort_weight = on(&api::CHANGE_SORT_WEIGHT)
+        .and(warp::path("sort_weight"))
         .and(warp::path::end())
         .and(warp::header::<String>(HEADER_AUTH))
         .and(warp::body::json())
-        .and(get_connection())
+        .and(get_storage())
+        .and(get_events())
         .and_then(
-            move |auth, data: EditWeight, mut c: PooledConnection| async move {
-                misc::change_sort_weight(auth, &data, &mut *c)
+            move |auth, data: EditWeight, mut s: DbStorage, events: EventBus| async move {
+                misc::change_sort_weight(auth, &data, &mut *s, &events)
                     .await
                     .map(|()| warp::reply())
                     .map_err(warp::reject::custom)
             },
         );
 
-    let post_routes = warp::post().and(
-        create_product
-            .or(create_aisle)
-            .or(create_store)
-            .or(login)
-            .or(create_user)
-            .or(logout)
-            .or(nuke),
-    );
-
-    let put_routes = warp::put().and(
-        change_sort_weight
-            .or(edit_product)
-            .or(edit_aisle)
-            .or(edit_store),
-    );
-
-    let get_routes = warp::get().and(get_all_stores.or(list_store));
-
-    let del_routes = warp::delete().and(
-        delete_product
-            .or(delete_aisle)
-            .or(delete_store)
-            .or(delete_user),
-    );
+    // POST /batch
+    let apply_batch = on(&api::APPLY_BATCH)
+        .and(warp::path("batch"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::body::json())
+        .and(get_storage())
+        .and(get_events())
+        .and_then(
+            move |auth, batch: Batch, mut s: DbStorage, events: EventBus| async move {
+                batch::apply_batch(auth, &batch, &mut *s, &events)
+                    .await
+                    .map(|results| warp::reply::json(&results))
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // GET /export, the stores owned by the user
+    let export_account = on(&api::EXPORT_ACCOUNT)
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(get_storage())
+        .and_then(move |auth, mut s: DbStorage| async move {
+            account::export_account(auth, &mut *s)
+                .await
+                .map(|export| warp::reply::json(&export))
+                .map_err(warp::reject::custom)
+        });
+
+    // POST /import
+    let import_account = on(&api::IMPORT_ACCOUNT)
+        .and(warp::path("import"))
+        .and(warp::path::end())
+        .and(warp::header::<String>(HEADER_AUTH))
+        .and(warp::body::json())
+        .and(get_storage())
+        .and_then(
+            move |auth, data: AccountExport, mut s: DbStorage| async move {
+                account::import_account(auth, &data, &mut *s)
+                    .await
+                    .map(|report| warp::reply::json(&report))
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // GET /openapi.json
+    let openapi_doc = on(&api::OPENAPI_DOC)
+        .and(warp::path("openapi.json"))
+        .and(warp::path::end())
+        .map(|| warp::reply::json(&openapi::document()));
+
+    let post_routes = apply_batch
+        .or(move_product)
+        .or(move_aisle)
+        .or(create_product)
+        .or(create_aisle)
+        .or(create_store)
+        .or(duplicate_store)
+        .or(restore_store)
+        .or(restore_aisle)
+        .or(restore_product)
+        .or(undo)
+        .or(import_account)
+        .or(clear_done_products)
+        .or(create_invitation)
+        .or(redeem_invitation)
+        .or(login)
+        .or(create_user)
+        .or(logout)
+        .or(request_password_reset)
+        .or(reset_password)
+        .or(nuke);
+
+    let put_routes = change_sort_weight
+        .or(set_store_done)
+        .or(set_aisle_done)
+        .or(change_password)
+        .or(edit_product)
+        .or(edit_aisle)
+        .or(edit_store)
+        .or(set_member);
+
+    let get_routes = get_all_stores
+        .or(list_store)
+        .or(list_members)
+        .or(list_invitations)
+        .or(store_changes)
+        .or(store_history)
+        .or(watch_store)
+        .or(user_feed)
+        .or(list_sessions)
+        .or(list_trash)
+        .or(export_account)
+        .or(openapi_doc);
+
+    let del_routes = delete_product
+        .or(delete_aisle)
+        .or(delete_store)
+        .or(remove_member)
+        .or(revoke_invitation)
+        .or(delete_user)
+        .or(revoke_session)
+        .or(revoke_other_sessions);
 
     let get_index = warp::get()
         .and(warp::fs::dir("./static/"));
 
-    let routes = warp::path("api")
-        .and(get_routes.or(post_routes).or(put_routes).or(del_routes))
-        .or(get_index)
-        .recover(customize_error);
+    let v1 = get_routes
+        .or(post_routes)
+        .or(put_routes)
+        .or(del_routes)
+        .boxed();
+    // breaking changes go in front of the v1 routes they replace
+    let v2 = v1.clone();
+    // the paths from before versioning still answer like v1
+    let unversioned = warp::path::full()
+        .and(v1.clone())
+        .map(|path: FullPath, reply| {
+            let successor = path.as_str().replacen("/api", "/api/v1", 1);
+            let reply = warp::reply::with_header(reply, HEADER_DEPRECATION, "true");
+            warp::reply::with_header(
+                reply,
+                HEADER_LINK,
+                format!("<{}>; rel=\"successor-version\"", successor),
+            )
+        });
+
+    let routes = warp::path::full()
+        .and(
+            warp::path("api")
+                .and(
+                    warp::path("v1")
+                        .and(v1)
+                        .or(warp::path("v2").and(v2))
+                        .or(unversioned),
+                )
+                .or(get_index)
+                .recover(customize_error),
+        )
+        .map(versioned_status);
     info!("Efficio's ready for requests...");
     warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
     Ok(())
 }
 
+// Before v2, invalid parameters were answered with 412 like a version mismatch, v1 and the
+// unversioned paths still do. The `code` of the error tells them apart.
+fn versioned_status(path: FullPath, reply: impl Reply) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    if response.status() == INVALID_PARAMS && !path.as_str().starts_with("/api/v2/") {
+        *response.status_mut() = StatusCode::PRECONDITION_FAILED;
+    }
+    response
+}
+
+// every error is sent as a JSON `ServerError`, warp's own rejections included
 async fn customize_error(err: Rejection) -> Result<impl Reply, Infallible> {
-    let (code, message) = match err.find::<error::ServerError>() {
-        Some(server_error) => (server_error.status, server_error.msg.to_owned()),
-        _ => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "UNHANDLED REJECTION".to_string(),
-        ),
+    use error::{ErrorCode, ServerError};
+    use warp::reject::*;
+
+    let bad_request =
+        |code: ErrorCode, msg: &str| ServerError::new(StatusCode::BAD_REQUEST, msg).with_code(code);
+    let server_error = if let Some(server_error) = err.find::<ServerError>() {
+        server_error.clone()
+    } else if err.is_not_found() {
+        ServerError::new(error::NOT_FOUND, "No such route")
+    } else if let Some(e) = err.find::<MissingHeader>() {
+        bad_request(ErrorCode::MissingHeader, &e.to_string()).on_field(e.name())
+    } else if let Some(e) = err.find::<InvalidHeader>() {
+        bad_request(ErrorCode::InvalidHeader, &e.to_string()).on_field(e.name())
+    } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+        bad_request(ErrorCode::InvalidBody, &e.to_string())
+    } else if let Some(e) = err.find::<UnsupportedMediaType>() {
+        bad_request(ErrorCode::InvalidBody, &e.to_string())
+    } else if let Some(e) = err.find::<InvalidQuery>() {
+        bad_request(ErrorCode::InvalidQuery, &e.to_string())
+    } else if let Some(e) = err.find::<MethodNotAllowed>() {
+        ServerError::new(StatusCode::METHOD_NOT_ALLOWED, &e.to_string())
+    } else {
+        error!("Unhandled rejection: {:?}", err);
+        ServerError::new(error::INTERNAL_ERROR, "Unhandled rejection")
     };
-    Ok(warp::reply::with_status(message, code))
+    Ok(warp::reply::with_status(
+        warp::reply::json(&server_error),
+        server_error.status,
+    ))
 }
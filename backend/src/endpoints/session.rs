@@ -1,22 +1,34 @@
-use crate::{
-    db::{sessions, users},
-    error::Result,
-    types::*,
-};
+use crate::{db::Storage, error::Result, types::*};
 
-#[cfg(not(test))]
-use redis::Connection;
+pub async fn login(
+    auth_info: &AuthInfo,
+    client: &ClientInfo,
+    s: &mut dyn Storage,
+) -> Result<ConnectionToken> {
+    s.login(&auth_info, &client)
+}
 
-#[cfg(test)]
-use fake_redis::FakeConnection as Connection;
+pub async fn logout(auth: &str, user_id: &str, s: &mut dyn Storage) -> Result<()> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.delete_session(&auth, &UserId(user_id.to_owned()))?;
+    Ok(())
+}
 
-pub async fn login(auth_info: &AuthInfo, c: &mut Connection) -> Result<ConnectionToken> {
-    users::login(c, &auth_info)
+pub async fn list_sessions(auth: String, s: &mut dyn Storage) -> Result<SessionList> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    Ok(SessionList::new(s.list_sessions(&auth)?))
 }
 
-pub async fn logout(auth: &str, user_id: &str, c: &mut Connection) -> Result<()> {
+pub async fn revoke_session(auth: String, session_id: String, s: &mut dyn Storage) -> Result<()> {
     let auth = Auth(&auth);
-    sessions::validate_session(c, &auth)?;
-    sessions::delete_session(c, &auth, &UserId(user_id.to_owned()))?;
-    Ok(())
+    s.validate_session(&auth)?;
+    s.revoke_session(&auth, &session_id)
+}
+
+pub async fn revoke_other_sessions(auth: String, s: &mut dyn Storage) -> Result<()> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.revoke_other_sessions(&auth)
 }
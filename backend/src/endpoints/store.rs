@@ -1,42 +1,279 @@
-use crate::{db, error::Result, types::*};
+use crate::{
+    db::Storage,
+    endpoints::{undo, INVALID_PARAMS},
+    error::*,
+    events::{EventBus, StoreEvent},
+    types::*,
+};
 
-#[cfg(not(test))]
-use redis::Connection;
+// seconds, the default and longest lifetime of an invitation
+const INVITATION_TTL: i64 = 7 * 24 * 3600;
+// the default and largest page of a store's history
+const HISTORY_PAGE: u32 = 50;
+const MAX_HISTORY_PAGE: u32 = 200;
 
-#[cfg(test)]
-use fake_redis::FakeConnection as Connection;
+pub async fn create_store(auth: String, data: &NameData, s: &mut dyn Storage) -> Result<StoreId> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let store_id = s.save_store(&auth, &data.name)?;
+    let version = s.store_version(&store_id);
+    undo::record_at(s, &auth, version, |version| UndoOp::DeleteStore {
+        store_id: store_id.to_string(),
+        version,
+    });
+    Ok(store_id)
+}
 
-pub async fn create_store(auth: String, data: &NameData, c: &mut Connection) -> Result<StoreId> {
+pub async fn duplicate_store(
+    auth: String,
+    store_id: String,
+    data: &DuplicateStore,
+    s: &mut dyn Storage,
+) -> Result<StoreId> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::stores::save_store(c, &auth, &data.name)
+    s.validate_session(&auth)?;
+    let copy_id = s.duplicate_store(
+        &auth,
+        &StoreId::new(store_id),
+        data.name.as_deref(),
+        data.reset_done,
+    )?;
+    let version = s.store_version(&copy_id);
+    undo::record_at(s, &auth, version, |version| UndoOp::DeleteStore {
+        store_id: copy_id.to_string(),
+        version,
+    });
+    Ok(copy_id)
 }
 
 pub async fn edit_store(
     auth: String,
     id: String,
     data: &NameData,
-    c: &mut Connection,
+    if_match: Option<String>,
+    s: &mut dyn Storage,
+    events: &EventBus,
 ) -> Result<()> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::stores::edit_store(c, &auth, &StoreId::new(id), &data.name)
+    s.validate_session(&auth)?;
+    let store_id = StoreId::new(id);
+    let previous = s.store_name(&store_id)?;
+    s.edit_store(&auth, &store_id, &data.name, &IfMatch(if_match.as_deref()))?;
+    let version = s.store_version(&store_id);
+    undo::record_at(s, &auth, version, |version| UndoOp::RenameStore {
+        store_id: store_id.to_string(),
+        name: previous,
+        version,
+    });
+    events.publish(
+        s,
+        &store_id,
+        StoreEvent::StoreRenamed {
+            name: data.name.to_owned(),
+        },
+    );
+    Ok(())
+}
+
+pub async fn list_stores(auth: String, s: &mut dyn Storage) -> Result<StoreLightList> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    Ok(StoreLightList::new(s.get_all_stores(&auth)?))
+}
+
+pub async fn list_store(auth: String, store_id: String, s: &mut dyn Storage) -> Result<Store> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.list_store(&auth, &StoreId::new(store_id))
+}
+
+pub async fn store_changes(
+    auth: String,
+    store_id: String,
+    query: &ChangesQuery,
+    s: &mut dyn Storage,
+) -> Result<StoreChanges> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.store_changes(&auth, &StoreId::new(store_id), query.since)
+}
+
+pub async fn store_history(
+    auth: String,
+    store_id: String,
+    query: &HistoryQuery,
+    s: &mut dyn Storage,
+) -> Result<StoreHistory> {
+    let limit = query.limit.unwrap_or(HISTORY_PAGE);
+    if limit == 0 || limit > MAX_HISTORY_PAGE {
+        return Err(ServerError::new(
+            INVALID_PARAMS,
+            &format!("The limit must be between 1 and {}", MAX_HISTORY_PAGE),
+        ));
+    }
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.store_history(&auth, &StoreId::new(store_id), query.before, limit)
+}
+
+pub async fn delete_store(
+    auth: String,
+    store_id: String,
+    if_match: Option<String>,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<()> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let store_id = StoreId::new(store_id);
+    // the members are gone with the store
+    let members = s.member_ids(&store_id)?;
+    s.delete_store(&auth, &store_id, &IfMatch(if_match.as_deref()))?;
+    undo::record(
+        s,
+        &auth,
+        UndoOp::RestoreStore {
+            store_id: store_id.to_string(),
+        },
+    );
+    events.publish_to(s, &members, &store_id, StoreEvent::StoreDeleted);
+    Ok(())
+}
+
+pub async fn restore_store(
+    auth: String,
+    store_id: String,
+    s: &mut dyn Storage,
+    events: &EventBus,
+) -> Result<Store> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    let store_id = StoreId::new(store_id);
+    s.restore_store(&auth, &store_id)?;
+    let version = s.store_version(&store_id);
+    undo::record_at(s, &auth, version, |version| UndoOp::DeleteStore {
+        store_id: store_id.to_string(),
+        version,
+    });
+    events.publish(s, &store_id, StoreEvent::StoreRestored);
+    s.list_store(&auth, &store_id)
+}
+
+pub async fn list_trash(auth: String, s: &mut dyn Storage) -> Result<TrashList> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    Ok(TrashList::new(s.list_trash(&auth)?))
 }
 
-pub async fn list_stores(auth: String, c: &mut Connection) -> Result<StoreLightList> {
+// members can follow the changes made to the store
+pub async fn watch_store(auth: String, store_id: &StoreId, s: &mut dyn Storage) -> Result<()> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    Ok(StoreLightList::new(db::stores::get_all_stores(c, &auth)?))
+    s.validate_session(&auth)?;
+    s.verify_role(&auth, store_id, Role::Viewer)
 }
 
-pub async fn list_store(auth: String, store_id: String, c: &mut Connection) -> Result<Store> {
+// checked again while the store is watched, an idle watcher doesn't keep the session alive
+pub async fn check_watcher(auth: String, store_id: &StoreId, s: &mut dyn Storage) -> Result<()> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::stores::list_store(c, &auth, &StoreId::new(store_id))
+    s.check_session(&auth)?;
+    s.verify_role(&auth, store_id, Role::Viewer)
 }
 
-pub async fn delete_store(auth: String, store_id: String, c: &mut Connection) -> Result<()> {
+pub async fn create_invitation(
+    auth: String,
+    store_id: String,
+    data: &NewInvitation,
+    s: &mut dyn Storage,
+) -> Result<Invitation> {
+    let role = data.role.unwrap_or(Role::Editor);
+    let ttl = data.expires_in.unwrap_or(INVITATION_TTL);
+    let max_uses = data.max_uses.unwrap_or(1);
+    let invalid = if role == Role::Owner {
+        Some("A store can only have one owner")
+    } else if ttl <= 0 || ttl > INVITATION_TTL {
+        Some("An invitation must expire within a week")
+    } else if max_uses == 0 {
+        Some("An invitation must have at least a use")
+    } else {
+        None
+    };
+    if let Some(msg) = invalid {
+        return Err(ServerError::new(INVALID_PARAMS, msg));
+    }
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.create_invitation(&auth, &StoreId::new(store_id), role, ttl, max_uses)
+}
+
+pub async fn list_invitations(
+    auth: String,
+    store_id: String,
+    s: &mut dyn Storage,
+) -> Result<InvitationList> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    Ok(InvitationList::new(
+        s.list_invitations(&auth, &StoreId::new(store_id))?,
+    ))
+}
+
+pub async fn revoke_invitation(
+    auth: String,
+    store_id: String,
+    code: String,
+    s: &mut dyn Storage,
+) -> Result<()> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.revoke_invitation(&auth, &StoreId::new(store_id), &code)
+}
+
+pub async fn redeem_invitation(
+    auth: String,
+    code: String,
+    s: &mut dyn Storage,
+) -> Result<StoreLight> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.redeem_invitation(&auth, &code)
+}
+
+pub async fn list_members(
+    auth: String,
+    store_id: String,
+    s: &mut dyn Storage,
+) -> Result<StoreMemberList> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    Ok(StoreMemberList::new(
+        s.list_members(&auth, &StoreId::new(store_id))?,
+    ))
+}
+
+pub async fn set_member(
+    auth: String,
+    store_id: String,
+    data: &EditMember,
+    s: &mut dyn Storage,
+) -> Result<()> {
+    if data.role == Role::Owner {
+        return Err(
+            ServerError::new(INVALID_PARAMS, "A member can only be an editor or a viewer")
+                .on_field("role"),
+        );
+    }
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    s.set_member(&auth, &StoreId::new(store_id), &data.username, data.role)
+}
+
+pub async fn remove_member(
+    auth: String,
+    store_id: String,
+    user_id: String,
+    s: &mut dyn Storage,
+) -> Result<()> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::stores::delete_store(c, &auth, &StoreId::new(store_id))
+    s.validate_session(&auth)?;
+    s.remove_member(&auth, &StoreId::new(store_id), &UserId(user_id))
 }
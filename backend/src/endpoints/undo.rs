@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+
+use log::*;
+
+use crate::{
+    db::Storage,
+    endpoints::{aisle, misc, product},
+    error::*,
+    events::{EventBus, StoreEvent},
+    types::*,
+};
+
+// The mutations of stores, aisles and products record what reverts them, sharing a
+// store isn't undone. The mutation is already applied, a failure to record only loses
+// its undo.
+pub fn record(s: &mut dyn Storage, auth: &Auth, op: UndoOp) {
+    match serde_json::to_string(&op) {
+        Ok(op) => {
+            if let Err(e) = s.push_undo(auth, &op) {
+                error!("Cannot record undo: {}", e.msg);
+            }
+        }
+        Err(e) => error!("Cannot serialize undo: {}", e),
+    }
+}
+
+// records an undo which holds the version the mutation left the item at
+pub fn record_at(
+    s: &mut dyn Storage,
+    auth: &Auth,
+    version: Result<u64>,
+    op: impl FnOnce(u64) -> UndoOp,
+) {
+    match version {
+        Ok(version) => record(s, auth, op(version)),
+        Err(e) => error!("Cannot record undo: {}", e.msg),
+    }
+}
+
+// reverts the latest recorded mutation of the user, an undo that can't be applied
+// anymore is dropped, `VERSION_MISMATCH` when the item changed since the mutation
+pub async fn undo(auth: String, s: &mut dyn Storage, events: &EventBus) -> Result<()> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    match s.pop_undo(&auth)? {
+        Some(op) => revert(&auth, serde_json::from_str(&op)?, s, events),
+        None => Err(ServerError::new(NOT_FOUND, "Nothing to undo")),
+    }
+}
+
+fn revert(auth: &Auth, op: UndoOp, s: &mut dyn Storage, events: &EventBus) -> Result<()> {
+    match op {
+        UndoOp::DeleteStore { store_id, version } => {
+            let store_id = StoreId::new(store_id);
+            let members = s.member_ids(&store_id)?;
+            s.delete_store(auth, &store_id, &IfMatch(Some(&etag(version))))?;
+            events.publish_to(s, &members, &store_id, StoreEvent::StoreDeleted);
+        }
+        UndoOp::RestoreStore { store_id } => {
+            let store_id = StoreId::new(store_id);
+            s.restore_store(auth, &store_id)?;
+            events.publish(s, &store_id, StoreEvent::StoreRestored);
+        }
+        UndoOp::RenameStore {
+            store_id,
+            name,
+            version,
+        } => {
+            let store_id = StoreId::new(store_id);
+            s.edit_store(auth, &store_id, &name, &IfMatch(Some(&etag(version))))?;
+            events.publish(s, &store_id, StoreEvent::StoreRenamed { name });
+        }
+        UndoOp::DeleteAisle { aisle_id, version } => {
+            let aisle_id = AisleId(aisle_id);
+            let store_id = s.aisle_store(&aisle_id)?;
+            s.delete_aisle(auth, &aisle_id, &IfMatch(Some(&etag(version))))?;
+            events.publish(
+                s,
+                &store_id,
+                StoreEvent::AisleDeleted {
+                    aisle_id: aisle_id.to_string(),
+                },
+            );
+        }
+        UndoOp::RestoreAisle { aisle_id } => {
+            let (store_id, aisle) = s.restore_aisle(auth, &AisleId(aisle_id))?;
+            events.publish(s, &store_id, StoreEvent::AisleCreated { aisle });
+        }
+        UndoOp::RenameAisle {
+            aisle_id,
+            name,
+            version,
+        } => {
+            let aisle_id = AisleId(aisle_id);
+            let store_id = s.aisle_store(&aisle_id)?;
+            s.edit_aisle(auth, &aisle_id, &name, &IfMatch(Some(&etag(version))))?;
+            events.publish(
+                s,
+                &store_id,
+                StoreEvent::AisleRenamed {
+                    aisle_id: aisle_id.to_string(),
+                    name,
+                },
+            );
+        }
+        UndoOp::MoveAisle {
+            aisle_id,
+            store_id,
+            sort_weight,
+            version,
+        } => {
+            let aisle_id = AisleId(aisle_id);
+            let from_store_id = s.aisle_store(&aisle_id)?;
+            let to_store_id = StoreId::new(store_id);
+            let aisle = s.move_aisle(
+                auth,
+                &aisle_id,
+                &to_store_id,
+                Some(sort_weight),
+                &IfMatch(Some(&etag(version))),
+            )?;
+            aisle::publish_move(s, events, &from_store_id, &to_store_id, &aisle);
+        }
+        UndoOp::DeleteProduct {
+            product_id,
+            version,
+        } => {
+            let product_id = ProductId(product_id);
+            let store_id = s.product_store(&product_id)?;
+            s.delete_product(auth, &product_id, &IfMatch(Some(&etag(version))))?;
+            events.publish(
+                s,
+                &store_id,
+                StoreEvent::ProductDeleted {
+                    product_id: product_id.to_string(),
+                },
+            );
+        }
+        UndoOp::RestoreProduct { product_id } => {
+            let (aisle_id, product) = s.restore_product(auth, &ProductId(product_id))?;
+            let store_id = s.aisle_store(&aisle_id)?;
+            events.publish(
+                s,
+                &store_id,
+                StoreEvent::ProductCreated {
+                    aisle_id: aisle_id.to_string(),
+                    product,
+                },
+            );
+        }
+        UndoOp::RestoreProducts { product_ids } => {
+            let mut restored: HashMap<String, Vec<ProductChange>> = HashMap::new();
+            for product_id in product_ids {
+                let (aisle_id, product) = s.restore_product(auth, &ProductId(product_id))?;
+                let store_id = s.aisle_store(&aisle_id)?;
+                restored
+                    .entry(store_id.to_string())
+                    .or_default()
+                    .push(ProductChange::new(aisle_id.to_string(), product));
+            }
+            for (store_id, products) in restored {
+                events.publish(
+                    s,
+                    &StoreId::new(store_id),
+                    StoreEvent::ProductsCreated { products },
+                );
+            }
+        }
+        UndoOp::EditProduct {
+            product_id,
+            changes,
+            version,
+        } => {
+            let product_id = ProductId(product_id);
+            let store_id = s.product_store(&product_id)?;
+            s.modify_product(auth, &changes, &product_id, &IfMatch(Some(&etag(version))))?;
+            events.publish(
+                s,
+                &store_id,
+                StoreEvent::ProductModified {
+                    product_id: product_id.to_string(),
+                    changes,
+                },
+            );
+        }
+        UndoOp::MoveProduct {
+            product_id,
+            aisle_id,
+            sort_weight,
+            version,
+        } => {
+            let product_id = ProductId(product_id);
+            let aisle_id = AisleId(aisle_id);
+            let from_store_id = s.product_store(&product_id)?;
+            let product = s.move_product(
+                auth,
+                &product_id,
+                &aisle_id,
+                Some(sort_weight),
+                &IfMatch(Some(&etag(version))),
+            )?;
+            let to_store_id = s.aisle_store(&aisle_id)?;
+            product::publish_move(s, events, &from_store_id, &to_store_id, &aisle_id, &product);
+        }
+        UndoOp::SetDone {
+            product_ids,
+            is_done,
+        } => {
+            let product_ids: Vec<_> = product_ids.into_iter().map(ProductId).collect();
+            let mut changed: HashMap<String, Vec<ProductId>> = HashMap::new();
+            for product_id in s.set_products_done(auth, &product_ids, is_done)? {
+                if let Ok(store_id) = s.product_store(&product_id) {
+                    changed
+                        .entry(store_id.to_string())
+                        .or_default()
+                        .push(product_id);
+                }
+            }
+            for (store_id, changed) in changed {
+                product::publish_done(s, events, &StoreId::new(store_id), changed, is_done);
+            }
+        }
+        UndoOp::ChangeSortWeight { aisles, products } => {
+            let weights = EditWeight::new(Some(aisles), Some(products));
+            let changes = misc::weights_by_store(s, &weights)?;
+            s.change_sort_weight(auth, &weights)?;
+            misc::publish_weights(s, events, changes);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sessions::tests::TTL;
+    use crate::db::{sqlite::tests::open_for_test, sqlite::SqliteStorage, users::tests::*};
+    use crate::endpoints::store;
+
+    #[tokio::test]
+    async fn undo_test() {
+        let mut c = open_for_test();
+        let mut s = SqliteStorage::new(&mut c, TTL);
+        let events = EventBus::default();
+        let token = s.save_user(&gen_user()).unwrap();
+        let auth = token.session_token.clone();
+        let name_data = |name: &str| NameData {
+            name: name.to_owned(),
+        };
+
+        let store_id = store::create_store(auth.clone(), &name_data("store"), &mut s)
+            .await
+            .unwrap();
+        let aisle = aisle::create_aisle(
+            auth.clone(),
+            store_id.to_string(),
+            &name_data("aisle"),
+            &mut s,
+            &events,
+        )
+        .await
+        .unwrap();
+        let aisle_id = aisle.id().to_string();
+        let product = product::create_product(
+            auth.clone(),
+            aisle_id.clone(),
+            &name_data("milk"),
+            &mut s,
+            &events,
+        )
+        .await
+        .unwrap();
+        let product_id = product.id().to_string();
+        let edit = EditProduct::new(Some("water".to_owned()), Some(6), None, None);
+        product::edit_product(
+            auth.clone(),
+            product_id.clone(),
+            &edit,
+            None,
+            &mut s,
+            &events,
+        )
+        .await
+        .unwrap();
+        aisle::rename_aisle(
+            auth.clone(),
+            aisle_id.clone(),
+            &name_data("drinks"),
+            None,
+            &mut s,
+            &events,
+        )
+        .await
+        .unwrap();
+        product::delete_product(auth.clone(), product_id.clone(), None, &mut s, &events)
+            .await
+            .unwrap();
+
+        let read = |s: &mut dyn Storage| {
+            let store = s.list_store(&Auth(&auth), &store_id).unwrap();
+            serde_json::to_value(&store).unwrap()
+        };
+        assert_eq!(Ok(()), undo(auth.clone(), &mut s, &events).await);
+        assert_eq!("water", read(&mut s)["aisles"][0]["products"][0]["name"]);
+        assert_eq!(Ok(()), undo(auth.clone(), &mut s, &events).await);
+        assert_eq!("aisle", read(&mut s)["aisles"][0]["name"]);
+        assert_eq!(Ok(()), undo(auth.clone(), &mut s, &events).await);
+        let store = read(&mut s);
+        assert_eq!("milk", store["aisles"][0]["products"][0]["name"]);
+        assert_eq!(1, store["aisles"][0]["products"][0]["quantity"]);
+        // back to an empty account
+        for _ in 0..3 {
+            assert_eq!(Ok(()), undo(auth.clone(), &mut s, &events).await);
+        }
+        assert_eq!(Ok(0), s.get_all_stores(&Auth(&auth)).map(|s| s.len()));
+        assert_eq!(
+            Err(NOT_FOUND),
+            undo(auth.clone(), &mut s, &events)
+                .await
+                .map_err(|e| e.status)
+        );
+    }
+
+    #[tokio::test]
+    async fn undo_modified_test() {
+        let mut c = open_for_test();
+        let mut s = SqliteStorage::new(&mut c, TTL);
+        let events = EventBus::default();
+        let token = s.save_user(&gen_user()).unwrap();
+        let auth = token.session_token.clone();
+        let store_id = s.save_store(&Auth(&auth), "store").unwrap();
+        let aisle_id = s.save_aisle(&Auth(&auth), &store_id, "aisle").unwrap().id();
+        let name_data = NameData {
+            name: "drinks".to_owned(),
+        };
+        aisle::rename_aisle(
+            auth.clone(),
+            aisle_id.to_string(),
+            &name_data,
+            None,
+            &mut s,
+            &events,
+        )
+        .await
+        .unwrap();
+        // renamed again by someone else
+        s.edit_aisle(&Auth(&auth), &aisle_id, "fruits", &IfMatch::default())
+            .unwrap();
+
+        assert_eq!(
+            Err(VERSION_MISMATCH),
+            undo(auth.clone(), &mut s, &events)
+                .await
+                .map_err(|e| e.status)
+        );
+        assert_eq!(
+            Ok("fruits".to_owned()),
+            s.get_aisle(&aisle_id).map(|a| a.name().to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn undo_clear_done_test() {
+        let mut c = open_for_test();
+        let mut s = SqliteStorage::new(&mut c, TTL);
+        let events = EventBus::default();
+        let token = s.save_user(&gen_user()).unwrap();
+        let auth = token.session_token.clone();
+        let store_id = s.save_store(&Auth(&auth), "store").unwrap();
+        let aisle_id = s.save_aisle(&Auth(&auth), &store_id, "aisle").unwrap().id();
+        for name in &["milk", "water"] {
+            s.save_product(&Auth(&auth), name, &aisle_id).unwrap();
+        }
+        let done = DoneState { is_done: true };
+        product::set_store_done(auth.clone(), store_id.to_string(), &done, &mut s, &events)
+            .await
+            .unwrap();
+        let mut rx = events.subscribe();
+        product::clear_done_products(auth.clone(), store_id.to_string(), &mut s, &events)
+            .await
+            .unwrap();
+        // one event for the whole list
+        let event = serde_json::to_value(&*rx.try_recv().unwrap()).unwrap();
+        assert_eq!("products_deleted", event["type"]);
+        assert_eq!(
+            Some(2),
+            event["product_ids"].as_array().map(|ids| ids.len())
+        );
+        assert!(rx.try_recv().is_err());
+
+        let products = |s: &mut dyn Storage| {
+            let store = s.list_store(&Auth(&auth), &store_id).unwrap();
+            serde_json::to_value(&store).unwrap()["aisles"][0]["products"]
+                .as_array()
+                .map_or(0, |p| p.len())
+        };
+        assert_eq!(0, products(&mut s));
+        assert_eq!(Ok(()), undo(auth.clone(), &mut s, &events).await);
+        assert_eq!(2, products(&mut s));
+        let event = serde_json::to_value(&*rx.try_recv().unwrap()).unwrap();
+        assert_eq!("products_created", event["type"]);
+        assert!(rx.try_recv().is_err());
+    }
+}
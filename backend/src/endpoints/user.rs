@@ -1,45 +1,96 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 
-#[cfg(not(test))]
-use redis::Connection;
-
-#[cfg(test)]
-use fake_redis::FakeConnection as Connection;
-
 use crate::{
-    db,
+    db::{users::RESET_TOKEN_TTL, Storage},
     endpoints::INVALID_PARAMS,
-    error::{Result, ServerError},
+    error::{ErrorCode, Result, ServerError},
+    mailer::Mailer,
     types::*,
 };
 
 const MIN_ENTROPY_SCORE: u8 = 2;
 
-pub async fn create_user(user: &User, c: &mut Connection) -> Result<ConnectionToken> {
+pub async fn create_user(user: &User, s: &mut dyn Storage) -> Result<ConnectionToken> {
     validate_email(&user.email)?;
-    validate_password(&user)?;
+    validate_password(&user.password, "password", &[&user.username, &user.email])?;
     validate_username(&user.username)?;
-    db::users::save_user(c, &user)
+    s.save_user(&user)
+}
+
+pub async fn change_password(
+    auth: &str,
+    user_id: &str,
+    data: &ChangePassword,
+    s: &mut dyn Storage,
+) -> Result<()> {
+    let auth = Auth(&auth);
+    s.validate_session(&auth)?;
+    validate_password(&data.new_password, "new_password", &[&data.old_password])?;
+    s.change_password(
+        &auth,
+        &UserId(user_id.to_string()),
+        &data.old_password,
+        &data.new_password,
+    )?;
+    if data.revoke_other_sessions {
+        s.revoke_other_sessions(&auth)?;
+    }
+    Ok(())
 }
 
-pub async fn delete_user(auth: &str, user_id: &str, c: &mut Connection) -> Result<()> {
+pub async fn delete_user(auth: &str, user_id: &str, s: &mut dyn Storage) -> Result<()> {
     let auth = Auth(&auth);
-    db::sessions::validate_session(c, &auth)?;
-    db::users::delete_user(c, &auth, &UserId(user_id.to_string()))
+    s.validate_session(&auth)?;
+    s.delete_user(&auth, &UserId(user_id.to_string()))
+}
+
+// the answer is the same whether the account exists or not, not to disclose it
+pub async fn request_password_reset(
+    data: &PasswordResetRequest,
+    s: &mut dyn Storage,
+    mailer: &dyn Mailer,
+) -> Result<()> {
+    if let Some(token) = s.create_reset_token(&data.username, &data.email)? {
+        mailer.send(
+            &data.email,
+            "Efficio password reset",
+            &format!(
+                "A password reset was requested for the Efficio account {}.\n\n\
+                 Use this code to choose a new password, it is valid for {} minutes:\n\n\
+                 {}\n\n\
+                 If you did not ask for it, you can ignore this message.",
+                data.username,
+                RESET_TOKEN_TTL / 60,
+                token
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+pub async fn reset_password(token: &str, data: &NewPassword, s: &mut dyn Storage) -> Result<()> {
+    validate_password(&data.new_password, "new_password", &[])?;
+    s.reset_password(token, &data.new_password)
 }
 
 fn validate_email(mail: &str) -> Result<()> {
     if !validator::validate_email(mail) {
-        Err(ServerError::new(INVALID_PARAMS, "Email field is invalid"))
+        Err(ServerError::new(INVALID_PARAMS, "Email field is invalid")
+            .with_code(ErrorCode::InvalidEmail)
+            .on_field("email"))
     } else {
         Ok(())
     }
 }
 
-fn validate_password(user: &User) -> Result<()> {
-    let entropy = zxcvbn::zxcvbn(&user.password, &[&user.username, &user.email])
-        .map_err(|_| ServerError::new(INVALID_PARAMS, "Empty password"))?;
+// `field` is the name of the password in the request
+fn validate_password(password: &str, field: &str, user_inputs: &[&str]) -> Result<()> {
+    let entropy = zxcvbn::zxcvbn(password, user_inputs).map_err(|_| {
+        ServerError::new(INVALID_PARAMS, "Empty password")
+            .with_code(ErrorCode::WeakPassword)
+            .on_field(field)
+    })?;
 
     if entropy.score() < MIN_ENTROPY_SCORE {
         Err(ServerError::new(
@@ -54,7 +105,9 @@ fn validate_password(user: &User) -> Result<()> {
                         .map_or_else(|| "Unknown reason".to_string(), |v| format!("{}", v))
                 )
             ),
-        ))
+        )
+        .with_code(ErrorCode::WeakPassword)
+        .on_field(field))
     } else {
         Ok(())
     }
@@ -67,7 +120,9 @@ fn validate_username(username: &str) -> Result<()> {
     }
 
     if !VALID_USERNAME_RE.is_match(username) {
-        Err(ServerError::new(INVALID_PARAMS, "Invalid username"))
+        Err(ServerError::new(INVALID_PARAMS, "Invalid username")
+            .with_code(ErrorCode::InvalidUsername)
+            .on_field("username"))
     } else {
         Ok(())
     }
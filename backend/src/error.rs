@@ -8,13 +8,65 @@ pub const USERNAME_TAKEN: StatusCode = StatusCode::NOT_ACCEPTABLE;
 pub const INVALID_USER_OR_PWD: StatusCode = StatusCode::BAD_REQUEST;
 pub const UNAUTHORISED: StatusCode = StatusCode::UNAUTHORIZED;
 pub const PERMISSION_DENIED: StatusCode = StatusCode::FORBIDDEN;
+pub const NOT_FOUND: StatusCode = StatusCode::NOT_FOUND;
 pub const INTERNAL_ERROR: StatusCode = StatusCode::INTERNAL_SERVER_ERROR;
+pub const VERSION_MISMATCH: StatusCode = StatusCode::PRECONDITION_FAILED;
+pub const OP_IN_PROGRESS: StatusCode = StatusCode::CONFLICT;
 
+// Stable reason of an error, for clients to act upon without parsing the message.
+// Never rename a variant, add a new one instead.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidParams,
+    MissingField,
+    InvalidUsername,
+    InvalidEmail,
+    WeakPassword,
+    UsernameTaken,
+    InvalidCredentials,
+    Unauthorised,
+    PermissionDenied,
+    NotFound,
+    VersionMismatch,
+    MissingHeader,
+    InvalidHeader,
+    InvalidBody,
+    InvalidQuery,
+    MethodNotAllowed,
+    InternalError,
+    OpInProgress,
+}
+
+impl ErrorCode {
+    // the code of an error only known by its status
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            USERNAME_TAKEN => ErrorCode::UsernameTaken,
+            INVALID_USER_OR_PWD => ErrorCode::InvalidCredentials,
+            UNAUTHORISED => ErrorCode::Unauthorised,
+            PERMISSION_DENIED => ErrorCode::PermissionDenied,
+            NOT_FOUND => ErrorCode::NotFound,
+            VERSION_MISMATCH => ErrorCode::VersionMismatch,
+            OP_IN_PROGRESS => ErrorCode::OpInProgress,
+            StatusCode::UNPROCESSABLE_ENTITY => ErrorCode::InvalidParams,
+            StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+            _ => ErrorCode::InternalError,
+        }
+    }
+}
+
+// Sent as the JSON body of error responses
 #[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct ServerError {
     #[serde(skip)]
     pub status: StatusCode,
+    pub code: ErrorCode,
+    #[serde(rename = "message")]
     pub msg: String,
+    // the request field the error is about
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
 }
 
 impl std::error::Error for ServerError {}
@@ -29,10 +81,7 @@ impl Display for ServerError {
 
 impl From<RedisError> for ServerError {
     fn from(err: RedisError) -> Self {
-        ServerError {
-            status: INTERNAL_ERROR,
-            msg: err.to_string(),
-        }
+        ServerError::new(INTERNAL_ERROR, &err.to_string())
     }
 }
 
@@ -42,21 +91,33 @@ impl From<ServerError> for RedisError {
     }
 }
 
+impl From<std::io::Error> for ServerError {
+    fn from(err: std::io::Error) -> Self {
+        ServerError::new(INTERNAL_ERROR, &err.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for ServerError {
+    fn from(err: rusqlite::Error) -> Self {
+        ServerError::new(INTERNAL_ERROR, &err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ServerError {
+    fn from(err: serde_json::Error) -> Self {
+        ServerError::new(INTERNAL_ERROR, &err.to_string())
+    }
+}
+
 impl From<r2d2::Error> for ServerError {
     fn from(err: r2d2::Error) -> Self {
-        ServerError {
-            status: StatusCode::INTERNAL_SERVER_ERROR,
-            msg: err.to_string(),
-        }
+        ServerError::new(INTERNAL_ERROR, &err.to_string())
     }
 }
 
 impl From<&r2d2::Error> for ServerError {
     fn from(err: &r2d2::Error) -> Self {
-        ServerError {
-            status: StatusCode::INTERNAL_SERVER_ERROR,
-            msg: err.to_string(),
-        }
+        ServerError::new(INTERNAL_ERROR, &err.to_string())
     }
 }
 
@@ -66,7 +127,50 @@ impl ServerError {
     pub fn new(status: StatusCode, msg: &str) -> Self {
         ServerError {
             status,
+            code: ErrorCode::from_status(status),
             msg: msg.to_owned(),
+            field: None,
         }
     }
+
+    pub fn with_code(self, code: ErrorCode) -> Self {
+        ServerError { code, ..self }
+    }
+
+    pub fn on_field(self, field: &str) -> Self {
+        ServerError {
+            field: Some(field.to_owned()),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_error_json_test() {
+        assert_eq!(
+            serde_json::json!({"code": "not_found", "message": "Store not found"}),
+            serde_json::to_value(ServerError::new(NOT_FOUND, "Store not found")).unwrap()
+        );
+        assert_eq!(
+            serde_json::json!({
+                "code": "weak_password",
+                "message": "Too weak",
+                "field": "password"
+            }),
+            serde_json::to_value(
+                ServerError::new(StatusCode::UNPROCESSABLE_ENTITY, "Too weak")
+                    .with_code(ErrorCode::WeakPassword)
+                    .on_field("password")
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            ErrorCode::InternalError,
+            ServerError::from(std::io::Error::from(std::io::ErrorKind::Other)).code
+        );
+    }
 }
@@ -0,0 +1,271 @@
+use std::sync::{Arc, Mutex};
+
+use derive_new::new;
+use log::*;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{db::Storage, types::*};
+
+// a subscriber lagging further behind misses events and is told to resync
+const EVENT_CAPACITY: usize = 256;
+
+/// A change made to a store
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StoreEvent {
+    StoreRenamed {
+        name: String,
+    },
+    StoreDeleted,
+    // taken out of the trash, the store has to be fetched again
+    StoreRestored,
+    AisleCreated {
+        aisle: Aisle,
+    },
+    AisleRenamed {
+        aisle_id: String,
+        name: String,
+    },
+    AisleDeleted {
+        aisle_id: String,
+    },
+    ProductCreated {
+        aisle_id: String,
+        product: Product,
+    },
+    // only the modified fields are present
+    ProductModified {
+        product_id: String,
+        #[serde(flatten)]
+        changes: EditProduct,
+    },
+    ProductDeleted {
+        product_id: String,
+    },
+    // the bulk changes are one event, not one per product
+    ProductsCreated {
+        products: Vec<ProductChange>,
+    },
+    ProductsModified {
+        product_ids: Vec<String>,
+        #[serde(flatten)]
+        changes: EditProduct,
+    },
+    ProductsDeleted {
+        product_ids: Vec<String>,
+    },
+    // to another aisle of the same store, moves across stores are a deletion and a creation
+    ProductMoved {
+        product_id: String,
+        aisle_id: String,
+        sort_weight: f32,
+    },
+    SortWeightChanged {
+        aisles: Vec<AisleItemWeight>,
+        products: Vec<ProductItemWeight>,
+    },
+    // events were missed, the store has to be fetched again
+    Resync,
+}
+
+#[derive(Debug, Serialize, new)]
+pub struct Event {
+    pub store_id: String,
+    #[serde(flatten)]
+    pub change: StoreEvent,
+    // user id and id of the event in their change feed
+    #[serde(skip)]
+    #[new(default)]
+    pub recipients: Vec<(String, u64)>,
+}
+
+impl Event {
+    // `None` if the event wasn't logged in the user's change feed
+    pub fn id_for(&self, user_id: &UserId) -> Option<u64> {
+        self.recipients
+            .iter()
+            .find(|(recipient, _)| *recipient == **user_id)
+            .map(|(_, id)| *id)
+    }
+}
+
+/// Broadcasts the changes made by the endpoints to every subscriber
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<Event>>,
+    // the events of changes not committed yet, see `hold`
+    held: Option<Arc<Mutex<Vec<(Vec<UserId>, StoreId, StoreEvent)>>>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+        EventBus { sender, held: None }
+    }
+}
+
+impl EventBus {
+    // log the change in the feed of every member of the store, then broadcast it
+    pub fn publish(&self, s: &mut dyn Storage, store_id: &StoreId, change: StoreEvent) {
+        let members = s.member_ids(store_id).unwrap_or_else(|e| {
+            error!("Cannot get the members of store {}: {}", **store_id, e.msg);
+            vec![]
+        });
+        self.publish_to(s, &members, store_id, change);
+    }
+
+    // for changes after which the members of the store are gone
+    pub fn publish_to(
+        &self,
+        s: &mut dyn Storage,
+        members: &[UserId],
+        store_id: &StoreId,
+        change: StoreEvent,
+    ) {
+        match &self.held {
+            Some(held) => lock(held).push((members.to_vec(), store_id.clone(), change)),
+            None => self.send(s, members, store_id, change),
+        }
+    }
+
+    // A bus keeping its events until `release`, for changes that can still be rolled back:
+    // the events of dropped changes are never seen nor logged.
+    pub fn hold(&self) -> EventBus {
+        EventBus {
+            sender: self.sender.clone(),
+            held: Some(Arc::new(Mutex::new(vec![]))),
+        }
+    }
+
+    // publish the held events, once their changes are committed
+    pub fn release(&self, s: &mut dyn Storage) {
+        if let Some(held) = &self.held {
+            let held = std::mem::take(&mut *lock(held));
+            for (members, store_id, change) in held {
+                self.send(s, &members, &store_id, change);
+            }
+        }
+    }
+
+    fn send(
+        &self,
+        s: &mut dyn Storage,
+        members: &[UserId],
+        store_id: &StoreId,
+        change: StoreEvent,
+    ) {
+        let mut event = Event::new(store_id.to_string(), change);
+        match serde_json::to_string(&event) {
+            Ok(data) => {
+                for user_id in members {
+                    match s.log_event(user_id, &data) {
+                        Ok(id) => event.recipients.push((user_id.to_string(), id)),
+                        Err(e) => error!("Cannot log event for user {}: {}", **user_id, e.msg),
+                    }
+                }
+            }
+            Err(e) => error!("Cannot serialize event: {}", e),
+        }
+        // an error only means nobody is listening
+        let _ = self.sender.send(Arc::new(event));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.sender.subscribe()
+    }
+}
+
+// the held events stay usable after a panic while holding them
+fn lock<T>(held: &Mutex<T>) -> std::sync::MutexGuard<T> {
+    held.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        sessions::tests::TTL,
+        sqlite::{tests::open_for_test, SqliteStorage},
+        users::tests::gen_user,
+    };
+
+    #[test]
+    fn publish_test() {
+        let mut c = open_for_test();
+        let mut s = SqliteStorage::new(&mut c, TTL);
+        let token = s.save_user(&gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let user_id = UserId(token.user_id.clone());
+        let store_id = s.save_store(&auth, "store").unwrap();
+        let bus = EventBus::default();
+        // nobody listening
+        bus.publish(&mut s, &store_id, StoreEvent::StoreDeleted);
+
+        let mut rx = bus.subscribe();
+        bus.publish(
+            &mut s,
+            &store_id,
+            StoreEvent::ProductModified {
+                product_id: "p1".to_owned(),
+                changes: EditProduct::new(None, None, None, Some(true)),
+            },
+        );
+        let event = rx.try_recv().unwrap();
+        let json = serde_json::json!({
+            "store_id": store_id.to_string(),
+            "type": "product_modified",
+            "product_id": "p1",
+            "is_done": true
+        });
+        assert_eq!(json, serde_json::to_value(&*event).unwrap());
+        assert_eq!(Some(2), event.id_for(&user_id));
+        assert_eq!(None, event.id_for(&UserId("other".to_owned())));
+        assert!(rx.try_recv().is_err());
+
+        let feed = s.events_since(&auth, Some(1)).unwrap();
+        assert_eq!(1, feed.events.len());
+        assert_eq!(
+            json,
+            serde_json::from_str::<serde_json::Value>(&feed.events[0].data).unwrap()
+        );
+    }
+
+    #[test]
+    fn hold_test() {
+        let mut c = open_for_test();
+        let mut s = SqliteStorage::new(&mut c, TTL);
+        let token = s.save_user(&gen_user()).unwrap();
+        let auth = Auth(&token.session_token);
+        let store_id = s.save_store(&auth, "store").unwrap();
+        let bus = EventBus::default();
+        let mut rx = bus.subscribe();
+
+        let held = bus.hold();
+        held.publish(&mut s, &store_id, StoreEvent::StoreDeleted);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(
+            Ok(0),
+            s.events_since(&auth, Some(0)).map(|f| f.events.len())
+        );
+        held.release(&mut s);
+        assert!(rx.try_recv().is_ok());
+        assert_eq!(
+            Ok(1),
+            s.events_since(&auth, Some(0)).map(|f| f.events.len())
+        );
+        // only once
+        held.release(&mut s);
+        assert!(rx.try_recv().is_err());
+
+        // dropped with their changes
+        let held = bus.hold();
+        held.publish(&mut s, &store_id, StoreEvent::StoreDeleted);
+        drop(held);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(
+            Ok(1),
+            s.events_since(&auth, Some(0)).map(|f| f.events.len())
+        );
+    }
+}
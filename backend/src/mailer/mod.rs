@@ -0,0 +1,12 @@
+use crate::error::Result;
+
+mod smtp;
+mod spool;
+
+pub use smtp::SmtpMailer;
+pub use spool::SpoolMailer;
+
+/// Sends emails to the users
+pub trait Mailer: Send + Sync {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<()>;
+}
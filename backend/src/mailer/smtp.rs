@@ -0,0 +1,94 @@
+use derive_new::new;
+use lettre::{
+    smtp::authentication::Credentials, SendableEmail, SmtpClient, SmtpTransport, Transport,
+};
+use lettre_email::EmailBuilder;
+
+use crate::{
+    error::{self, Result, ServerError},
+    mailer::Mailer,
+};
+
+// Sends the messages through an SMTP relay, using STARTTLS on the submission port
+#[derive(new)]
+pub struct SmtpMailer {
+    host: String,
+    credentials: Option<(String, String)>,
+    from: String,
+}
+
+impl SmtpMailer {
+    // the message with its envelope, as handed to the relay
+    fn message(&self, to: &str, subject: &str, body: &str) -> Result<SendableEmail> {
+        let email = EmailBuilder::new()
+            .to(to)
+            .from(self.from.as_str())
+            .subject(subject)
+            .text(body)
+            .build()
+            .map_err(|e| {
+                ServerError::new(error::INTERNAL_ERROR, &format!("Invalid email: {}", e))
+            })?;
+        Ok(email.into())
+    }
+
+    // resolves the relay, only done when a message is sent
+    fn transport(&self) -> Result<SmtpTransport> {
+        let client = SmtpClient::new_simple(&self.host)
+            .map_err(|e| ServerError::new(error::INTERNAL_ERROR, &format!("SMTP error: {}", e)))?;
+        Ok(match self.credentials {
+            Some((ref user, ref password)) => client
+                .credentials(Credentials::new(user.to_owned(), password.to_owned()))
+                .transport(),
+            None => client.transport(),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+        let email = self.message(to, subject, body)?;
+        self.transport()?
+            .send(email)
+            .map_err(|e| ServerError::new(error::INTERNAL_ERROR, &format!("SMTP error: {}", e)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_test() {
+        let mailer = SmtpMailer::new(
+            "smtp.m.com".to_owned(),
+            Some(("user".to_owned(), "pwd".to_owned())),
+            "noreply@m.com".to_owned(),
+        );
+        let email = mailer.message("m@m.com", "Hello", "Body").unwrap();
+        assert_eq!(
+            Some("noreply@m.com".to_owned()),
+            email.envelope().from().map(|a| a.to_string())
+        );
+        assert_eq!(
+            vec!["m@m.com".to_owned()],
+            email
+                .envelope()
+                .to()
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+        );
+        let text = email.message_to_string().unwrap();
+        assert!(text.contains("Subject: Hello\r\n"));
+        assert!(text.contains("\r\n\r\nBody"));
+        assert_eq!(
+            Err(error::INTERNAL_ERROR),
+            mailer
+                .message("not an address", "Hello", "Body")
+                .map(|_| ())
+                .map_err(|e| e.status)
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::fs;
+use std::path::PathBuf;
+
+use derive_new::new;
+use log::*;
+use uuid::Uuid;
+
+use crate::{db, error::Result, mailer::Mailer};
+
+// Writes each message to its own file in `dir` instead of sending it, for
+// development or to be picked up by an external delivery agent
+#[derive(new)]
+pub struct SpoolMailer {
+    dir: PathBuf,
+}
+
+impl Mailer for SpoolMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let file = self.dir.join(format!(
+            "{}-{}.eml",
+            db::timestamp(),
+            Uuid::new_v4().to_simple()
+        ));
+        debug!("Spooling mail to {}", file.display());
+        fs::write(
+            file,
+            format!("To: {}\r\nSubject: {}\r\n\r\n{}\r\n", to, subject, body),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spool_mailer_test() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let mailer = SpoolMailer::new(dir.clone());
+        assert_eq!(Ok(()), mailer.send("m@m.com", "Hello", "Body"));
+        let files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|f| f.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(1, files.len());
+        assert_eq!(
+            "To: m@m.com\r\nSubject: Hello\r\n\r\nBody\r\n",
+            fs::read_to_string(&files[0]).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
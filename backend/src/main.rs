@@ -1,9 +1,15 @@
+// for the `json!` of the OpenAPI schemas
+#![recursion_limit = "256"]
+
+mod backup;
 #[cfg(not(test))]
 mod cli;
 mod db;
-#[cfg(not(test))]
 mod endpoints;
 mod error;
+mod events;
+mod mailer;
+mod openapi;
 mod types;
 
 #[cfg(not(test))]
@@ -13,5 +19,9 @@ async fn main() -> error::Result<()> {
 
     log::info!("Starting Efficio…");
     let opt: cli::Opt = argh::from_env();
-    endpoints::routes::start_server(&opt).await
+    match opt.command {
+        Some(cli::Command::Backup(ref cmd)) => backup::backup(&opt, cmd),
+        Some(cli::Command::Restore(ref cmd)) => backup::restore(&opt, cmd),
+        None => endpoints::routes::start_server(&opt).await,
+    }
 }
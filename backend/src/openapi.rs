@@ -0,0 +1,947 @@
+use serde_json::{json, Map, Value};
+
+use crate::endpoints::api::{self, ApiRoute};
+
+// What a route answers on success
+enum Response {
+    Empty,
+    Json(&'static str),
+    EventStream,
+    WebSocket,
+}
+
+struct Route {
+    route: ApiRoute,
+    summary: &'static str,
+    // needs the `x-auth-token` header
+    auth: bool,
+    body: Option<&'static str>,
+    query: &'static [&'static str],
+    headers: &'static [&'static str],
+    response: Response,
+}
+
+// Every route of `endpoints::api`, a test checks none is missing
+const ROUTES: &[Route] = &[
+    Route {
+        route: api::NUKE,
+        summary: "Reset the database, debug builds only",
+        auth: false,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::CREATE_USER,
+        summary: "Create an account and log into it",
+        auth: false,
+        body: Some("User"),
+        query: &[],
+        headers: &[],
+        response: Response::Json("ConnectionToken"),
+    },
+    Route {
+        route: api::LOGIN,
+        summary: "Open a session",
+        auth: false,
+        body: Some("AuthInfo"),
+        query: &[],
+        headers: &[],
+        response: Response::Json("ConnectionToken"),
+    },
+    Route {
+        route: api::LOGOUT,
+        summary: "Close the current session",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::REQUEST_PASSWORD_RESET,
+        summary: "Mail a password reset token",
+        auth: false,
+        body: Some("PasswordResetRequest"),
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::RESET_PASSWORD,
+        summary: "Choose a new password with a reset token",
+        auth: false,
+        body: Some("NewPassword"),
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::LIST_SESSIONS,
+        summary: "List the sessions of the user",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("SessionList"),
+    },
+    Route {
+        route: api::REVOKE_SESSION,
+        summary: "Revoke a session",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::REVOKE_OTHER_SESSIONS,
+        summary: "Revoke every session but the current one",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::DELETE_USER,
+        summary: "Delete the account",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::CHANGE_PASSWORD,
+        summary: "Change the password",
+        auth: true,
+        body: Some("ChangePassword"),
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::CREATE_STORE,
+        summary: "Create a store",
+        auth: true,
+        body: Some("NameData"),
+        query: &[],
+        headers: &[],
+        response: Response::Json("StoreId"),
+    },
+    Route {
+        route: api::DUPLICATE_STORE,
+        summary: "Copy a store, its aisles and products, into a new store of the user",
+        auth: true,
+        body: Some("DuplicateStore"),
+        query: &[],
+        headers: &[],
+        response: Response::Json("StoreId"),
+    },
+    Route {
+        route: api::EDIT_STORE,
+        summary: "Rename a store",
+        auth: true,
+        body: Some("NameData"),
+        query: &[],
+        headers: &["If-Match"],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::CREATE_AISLE,
+        summary: "Create an aisle",
+        auth: true,
+        body: Some("NameData"),
+        query: &[],
+        headers: &[],
+        response: Response::Json("Aisle"),
+    },
+    Route {
+        route: api::EDIT_AISLE,
+        summary: "Rename an aisle",
+        auth: true,
+        body: Some("NameData"),
+        query: &[],
+        headers: &["If-Match"],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::CREATE_PRODUCT,
+        summary: "Create a product",
+        auth: true,
+        body: Some("NameData"),
+        query: &[],
+        headers: &[],
+        response: Response::Json("Product"),
+    },
+    Route {
+        route: api::EDIT_PRODUCT,
+        summary: "Edit a product",
+        auth: true,
+        body: Some("EditProduct"),
+        query: &[],
+        headers: &["If-Match"],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::MOVE_PRODUCT,
+        summary: "Move a product to the end of an aisle, possibly of another store",
+        auth: true,
+        body: Some("MoveProduct"),
+        query: &[],
+        headers: &["If-Match"],
+        response: Response::Json("Product"),
+    },
+    Route {
+        route: api::MOVE_AISLE,
+        summary: "Move an aisle and its products to the end of a store",
+        auth: true,
+        body: Some("MoveAisle"),
+        query: &[],
+        headers: &["If-Match"],
+        response: Response::Json("Aisle"),
+    },
+    Route {
+        route: api::GET_ALL_STORES,
+        summary: "List the stores the user is a member of",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("StoreLightList"),
+    },
+    Route {
+        route: api::LIST_STORE,
+        summary: "Get a store with its aisles and products",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("Store"),
+    },
+    Route {
+        route: api::STORE_CHANGES,
+        summary: "What changed in a store after a revision",
+        auth: true,
+        body: None,
+        query: &["since"],
+        headers: &[],
+        response: Response::Json("StoreChanges"),
+    },
+    Route {
+        route: api::STORE_HISTORY,
+        summary: "Who changed what in a store, newest first",
+        auth: true,
+        body: None,
+        query: &["before", "limit"],
+        headers: &[],
+        response: Response::Json("StoreHistory"),
+    },
+    Route {
+        route: api::WATCH_STORE,
+        summary: "Follow the changes of a store over a WebSocket",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::WebSocket,
+    },
+    Route {
+        route: api::USER_FEED,
+        summary: "Follow the changes of every store of the user",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &["Last-Event-ID"],
+        response: Response::EventStream,
+    },
+    Route {
+        route: api::CREATE_INVITATION,
+        summary: "Invite to a store",
+        auth: true,
+        body: Some("NewInvitation"),
+        query: &[],
+        headers: &[],
+        response: Response::Json("Invitation"),
+    },
+    Route {
+        route: api::LIST_INVITATIONS,
+        summary: "List the pending invitations of a store",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("InvitationList"),
+    },
+    Route {
+        route: api::REVOKE_INVITATION,
+        summary: "Revoke an invitation",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::REDEEM_INVITATION,
+        summary: "Join a store with an invitation",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("StoreLight"),
+    },
+    Route {
+        route: api::LIST_MEMBERS,
+        summary: "List the members of a store",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("StoreMemberList"),
+    },
+    Route {
+        route: api::SET_MEMBER,
+        summary: "Add a member or change their role",
+        auth: true,
+        body: Some("EditMember"),
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::REMOVE_MEMBER,
+        summary: "Remove a member, or leave the store",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::DELETE_PRODUCT,
+        summary: "Delete a product",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &["If-Match"],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::DELETE_AISLE,
+        summary: "Delete an aisle and its products",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &["If-Match"],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::DELETE_STORE,
+        summary: "Delete a store",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &["If-Match"],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::UNDO,
+        summary: "Revert the latest change the user made to a store, aisle or product",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::LIST_TRASH,
+        summary: "List the deleted items of the user, the latest first",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("TrashList"),
+    },
+    Route {
+        route: api::RESTORE_STORE,
+        summary: "Take a store out of the trash",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("Store"),
+    },
+    Route {
+        route: api::RESTORE_AISLE,
+        summary: "Take an aisle and its products out of the trash",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("Aisle"),
+    },
+    Route {
+        route: api::RESTORE_PRODUCT,
+        summary: "Take a product out of the trash",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("Product"),
+    },
+    Route {
+        route: api::SET_STORE_DONE,
+        summary: "Check or uncheck all the products of a store",
+        auth: true,
+        body: Some("DoneState"),
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::SET_AISLE_DONE,
+        summary: "Check or uncheck all the products of an aisle",
+        auth: true,
+        body: Some("DoneState"),
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::CLEAR_DONE_PRODUCTS,
+        summary: "Move the checked products of a store to the trash",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::CHANGE_SORT_WEIGHT,
+        summary: "Reorder aisles and products",
+        auth: true,
+        body: Some("EditWeight"),
+        query: &[],
+        headers: &[],
+        response: Response::Empty,
+    },
+    Route {
+        route: api::APPLY_BATCH,
+        summary: "Apply operations made offline",
+        auth: true,
+        body: Some("Batch"),
+        query: &[],
+        headers: &[],
+        response: Response::Json("BatchResults"),
+    },
+    Route {
+        route: api::EXPORT_ACCOUNT,
+        summary: "Export the stores the user owns, with their aisles and products",
+        auth: true,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("AccountExport"),
+    },
+    Route {
+        route: api::IMPORT_ACCOUNT,
+        summary: "Recreate exported stores under new ids, skipping the names already used",
+        auth: true,
+        body: Some("AccountExport"),
+        query: &[],
+        headers: &[],
+        response: Response::Json("ImportReport"),
+    },
+    Route {
+        route: api::OPENAPI_DOC,
+        summary: "This document",
+        auth: false,
+        body: None,
+        query: &[],
+        headers: &[],
+        response: Response::Json("OpenApi"),
+    },
+];
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn string_param(name: &str, location: &str, required: bool) -> Value {
+    json!({
+        "name": name,
+        "in": location,
+        "required": required,
+        "schema": { "type": "string" },
+    })
+}
+
+fn operation(route: &Route) -> Value {
+    let mut parameters: Vec<Value> = route
+        .route
+        .path
+        .split('/')
+        .filter(|part| part.starts_with('{'))
+        .map(|part| string_param(part.trim_matches(|c| c == '{' || c == '}'), "path", true))
+        .collect();
+    parameters.extend(route.query.iter().map(|name| {
+        json!({
+            "name": name,
+            "in": "query",
+            "required": true,
+            "schema": { "type": "integer", "minimum": 0 },
+        })
+    }));
+    parameters.extend(
+        route
+            .headers
+            .iter()
+            .map(|name| string_param(name, "header", false)),
+    );
+    let success = match route.response {
+        Response::Empty => json!({ "description": "Done" }),
+        Response::Json("OpenApi") => json!({
+            "description": "OpenAPI document",
+            "content": { "application/json": { "schema": { "type": "object" } } },
+        }),
+        Response::Json(schema) => json!({
+            "description": schema,
+            "content": { "application/json": { "schema": schema_ref(schema) } },
+        }),
+        Response::EventStream => json!({
+            "description": "Server-sent events, one `StoreEvent` as JSON per event",
+            "content": { "text/event-stream": { "schema": { "type": "string" } } },
+        }),
+        Response::WebSocket => json!({
+            "description": "Upgraded to a WebSocket sending `StoreEvent` as JSON",
+        }),
+    };
+    let (status, success) = match route.response {
+        Response::WebSocket => ("101", success),
+        _ => ("200", success),
+    };
+    let mut op = json!({
+        "summary": route.summary,
+        "parameters": parameters,
+        "responses": {
+            status: success,
+            "default": {
+                "description": "Error",
+                "content": { "application/json": { "schema": schema_ref("ServerError") } },
+            },
+        },
+    });
+    if let Some(body) = route.body {
+        op["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": schema_ref(body) } },
+        });
+    }
+    if route.auth {
+        op["security"] = json!([{ "auth_token": [] }]);
+    }
+    op
+}
+
+fn object(required: &[&str], properties: Value) -> Value {
+    json!({
+        "type": "object",
+        "required": required,
+        "properties": properties,
+    })
+}
+
+fn list_of(field: &str, item: &str) -> Value {
+    object(
+        &[field],
+        json!({ field: { "type": "array", "items": schema_ref(item) } }),
+    )
+}
+
+// The JSON of the types in `types.rs` and `error.rs`
+fn schemas() -> Value {
+    let string = json!({ "type": "string" });
+    let boolean = json!({ "type": "boolean" });
+    let integer = json!({ "type": "integer", "minimum": 0 });
+    let timestamp = json!({ "type": "integer", "description": "seconds since the Unix epoch" });
+    let weight = json!({ "type": "number" });
+    let role = json!({ "type": "string", "enum": ["viewer", "editor", "owner"] });
+    let unit = json!({ "type": "integer", "enum": [0, 1, 2], "description": "unit, gram, ml" });
+    let item_weight = object(
+        &["id", "sort_weight"],
+        json!({ "id": string, "sort_weight": weight }),
+    );
+    json!({
+        "User": object(
+            &["username", "email", "password"],
+            json!({ "username": string, "email": string, "password": string }),
+        ),
+        "AuthInfo": object(
+            &["username", "password"],
+            json!({ "username": string, "password": string }),
+        ),
+        "ConnectionToken": object(
+            &["session_token", "user_id"],
+            json!({ "session_token": string, "user_id": string }),
+        ),
+        "PasswordResetRequest": object(
+            &["username", "email"],
+            json!({ "username": string, "email": string }),
+        ),
+        "NewPassword": object(&["new_password"], json!({ "new_password": string })),
+        "ChangePassword": object(
+            &["old_password", "new_password"],
+            json!({
+                "old_password": string,
+                "new_password": string,
+                "revoke_other_sessions": boolean,
+            }),
+        ),
+        "SessionInfo": object(
+            &["session_id", "created_at", "last_used", "current"],
+            json!({
+                "session_id": string,
+                "created_at": timestamp,
+                "last_used": timestamp,
+                "user_agent": string,
+                "ip": string,
+                "current": boolean,
+            }),
+        ),
+        "SessionList": list_of("sessions", "SessionInfo"),
+        "NameData": object(&["name"], json!({ "name": string })),
+        "DuplicateStore": object(
+            &[],
+            json!({
+                "name": { "type": "string", "description": "the name of the original by default" },
+                "reset_done": boolean,
+            }),
+        ),
+        "StoreId": object(&["store_id"], json!({ "store_id": string })),
+        "StoreLight": object(
+            &["name", "store_id", "role"],
+            json!({ "name": string, "store_id": string, "role": role }),
+        ),
+        "StoreLightList": list_of("stores", "StoreLight"),
+        "Product": object(
+            &["product_id", "name", "quantity", "is_done", "unit", "sort_weight", "version"],
+            json!({
+                "product_id": string,
+                "name": string,
+                "quantity": integer,
+                "is_done": boolean,
+                "unit": unit,
+                "sort_weight": weight,
+                "version": integer,
+            }),
+        ),
+        "Aisle": object(
+            &["aisle_id", "name", "sort_weight", "products", "version"],
+            json!({
+                "aisle_id": string,
+                "name": string,
+                "sort_weight": weight,
+                "products": { "type": "array", "items": schema_ref("Product") },
+                "version": integer,
+            }),
+        ),
+        "Store": object(
+            &["store_id", "name", "aisles", "revision", "version"],
+            json!({
+                "store_id": string,
+                "name": string,
+                "aisles": { "type": "array", "items": schema_ref("Aisle") },
+                "revision": integer,
+                "version": integer,
+            }),
+        ),
+        "EditProduct": json!({
+            "type": "object",
+            "description": "at least one field",
+            "properties": {
+                "name": string,
+                "quantity": integer,
+                "unit": unit,
+                "is_done": boolean,
+            },
+        }),
+        "DoneState": object(&["is_done"], json!({ "is_done": boolean })),
+        "MoveProduct": object(&["aisle_id"], json!({ "aisle_id": string })),
+        "MoveAisle": object(&["store_id"], json!({ "store_id": string })),
+        "ItemWeight": item_weight,
+        "EditWeight": json!({
+            "type": "object",
+            "description": "at least one item",
+            "properties": {
+                "aisles": { "type": "array", "items": schema_ref("ItemWeight") },
+                "products": { "type": "array", "items": schema_ref("ItemWeight") },
+            },
+        }),
+        "TrashedItem": object(
+            &["kind", "id", "name", "trashed_at"],
+            json!({
+                "kind": { "type": "string", "enum": ["store", "aisle", "product"] },
+                "id": string,
+                "name": string,
+                "trashed_at": timestamp,
+            }),
+        ),
+        "TrashList": list_of("items", "TrashedItem"),
+        "StoreChanges": object(
+            &[
+                "revision",
+                "resync",
+                "aisles",
+                "products",
+                "deleted_aisles",
+                "deleted_products",
+            ],
+            json!({
+                "revision": integer,
+                "resync": boolean,
+                "name": string,
+                "aisles": { "type": "array", "items": schema_ref("Aisle") },
+                "products": {
+                    "type": "array",
+                    "items": {
+                        "allOf": [
+                            schema_ref("Product"),
+                            object(&["aisle_id"], json!({ "aisle_id": string })),
+                        ],
+                    },
+                },
+                "deleted_aisles": { "type": "array", "items": string },
+                "deleted_products": { "type": "array", "items": string },
+            }),
+        ),
+        "AuditEntry": object(
+            &["id", "user_id", "at", "action", "item_id"],
+            json!({
+                "id": integer,
+                "user_id": string,
+                "at": timestamp,
+                "action": {
+                    "type": "string",
+                    "enum": [
+                        "store_created",
+                        "store_renamed",
+                        "store_deleted",
+                        "store_restored",
+                        "aisle_created",
+                        "aisle_renamed",
+                        "aisle_deleted",
+                        "aisle_restored",
+                        "aisle_moved",
+                        "aisle_reordered",
+                        "product_created",
+                        "product_modified",
+                        "product_deleted",
+                        "product_restored",
+                        "product_moved",
+                        "product_reordered",
+                    ],
+                },
+                "item_id": string,
+                "before": { "type": "object", "description": "the changed fields, if any" },
+                "after": { "type": "object", "description": "the changed fields, if any" },
+            }),
+        ),
+        "StoreHistory": object(
+            &["entries"],
+            json!({
+                "entries": { "type": "array", "items": schema_ref("AuditEntry") },
+                "next": { "type": "integer", "description": "`before` of the next page" },
+            }),
+        ),
+        "StoreMember": object(
+            &["user_id", "username", "role"],
+            json!({ "user_id": string, "username": string, "role": role }),
+        ),
+        "StoreMemberList": list_of("members", "StoreMember"),
+        "EditMember": object(
+            &["username", "role"],
+            json!({ "username": string, "role": role }),
+        ),
+        "NewInvitation": object(
+            &[],
+            json!({
+                "role": role,
+                "expires_in": { "type": "integer", "description": "seconds" },
+                "max_uses": integer,
+            }),
+        ),
+        "Invitation": object(
+            &["code", "store_id", "role", "expires_at", "max_uses", "uses"],
+            json!({
+                "code": string,
+                "store_id": string,
+                "role": role,
+                "expires_at": timestamp,
+                "max_uses": integer,
+                "uses": integer,
+            }),
+        ),
+        "InvitationList": list_of("invitations", "Invitation"),
+        "BatchOperation": object(
+            &["op_id", "op"],
+            json!({
+                "op_id": string,
+                "op": {
+                    "type": "string",
+                    "enum": [
+                        "create_store",
+                        "edit_store",
+                        "delete_store",
+                        "create_aisle",
+                        "edit_aisle",
+                        "delete_aisle",
+                        "create_product",
+                        "edit_product",
+                        "delete_product",
+                        "change_sort_weight",
+                    ],
+                },
+                "store_id": string,
+                "aisle_id": string,
+                "product_id": string,
+                "name": string,
+            }),
+        ),
+        "Batch": list_of("operations", "BatchOperation"),
+        "OpResult": object(
+            &["op_id", "status"],
+            json!({
+                "op_id": string,
+                "status": { "type": "string", "enum": ["applied", "failed", "skipped"] },
+                "result": { "type": "object" },
+                "replayed": boolean,
+                "http_status": integer,
+                "code": string,
+                "message": string,
+                "field": string,
+            }),
+        ),
+        "BatchResults": list_of("results", "OpResult"),
+        "ExportedProduct": object(
+            &["name", "quantity", "unit", "is_done", "sort_weight"],
+            json!({
+                "product_id": string,
+                "name": string,
+                "quantity": integer,
+                "unit": unit,
+                "is_done": boolean,
+                "sort_weight": weight,
+            }),
+        ),
+        "ExportedAisle": object(
+            &["name", "sort_weight", "products"],
+            json!({
+                "aisle_id": string,
+                "name": string,
+                "sort_weight": weight,
+                "products": { "type": "array", "items": schema_ref("ExportedProduct") },
+            }),
+        ),
+        "ExportedStore": object(
+            &["name", "aisles"],
+            json!({
+                "store_id": string,
+                "name": string,
+                "aisles": { "type": "array", "items": schema_ref("ExportedAisle") },
+            }),
+        ),
+        "AccountExport": object(
+            &["version", "stores"],
+            json!({
+                "version": { "type": "integer", "enum": [1] },
+                "stores": { "type": "array", "items": schema_ref("ExportedStore") },
+            }),
+        ),
+        "ImportReport": object(
+            &["imported", "conflicts"],
+            json!({
+                "imported": { "type": "array", "items": schema_ref("StoreLight") },
+                "conflicts": { "type": "array", "items": string },
+            }),
+        ),
+        "ServerError": object(
+            &["code", "message"],
+            json!({
+                "code": { "type": "string" },
+                "message": string,
+                "field": string,
+            }),
+        ),
+    })
+}
+
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for route in ROUTES {
+        let path = paths.entry(route.route.path).or_insert_with(|| json!({}));
+        path[route.route.method] = operation(route);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Efficio",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Invalid parameters are answered with 422 on v2. v1 answers them \
+                with 412 like a version mismatch, the `code` of the error tells them apart.",
+        },
+        // `/api` without a version is a deprecated alias of v1
+        "servers": [{ "url": "/api/v1" }, { "url": "/api/v2" }],
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "auth_token": { "type": "apiKey", "in": "header", "name": "x-auth-token" },
+            },
+            "schemas": schemas(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_route_is_described_test() {
+        assert!(api::ALL.len() > 30);
+        for route in api::ALL {
+            let described = ROUTES
+                .iter()
+                .filter(|r| r.route.method == route.method && r.route.path == route.path)
+                .count();
+            assert_eq!(1, described, "{} {}", route.method, route.path);
+        }
+        assert_eq!(api::ALL.len(), ROUTES.len());
+    }
+
+    #[test]
+    fn schemas_exist_test() {
+        let doc = document();
+        let schemas = &doc["components"]["schemas"];
+        for route in ROUTES {
+            let mut names = route.body.into_iter().collect::<Vec<_>>();
+            if let Response::Json(name) = route.response {
+                if name != "OpenApi" {
+                    names.push(name);
+                }
+            }
+            for name in names {
+                assert!(schemas.get(name).is_some(), "no schema for {}", name);
+            }
+        }
+        assert_eq!(
+            Some("auth_token"),
+            doc["paths"]["/store/{store_id}"]["get"]["security"][0]
+                .as_object()
+                .and_then(|o| o.keys().next())
+                .map(String::as_str)
+        );
+    }
+}
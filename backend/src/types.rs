@@ -1,4 +1,5 @@
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::string::ToString;
 
@@ -24,12 +25,89 @@ impl Drop for AuthInfo {
     }
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ChangePassword {
+    pub old_password: String,
+    pub new_password: String,
+    #[serde(default)]
+    pub revoke_other_sessions: bool,
+}
+
+impl Drop for ChangePassword {
+    fn drop(&mut self) {
+        self.old_password
+            .replace_range(..self.old_password.len(), "0");
+        self.new_password
+            .replace_range(..self.new_password.len(), "0");
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PasswordResetRequest {
+    pub username: String,
+    pub email: String,
+}
+
+impl Drop for PasswordResetRequest {
+    fn drop(&mut self) {
+        self.email.replace_range(..self.email.len(), "0");
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NewPassword {
+    pub new_password: String,
+}
+
+impl Drop for NewPassword {
+    fn drop(&mut self) {
+        self.new_password
+            .replace_range(..self.new_password.len(), "0");
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, new)]
 pub struct ConnectionToken {
     pub session_token: String,
     pub user_id: String,
 }
 
+// where a login comes from, as reported by the client
+#[derive(Debug, Default, new)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct SessionInfo {
+    session_id: String,
+    pub created_at: i64,
+    last_used: i64,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    current: bool,
+}
+
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct SessionList {
+    sessions: Vec<SessionInfo>,
+}
+
+#[cfg(test)]
+impl SessionInfo {
+    pub fn id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.current
+    }
+}
+
 #[derive(Default, Deserialize, Debug)]
 pub struct User {
     pub username: String,
@@ -44,7 +122,7 @@ impl Drop for User {
     }
 }
 
-#[derive(Debug, Deref, PartialEq, Eq)]
+#[derive(Debug, Clone, Deref, PartialEq, Eq)]
 pub struct UserId(pub String);
 
 impl ToString for UserId {
@@ -61,7 +139,7 @@ impl FromStr for UserId {
     }
 }
 
-#[derive(Serialize, Debug, new, Deref, PartialEq, Eq)]
+#[derive(Serialize, Debug, Clone, new, Deref, PartialEq, Eq)]
 pub struct StoreId {
     store_id: String,
 }
@@ -113,10 +191,262 @@ impl FromStr for ProductId {
     }
 }
 
+// ordered from the least to the most privileged
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl ToString for Role {
+    fn to_string(&self) -> String {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+        .to_owned()
+    }
+}
+
+impl FromStr for Role {
+    type Err = error::ServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "owner" => Ok(Role::Owner),
+            _ => Err(error::ServerError::new(
+                error::INTERNAL_ERROR,
+                &format!("Unknown role {}", s),
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, new, PartialEq, Eq)]
 pub struct StoreLight {
     name: String,
     store_id: String,
+    role: Role,
+}
+
+impl StoreLight {
+    pub fn id(&self) -> StoreId {
+        StoreId::new(self.store_id.clone())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+}
+
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct StoreMember {
+    user_id: String,
+    username: String,
+    role: Role,
+}
+
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct StoreMemberList {
+    members: Vec<StoreMember>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EditMember {
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NewInvitation {
+    pub role: Option<Role>,
+    // seconds
+    pub expires_in: Option<i64>,
+    pub max_uses: Option<u32>,
+}
+
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct Invitation {
+    code: String,
+    pub store_id: String,
+    pub role: Role,
+    pub expires_at: i64,
+    pub max_uses: u32,
+    uses: u32,
+}
+
+#[cfg(test)]
+impl Invitation {
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+}
+
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct InvitationList {
+    invitations: Vec<Invitation>,
+}
+
+// an event as recorded in a user's change feed, `data` is its JSON
+#[derive(Debug, Clone, new, PartialEq, Eq)]
+pub struct LoggedEvent {
+    pub id: u64,
+    pub data: String,
+}
+
+// where a user's change feed starts
+#[derive(Debug, new, PartialEq, Eq)]
+pub struct EventFeed {
+    pub user_id: UserId,
+    // the last event logged for the user, live events come after it
+    pub last_id: u64,
+    // the logged events after the one the client resumes from
+    pub events: Vec<LoggedEvent>,
+    // the client resumes from an event no longer in the log
+    pub missed: bool,
+}
+
+// an item modified by a store revision
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangedItem {
+    Store,
+    Aisle(String),
+    Product(String),
+}
+
+impl ToString for ChangedItem {
+    fn to_string(&self) -> String {
+        match self {
+            ChangedItem::Store => "store".to_owned(),
+            ChangedItem::Aisle(id) => format!("aisle:{}", id),
+            ChangedItem::Product(id) => format!("product:{}", id),
+        }
+    }
+}
+
+impl FromStr for ChangedItem {
+    type Err = error::ServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("store"), None) => Ok(ChangedItem::Store),
+            (Some("aisle"), Some(id)) => Ok(ChangedItem::Aisle(id.to_owned())),
+            (Some("product"), Some(id)) => Ok(ChangedItem::Product(id.to_owned())),
+            _ => Err(error::ServerError::new(
+                error::INTERNAL_ERROR,
+                &format!("Unknown changed item {}", s),
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChangesQuery {
+    pub since: u64,
+}
+
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct ProductChange {
+    aisle_id: String,
+    #[serde(flatten)]
+    product: Product,
+}
+
+#[cfg(test)]
+impl ProductChange {
+    pub fn id(&self) -> ProductId {
+        self.product.id()
+    }
+}
+
+// what changed in a store after a given revision
+#[derive(Debug, Default, Serialize, PartialEq, Eq)]
+pub struct StoreChanges {
+    pub revision: u64,
+    // the changes are no longer known, the whole store must be fetched again
+    pub resync: bool,
+    // only if the store was renamed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // created or updated, their products are in `products`
+    pub aisles: Vec<Aisle>,
+    pub products: Vec<ProductChange>,
+    pub deleted_aisles: Vec<String>,
+    pub deleted_products: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryQuery {
+    // the id of the oldest entry already read
+    pub before: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    StoreCreated,
+    StoreRenamed,
+    StoreDeleted,
+    StoreRestored,
+    AisleCreated,
+    AisleRenamed,
+    AisleDeleted,
+    AisleRestored,
+    // to another store or place
+    AisleMoved,
+    // only its sort weight changed
+    AisleReordered,
+    ProductCreated,
+    ProductModified,
+    ProductDeleted,
+    ProductRestored,
+    // to another aisle or place
+    ProductMoved,
+    // only its sort weight changed
+    ProductReordered,
+}
+
+// Who changed what in a store, `before` and `after` hold the fields of the item that
+// changed
+#[derive(Debug, Serialize, Deserialize, new, PartialEq)]
+pub struct AuditEntry {
+    // position in the store's history, from 1
+    #[new(default)]
+    #[serde(skip_deserializing)]
+    pub id: u64,
+    pub user_id: String,
+    // seconds since the Unix epoch
+    pub at: i64,
+    pub action: AuditAction,
+    pub item_id: String,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub before: serde_json::Value,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub after: serde_json::Value,
+}
+
+// the latest entries first
+#[derive(Debug, Serialize, new, PartialEq)]
+pub struct StoreHistory {
+    pub entries: Vec<AuditEntry>,
+    // to read the older entries, as `before`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -125,16 +455,285 @@ pub struct NameData {
     pub name: String,
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DuplicateStore {
+    // the name of the original when absent
+    pub name: Option<String>,
+    // uncheck the products of the copy
+    #[serde(default)]
+    pub reset_done: bool,
+}
+
+// version of the `AccountExport` layout, bumped when it changes
+pub const EXPORT_VERSION: u32 = 1;
+
+// The stores owned by a user, with their aisles and products
+#[derive(Debug, Serialize, Deserialize, new)]
+#[serde(deny_unknown_fields)]
+pub struct AccountExport {
+    pub version: u32,
+    pub stores: Vec<ExportedStore>,
+}
+
+// the ids are the exporting server's, an import gives new ones
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportedStore {
+    #[serde(default)]
+    pub store_id: String,
+    pub name: String,
+    pub aisles: Vec<ExportedAisle>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportedAisle {
+    #[serde(default)]
+    pub aisle_id: String,
+    pub name: String,
+    pub sort_weight: f32,
+    pub products: Vec<ExportedProduct>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportedProduct {
+    #[serde(default)]
+    pub product_id: String,
+    pub name: String,
+    pub quantity: u32,
+    pub unit: Unit,
+    pub is_done: bool,
+    pub sort_weight: f32,
+}
+
+impl From<Store> for ExportedStore {
+    fn from(store: Store) -> Self {
+        ExportedStore {
+            store_id: store.store_id,
+            name: store.name,
+            aisles: store.aisles.into_iter().map(ExportedAisle::from).collect(),
+        }
+    }
+}
+
+impl From<Aisle> for ExportedAisle {
+    fn from(aisle: Aisle) -> Self {
+        ExportedAisle {
+            aisle_id: aisle.aisle_id,
+            name: aisle.name,
+            sort_weight: aisle.sort_weight,
+            products: aisle
+                .products
+                .into_iter()
+                .map(ExportedProduct::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<Product> for ExportedProduct {
+    fn from(product: Product) -> Self {
+        ExportedProduct {
+            product_id: product.product_id,
+            name: product.name,
+            quantity: product.quantity,
+            unit: product.unit,
+            is_done: product.is_done,
+            sort_weight: product.sort_weight,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct ImportReport {
+    // the new stores, in the order of the export
+    imported: Vec<StoreLight>,
+    // the names of the stores not imported, the user already having a store so named
+    conflicts: Vec<String>,
+}
+
+// The whole database, as dumped by the backend it is restored into
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum BackupData {
+    Redis {
+        keys: Vec<RedisKey>,
+    },
+    Sqlite {
+        schema_version: u32,
+        tables: Vec<SqliteTable>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, new, PartialEq)]
+pub struct RedisKey {
+    pub key: String,
+    // seconds left before the key expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+    #[serde(flatten)]
+    pub value: RedisValue,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum RedisValue {
+    String(String),
+    Hash(BTreeMap<String, String>),
+    Set(Vec<String>),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Serialize, Deserialize, new, PartialEq)]
+pub struct SqliteTable {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+}
+
 #[derive(Debug, Serialize, new, PartialEq, Eq)]
 pub struct StoreLightList {
     stores: Vec<StoreLight>,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrashedKind {
+    Store,
+    Aisle,
+    Product,
+}
+
+impl ToString for TrashedKind {
+    fn to_string(&self) -> String {
+        match self {
+            TrashedKind::Store => "store",
+            TrashedKind::Aisle => "aisle",
+            TrashedKind::Product => "product",
+        }
+        .to_owned()
+    }
+}
+
+impl FromStr for TrashedKind {
+    type Err = error::ServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "store" => Ok(TrashedKind::Store),
+            "aisle" => Ok(TrashedKind::Aisle),
+            "product" => Ok(TrashedKind::Product),
+            _ => Err(error::ServerError::new(
+                error::INTERNAL_ERROR,
+                &format!("Unknown trashed item {}", s),
+            )),
+        }
+    }
+}
+
+// a deleted store, aisle or product, it can be restored until it is purged
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct TrashedItem {
+    pub kind: TrashedKind,
+    pub id: String,
+    name: String,
+    // seconds since the Unix epoch
+    pub trashed_at: i64,
+}
+
+#[derive(Debug, Serialize, new, PartialEq, Eq)]
+pub struct TrashList {
+    items: Vec<TrashedItem>,
+}
+
+// version of a newly created store, aisle or product, bumped by each edit
+pub const FIRST_VERSION: u64 = 1;
+
+// as sent in the `ETag` header
+pub fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+// The `If-Match` header of an edit, a list of ETags or `*`. Any version matches when
+// there is none.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IfMatch<'a>(pub Option<&'a str>);
+
+impl<'a> IfMatch<'a> {
+    pub fn matches(&self, version: u64) -> bool {
+        let etag = etag(version);
+        self.0.map_or(true, |if_match| {
+            if_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag == etag)
+        })
+    }
+
+    // `version` is the one of the item when it is about to be written
+    pub fn verify(&self, version: u64) -> error::Result<()> {
+        if self.matches(version) {
+            Ok(())
+        } else {
+            Err(error::ServerError::new(
+                error::VERSION_MISMATCH,
+                "The item was modified since it was read",
+            ))
+        }
+    }
+}
+
 #[derive(Debug, new, Serialize)]
 pub struct Store {
     store_id: String,
     name: String,
     aisles: Vec<Aisle>,
+    // to ask for the changes made after this tree was read
+    #[new(default)]
+    revision: u64,
+    #[new(value = "FIRST_VERSION")]
+    version: u64,
+}
+
+impl Store {
+    pub fn at_revision(self, revision: u64) -> Self {
+        Store { revision, ..self }
+    }
+
+    pub fn with_version(self, version: u64) -> Self {
+        Store { version, ..self }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn aisle(&self, aisle_id: &AisleId) -> Option<&Aisle> {
+        self.aisles.iter().find(|a| a.aisle_id == **aisle_id)
+    }
+
+    // with the aisle it is in
+    pub fn product(&self, product_id: &ProductId) -> Option<(&Aisle, &Product)> {
+        self.aisles.iter().find_map(|a| {
+            a.products
+                .iter()
+                .find(|p| p.product_id == **product_id)
+                .map(|p| (a, p))
+        })
+    }
+}
+
+#[cfg(test)]
+impl Store {
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
 }
 
 impl PartialEq for Store {
@@ -152,12 +751,36 @@ impl PartialEq for Store {
     }
 }
 
-#[derive(Debug, new, Serialize)]
+#[derive(Debug, Clone, new, Serialize)]
 pub struct Aisle {
     aisle_id: String,
     name: String,
     pub sort_weight: f32,
     products: Vec<Product>,
+    #[new(value = "FIRST_VERSION")]
+    version: u64,
+}
+
+impl Aisle {
+    pub fn with_version(self, version: u64) -> Self {
+        Aisle { version, ..self }
+    }
+
+    pub fn with_products(self, products: Vec<Product>) -> Self {
+        Aisle { products, ..self }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn id(&self) -> AisleId {
+        AisleId(self.aisle_id.to_owned())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 impl PartialEq for Aisle {
@@ -193,13 +816,6 @@ impl Ord for Aisle {
     }
 }
 
-#[cfg(test)]
-impl Aisle {
-    pub fn id(&self) -> AisleId {
-        AisleId(self.aisle_id.to_owned())
-    }
-}
-
 #[derive(Deserialize_repr, Serialize_repr, Debug, Clone, PartialEq)]
 #[repr(u32)]
 #[serde(deny_unknown_fields)]
@@ -231,7 +847,7 @@ impl From<u32> for Unit {
     }
 }
 
-#[derive(Debug, Serialize, new)]
+#[derive(Debug, Clone, Serialize, new)]
 pub struct Product {
     product_id: String,
     name: String,
@@ -239,6 +855,22 @@ pub struct Product {
     is_done: bool,
     unit: Unit,
     pub sort_weight: f32,
+    #[new(value = "FIRST_VERSION")]
+    version: u64,
+}
+
+impl Product {
+    pub fn with_version(self, version: u64) -> Self {
+        Product { version, ..self }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn id(&self) -> ProductId {
+        ProductId(self.product_id.to_owned())
+    }
 }
 
 impl PartialEq for Product {
@@ -274,20 +906,13 @@ impl Ord for Product {
     }
 }
 
-#[cfg(test)]
-impl Product {
-    pub fn id(&self) -> ProductId {
-        ProductId(self.product_id.to_owned())
-    }
-}
-
-#[derive(Debug, new, Deserialize)]
+#[derive(Debug, Clone, new, Deserialize, Serialize)]
 pub struct ProductItemWeight {
     pub id: String,
     pub sort_weight: f32,
 }
 
-#[derive(Debug, new, Deserialize)]
+#[derive(Debug, Clone, new, Deserialize, Serialize)]
 pub struct AisleItemWeight {
     pub id: String,
     pub sort_weight: f32,
@@ -310,12 +935,16 @@ impl EditWeight {
     }
 }
 
-#[derive(new, Deserialize)]
+#[derive(Debug, Clone, new, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct EditProduct {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub quantity: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub unit: Option<Unit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub is_done: Option<bool>,
 }
 
@@ -326,6 +955,191 @@ impl EditProduct {
             || self.unit.is_some()
             || self.is_done.is_some()
     }
+
+    // the values of `product` these changes overwrite
+    pub fn previous(&self, product: &Product) -> EditProduct {
+        EditProduct::new(
+            self.name.as_ref().map(|_| product.name.clone()),
+            self.quantity.map(|_| product.quantity),
+            self.unit.as_ref().map(|_| product.unit.clone()),
+            self.is_done.map(|_| product.is_done),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DoneState {
+    pub is_done: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MoveProduct {
+    pub aisle_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MoveAisle {
+    pub store_id: String,
+}
+
+// The ids in an operation can also be `$<op_id>`, to refer to the item created by an
+// earlier operation of the same batch
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    CreateStore {
+        name: String,
+    },
+    EditStore {
+        store_id: String,
+        name: String,
+    },
+    DeleteStore {
+        store_id: String,
+    },
+    CreateAisle {
+        store_id: String,
+        name: String,
+    },
+    EditAisle {
+        aisle_id: String,
+        name: String,
+    },
+    DeleteAisle {
+        aisle_id: String,
+    },
+    CreateProduct {
+        aisle_id: String,
+        name: String,
+    },
+    EditProduct {
+        product_id: String,
+        #[serde(flatten)]
+        changes: EditProduct,
+    },
+    DeleteProduct {
+        product_id: String,
+    },
+    ChangeSortWeight {
+        #[serde(flatten)]
+        weights: EditWeight,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchOperation {
+    // generated by the client, the operation is applied once per id
+    pub op_id: String,
+    #[serde(flatten)]
+    pub operation: Operation,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Batch {
+    pub operations: Vec<BatchOperation>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OpStatus {
+    // `replayed` when the operation was applied by an earlier request
+    Applied {
+        #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+        result: serde_json::Value,
+        replayed: bool,
+    },
+    // the HTTP status and body the operation would have got on its own
+    Failed {
+        http_status: u16,
+        #[serde(flatten)]
+        error: error::ServerError,
+    },
+    // not attempted because an earlier operation failed
+    Skipped,
+}
+
+#[derive(Debug, Serialize, new, PartialEq)]
+pub struct OpResult {
+    op_id: String,
+    #[serde(flatten)]
+    status: OpStatus,
+}
+
+#[derive(Debug, Serialize, new, PartialEq)]
+pub struct BatchResults {
+    results: Vec<OpResult>,
+}
+
+// What reverts a mutation, kept in the undo history of the user who made it. `version` is
+// the one the mutation left the item at, the revert is refused once the item changed.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum UndoOp {
+    DeleteStore {
+        store_id: String,
+        version: u64,
+    },
+    RestoreStore {
+        store_id: String,
+    },
+    RenameStore {
+        store_id: String,
+        name: String,
+        version: u64,
+    },
+    DeleteAisle {
+        aisle_id: String,
+        version: u64,
+    },
+    RestoreAisle {
+        aisle_id: String,
+    },
+    RenameAisle {
+        aisle_id: String,
+        name: String,
+        version: u64,
+    },
+    // back to the store and place it was moved from
+    MoveAisle {
+        aisle_id: String,
+        store_id: String,
+        sort_weight: f32,
+        version: u64,
+    },
+    DeleteProduct {
+        product_id: String,
+        version: u64,
+    },
+    RestoreProduct {
+        product_id: String,
+    },
+    // the done products cleared from a store
+    RestoreProducts {
+        product_ids: Vec<String>,
+    },
+    EditProduct {
+        product_id: String,
+        changes: EditProduct,
+        version: u64,
+    },
+    MoveProduct {
+        product_id: String,
+        aisle_id: String,
+        sort_weight: f32,
+        version: u64,
+    },
+    SetDone {
+        product_ids: Vec<String>,
+        is_done: bool,
+    },
+    ChangeSortWeight {
+        aisles: Vec<AisleItemWeight>,
+        products: Vec<ProductItemWeight>,
+    },
 }
 
 #[cfg(test)]
@@ -366,4 +1180,118 @@ mod tests {
         );
         assert_eq!(true, e.has_at_least_a_field());
     }
+
+    #[test]
+    fn test_change_password_revoke_defaults_to_false() {
+        let data: ChangePassword =
+            serde_json::from_str(r#"{"old_password": "a", "new_password": "b"}"#).unwrap();
+        assert_eq!(false, data.revoke_other_sessions);
+    }
+
+    #[test]
+    fn test_role() {
+        let data: EditMember =
+            serde_json::from_str(r#"{"username": "toto", "role": "editor"}"#).unwrap();
+        assert_eq!(Role::Editor, data.role);
+        assert_eq!(Ok(Role::Editor), Role::Editor.to_string().parse());
+        assert!(Role::Viewer < Role::Editor && Role::Editor < Role::Owner);
+        assert!("admin".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn test_batch() {
+        let batch: Batch = serde_json::from_str(
+            r#"{"operations": [
+                {"op_id": "1", "op": "create_aisle", "store_id": "s", "name": "a"},
+                {"op_id": "2", "op": "edit_product", "product_id": "p", "quantity": 2, "unit": 1},
+                {"op_id": "3", "op": "change_sort_weight", "aisles": [{"id": "$1", "sort_weight": 1.5}]}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(3, batch.operations.len());
+        assert_eq!("2", batch.operations[1].op_id);
+        match &batch.operations[1].operation {
+            Operation::EditProduct {
+                product_id,
+                changes,
+            } => {
+                assert_eq!("p", product_id);
+                assert_eq!(Some(2), changes.quantity);
+                assert_eq!(None, changes.name);
+            }
+            op => panic!("unexpected operation {:?}", op),
+        }
+        match &batch.operations[2].operation {
+            Operation::ChangeSortWeight { weights } => assert!(weights.has_at_least_a_field()),
+            op => panic!("unexpected operation {:?}", op),
+        }
+        assert!(serde_json::from_str::<Batch>(
+            r#"{"operations": [{"op_id": "1", "op": "rename_everything"}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_undo_op() {
+        let product = Product::new(
+            HASH_1.to_owned(),
+            "milk".to_owned(),
+            2,
+            false,
+            Unit::Unit,
+            1.0,
+        );
+        let aisle = Aisle::new(HASH_2.to_owned(), "dairy".to_owned(), 1.0, vec![product]);
+        let store = Store::new(HASH_3.to_owned(), "store".to_owned(), vec![aisle]);
+        assert_eq!("store", store.name());
+        assert_eq!(
+            Some("dairy"),
+            store.aisle(&AisleId(HASH_2.to_owned())).map(Aisle::name)
+        );
+        let (aisle, product) = store.product(&ProductId(HASH_1.to_owned())).unwrap();
+        assert_eq!(AisleId(HASH_2.to_owned()), aisle.id());
+        assert!(store.product(&ProductId(HASH_2.to_owned())).is_none());
+
+        let edit = EditProduct::new(None, Some(3), None, Some(true));
+        let op = UndoOp::EditProduct {
+            product_id: HASH_1.to_owned(),
+            changes: edit.previous(product),
+            version: FIRST_VERSION,
+        };
+        let json = serde_json::to_string(&op).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            UndoOp::EditProduct { changes, .. } => {
+                assert_eq!(None, changes.name);
+                assert_eq!(Some(2), changes.quantity);
+                assert_eq!(Some(false), changes.is_done);
+            }
+            op => panic!("unexpected operation {:?}", op),
+        }
+    }
+
+    #[test]
+    fn test_new_invitation_fields_are_optional() {
+        let data: NewInvitation = serde_json::from_str("{}").unwrap();
+        assert_eq!(None, data.role);
+        assert_eq!(None, data.expires_in);
+        assert_eq!(None, data.max_uses);
+    }
+
+    #[test]
+    fn test_if_match() {
+        assert_eq!("\"3\"", etag(3));
+        assert!(IfMatch(Some("\"3\"")).matches(3));
+        assert!(IfMatch(Some("\"1\", \"3\"")).matches(3));
+        assert!(IfMatch(Some("*")).matches(3));
+        assert!(IfMatch(None).matches(3));
+        assert!(!IfMatch(Some("\"2\"")).matches(3));
+        // weak tags never match
+        assert!(!IfMatch(Some("W/\"3\"")).matches(3));
+        assert_eq!(
+            Err((error::VERSION_MISMATCH, error::ErrorCode::VersionMismatch)),
+            IfMatch(Some("\"2\""))
+                .verify(3)
+                .map_err(|e| (e.status, e.code))
+        );
+    }
 }